@@ -1,12 +1,14 @@
 use clap::Parser;
 use console::style;
 use zb_cli::{
-    cli::{Cli, Commands},
+    cli::{Cli, Commands, ConfigAction},
     commands,
+    config::Config,
     init::ensure_init,
-    utils::get_root_path,
+    utils::{get_root_path, init_logging, is_ci, use_plain_output},
 };
 use zb_io::install::create_installer;
+use zb_io::{SignaturePolicy, SignatureVerifier};
 
 #[tokio::main]
 async fn main() {
@@ -14,49 +16,399 @@ async fn main() {
 
     if let Err(e) = run(cli).await {
         eprintln!("{} {}", style("error:").red().bold(), e);
-        std::process::exit(1);
+        if let Some(hint) = hint(&e) {
+            eprintln!("{hint}");
+        }
+        // The shell's convention for a process stopped by SIGINT
+        let code = if e == zb_core::Error::Cancelled {
+            130
+        } else {
+            1
+        };
+        std::process::exit(code);
     }
 }
 
-async fn run(cli: Cli) -> Result<(), zb_core::Error> {
+/// What the user can do about an error, where there's something useful to say
+fn hint(e: &zb_core::Error) -> Option<String> {
+    use zb_core::Error;
+
+    let hint = match e {
+        Error::LinkConflict { .. } => format!(
+            "Run `zb install` with {} to replace the conflicting link.",
+            style("--overwrite").bold()
+        ),
+        Error::HttpStatus { code: 404 } => {
+            "The mirror returned 404 — the formula may not have a bottle for your platform."
+                .to_string()
+        }
+        Error::HttpStatus { code: 401 | 403 } => {
+            "The server refused access — check any mirror credentials or token.".to_string()
+        }
+        Error::HttpStatus { code: 429 } => {
+            "The server is rate limiting requests — wait a bit, or lower --download-concurrency."
+                .to_string()
+        }
+        Error::HttpStatus { code } if *code >= 500 => {
+            "The server had a problem — try again later, or use --bottle-mirror.".to_string()
+        }
+        Error::MissingFormula { suggestions, .. } if !suggestions.is_empty() => {
+            let names: Vec<String> = suggestions
+                .iter()
+                .map(|name| style(name).bold().to_string())
+                .collect();
+            format!("Did you mean {}?", names.join(", "))
+        }
+        Error::NoBottleForPlatform { name, .. } => format!(
+            "zerobrew only installs bottles and can't build from source — try `brew install {name}`."
+        ),
+        Error::TooManyRedirects { .. } => {
+            "The server is redirecting in a loop — check --bottle-mirror and --api-url, or raise --max-redirects."
+                .to_string()
+        }
+        Error::CrossHostRedirect { .. } => format!(
+            "Drop {} if the mirror is meant to hand downloads off to another host.",
+            style("--mirror-same-host").bold()
+        ),
+        Error::HeadUnsupported { name } => {
+            format!("Homebrew can build it from the latest source: `brew install --HEAD {name}`.")
+        }
+        Error::LockMismatch { .. } => {
+            "The formula changed since the lockfile was written — review it, then run `zb freeze` again."
+                .to_string()
+        }
+        Error::LicenseNotAccepted { name, .. } => format!(
+            "Read its terms (`zb info {name}`), then rerun with --accept-license to accept them."
+        ),
+        Error::VersionUnavailable { name, .. } => {
+            format!("Run `zb search {name}@` to list the versioned formulae Homebrew ships.")
+        }
+        Error::Timeout => {
+            "The connection is too slow or stalled, or --deadline passed — check your network and try again."
+                .to_string()
+        }
+        Error::ConnectionReset => {
+            "The connection dropped — run the command again to resume where it stopped.".to_string()
+        }
+        _ => return None,
+    };
+    Some(hint)
+}
+
+async fn run(mut cli: Cli) -> Result<(), zb_core::Error> {
     if let Commands::Completion { shell } = cli.command {
         return commands::completion::execute(shell);
     }
 
-    let root = get_root_path(cli.root);
-    let prefix = cli.prefix.unwrap_or_else(|| root.join("prefix"));
+    if let Commands::Install {
+        build_from_source: true,
+        ..
+    } = cli.command
+    {
+        return Err(zb_core::Error::InvalidArgument {
+            message: "zerobrew only installs bottles and can't build from source; \
+                      use `brew install --build-from-source` instead"
+                .to_string(),
+        });
+    }
+
+    if let Commands::Install {
+        head: true,
+        ref formulas,
+        ..
+    } = cli.command
+    {
+        return Err(zb_core::Error::HeadUnsupported {
+            name: formulas.join(" "),
+        });
+    }
+
+    if cli.json {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+    init_logging(cli.verbose);
+
+    let root = get_root_path(cli.root.take());
+
+    // Runs before the file is loaded, so a broken setting can still be fixed
+    if let Commands::Config { action } = &cli.command {
+        return match action {
+            ConfigAction::Get { key } => commands::config::get(&root, key),
+            ConfigAction::Set { key, value } => commands::config::set(&root, key, value),
+            ConfigAction::List => commands::config::list(&root),
+        };
+    }
+
+    Config::load(&root)?.apply(&mut cli)?;
+    let prefix = cli.prefix.take().unwrap_or_else(|| root.join("prefix"));
+    let interactive = !(cli.non_interactive || is_ci());
+    let plain = use_plain_output(interactive, console::Term::stdout().is_term());
 
     if let Commands::Init { no_modify_path } = cli.command {
         return commands::init::execute(&root, &prefix, no_modify_path);
     }
 
+    if let Commands::Doctor = cli.command {
+        return commands::doctor::execute(&root, &prefix);
+    }
+
+    if let Commands::Env = cli.command {
+        return commands::env::execute(&root, &prefix);
+    }
+
     if !matches!(cli.command, Commands::Reset { .. }) {
-        ensure_init(&root, &prefix)?;
+        ensure_init(&root, &prefix, interactive)?;
     }
 
-    let mut installer = create_installer(&root, &prefix, cli.concurrency)?;
+    let concurrency = cli.concurrency.unwrap_or_default().resolve();
+    let api_concurrency = cli.api_concurrency.unwrap_or(concurrency);
+    let download_concurrency = cli.download_concurrency.unwrap_or(concurrency);
+    let mut installer = create_installer(&root, &prefix, api_concurrency, download_concurrency)?;
+    if cli.api_cache_in_db == Some(true) {
+        installer =
+            installer.with_api_cache(zb_io::ApiCache::in_database(&root.join("db/zb.sqlite3"))?);
+    }
+    if let Some(rate) = cli.max_rate {
+        installer = installer.with_max_download_rate(rate);
+    }
+    if let Some(secs) = cli.timeout {
+        installer = installer.with_timeout(std::time::Duration::from_secs(secs.max(1)));
+    }
+    if let Some(budget) = cli.deadline {
+        installer = installer.with_deadline(budget);
+    }
+    if let Some(size) = cli.cache_max_size {
+        installer = installer.with_cache_max_size(size);
+    }
+    if let Some(url) = &cli.proxy {
+        installer = installer.with_proxy(zb_io::parse_proxy(url)?);
+    }
+    if cli.max_redirects.is_some() || cli.mirror_same_host == Some(true) {
+        installer = installer.with_redirect_policy(zb_io::RedirectPolicy {
+            max_redirects: cli
+                .max_redirects
+                .unwrap_or(zb_io::download::DEFAULT_MAX_REDIRECTS),
+            pin_mirror_host: cli.mirror_same_host == Some(true),
+        });
+    }
+    if let Some(url) = &cli.api_url {
+        installer = installer.with_api_url(url);
+    }
+    if let Some(mirror) = &cli.bottle_mirror {
+        installer = installer.with_bottle_mirror(mirror);
+    }
+    if !cli.fallback_mirrors.is_empty() {
+        installer = installer.with_fallback_mirrors(cli.fallback_mirrors.clone());
+    }
+    for (tap, url) in &cli.taps {
+        installer = installer.with_tap(tap, url);
+    }
+    if let Some(tag) = &cli.bottle_tag {
+        installer = installer.with_bottle_tag(tag);
+    }
+    if cli.verify_cached == Some(true) {
+        installer = installer.with_verify_cached(true);
+    }
+    if !cli.trusted_keys.is_empty() {
+        let policy = if cli.require_signatures == Some(true) {
+            SignaturePolicy::Strict
+        } else {
+            SignaturePolicy::Permissive
+        };
+        installer =
+            installer.with_signature_verifier(SignatureVerifier::new(&cli.trusted_keys, policy)?);
+    } else if cli.require_signatures == Some(true) {
+        return Err(zb_core::Error::InvalidArgument {
+            message: "--require-signatures needs at least one --trusted-key".to_string(),
+        });
+    }
+    if cli.no_cache {
+        if cli.offline == Some(true) {
+            return Err(zb_core::Error::InvalidArgument {
+                message: "--no-cache can't be combined with offline mode".to_string(),
+            });
+        }
+        installer = installer.with_no_cache(true);
+    }
+    if cli.offline == Some(true) {
+        installer = installer.with_offline(true);
+    }
+    if let Some(mode) = cli.materialize_mode {
+        installer = installer.with_materialize_mode(mode);
+    }
+    let mut local_formulas = Vec::new();
+    if let Commands::Install {
+        overwrite,
+        include_build,
+        ignore_dependencies,
+        keep_going,
+        trust_on_first_use,
+        dedup,
+        prefix_relative,
+        ref formula_file,
+        ..
+    } = cli.command
+    {
+        for path in formula_file {
+            let formula = zb_io::ApiClient::formula_from_file(path)?;
+            local_formulas.push(formula.name.clone());
+            installer = installer.with_local_formula(formula);
+        }
+        installer = installer
+            .with_link_overwrite(overwrite)
+            .with_build_dependencies(include_build)
+            .with_ignore_dependencies(ignore_dependencies)
+            .with_keep_going(keep_going)
+            .with_trust_on_first_use(trust_on_first_use)
+            .with_dedup(dedup)
+            .with_relocation(prefix_relative);
+    }
+    if let Commands::Migrate { .. } = cli.command {
+        installer = installer.with_keep_going(true);
+    }
+    if cli.stream_extract == Some(true) {
+        installer = installer.with_streaming_extraction(true);
+    }
+    if cli.accept_license {
+        installer = installer.with_accepted_licenses(true);
+    }
 
     match cli.command {
         Commands::Init { .. } => unreachable!(),
         Commands::Completion { .. } => unreachable!(),
-        Commands::Install { formulas, no_link } => {
-            commands::install::execute(&mut installer, formulas, no_link).await
+        Commands::Doctor => unreachable!(),
+        Commands::Env => unreachable!(),
+        Commands::Config { .. } => unreachable!(),
+        Commands::Install {
+            no_link,
+            bottle_url: Some(url),
+            bottle_sha,
+            name,
+            version,
+            ..
+        } => {
+            let pinned = commands::install::PinnedBottle {
+                url,
+                sha256: bottle_sha.unwrap_or_default(),
+                name: name.unwrap_or_default(),
+                version: version.unwrap_or_default(),
+            };
+            let options = commands::install::InstallOptions {
+                no_link,
+                plain,
+                ..Default::default()
+            };
+            commands::install::execute_pinned(&mut installer, pinned, options).await
+        }
+        Commands::Install {
+            no_link,
+            dry_run,
+            reinstall,
+            locked: Some(path),
+            ..
+        } => {
+            let options = commands::install::InstallOptions {
+                no_link,
+                dry_run,
+                reinstall,
+                json: cli.json,
+                plain,
+                ..Default::default()
+            };
+            commands::install::execute_locked(&mut installer, &path, options).await
+        }
+        Commands::Install {
+            mut formulas,
+            no_link,
+            only_dependencies,
+            dry_run,
+            reinstall,
+            version,
+            ..
+        } => {
+            if let Some(version) = version {
+                let [formula] = formulas.as_mut_slice() else {
+                    return Err(zb_core::Error::InvalidArgument {
+                        message: "--version needs exactly one formula".to_string(),
+                    });
+                };
+                *formula = format!("{formula}@{version}");
+            }
+            formulas.extend(local_formulas);
+            let options = commands::install::InstallOptions {
+                no_link,
+                only_dependencies,
+                dry_run,
+                reinstall,
+                json: cli.json,
+                plain,
+            };
+            commands::install::execute(&mut installer, formulas, options).await
         }
         Commands::Bundle { file, no_link } => {
-            commands::bundle::execute(&mut installer, &file, no_link).await
+            let options = commands::install::InstallOptions {
+                no_link,
+                plain,
+                ..Default::default()
+            };
+            commands::bundle::execute(&mut installer, &file, options).await
         }
-        Commands::Uninstall { formulas, all } => {
-            commands::uninstall::execute(&mut installer, formulas, all)
+        Commands::Export { output } => {
+            commands::export::execute_export(&mut installer, output.as_deref())
         }
+        Commands::Freeze { formulas } => commands::freeze::execute(&mut installer, formulas).await,
+        Commands::Import { file, no_link } => {
+            let options = commands::install::InstallOptions {
+                no_link,
+                plain,
+                ..Default::default()
+            };
+            commands::export::execute_import(&mut installer, &file, options).await
+        }
+        Commands::Uninstall {
+            formulas,
+            all,
+            force,
+        } => commands::uninstall::execute(&mut installer, formulas, all, force).await,
         Commands::Migrate { yes, force } => {
-            commands::migrate::execute(&mut installer, yes, force).await
+            commands::migrate::execute(&mut installer, yes, force, interactive).await
+        }
+        Commands::List { long } => commands::list::execute(&mut installer, cli.json, long),
+        Commands::Info {
+            formula,
+            installed_only,
+        } => commands::info::execute(&mut installer, formula, installed_only, cli.json).await,
+        Commands::Deps {
+            formula,
+            tree,
+            skip_build,
+        } => commands::deps::execute(&mut installer, formula, tree, skip_build).await,
+        Commands::Uses { formula, all, .. } => {
+            commands::uses::execute(&mut installer, formula, all).await
+        }
+        Commands::Outdated => commands::outdated::execute(&mut installer, cli.json).await,
+        Commands::Upgrade { formula } => {
+            commands::upgrade::execute(&mut installer, formula, plain).await
         }
-        Commands::List => commands::list::execute(&mut installer),
-        Commands::Info { formula } => commands::info::execute(&mut installer, formula),
-        Commands::Gc => commands::gc::execute(&mut installer),
-        Commands::Reset { yes } => commands::reset::execute(&root, &prefix, yes),
-        Commands::Run { formula, args } => {
-            commands::run::execute(&mut installer, formula, args).await
+        Commands::Pin { formula } => commands::pin::execute_pin(&mut installer, formula),
+        Commands::Unpin { formula } => commands::pin::execute_unpin(&mut installer, formula),
+        Commands::Rollback { formula } => commands::rollback::execute(&mut installer, formula),
+        Commands::Prefix { formula } => commands::prefix::execute(&mut installer, &prefix, formula),
+        Commands::Which { formula } => commands::which::execute(&mut installer, formula),
+        Commands::Verify { formula } => commands::verify::execute(&mut installer, formula),
+        Commands::Search { query } => commands::search::execute(&mut installer, query).await,
+        Commands::Fetch { url } => commands::fetch::execute(&mut installer, url, cli.json).await,
+        Commands::Gc { store_only } => commands::gc::execute(&mut installer, store_only),
+        Commands::Cleanup { dry_run, prune } => {
+            commands::cleanup::execute(&mut installer, dry_run, prune)
         }
+        Commands::Reset { yes } => commands::reset::execute(&root, &prefix, yes, interactive),
+        Commands::Run {
+            bin,
+            rm,
+            formula,
+            args,
+        } => commands::run::execute(&mut installer, formula, bin, args, rm).await,
     }
 }
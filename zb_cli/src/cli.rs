@@ -1,6 +1,9 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::config::Concurrency;
+use crate::utils::{parse_byte_rate, parse_byte_size, parse_duration, parse_tap_mapping};
+
 #[derive(Parser)]
 #[command(name = "zb")]
 #[command(about = "Zerobrew - A fast Homebrew-compatible package installer")]
@@ -12,8 +15,191 @@ pub struct Cli {
     #[arg(long, env = "ZEROBREW_PREFIX")]
     pub prefix: Option<PathBuf>,
 
-    #[arg(long, default_value = "48")]
-    pub concurrency: usize,
+    /// Concurrent network operations: a number, or auto for four per core up
+    /// to 48 (default auto)
+    #[arg(long, value_name = "N|auto")]
+    pub concurrency: Option<Concurrency>,
+
+    /// Concurrent formula metadata fetches (defaults to --concurrency)
+    #[arg(long)]
+    pub api_concurrency: Option<usize>,
+
+    /// Concurrent bottle downloads (defaults to --concurrency)
+    #[arg(long)]
+    pub download_concurrency: Option<usize>,
+
+    /// Cap download bandwidth in bytes per second (accepts k/M/G suffixes, e.g. 500k, 2M)
+    #[arg(long, value_name = "RATE", value_parser = parse_byte_rate)]
+    pub max_rate: Option<u64>,
+
+    /// Seconds to wait for a connection, or for more data on one, before giving up (default 60)
+    #[arg(long, value_name = "SECS", env = "ZEROBREW_TIMEOUT")]
+    pub timeout: Option<u64>,
+
+    /// Give up on an install that hasn't finished within this long (e.g. 300s,
+    /// 10m), removing anything half-done
+    #[arg(long, value_name = "DURATION", env = "ZEROBREW_DEADLINE", value_parser = parse_duration)]
+    pub deadline: Option<std::time::Duration>,
+
+    /// Use only cached formula data and bottles; fail instead of downloading
+    #[arg(
+        long,
+        env = "ZEROBREW_OFFLINE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    pub offline: Option<bool>,
+
+    /// Ignore cached formula data and bottles and fetch them again, refreshing the cache
+    #[arg(long, conflicts_with = "offline")]
+    pub no_cache: bool,
+
+    /// Evict least recently used bottles once the download cache exceeds this size (e.g. 5G)
+    #[arg(
+        long,
+        value_name = "SIZE",
+        env = "ZEROBREW_CACHE_MAX_SIZE",
+        value_parser = parse_byte_size
+    )]
+    pub cache_max_size: Option<u64>,
+
+    /// Re-hash cached bottles before use and redownload any that are corrupt
+    #[arg(
+        long,
+        env = "ZEROBREW_VERIFY_CACHED",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    pub verify_cached: Option<bool>,
+
+    /// Unpack bottles while they download instead of after
+    #[arg(
+        long,
+        env = "ZEROBREW_STREAM_EXTRACT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    pub stream_extract: Option<bool>,
+
+    /// Cache formula metadata in the install database instead of a file of its own
+    #[arg(
+        long,
+        env = "ZEROBREW_API_CACHE_IN_DB",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    pub api_cache_in_db: Option<bool>,
+
+    /// Trusted minisign public key for bottle signatures (repeatable)
+    #[arg(
+        long = "trusted-key",
+        value_name = "KEY",
+        env = "ZEROBREW_TRUSTED_KEYS",
+        value_delimiter = ','
+    )]
+    pub trusted_keys: Vec<String>,
+
+    /// Reject bottles without a signature instead of warning (needs --trusted-key)
+    #[arg(
+        long,
+        env = "ZEROBREW_REQUIRE_SIGNATURES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    pub require_signatures: Option<bool>,
+
+    /// Base URL of the formula API (default https://formulae.brew.sh/api/formula)
+    #[arg(long, value_name = "URL", env = "ZEROBREW_API_URL")]
+    pub api_url: Option<String>,
+
+    /// Download ghcr.io bottles from this URL prefix instead (e.g. an internal mirror)
+    #[arg(long, value_name = "URL", env = "ZEROBREW_BOTTLE_MIRROR")]
+    pub bottle_mirror: Option<String>,
+
+    /// Mirror of ghcr.io's bottles to try when a download fails (repeatable,
+    /// tried in order)
+    #[arg(
+        long = "fallback-mirror",
+        value_name = "URL",
+        env = "ZEROBREW_FALLBACK_MIRRORS",
+        value_delimiter = ','
+    )]
+    pub fallback_mirrors: Vec<String>,
+
+    /// Send all requests through this proxy (default: HTTPS_PROXY, HTTP_PROXY
+    /// and NO_PROXY from the environment)
+    #[arg(long, value_name = "URL", env = "ZEROBREW_PROXY")]
+    pub proxy: Option<String>,
+
+    /// Fail a request that is redirected more than this many times (at least
+    /// 1, as ghcr.io always redirects; default 10)
+    #[arg(
+        long,
+        value_name = "N",
+        env = "ZEROBREW_MAX_REDIRECTS",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub max_redirects: Option<usize>,
+
+    /// Refuse redirects that send --bottle-mirror requests to another host
+    #[arg(
+        long,
+        env = "ZEROBREW_MIRROR_SAME_HOST",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    pub mirror_same_host: Option<bool>,
+
+    /// Fetch formulas named user/repo/formula from URL (repeatable, e.g.
+    /// mycompany/tools=https://taps.example.com/tools)
+    #[arg(
+        long = "tap",
+        value_name = "TAP=URL",
+        env = "ZEROBREW_TAPS",
+        value_delimiter = ',',
+        value_parser = parse_tap_mapping
+    )]
+    pub taps: Vec<(String, String)>,
+
+    /// Install bottles built for this tag (e.g. x86_64_linux) instead of the
+    /// detected platform's, for emulation or reproducing bug reports
+    #[arg(long, value_name = "TAG", hide = true)]
+    pub bottle_tag: Option<String>,
+
+    /// How kegs are populated from the store: copy, hardlink, reflink or auto
+    #[arg(long, value_name = "MODE", env = "ZEROBREW_MATERIALIZE_MODE")]
+    pub materialize_mode: Option<zb_io::MaterializeMode>,
+
+    /// Accept the licenses of formulas that require explicit acceptance, for
+    /// any command that installs formulas
+    #[arg(long, global = true)]
+    pub accept_license: bool,
+
+    /// Print machine-readable JSON from list, info and outdated, and stream
+    /// install progress as JSON lines
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Log what zerobrew is doing to stderr; repeat for more detail (-vv)
+    #[arg(long, short = 'v', action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Never prompt; take the safe default and print plain lines instead of
+    /// progress bars (implied when CI=true)
+    #[arg(long, short = 'q', visible_alias = "quiet", global = true)]
+    pub non_interactive: bool,
 
     #[command(subcommand)]
     pub command: Commands,
@@ -22,10 +208,91 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     Install {
-        #[arg(required = true, num_args = 1..)]
+        #[arg(
+            required_unless_present_any = ["bottle_url", "formula_file", "locked"],
+            num_args = 1..
+        )]
         formulas: Vec<String>,
         #[arg(long)]
         no_link: bool,
+        /// Replace files and other formulas' links that conflict when linking
+        #[arg(long, conflicts_with = "no_link")]
+        overwrite: bool,
+        /// Also install build-only dependencies, as a source build would need
+        #[arg(long)]
+        include_build: bool,
+        /// Install the formulas' dependencies but not the formulas themselves
+        #[arg(long, conflicts_with = "bottle_url")]
+        only_dependencies: bool,
+        /// Install only the named formulas, skipping their dependencies entirely
+        /// (for debugging a bottle in isolation; it may not run)
+        #[arg(long, conflicts_with = "only_dependencies")]
+        ignore_dependencies: bool,
+        /// Keep installing the other formulas when one fails, then report the failures
+        #[arg(long)]
+        keep_going: bool,
+        /// Install bottles whose formula gives no sha256, hashing each on its
+        /// first download and printing the digest to add to the formula
+        #[arg(long)]
+        trust_on_first_use: bool,
+        /// Remove and unpack again formulas that are already installed,
+        /// reusing their cached bottles unless --no-cache is given
+        #[arg(long, conflicts_with_all = ["only_dependencies", "bottle_url"])]
+        reinstall: bool,
+        /// Rewrite library paths bottles hardcode to Homebrew's prefix so they
+        /// point into ours, listing the files changed
+        #[arg(long)]
+        prefix_relative: bool,
+        /// Hardlink files identical to ones already in the store instead of
+        /// storing them again
+        #[arg(long)]
+        dedup: bool,
+        /// Not supported: zerobrew only installs bottles, so this fails with a
+        /// pointer to Homebrew
+        #[arg(long, short = 's')]
+        build_from_source: bool,
+        /// Not supported: installing the latest commit needs a source build,
+        /// so this fails with a pointer to Homebrew
+        #[arg(long = "HEAD", visible_alias = "head")]
+        head: bool,
+        /// Show what would be installed and how much would be downloaded, then stop
+        #[arg(long, conflicts_with = "bottle_url")]
+        dry_run: bool,
+        /// Install exactly what a lockfile from `zb freeze` records (default
+        /// zb.lock), failing if the API no longer serves the same bottles
+        #[arg(
+            long,
+            value_name = "FILE",
+            num_args = 0..=1,
+            default_missing_value = "zb.lock",
+            conflicts_with_all = ["formulas", "bottle_url", "formula_file", "only_dependencies"]
+        )]
+        locked: Option<PathBuf>,
+        /// Install the formula described by this JSON file instead of fetching it
+        /// (repeatable; file:// bottle URLs in it are read from disk)
+        #[arg(long, value_name = "FILE", conflicts_with = "bottle_url")]
+        formula_file: Vec<PathBuf>,
+        /// Install the bottle at this URL instead of resolving through the formula API
+        #[arg(
+            long,
+            value_name = "URL",
+            conflicts_with = "formulas",
+            requires_all = ["bottle_sha", "name", "version", "no_deps"]
+        )]
+        bottle_url: Option<String>,
+        /// Expected sha256 of the bottle given by --bottle-url
+        #[arg(long, value_name = "SHA256", requires = "bottle_url")]
+        bottle_sha: Option<String>,
+        /// Formula name to record for --bottle-url
+        #[arg(long, requires = "bottle_url")]
+        name: Option<String>,
+        /// Install this version of the formula (the same as formula@version),
+        /// or with --bottle-url, the version to record
+        #[arg(long)]
+        version: Option<String>,
+        /// Skip dependency resolution (required with --bottle-url)
+        #[arg(long, requires = "bottle_url")]
+        no_deps: bool,
     },
     Bundle {
         #[arg(long, short = 'f', value_name = "FILE", default_value = "Brewfile")]
@@ -33,11 +300,32 @@ pub enum Commands {
         #[arg(long)]
         no_link: bool,
     },
+    /// Write the installed formulas and their versions to a JSON manifest
+    Export {
+        /// Write to this file instead of stdout
+        #[arg(long, short = 'o', value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Print a lockfile pinning the exact version and bottle sha256 of the
+    /// formulas and all their dependencies
+    Freeze {
+        #[arg(required = true, num_args = 1..)]
+        formulas: Vec<String>,
+    },
+    /// Install the formulas in a manifest written by `zb export`
+    Import {
+        file: PathBuf,
+        #[arg(long)]
+        no_link: bool,
+    },
     Uninstall {
         #[arg(required_unless_present = "all", num_args = 1..)]
         formulas: Vec<String>,
         #[arg(long)]
         all: bool,
+        /// Remove even if other installed formulas depend on it
+        #[arg(long)]
+        force: bool,
     },
     Migrate {
         #[arg(long, short = 'y')]
@@ -45,11 +333,83 @@ pub enum Commands {
         #[arg(long)]
         force: bool,
     },
-    List,
+    /// List installed formulas
+    List {
+        /// Show each formula's description too
+        #[arg(long, short = 'l')]
+        long: bool,
+    },
     Info {
         formula: String,
+        /// Only describe installed formulas instead of looking others up
+        #[arg(long)]
+        installed_only: bool,
+    },
+    /// Show the dependencies a formula would pull in
+    Deps {
+        formula: String,
+        /// Print an indented tree instead of a flat list
+        #[arg(long)]
+        tree: bool,
+        /// Leave out build-time-only dependencies
+        #[arg(long)]
+        skip_build: bool,
+    },
+    /// Show which formulas depend on the given formula
+    Uses {
+        formula: String,
+        /// Only consider installed formulas (the default)
+        #[arg(long, conflicts_with = "all")]
+        installed_only: bool,
+        /// Search every known formula, not just installed ones
+        #[arg(long)]
+        all: bool,
+    },
+    /// List installed formulas with newer versions available; exits 1 if any
+    Outdated,
+    /// Upgrade outdated formulas (all of them, or just the one given)
+    Upgrade { formula: Option<String> },
+    /// Hold a formula at its installed version so upgrades skip it
+    Pin { formula: String },
+    /// Allow a pinned formula to be upgraded again
+    Unpin { formula: String },
+    /// Switch a formula back to the version it had before its last upgrade
+    Rollback { formula: String },
+    /// Print the prefix, or an installed formula's keg path
+    Prefix { formula: Option<String> },
+    /// Print the full paths of the executables an installed formula provides
+    Which { formula: String },
+    /// Rehash store entries and check kegs against them (all, or just one)
+    Verify { formula: Option<String> },
+    /// Search formula names and descriptions
+    Search { query: String },
+    /// Download a URL into the bottle cache and print its sha256, for
+    /// writing formula JSON
+    Fetch { url: String },
+    /// Remove store entries, kegs and cached bottles no installed formula uses
+    Gc {
+        /// Only remove store entries whose refcount has dropped to zero
+        #[arg(long)]
+        store_only: bool,
+    },
+    /// Check the installation for common problems
+    Doctor,
+    /// Print shell exports for the current session: eval "$(zb env)"
+    Env,
+    /// Read and change settings in <root>/config.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Remove cached bottles and old versions no longer in use
+    Cleanup {
+        /// Show what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Also remove cached bottles older than this many days, even if in use
+        #[arg(long, value_name = "DAYS")]
+        prune: Option<u64>,
     },
-    Gc,
     Reset {
         #[arg(long, short = 'y')]
         yes: bool,
@@ -62,10 +422,27 @@ pub enum Commands {
         #[arg(value_enum)]
         shell: clap_complete::shells::Shell,
     },
+    /// Run an executable from a formula, installing it first if needed
     #[command(disable_help_flag = true)]
     Run {
+        /// Executable under the formula's bin/ to run; `formula:binary` also works
+        #[arg(long, value_name = "NAME")]
+        bin: Option<String>,
+        /// Uninstall the formula afterwards if this run installed it
+        #[arg(long)]
+        rm: bool,
         formula: String,
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
 }
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print the value of a setting
+    Get { key: String },
+    /// Check a value and write it to config.toml
+    Set { key: String, value: String },
+    /// Print every setting in config.toml
+    List,
+}
@@ -8,7 +8,7 @@ use super::install;
 pub async fn execute(
     installer: &mut zb_io::install::Installer,
     manifest_path: &Path,
-    no_link: bool,
+    options: install::InstallOptions,
 ) -> Result<(), zb_core::Error> {
     let formulas = load_manifest(manifest_path)?;
     println!(
@@ -20,7 +20,7 @@ pub async fn execute(
 
     let start = Instant::now();
     for formula in formulas {
-        install::execute(installer, vec![formula], no_link).await?;
+        install::execute(installer, vec![formula], options).await?;
     }
 
     println!(
@@ -0,0 +1,32 @@
+use console::style;
+use indicatif::HumanBytes;
+
+pub fn execute(
+    installer: &mut zb_io::install::Installer,
+    dry_run: bool,
+    prune_days: Option<u64>,
+) -> Result<(), zb_core::Error> {
+    println!("{} Cleaning up...", style("==>").cyan().bold());
+    let report = installer.cleanup(dry_run, prune_days)?;
+
+    if report.blobs.is_empty() && report.kegs.is_empty() {
+        println!("Nothing to clean up.");
+        return Ok(());
+    }
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for path in report.kegs.iter().chain(&report.blobs) {
+        println!("    {} {verb} {}", style("✓").green(), path.display());
+    }
+
+    println!(
+        "{} {verb} {} old versions and {} cached bottles, {} {}",
+        style("==>").cyan().bold(),
+        style(report.kegs.len()).green().bold(),
+        style(report.blobs.len()).green().bold(),
+        style(HumanBytes(report.bytes_reclaimed)).green().bold(),
+        if dry_run { "reclaimable" } else { "reclaimed" }
+    );
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::{Concurrency, Config};
+use crate::utils::{parse_byte_rate, parse_byte_size};
+
+/// What a setting holds, which decides how a value from the command line is
+/// checked and written to the file
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    Bool,
+    Count,
+    Seconds,
+    Concurrency,
+    ByteSize,
+    ByteRate,
+    MaterializeMode,
+}
+
+/// The settings `zb config` can read and write; `taps`, `trusted_keys` and
+/// `fallback_mirrors` are collections and are left to editing the file
+/// directly
+const SETTINGS: &[(&str, Kind)] = &[
+    ("prefix", Kind::Text),
+    ("concurrency", Kind::Concurrency),
+    ("api_concurrency", Kind::Count),
+    ("download_concurrency", Kind::Count),
+    ("max_rate", Kind::ByteRate),
+    ("timeout", Kind::Seconds),
+    ("cache_max_size", Kind::ByteSize),
+    ("offline", Kind::Bool),
+    ("verify_cached", Kind::Bool),
+    ("stream_extract", Kind::Bool),
+    ("api_cache_in_db", Kind::Bool),
+    ("api_url", Kind::Text),
+    ("require_signatures", Kind::Bool),
+    ("bottle_mirror", Kind::Text),
+    ("proxy", Kind::Text),
+    ("max_redirects", Kind::Count),
+    ("mirror_same_host", Kind::Bool),
+    ("materialize_mode", Kind::MaterializeMode),
+];
+
+/// Print the value of `key`, or nothing if it isn't set
+pub fn get(root: &Path, key: &str) -> Result<(), zb_core::Error> {
+    if let Some(value) = get_value(root, key)? {
+        println!("{value}");
+    }
+    Ok(())
+}
+
+/// Check `value` against what `key` accepts and write it to `config.toml`
+pub fn set(root: &Path, key: &str, value: &str) -> Result<(), zb_core::Error> {
+    set_value(root, key, value)?;
+    println!("{key} = {}", get_value(root, key)?.unwrap_or_default());
+    Ok(())
+}
+
+/// Print everything set in `config.toml`
+pub fn list(root: &Path) -> Result<(), zb_core::Error> {
+    let table = read_table(&config_path(root))?;
+    print!("{}", to_toml(&table)?);
+    Ok(())
+}
+
+fn get_value(root: &Path, key: &str) -> Result<Option<String>, zb_core::Error> {
+    setting(key)?;
+    let table = read_table(&config_path(root))?;
+    Ok(table.get(key).map(|value| match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }))
+}
+
+fn set_value(root: &Path, key: &str, value: &str) -> Result<(), zb_core::Error> {
+    let kind = setting(key)?;
+    let value = parse_value(kind, value).map_err(|message| zb_core::Error::InvalidArgument {
+        message: format!("{key}: {message}"),
+    })?;
+
+    let path = config_path(root);
+    let contents = set_in_toml(&read_file(&path)?, key, &value).map_err(|e| {
+        zb_core::Error::InvalidArgument {
+            message: format!("invalid {}: {e}", path.display()),
+        }
+    })?;
+
+    // Refuse to write a file the next run couldn't load
+    toml::from_str::<Config>(&contents).map_err(|e| zb_core::Error::InvalidArgument {
+        message: format!("invalid {}: {e}", path.display()),
+    })?;
+
+    std::fs::create_dir_all(root)
+        .and_then(|()| std::fs::write(&path, contents))
+        .map_err(|e| zb_core::Error::FileError {
+            message: format!("failed to write {}: {e}", path.display()),
+        })
+}
+
+fn setting(key: &str) -> Result<Kind, zb_core::Error> {
+    SETTINGS
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| {
+            let known: Vec<&str> = SETTINGS.iter().map(|(name, _)| *name).collect();
+            zb_core::Error::InvalidArgument {
+                message: format!(
+                    "unknown config key '{key}' (known keys: {})",
+                    known.join(", ")
+                ),
+            }
+        })
+}
+
+fn parse_value(kind: Kind, value: &str) -> Result<toml::Value, String> {
+    let count = |what: &str| match value.parse::<u64>() {
+        Ok(n) if n > 0 => i64::try_from(n)
+            .map(toml::Value::Integer)
+            .map_err(|_| format!("{what} '{value}' is too large")),
+        _ => Err(format!(
+            "invalid {what} '{value}' (expected a positive number)"
+        )),
+    };
+
+    match kind {
+        Kind::Text => Ok(toml::Value::String(value.to_string())),
+        Kind::Bool => match value.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(toml::Value::Boolean(true)),
+            "false" | "no" | "off" | "0" => Ok(toml::Value::Boolean(false)),
+            _ => Err(format!(
+                "invalid boolean '{value}' (expected true or false)"
+            )),
+        },
+        Kind::Count => count("count"),
+        Kind::Seconds => count("number of seconds"),
+        Kind::Concurrency => match value.parse::<Concurrency>()? {
+            Concurrency::Auto => Ok(toml::Value::String("auto".to_string())),
+            Concurrency::Fixed(_) => count("concurrency"),
+        },
+        Kind::ByteSize => {
+            parse_byte_size(value)?;
+            Ok(toml::Value::String(value.to_string()))
+        }
+        Kind::ByteRate => {
+            parse_byte_rate(value)?;
+            Ok(toml::Value::String(value.to_string()))
+        }
+        Kind::MaterializeMode => {
+            value.parse::<zb_io::MaterializeMode>()?;
+            Ok(toml::Value::String(value.to_ascii_lowercase()))
+        }
+    }
+}
+
+fn config_path(root: &Path) -> PathBuf {
+    root.join("config.toml")
+}
+
+/// The file's contents, empty if it doesn't exist yet
+fn read_file(path: &Path) -> Result<String, zb_core::Error> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(zb_core::Error::FileError {
+            message: format!("failed to read {}: {e}", path.display()),
+        }),
+    }
+}
+
+/// Read the file as a plain table, for settings this command doesn't manage
+/// like `[taps]` as well as its own
+fn read_table(path: &Path) -> Result<toml::Table, zb_core::Error> {
+    read_file(path)?
+        .parse()
+        .map_err(|e| zb_core::Error::InvalidArgument {
+            message: format!("invalid {}: {e}", path.display()),
+        })
+}
+
+/// Write `key = value` into the top-level table of `contents`, keeping an
+/// existing setting's comment. Every other part of the file, comments
+/// included, is kept as it was.
+fn set_in_toml(contents: &str, key: &str, value: &toml::Value) -> Result<String, String> {
+    let mut document: toml_edit::DocumentMut = contents.parse().map_err(|e| format!("{e}"))?;
+    let mut value: toml_edit::Value = value
+        .to_string()
+        .parse()
+        .map_err(|e: toml_edit::TomlError| e.to_string())?;
+    if let Some(old) = document.get(key).and_then(toml_edit::Item::as_value) {
+        *value.decor_mut() = old.decor().clone();
+    }
+    document[key] = toml_edit::Item::Value(value);
+    Ok(document.to_string())
+}
+
+fn to_toml(table: &toml::Table) -> Result<String, zb_core::Error> {
+    toml::to_string(table).map_err(|e| zb_core::Error::ExecutionError {
+        message: format!("failed to serialize config: {e}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn set_values_read_back_and_load() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(
+            tmp.path().join("config.toml"),
+            "offline = false\n\n[taps]\n\"mycompany/tools\" = \"https://taps.example.com/tools\"\n",
+        )
+        .unwrap();
+
+        set_value(tmp.path(), "concurrency", "12").unwrap();
+        set_value(tmp.path(), "cache_max_size", "2G").unwrap();
+        set_value(tmp.path(), "offline", "true").unwrap();
+        set_value(tmp.path(), "bottle_mirror", "https://mirror.example.com").unwrap();
+
+        assert_eq!(
+            get_value(tmp.path(), "concurrency").unwrap().as_deref(),
+            Some("12")
+        );
+        assert_eq!(
+            get_value(tmp.path(), "cache_max_size").unwrap().as_deref(),
+            Some("2G")
+        );
+        assert_eq!(
+            get_value(tmp.path(), "bottle_mirror").unwrap().as_deref(),
+            Some("https://mirror.example.com")
+        );
+        assert_eq!(get_value(tmp.path(), "proxy").unwrap(), None);
+
+        let config = Config::load(tmp.path()).unwrap();
+        assert_eq!(config.concurrency, Some(Concurrency::Fixed(12)));
+        assert_eq!(config.offline, Some(true));
+        assert_eq!(
+            config.taps.get("mycompany/tools").map(String::as_str),
+            Some("https://taps.example.com/tools")
+        );
+
+        set_value(tmp.path(), "concurrency", "auto").unwrap();
+        assert_eq!(
+            Config::load(tmp.path()).unwrap().concurrency,
+            Some(Concurrency::Auto)
+        );
+    }
+
+    #[test]
+    fn set_keeps_comments_and_the_order_of_the_file() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "# c\nconcurrency = 4 # x\nproxy = \"http://p#1\" # y\n\n# taps\n[taps]\nfoo = \"bar\"\n",
+        )
+        .unwrap();
+
+        set_value(tmp.path(), "concurrency", "8").unwrap();
+        set_value(tmp.path(), "proxy", "http://q").unwrap();
+        set_value(tmp.path(), "offline", "true").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# c\nconcurrency = 8 # x\nproxy = \"http://q\" # y\noffline = true\n\n# taps\n[taps]\nfoo = \"bar\"\n"
+        );
+
+        // With no top-level settings yet, new ones go above the first table
+        std::fs::write(&path, "# taps\n[taps]\nfoo = \"bar\"\n").unwrap();
+        set_value(tmp.path(), "offline", "true").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "offline = true\n# taps\n[taps]\nfoo = \"bar\"\n"
+        );
+    }
+
+    #[test]
+    fn set_leaves_multi_line_values_whole() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "trusted_keys = [\n  \"RWkey\", # primary\n]\nconcurrency = 4 # x\n",
+        )
+        .unwrap();
+
+        set_value(tmp.path(), "offline", "true").unwrap();
+        set_value(tmp.path(), "concurrency", "8").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "trusted_keys = [\n  \"RWkey\", # primary\n]\nconcurrency = 8 # x\noffline = true\n"
+        );
+        let config = Config::load(tmp.path()).unwrap();
+        assert_eq!(config.trusted_keys, ["RWkey"]);
+        assert_eq!(config.offline, Some(true));
+    }
+
+    #[test]
+    fn unknown_keys_and_bad_values_are_rejected() {
+        let tmp = TempDir::new().unwrap();
+
+        for (key, value) in [
+            ("concurency", "8"),
+            ("taps", "x"),
+            ("concurrency", "0"),
+            ("cache_max_size", "lots"),
+            ("offline", "maybe"),
+        ] {
+            assert!(
+                matches!(
+                    set_value(tmp.path(), key, value),
+                    Err(zb_core::Error::InvalidArgument { .. })
+                ),
+                "{key} = {value} should be rejected"
+            );
+        }
+        assert!(get_value(tmp.path(), "concurency").is_err());
+        assert!(!tmp.path().join("config.toml").exists());
+    }
+}
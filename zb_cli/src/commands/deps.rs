@@ -0,0 +1,234 @@
+use console::style;
+use std::collections::{BTreeMap, BTreeSet};
+use zb_core::{DependencyKind, Formula, resolve_closure};
+
+use crate::utils::normalize_tap_formula_name;
+
+/// One dependency to print, at `depth` levels below the requested formula
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DepLine {
+    depth: usize,
+    name: String,
+    build_only: bool,
+}
+
+pub async fn execute(
+    installer: &mut zb_io::install::Installer,
+    formula: String,
+    tree: bool,
+    skip_build: bool,
+) -> Result<(), zb_core::Error> {
+    let name = normalize_tap_formula_name(&formula, |tap| installer.has_tap(tap))?;
+    let formulas = installer
+        .fetch_all_formulas(std::slice::from_ref(&name))
+        .await?;
+
+    if !formulas.contains_key(&name) {
+        return Err(zb_core::Error::UnsupportedBottle { name });
+    }
+
+    let lines = if tree {
+        tree_lines(&name, &formulas, skip_build)
+    } else {
+        flat_lines(&name, &formulas, skip_build)?
+    };
+
+    if tree {
+        println!("{}", style(&name).bold());
+    } else if lines.is_empty() {
+        println!("{} has no dependencies.", style(&name).bold());
+        return Ok(());
+    }
+
+    for line in lines {
+        let indent = "  ".repeat(line.depth);
+        let status = if line.build_only {
+            style("(build)").dim().to_string()
+        } else if installer.is_installed(&line.name) {
+            format!("{} installed", style("✓").green())
+        } else {
+            style("(will be installed)").dim().to_string()
+        };
+        println!("{indent}{} {status}", line.name);
+    }
+
+    Ok(())
+}
+
+/// Every dependency once, in install order, followed by build-only dependencies
+fn flat_lines(
+    root: &str,
+    formulas: &BTreeMap<String, Formula>,
+    skip_build: bool,
+) -> Result<Vec<DepLine>, zb_core::Error> {
+    let ordered = resolve_closure(&[root.to_string()], formulas)?;
+    let runtime: BTreeSet<&str> = ordered.iter().map(String::as_str).collect();
+
+    let mut lines: Vec<DepLine> = ordered
+        .iter()
+        .filter(|name| *name != root)
+        .map(|name| DepLine {
+            depth: 0,
+            name: name.clone(),
+            build_only: false,
+        })
+        .collect();
+
+    if !skip_build {
+        let build: BTreeSet<String> = ordered
+            .iter()
+            .filter_map(|name| formulas.get(name))
+            .flat_map(build_dependencies)
+            .filter(|dep| !runtime.contains(dep.as_str()))
+            .collect();
+        lines.extend(build.into_iter().map(|name| DepLine {
+            depth: 0,
+            name,
+            build_only: true,
+        }));
+    }
+
+    Ok(lines)
+}
+
+/// The dependency tree below `root`, with build-only dependencies as leaves
+fn tree_lines(root: &str, formulas: &BTreeMap<String, Formula>, skip_build: bool) -> Vec<DepLine> {
+    fn walk(
+        name: &str,
+        depth: usize,
+        formulas: &BTreeMap<String, Formula>,
+        skip_build: bool,
+        ancestors: &mut Vec<String>,
+        lines: &mut Vec<DepLine>,
+    ) {
+        let Some(formula) = formulas.get(name) else {
+            return;
+        };
+        ancestors.push(name.to_string());
+
+        let mut deps: Vec<String> = formula
+            .install_dependencies(false)
+            .into_iter()
+            .map(|dep| dep.name)
+            .filter(|dep| formulas.contains_key(dep) && !ancestors.contains(dep))
+            .collect();
+        deps.sort();
+        for dep in deps {
+            lines.push(DepLine {
+                depth,
+                name: dep.clone(),
+                build_only: false,
+            });
+            walk(&dep, depth + 1, formulas, skip_build, ancestors, lines);
+        }
+
+        if !skip_build {
+            let mut build = build_dependencies(formula);
+            build.sort();
+            lines.extend(build.into_iter().map(|name| DepLine {
+                depth,
+                name,
+                build_only: true,
+            }));
+        }
+
+        ancestors.pop();
+    }
+
+    let mut lines = Vec::new();
+    walk(root, 1, formulas, skip_build, &mut Vec::new(), &mut lines);
+    lines
+}
+
+fn build_dependencies(formula: &Formula) -> Vec<String> {
+    formula
+        .dependency_specs()
+        .into_iter()
+        .filter(|dep| dep.kind == Some(DependencyKind::Build))
+        .map(|dep| dep.name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zb_core::formula::{Bottle, BottleStable, Versions};
+
+    fn formula(name: &str, deps: &[&str], build_deps: &[&str]) -> (String, Formula) {
+        let formula = Formula {
+            name: name.to_string(),
+            versions: Versions {
+                stable: "1.0.0".to_string(),
+            },
+            dependencies: deps.iter().map(|d| d.to_string()).collect(),
+            build_dependencies: build_deps.iter().map(|d| d.to_string()).collect(),
+            bottle: Bottle {
+                stable: BottleStable {
+                    files: BTreeMap::new(),
+                    rebuild: 0,
+                },
+            },
+            revision: 0,
+            keg_only: false,
+            keg_only_reason: None,
+            caveats: None,
+            desc: None,
+            homepage: None,
+            license: None,
+            requires_license_acceptance: false,
+            tap: None,
+        };
+        (name.to_string(), formula)
+    }
+
+    fn graph() -> BTreeMap<String, Formula> {
+        BTreeMap::from([
+            formula("app", &["libb", "liba"], &["cmake"]),
+            formula("liba", &["zlib"], &[]),
+            formula("libb", &["zlib"], &["pkgconf"]),
+            formula("zlib", &[], &[]),
+        ])
+    }
+
+    fn line(depth: usize, name: &str, build_only: bool) -> DepLine {
+        DepLine {
+            depth,
+            name: name.to_string(),
+            build_only,
+        }
+    }
+
+    #[test]
+    fn flat_list_is_deduplicated_in_install_order() {
+        let lines = flat_lines("app", &graph(), false).unwrap();
+        assert_eq!(
+            lines,
+            [
+                line(0, "zlib", false),
+                line(0, "liba", false),
+                line(0, "libb", false),
+                line(0, "cmake", true),
+                line(0, "pkgconf", true),
+            ]
+        );
+
+        let lines = flat_lines("app", &graph(), true).unwrap();
+        assert!(lines.iter().all(|l| !l.build_only));
+    }
+
+    #[test]
+    fn tree_repeats_shared_dependencies_under_each_parent() {
+        let lines = tree_lines("app", &graph(), false);
+        assert_eq!(
+            lines,
+            [
+                line(1, "liba", false),
+                line(2, "zlib", false),
+                line(1, "libb", false),
+                line(2, "zlib", false),
+                line(2, "pkgconf", true),
+                line(1, "cmake", true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use console::style;
+use std::path::Path;
+
+use crate::init::is_writable;
+
+/// Run every check and report each one; returns an error if any failed
+pub fn execute(root: &Path, prefix: &Path) -> Result<(), zb_core::Error> {
+    println!(
+        "{} Checking zerobrew installation...",
+        style("==>").cyan().bold()
+    );
+    let mut problems = 0;
+
+    let bin = prefix.join("bin");
+    let on_path = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| dir == bin))
+        .unwrap_or(false);
+    problems += report(
+        on_path,
+        &format!("{} is on PATH", bin.display()),
+        &[],
+        "Run `zb init` or add it to PATH in your shell config.",
+    );
+
+    let unwritable: Vec<String> = [
+        root.to_path_buf(),
+        root.join("store"),
+        root.join("db"),
+        root.join("cache"),
+        root.join("locks"),
+        prefix.to_path_buf(),
+        bin,
+    ]
+    .iter()
+    .filter(|dir| !is_writable(dir))
+    .map(|dir| dir.display().to_string())
+    .collect();
+    problems += report(
+        unwritable.is_empty(),
+        "directories are writable",
+        &unwritable,
+        "Run `zb init` to create them and fix ownership.",
+    );
+    if !unwritable.is_empty() {
+        println!("    Skipping remaining checks until the directories are fixed.");
+        return finish(problems);
+    }
+
+    let installer = zb_io::install::create_installer(root, prefix, 1, 1)?;
+
+    let dangling: Vec<String> = installer
+        .dangling_links()
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    problems += report(
+        dangling.is_empty(),
+        "no dangling symlinks in prefix",
+        &dangling,
+        "Remove them, or reinstall the formula that owned them.",
+    );
+
+    let missing_kegs = installer.missing_kegs()?;
+    problems += report(
+        missing_kegs.is_empty(),
+        "installed kegs exist in the cellar",
+        &missing_kegs,
+        "Reinstall them, or `zb uninstall --force` to forget them.",
+    );
+
+    let missing_entries = installer.missing_store_entries()?;
+    problems += report(
+        missing_entries.is_empty(),
+        "store entries exist for installed kegs",
+        &missing_entries,
+        "Reinstall them to restore their store entries.",
+    );
+
+    finish(problems)
+}
+
+fn report(ok: bool, check: &str, details: &[String], hint: &str) -> usize {
+    if ok {
+        println!("    {} {check}", style("✓").green());
+        return 0;
+    }
+
+    println!("    {} {check}", style("✗").red());
+    for detail in details {
+        println!("        {detail}");
+    }
+    println!("      {}", style(hint).dim());
+    1
+}
+
+fn finish(problems: usize) -> Result<(), zb_core::Error> {
+    if problems == 0 {
+        println!("{} No problems found.", style("==>").cyan().bold());
+        return Ok(());
+    }
+
+    Err(zb_core::Error::ExecutionError {
+        message: format!(
+            "doctor found {problems} problem{}",
+            if problems == 1 { "" } else { "s" }
+        ),
+    })
+}
@@ -0,0 +1,89 @@
+use std::path::Path;
+
+/// The syntax to print exports in, picked from `$SHELL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellSyntax {
+    Posix,
+    Fish,
+}
+
+impl ShellSyntax {
+    pub fn detect(shell: &str) -> Self {
+        let name = Path::new(shell)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(shell);
+        if name == "fish" {
+            ShellSyntax::Fish
+        } else {
+            ShellSyntax::Posix
+        }
+    }
+}
+
+/// Print the environment zerobrew needs for `eval "$(zb env)"`, leaving
+/// shell config files alone
+pub fn execute(root: &Path, prefix: &Path) -> Result<(), zb_core::Error> {
+    let syntax = ShellSyntax::detect(&std::env::var("SHELL").unwrap_or_default());
+    print!("{}", exports(syntax, root, prefix));
+    Ok(())
+}
+
+/// One line per variable: plain values are set, search paths get the
+/// zerobrew directory put in front of whatever is already there
+fn exports(syntax: ShellSyntax, root: &Path, prefix: &Path) -> String {
+    let set = [
+        ("ZEROBREW_ROOT", root.to_path_buf()),
+        ("ZEROBREW_PREFIX", prefix.to_path_buf()),
+    ];
+    let prepend = [
+        ("PATH", prefix.join("bin")),
+        ("PKG_CONFIG_PATH", prefix.join("lib/pkgconfig")),
+    ];
+
+    let mut out = String::new();
+    for (var, value) in set {
+        let value = value.display();
+        out.push_str(&match syntax {
+            ShellSyntax::Posix => format!("export {var}=\"{value}\"\n"),
+            ShellSyntax::Fish => format!("set -gx {var} \"{value}\"\n"),
+        });
+    }
+    for (var, dir) in prepend {
+        let dir = dir.display();
+        out.push_str(&match syntax {
+            ShellSyntax::Posix => format!("export {var}=\"{dir}:${{{var}:-}}\"\n"),
+            ShellSyntax::Fish => format!("set -gx {var} \"{dir}\" ${var}\n"),
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_fish_from_its_path() {
+        assert_eq!(ShellSyntax::detect("/usr/bin/fish"), ShellSyntax::Fish);
+        assert_eq!(ShellSyntax::detect("/bin/bash"), ShellSyntax::Posix);
+        assert_eq!(ShellSyntax::detect("/bin/zsh"), ShellSyntax::Posix);
+        assert_eq!(ShellSyntax::detect(""), ShellSyntax::Posix);
+    }
+
+    #[test]
+    fn bash_and_fish_exports_use_their_own_syntax() {
+        let root = Path::new("/opt/zerobrew");
+        let prefix = Path::new("/opt/zerobrew/prefix");
+
+        let bash = exports(ShellSyntax::Posix, root, prefix);
+        assert!(bash.contains("export ZEROBREW_ROOT=\"/opt/zerobrew\"\n"));
+        assert!(bash.contains("export PATH=\"/opt/zerobrew/prefix/bin:${PATH:-}\"\n"));
+        assert!(!bash.contains("set -gx"));
+
+        let fish = exports(ShellSyntax::Fish, root, prefix);
+        assert!(fish.contains("set -gx ZEROBREW_ROOT \"/opt/zerobrew\"\n"));
+        assert!(fish.contains("set -gx PATH \"/opt/zerobrew/prefix/bin\" $PATH\n"));
+        assert!(!fish.contains("export"));
+    }
+}
@@ -0,0 +1,132 @@
+use console::style;
+use std::path::Path;
+use std::time::Instant;
+
+use zb_io::install::ExportManifest;
+
+use super::install;
+
+/// Write the installed formulas as a JSON manifest, to `output` or stdout
+pub fn execute_export(
+    installer: &mut zb_io::install::Installer,
+    output: Option<&Path>,
+) -> Result<(), zb_core::Error> {
+    let manifest = installer.export()?;
+    let json =
+        serde_json::to_string_pretty(&manifest).map_err(|e| zb_core::Error::ExecutionError {
+            message: format!("failed to serialize manifest: {e}"),
+        })?;
+
+    let Some(path) = output else {
+        println!("{json}");
+        return Ok(());
+    };
+
+    std::fs::write(path, format!("{json}\n")).map_err(|e| zb_core::Error::FileError {
+        message: format!("failed to write manifest {}: {}", path.display(), e),
+    })?;
+    println!(
+        "{} Exported {} formulas to {}",
+        style("==>").cyan().bold(),
+        style(manifest.formulas.len()).green().bold(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Install the formulas a manifest from `zb export` lists, at their recorded
+/// versions where those are still available
+pub async fn execute_import(
+    installer: &mut zb_io::install::Installer,
+    manifest_path: &Path,
+    options: install::InstallOptions,
+) -> Result<(), zb_core::Error> {
+    let start = Instant::now();
+    let manifest = load_manifest(manifest_path)?;
+    println!(
+        "{} Importing {} formulas from {}...",
+        style("==>").cyan().bold(),
+        style(manifest.formulas.len()).green().bold(),
+        manifest_path.display()
+    );
+
+    let import = installer.plan_import(&manifest).await?;
+    for missing in &import.unavailable {
+        eprintln!(
+            "{} {} {} is no longer available, importing {} instead",
+            style("warning:").yellow().bold(),
+            style(&missing.name).bold(),
+            missing.recorded,
+            missing.current
+        );
+    }
+    let plan = import.plan;
+    if plan.formulas.is_empty() {
+        println!("Everything in the manifest is already installed.");
+    } else {
+        for f in &plan.formulas {
+            println!(
+                "    {} {}",
+                style(&f.name).green(),
+                style(f.effective_version()).dim()
+            );
+        }
+        let failed = install::execute_plan(installer, plan, options, start).await?;
+        install::report_failed(&failed)?;
+    }
+
+    for name in installer.pin_imported(&manifest)? {
+        println!(
+            "{} Pinned {}",
+            style("==>").cyan().bold(),
+            style(name).bold()
+        );
+    }
+    Ok(())
+}
+
+fn load_manifest(path: &Path) -> Result<ExportManifest, zb_core::Error> {
+    let contents = std::fs::read_to_string(path).map_err(|e| zb_core::Error::FileError {
+        message: format!("failed to read manifest {}: {}", path.display(), e),
+    })?;
+    serde_json::from_str(&contents).map_err(|e| zb_core::Error::FileError {
+        message: format!("invalid manifest {}: {}", path.display(), e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn load_manifest_reads_entries_with_optional_fields() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"{{"formulas": [{{"name": "jq", "version": "1.7.1", "pinned": true}}, {{"name": "wget"}}]}}"#
+        )
+        .unwrap();
+
+        let manifest = load_manifest(file.path()).unwrap();
+        assert_eq!(manifest.formulas.len(), 2);
+        assert_eq!(manifest.formulas[0].version.as_deref(), Some("1.7.1"));
+        assert!(manifest.formulas[0].pinned);
+        assert_eq!(manifest.formulas[1].version, None);
+        assert!(!manifest.formulas[1].pinned);
+    }
+
+    #[test]
+    fn load_manifest_rejects_other_formats() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "jq\nwget").unwrap();
+
+        let err = load_manifest(file.path()).unwrap_err();
+        match err {
+            zb_core::Error::FileError { message } => {
+                assert!(message.contains("invalid manifest"))
+            }
+            other => panic!("expected file error, got {other:?}"),
+        }
+    }
+}
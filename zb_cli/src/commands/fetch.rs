@@ -0,0 +1,20 @@
+/// Download `url` and print its sha256 with the cached file, or as JSON
+pub async fn execute(
+    installer: &mut zb_io::install::Installer,
+    url: String,
+    json: bool,
+) -> Result<(), zb_core::Error> {
+    let (path, sha256) = installer.fetch_url(&url).await?;
+
+    if json {
+        let value = serde_json::json!({
+            "url": url,
+            "sha256": sha256,
+            "path": path,
+        });
+        println!("{value}");
+    } else {
+        println!("{sha256}  {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use crate::utils::normalize_tap_formula_name;
+
+/// Print a lockfile recording the exact version and bottle of `formulas`
+/// and everything they depend on, for `zb install --locked`
+pub async fn execute(
+    installer: &mut zb_io::install::Installer,
+    formulas: Vec<String>,
+) -> Result<(), zb_core::Error> {
+    let mut names = Vec::with_capacity(formulas.len());
+    for formula in &formulas {
+        names.push(normalize_tap_formula_name(formula, |tap| {
+            installer.has_tap(tap)
+        })?);
+    }
+
+    let lockfile = installer.freeze(&names).await?;
+    let json =
+        serde_json::to_string_pretty(&lockfile).map_err(|e| zb_core::Error::ExecutionError {
+            message: format!("failed to serialize lockfile: {e}"),
+        })?;
+    println!("{json}");
+    Ok(())
+}
@@ -1,24 +1,39 @@
 use console::style;
+use indicatif::HumanBytes;
 
-pub fn execute(installer: &mut zb_io::install::Installer) -> Result<(), zb_core::Error> {
+pub fn execute(
+    installer: &mut zb_io::install::Installer,
+    store_only: bool,
+) -> Result<(), zb_core::Error> {
     println!(
         "{} Running garbage collection...",
         style("==>").cyan().bold()
     );
-    let removed = installer.gc()?;
+    let report = installer.gc(store_only)?;
 
-    if removed.is_empty() {
-        println!("No unreferenced store entries to remove.");
-    } else {
-        for key in &removed {
-            println!("    {} Removed {}", style("✓").green(), &key[..12]);
-        }
+    if report.store_entries.is_empty() && report.kegs.is_empty() && report.blobs.is_empty() {
+        println!("Nothing unreferenced to remove.");
+        return Ok(());
+    }
+
+    for key in &report.store_entries {
         println!(
-            "{} Removed {} store entries",
-            style("==>").cyan().bold(),
-            style(removed.len()).green().bold()
+            "    {} Removed {}",
+            style("✓").green(),
+            &key[..12.min(key.len())]
         );
     }
+    for path in report.kegs.iter().chain(&report.blobs) {
+        println!("    {} Removed {}", style("✓").green(), path.display());
+    }
+    println!(
+        "{} Removed {} store entries, {} kegs and {} cached bottles, {} reclaimed",
+        style("==>").cyan().bold(),
+        style(report.store_entries.len()).green().bold(),
+        style(report.kegs.len()).green().bold(),
+        style(report.blobs.len()).green().bold(),
+        style(HumanBytes(report.bytes_reclaimed)).green().bold()
+    );
 
     Ok(())
 }
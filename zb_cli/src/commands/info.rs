@@ -1,53 +1,220 @@
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, TimeZone};
 use console::style;
 
-pub fn execute(
+use zb_core::{Formula, select_bottle};
+
+use crate::utils::format_iso8601;
+
+pub async fn execute(
     installer: &mut zb_io::install::Installer,
     formula: String,
+    installed_only: bool,
+    json: bool,
 ) -> Result<(), zb_core::Error> {
-    if let Some(keg) = installer.get_installed(&formula) {
+    let installed = installer.get_installed(&formula);
+
+    if json {
+        let value = match installed {
+            Some(keg) => {
+                let cached = installer.cached_formula(&keg.qualified_name());
+                serde_json::json!({
+                    "name": keg.name,
+                    "tap": keg.tap,
+                    "version": keg.version,
+                    "store_key": keg.store_key,
+                    "installed_at": format_iso8601(keg.installed_at),
+                    "keg_only": cached.as_ref().is_some_and(|f| f.keg_only),
+                })
+            }
+            None if installed_only => serde_json::Value::Null,
+            None => {
+                let available = installer.get_formula(&formula).await?;
+                serde_json::json!({
+                    "name": available.name,
+                    "version": available.effective_version(),
+                    "desc": available.desc,
+                    "homepage": available.homepage,
+                    "license": available.license,
+                    "requires_license_acceptance": available.requires_license_acceptance,
+                    "dependencies": available.dependencies,
+                    "bottle_available": select_bottle(&available).is_ok(),
+                    "installed": false,
+                })
+            }
+        };
+        println!("{value}");
+        return Ok(());
+    }
+
+    if let Some(keg) = installed {
         print_field("Name:", style(&keg.name).bold());
         print_field("Version:", &keg.version);
+        if let Some(tap) = &keg.tap {
+            print_field("Tap:", tap);
+        }
         print_field("Store key:", &keg.store_key[..12]);
         print_field("Installed:", format_timestamp(keg.installed_at));
-    } else {
+        if let Some(cached) = installer.cached_formula(&keg.qualified_name())
+            && cached.keg_only
+        {
+            let reason = cached
+                .keg_only_reason
+                .map(|r| format!(" ({r})"))
+                .unwrap_or_default();
+            print_field("Linking:", format!("keg-only{reason}"));
+        }
+    } else if installed_only {
         println!("Formula '{}' is not installed.", formula);
+    } else {
+        let available = installer.get_formula(&formula).await?;
+        for (label, value) in available_fields(&available) {
+            print_field(label, value);
+        }
     }
 
     Ok(())
 }
 
+/// What the formula API says about a formula that isn't installed
+fn available_fields(formula: &Formula) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("Name:", style(&formula.name).bold().to_string()),
+        ("Version:", formula.effective_version()),
+    ];
+    if let Some(desc) = &formula.desc {
+        fields.push(("Description:", desc.clone()));
+    }
+    if let Some(homepage) = &formula.homepage {
+        fields.push(("Homepage:", homepage.clone()));
+    }
+    if let Some(license) = &formula.license {
+        let acceptance = if formula.requires_license_acceptance {
+            " (must be accepted with --accept-license)"
+        } else {
+            ""
+        };
+        fields.push(("License:", format!("{license}{acceptance}")));
+    }
+    let dependencies = if formula.dependencies.is_empty() {
+        "none".to_string()
+    } else {
+        formula.dependencies.join(", ")
+    };
+    fields.push(("Depends on:", dependencies));
+    let bottle = match select_bottle(formula) {
+        Ok(bottle) => format!("available ({})", bottle.tag),
+        Err(_) => "none for this platform".to_string(),
+    };
+    fields.push(("Bottle:", bottle));
+    fields.push(("Installed:", "no".to_string()));
+    fields
+}
+
 fn print_field(label: &str, value: impl std::fmt::Display) {
-    println!("{:<10}  {}", style(label).dim(), value);
+    println!("{:<12}  {}", style(label).dim(), value);
 }
 
 fn format_timestamp(timestamp: i64) -> String {
-    match DateTime::from_timestamp(timestamp, 0) {
-        Some(dt) => {
-            let local_dt = dt.with_timezone(&Local);
-            let now = Local::now();
-            let duration = now.signed_duration_since(local_dt);
-
-            if duration.num_days() > 0 {
-                format!(
-                    "{} ({} days ago)",
-                    local_dt.format("%Y-%m-%d"),
-                    duration.num_days()
-                )
-            } else if duration.num_hours() > 0 {
-                format!(
-                    "{} ({} hours ago)",
-                    local_dt.format("%Y-%m-%d %H:%M"),
-                    duration.num_hours()
-                )
-            } else {
-                format!(
-                    "{} ({} minutes ago)",
-                    local_dt.format("%H:%M"),
-                    duration.num_minutes()
-                )
-            }
-        }
-        None => "invalid timestamp".to_string(),
+    format_timestamp_at(timestamp, Local::now())
+}
+
+/// Full local date and time plus how long ago that was relative to `now`
+fn format_timestamp_at<Tz: TimeZone>(timestamp: i64, now: DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let Some(dt) = DateTime::from_timestamp(timestamp, 0) else {
+        return "invalid timestamp".to_string();
+    };
+    let local_dt = dt.with_timezone(&now.timezone());
+    let duration = now.signed_duration_since(&local_dt);
+
+    let ago = if duration.num_days() > 0 {
+        format!("{} days ago", duration.num_days())
+    } else if duration.num_hours() > 0 {
+        format!("{} hours ago", duration.num_hours())
+    } else {
+        format!("{} minutes ago", duration.num_minutes())
+    };
+    format!("{} ({ago})", local_dt.format("%Y-%m-%d %H:%M:%S"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use zb_io::install::Installer;
+    use zb_io::{ApiClient, BlobCache, Cellar, Database, Linker, Store};
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn formats_known_epoch_values() {
+        assert_eq!(
+            format_timestamp_at(0, at(90)),
+            "1970-01-01 00:00:00 (1 minutes ago)"
+        );
+        // 2024-02-29 is a leap day
+        assert_eq!(
+            format_timestamp_at(1_709_210_096, at(1_709_210_096 + 3 * 86_400)),
+            "2024-02-29 12:34:56 (3 days ago)"
+        );
+        assert_eq!(
+            format_timestamp_at(951_782_400, at(951_782_400 + 2 * 3_600)),
+            "2000-02-29 00:00:00 (2 hours ago)"
+        );
+    }
+
+    #[tokio::test]
+    async fn uninstalled_formula_is_described_from_the_api() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let formula_json = serde_json::json!({
+            "name": "notyet",
+            "desc": "Lightweight and flexible command-line JSON processor",
+            "homepage": "https://example.com/notyet",
+            "versions": { "stable": "1.7.1" },
+            "dependencies": ["oniguruma"],
+            "bottle": { "stable": { "files": {
+                "some_other_platform": {
+                    "url": format!("{}/bottles/notyet.tar.gz", mock_server.uri()),
+                    "sha256": "a".repeat(64),
+                }
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/notyet.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        std::fs::create_dir_all(root.join("db")).unwrap();
+        let installer = Installer::new(
+            ApiClient::with_base_url(mock_server.uri()),
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(&root).unwrap(),
+            Cellar::new(&root).unwrap(),
+            Linker::new(&tmp.path().join("prefix")).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+        );
+        assert!(installer.get_installed("notyet").is_none());
+
+        let formula = installer.get_formula("notyet").await.unwrap();
+        let fields: BTreeMap<&str, String> = available_fields(&formula).into_iter().collect();
+        assert_eq!(
+            fields["Description:"],
+            "Lightweight and flexible command-line JSON processor"
+        );
+        assert_eq!(fields["Homepage:"], "https://example.com/notyet");
+        assert_eq!(fields["Version:"], "1.7.1");
+        assert_eq!(fields["Depends on:"], "oniguruma");
+        assert_eq!(fields["Bottle:"], "none for this platform");
     }
 }
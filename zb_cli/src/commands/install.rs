@@ -1,44 +1,365 @@
 use console::style;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
+use zb_io::install::{ExecuteResult, Lockfile};
 use zb_io::{InstallProgress, ProgressCallback};
 
-use crate::utils::normalize_formula_name;
+use crate::utils::normalize_tap_formula_name;
+
+/// An exact bottle to install without consulting the formula API
+pub struct PinnedBottle {
+    pub url: String,
+    pub sha256: String,
+    pub name: String,
+    pub version: String,
+}
+
+/// How `zb install` should treat the formulas it is given
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InstallOptions {
+    pub no_link: bool,
+    pub only_dependencies: bool,
+    /// Print the plan and stop before downloading anything
+    pub dry_run: bool,
+    /// Replace the kegs of requested formulas that are already installed
+    pub reinstall: bool,
+    /// Print the dry-run plan as JSON, or stream progress as JSON lines
+    /// followed by the result instead of drawing progress bars
+    pub json: bool,
+    /// Print one line per step instead of drawing progress bars
+    pub plain: bool,
+}
 
 pub async fn execute(
     installer: &mut zb_io::install::Installer,
     formulas: Vec<String>,
-    no_link: bool,
+    options: InstallOptions,
 ) -> Result<(), zb_core::Error> {
     let start = Instant::now();
-    println!(
-        "{} Installing {}...",
-        style("==>").cyan().bold(),
-        style(formulas.join(", ")).bold()
-    );
+    if !options.json {
+        println!(
+            "{} Installing {}{}...",
+            style("==>").cyan().bold(),
+            if options.only_dependencies {
+                "dependencies of "
+            } else {
+                ""
+            },
+            style(formulas.join(", ")).bold()
+        );
+    }
 
     let mut normalized_names = Vec::new();
     for formula in &formulas {
-        normalized_names.push(normalize_formula_name(formula)?);
+        normalized_names.push(normalize_tap_formula_name(formula, |tap| {
+            installer.has_tap(tap)
+        })?);
     }
 
-    let plan = installer.plan(&normalized_names).await?;
+    let (plan, failed) = if options.only_dependencies {
+        (
+            installer.plan_dependencies(&normalized_names).await?,
+            Vec::new(),
+        )
+    } else {
+        let spinner = (!options.json && !options.plain).then(resolve_spinner);
+        let progress = if options.json && !options.dry_run {
+            Some(json_progress_callback())
+        } else {
+            spinner.clone().map(resolve_callback)
+        };
+        let planned = installer
+            .plan_partial_with_progress(&normalized_names, progress)
+            .await;
+        if let Some(spinner) = spinner {
+            spinner.finish_and_clear();
+        }
+        planned?
+    };
 
+    execute_planned(installer, plan, failed, &normalized_names, options, start).await
+}
+
+/// Print the plan, then stop if it's a dry run or install it, replacing the
+/// kegs of `requested` formulas when reinstalling
+async fn execute_planned(
+    installer: &mut zb_io::install::Installer,
+    plan: zb_io::install::InstallPlan,
+    failed: Vec<(String, zb_core::Error)>,
+    requested: &[String],
+    options: InstallOptions,
+    start: Instant,
+) -> Result<(), zb_core::Error> {
+    if options.dry_run && options.json {
+        println!("{}", plan_json(&plan));
+        return report_failed(&failed);
+    }
+
+    if !options.json {
+        println!(
+            "{} Resolving dependencies ({} packages)...",
+            style("==>").cyan().bold(),
+            plan.formulas.len()
+        );
+        for f in &plan.formulas {
+            println!(
+                "    {} {}",
+                style(&f.name).green(),
+                style(&f.versions.stable).dim()
+            );
+        }
+    }
+
+    if options.dry_run {
+        let size = plan
+            .download_size()
+            .map(|bytes| HumanBytes(bytes).to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        println!(
+            "{} Would download {} bottles ({})",
+            style("==>").cyan().bold(),
+            style(plan.bottles.len()).green().bold(),
+            size
+        );
+        return report_failed(&failed);
+    }
+
+    if options.reinstall {
+        let replacing: Vec<String> = plan
+            .formulas
+            .iter()
+            .filter(|f| {
+                requested.iter().any(|n| {
+                    let n = n.rsplit('/').next().unwrap_or(n);
+                    n == f.name || n.split_once('@').is_some_and(|(base, _)| base == f.name)
+                })
+            })
+            .map(|f| f.name.clone())
+            .collect();
+        installer.prepare_reinstall(&replacing);
+    }
+
+    let mut failed = failed;
+    failed.extend(execute_plan(installer, plan, options, start).await?);
+    report_failed(&failed)
+}
+
+/// Spins while formula metadata is fetched, since how many formulas a plan
+/// needs isn't known until the dependency graph has been walked
+fn resolve_spinner() -> ProgressBar {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("    {spinner:.cyan} {msg}")
+            .unwrap(),
+    );
+    pb.set_message("resolving dependencies");
+    pb.enable_steady_tick(std::time::Duration::from_millis(80));
+    pb
+}
+
+fn resolve_callback(pb: ProgressBar) -> Arc<ProgressCallback> {
+    Arc::new(Box::new(move |event| {
+        if let InstallProgress::ResolveProgress {
+            resolved,
+            total_known,
+        } = event
+        {
+            pb.set_message(format!("resolved {resolved}/{total_known}"));
+        }
+    }))
+}
+
+/// List the formulas that couldn't be installed, failing if there were any
+pub(crate) fn report_failed(failed: &[(String, zb_core::Error)]) -> Result<(), zb_core::Error> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+
+    eprintln!(
+        "{} Could not install {} {}:",
+        style("==>").red().bold(),
+        failed.len(),
+        if failed.len() == 1 {
+            "formula"
+        } else {
+            "formulas"
+        }
+    );
+    for (name, err) in failed {
+        eprintln!("    {} {}: {}", style("✗").red(), style(name).bold(), err);
+    }
+
+    let names: Vec<&str> = failed.iter().map(|(name, _)| name.as_str()).collect();
+    Err(zb_core::Error::ExecutionError {
+        message: format!("failed to install {}", names.join(", ")),
+    })
+}
+
+fn plan_json(plan: &zb_io::install::InstallPlan) -> serde_json::Value {
+    let formulas: Vec<_> = plan
+        .formulas
+        .iter()
+        .zip(&plan.bottles)
+        .map(|(formula, bottle)| {
+            serde_json::json!({
+                "name": formula.name,
+                "version": formula.effective_version(),
+                "tag": bottle.tag,
+                "url": bottle.url,
+                "sha256": bottle.sha256,
+                "size": bottle.size,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "formulas": formulas,
+        "download_size": plan.download_size(),
+    })
+}
+
+pub async fn execute_pinned(
+    installer: &mut zb_io::install::Installer,
+    pinned: PinnedBottle,
+    options: InstallOptions,
+) -> Result<(), zb_core::Error> {
+    let start = Instant::now();
     println!(
-        "{} Resolving dependencies ({} packages)...",
+        "{} Installing {} {} from {}...",
         style("==>").cyan().bold(),
-        plan.formulas.len()
+        style(&pinned.name).bold(),
+        style(&pinned.version).dim(),
+        pinned.url
     );
-    for f in &plan.formulas {
+
+    let plan = installer.plan_bottle(&pinned.name, &pinned.version, &pinned.url, &pinned.sha256)?;
+
+    let failed = execute_plan(installer, plan, options, start).await?;
+    report_failed(&failed)
+}
+
+/// Install exactly the formulas, versions and bottles the lockfile at
+/// `path` records, as written by `zb freeze`
+pub async fn execute_locked(
+    installer: &mut zb_io::install::Installer,
+    path: &std::path::Path,
+    options: InstallOptions,
+) -> Result<(), zb_core::Error> {
+    let start = Instant::now();
+    let contents = std::fs::read_to_string(path).map_err(|e| zb_core::Error::FileError {
+        message: format!("failed to read lockfile {}: {}", path.display(), e),
+    })?;
+    let lockfile: Lockfile =
+        serde_json::from_str(&contents).map_err(|e| zb_core::Error::FileError {
+            message: format!("invalid lockfile {}: {}", path.display(), e),
+        })?;
+    if !options.json {
         println!(
-            "    {} {}",
-            style(&f.name).green(),
-            style(&f.versions.stable).dim()
+            "{} Installing {} locked formulas from {}...",
+            style("==>").cyan().bold(),
+            style(lockfile.formulas.len()).green().bold(),
+            path.display()
         );
     }
 
+    let plan = installer.plan_locked(&lockfile).await?;
+    let requested: Vec<String> = lockfile
+        .formulas
+        .iter()
+        .map(|locked| locked.name.clone())
+        .collect();
+    execute_planned(installer, plan, Vec::new(), &requested, options, start).await
+}
+
+fn plan_sized_message(total_bytes: Option<u64>, packages: usize) -> String {
+    let packages = match packages {
+        1 => "1 package".to_string(),
+        n => format!("{n} packages"),
+    };
+    let message = match total_bytes {
+        Some(total) => format!("Downloading {} across {packages}", HumanBytes(total)),
+        None => format!("Downloading {packages}"),
+    };
+    format!("{} {message}", style("==>").cyan().bold())
+}
+
+/// Reports each finished step on its own line, for logs and CI
+fn plain_progress_callback() -> Arc<ProgressCallback> {
+    Arc::new(Box::new(|event| match event {
+        InstallProgress::PlanSized {
+            total_bytes,
+            packages,
+        } => println!("{}", plan_sized_message(total_bytes, packages)),
+        InstallProgress::DownloadCompleted { name, .. } => println!("    {name} downloaded"),
+        InstallProgress::Relocated { name, files } => {
+            print!("{}", relocated_message(&name, &files))
+        }
+        InstallProgress::InstallCompleted { name } => println!("    {name} installed"),
+        _ => {}
+    }))
+}
+
+/// Writes each event as a line of JSON on stdout, for tools driving zb
+fn json_progress_callback() -> Arc<ProgressCallback> {
+    Arc::new(Box::new(|event| {
+        if let Ok(line) = serde_json::to_string(&event) {
+            println!("{line}");
+        }
+    }))
+}
+
+/// The files `--prefix-relative` rewrote in one package, one per line
+fn relocated_message(name: &str, files: &[std::path::PathBuf]) -> String {
+    let mut message = format!("    {name}: relocated {} file(s)\n", files.len());
+    for file in files {
+        message.push_str(&format!("        {}\n", file.display()));
+    }
+    message
+}
+
+/// Install `plan` with progress output, returning the packages that failed
+/// when the installer keeps going past failures
+pub(crate) async fn execute_plan(
+    installer: &mut zb_io::install::Installer,
+    plan: zb_io::install::InstallPlan,
+    options: InstallOptions,
+    start: Instant,
+) -> Result<Vec<(String, zb_core::Error)>, zb_core::Error> {
+    let caveats: Vec<(String, String)> = plan
+        .caveats()
+        .into_iter()
+        .map(|(name, caveats)| (name.to_string(), caveats.to_string()))
+        .collect();
+
+    if options.json {
+        let result = installer
+            .execute_until(
+                plan,
+                !options.no_link,
+                Some(json_progress_callback()),
+                interrupted(),
+            )
+            .await?;
+        print_result(&result, options, start);
+        return Ok(result.failed);
+    }
+
+    if options.plain {
+        let result = installer
+            .execute_until(
+                plan,
+                !options.no_link,
+                Some(plain_progress_callback()),
+                interrupted(),
+            )
+            .await?;
+        print_result(&result, options, start);
+        print_caveats(&caveats, &result.failed);
+        return Ok(result.failed);
+    }
+
     let multi = MultiProgress::new();
     let bars: Arc<Mutex<HashMap<String, ProgressBar>>> = Arc::new(Mutex::new(HashMap::new()));
 
@@ -47,6 +368,11 @@ pub async fn execute(
         .unwrap()
         .progress_chars("━━╸");
 
+    let unpack_style = ProgressStyle::default_bar()
+        .template("    {prefix:<16} {bar:25.magenta/dim} {bytes:>10}/{total_bytes:<10} {msg}")
+        .unwrap()
+        .progress_chars("━━╸");
+
     let spinner_style = ProgressStyle::default_spinner()
         .template("    {prefix:<16} {spinner:.cyan} {msg}")
         .unwrap()
@@ -56,20 +382,55 @@ pub async fn execute(
         .template("    {prefix:<16} {msg}")
         .unwrap();
 
-    println!(
-        "{} Downloading and installing...",
-        style("==>").cyan().bold()
-    );
+    let overall: Arc<Mutex<Option<ProgressBar>>> = Arc::new(Mutex::new(None));
+    let overall_style = ProgressStyle::default_bar()
+        .template(
+            "    {prefix:<16} {bar:25.green/dim} {bytes:>10}/{total_bytes:<10} {binary_bytes_per_sec:>12}",
+        )
+        .unwrap()
+        .progress_chars("━━╸");
+    let overall_spinner_style = ProgressStyle::default_spinner()
+        .template("    {prefix:<16} {spinner:.green} {bytes:>10} {binary_bytes_per_sec:>12}")
+        .unwrap()
+        .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏");
 
     let bars_clone = bars.clone();
+    let overall_clone = overall.clone();
     let multi_clone = multi.clone();
     let download_style_clone = download_style.clone();
+    let unpack_style_clone = unpack_style.clone();
     let spinner_style_clone = spinner_style.clone();
     let done_style_clone = done_style.clone();
 
     let progress_callback: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
         let mut bars = bars_clone.lock().unwrap();
         match event {
+            InstallProgress::PlanSized {
+                total_bytes,
+                packages,
+            } => {
+                let _ = multi_clone.println(plan_sized_message(total_bytes, packages));
+            }
+            InstallProgress::OverallProgress { downloaded, total } => {
+                let mut overall = overall_clone.lock().unwrap();
+                let pb = overall.get_or_insert_with(|| {
+                    let pb = multi_clone.insert(0, ProgressBar::no_length());
+                    pb.set_prefix("total");
+                    pb.set_style(overall_spinner_style.clone());
+                    pb.enable_steady_tick(std::time::Duration::from_millis(80));
+                    pb
+                });
+                if let Some(total) = total
+                    && pb.length() != Some(total)
+                {
+                    pb.set_length(total);
+                    pb.set_style(overall_style.clone());
+                }
+                pb.set_position(downloaded);
+                if total.is_some_and(|total| downloaded >= total) && !pb.is_finished() {
+                    pb.finish();
+                }
+            }
             InstallProgress::DownloadStarted { name, total_bytes } => {
                 let pb = if let Some(total) = total_bytes {
                     let pb = multi_clone.add(ProgressBar::new(total));
@@ -111,11 +472,34 @@ pub async fn execute(
                     pb.set_message("unpacking...");
                 }
             }
+            InstallProgress::UnpackProgress {
+                name,
+                extracted,
+                total,
+            } => {
+                if let Some(pb) = bars.get(&name) {
+                    match total {
+                        Some(total) => {
+                            if pb.length() != Some(total) {
+                                pb.set_length(total);
+                                pb.set_style(unpack_style_clone.clone());
+                                pb.set_message("unpacking");
+                            }
+                            pb.set_position(extracted);
+                        }
+                        None => pb.set_message(format!("unpacking... {}", HumanBytes(extracted))),
+                    }
+                }
+            }
             InstallProgress::UnpackCompleted { name } => {
                 if let Some(pb) = bars.get(&name) {
+                    pb.set_style(spinner_style_clone.clone());
                     pb.set_message("unpacked");
                 }
             }
+            InstallProgress::Relocated { name, files } => {
+                let _ = multi_clone.println(relocated_message(&name, &files).trim_end());
+            }
             InstallProgress::LinkStarted { name } => {
                 if let Some(pb) = bars.get(&name) {
                     pb.set_message("linking...");
@@ -133,13 +517,25 @@ pub async fn execute(
                     pb.finish();
                 }
             }
+            InstallProgress::ResolveProgress { .. } => {}
         }
     }));
 
     let result_val = installer
-        .execute_with_progress(plan, !no_link, Some(progress_callback))
+        .execute_until(
+            plan,
+            !options.no_link,
+            Some(progress_callback),
+            interrupted(),
+        )
         .await;
 
+    if let Some(pb) = overall.lock().unwrap().as_ref()
+        && !pb.is_finished()
+    {
+        pb.finish();
+    }
+
     {
         let bars = bars.lock().unwrap();
         for (_, pb) in bars.iter() {
@@ -150,15 +546,164 @@ pub async fn execute(
     }
 
     let result = result_val?;
-
-    let elapsed = start.elapsed();
     println!();
+    print_result(&result, options, start);
+    print_caveats(&caveats, &result.failed);
+
+    Ok(result.failed)
+}
+
+/// Completes when the user hits Ctrl-C. Never completes if the handler
+/// can't be installed, so the install just runs to the end.
+async fn interrupted() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Show caveats for the formulas that actually got installed
+fn print_caveats(caveats: &[(String, String)], failed: &[(String, zb_core::Error)]) {
+    for (name, text) in caveats {
+        if failed.iter().any(|(failed, _)| failed == name) {
+            continue;
+        }
+        println!();
+        println!(
+            "{} Caveats for {}",
+            style("==>").cyan().bold(),
+            style(name).bold()
+        );
+        println!("{text}");
+    }
+}
+
+fn print_result(result: &ExecuteResult, options: InstallOptions, start: Instant) {
+    if options.json {
+        println!("{}", result_json(result));
+        return;
+    }
+
     println!(
         "{} Installed {} packages in {:.2}s",
         style("==>").cyan().bold(),
         style(result.installed).green().bold(),
-        elapsed.as_secs_f64()
+        start.elapsed().as_secs_f64()
     );
+    if !result.already_installed.is_empty() {
+        let names: Vec<&str> = result
+            .already_installed
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        println!(
+            "{} Already installed: {}",
+            style("==>").cyan().bold(),
+            style(names.join(", ")).dim()
+        );
+    }
+}
 
-    Ok(())
+fn result_json(result: &ExecuteResult) -> serde_json::Value {
+    let failed: Vec<_> = result
+        .failed
+        .iter()
+        .map(|(name, e)| serde_json::json!({ "name": name, "error": e.to_string() }))
+        .collect();
+    serde_json::json!({
+        "event": "result",
+        "installed": result.packages,
+        "already_installed": result.already_installed,
+        "failed": failed,
+        "timings": result.timings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use zb_io::install::Installer;
+    use zb_io::{ApiClient, BlobCache, Cellar, Database, Linker, Store};
+
+    #[tokio::test]
+    async fn dry_run_plans_without_downloading() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = zb_core::Platform::current().bottle_tags().remove(0);
+
+        for (name, deps) in [("dryapp", vec!["drylib"]), ("drylib", vec![])] {
+            let formula_json = serde_json::json!({
+                "name": name,
+                "versions": { "stable": "1.0.0" },
+                "dependencies": deps,
+                "bottle": { "stable": { "files": {
+                    tag.as_str(): {
+                        "url": format!("{}/bottles/{name}.tar.gz", mock_server.uri()),
+                        "sha256": "a".repeat(64),
+                        "size": 1024,
+                    }
+                } } }
+            });
+            Mock::given(method("GET"))
+                .and(path(format!("/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200))
+                .expect(0)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        std::fs::create_dir_all(root.join("db")).unwrap();
+        let mut installer = Installer::new(
+            ApiClient::with_base_url(mock_server.uri()),
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(&root).unwrap(),
+            Cellar::new(&root).unwrap(),
+            Linker::new(&prefix).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+        );
+
+        let plan = installer.plan(&["dryapp".to_string()]).await.unwrap();
+        assert_eq!(plan.download_size(), Some(2048));
+        let json = plan_json(&plan);
+        assert_eq!(json["formulas"][0]["name"], "drylib");
+        assert_eq!(json["formulas"][1]["name"], "dryapp");
+
+        let options = InstallOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        execute(&mut installer, vec!["dryapp".to_string()], options)
+            .await
+            .unwrap();
+        assert!(!installer.is_installed("dryapp"));
+        assert!(!installer.is_installed("drylib"));
+    }
+
+    #[test]
+    fn result_line_is_tagged_like_the_progress_events() {
+        let result = ExecuteResult {
+            installed: 0,
+            packages: Vec::new(),
+            already_installed: Vec::new(),
+            failed: vec![(
+                "jq".to_string(),
+                zb_core::Error::NotInstalled {
+                    name: "jq".to_string(),
+                },
+            )],
+            timings: Default::default(),
+        };
+        let json = result_json(&result);
+        assert_eq!(json["event"], "result");
+        assert_eq!(json["failed"][0]["name"], "jq");
+    }
 }
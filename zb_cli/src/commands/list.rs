@@ -1,13 +1,47 @@
 use console::style;
 
-pub fn execute(installer: &mut zb_io::install::Installer) -> Result<(), zb_core::Error> {
+use crate::utils::format_iso8601;
+
+pub fn execute(
+    installer: &mut zb_io::install::Installer,
+    json: bool,
+    long: bool,
+) -> Result<(), zb_core::Error> {
     let installed = installer.list_installed()?;
 
+    if json {
+        let kegs: Vec<_> = installed
+            .iter()
+            .map(|keg| {
+                serde_json::json!({
+                    "name": keg.name,
+                    "version": keg.version,
+                    "store_key": keg.store_key,
+                    "installed_at": format_iso8601(keg.installed_at),
+                    "pinned": keg.pinned,
+                    "description": keg.description,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(kegs));
+        return Ok(());
+    }
+
     if installed.is_empty() {
         println!("No formulas installed.");
     } else {
         for keg in installed {
-            println!("{} {}", style(&keg.name).bold(), style(&keg.version).dim());
+            let description = match &keg.description {
+                Some(description) if long => format!("  {description}"),
+                _ => String::new(),
+            };
+            println!(
+                "{} {}{}{}",
+                style(&keg.name).bold(),
+                style(&keg.version).dim(),
+                if keg.pinned { " (pinned)" } else { "" },
+                description
+            );
         }
     }
 
@@ -6,6 +6,7 @@ pub async fn execute(
     installer: &mut zb_io::install::Installer,
     yes: bool,
     force: bool,
+    interactive: bool,
 ) -> Result<(), zb_core::Error> {
     println!(
         "{} Fetching installed Homebrew packages...",
@@ -73,6 +74,12 @@ pub async fn execute(
     }
     println!();
 
+    if !yes && !interactive {
+        return Err(zb_core::Error::InvalidArgument {
+            message: "refusing to migrate without --yes in non-interactive mode".to_string(),
+        });
+    }
+
     if !yes {
         print!("Continue with migration? [y/N] ");
         io::stdout().flush().unwrap();
@@ -100,8 +107,20 @@ pub async fn execute(
 
         match installer.plan(std::slice::from_ref(&pkg.name)).await {
             Ok(plan) => match installer.execute(plan, true).await {
-                Ok(_) => {
-                    println!(" {}", style("✓").green());
+                Ok(result) if !result.failed.is_empty() => {
+                    println!(" {}", style("✗").red());
+                    for (name, e) in &result.failed {
+                        eprintln!(
+                            "      {} Failed to install {}: {}",
+                            style("error:").red().bold(),
+                            name,
+                            e
+                        );
+                    }
+                    failed.push(pkg.name.clone());
+                }
+                Ok(result) => {
+                    println!(" {} {}", style("✓").green(), style(landed(&result)).dim());
                     success_count += 1;
                 }
                 Err(e) => {
@@ -234,3 +253,16 @@ pub async fn execute(
 
     Ok(())
 }
+
+/// The versions an install put in place, e.g. `jq 1.7.1, oniguruma 6.9.9`
+fn landed(result: &zb_io::install::ExecuteResult) -> String {
+    if result.packages.is_empty() {
+        return "already installed".to_string();
+    }
+    result
+        .packages
+        .iter()
+        .map(|p| format!("{} {}", p.name, p.version))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
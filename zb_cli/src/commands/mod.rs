@@ -1,11 +1,28 @@
 pub mod bundle;
+pub mod cleanup;
 pub mod completion;
+pub mod config;
+pub mod deps;
+pub mod doctor;
+pub mod env;
+pub mod export;
+pub mod fetch;
+pub mod freeze;
 pub mod gc;
 pub mod info;
 pub mod init;
 pub mod install;
 pub mod list;
 pub mod migrate;
+pub mod outdated;
+pub mod pin;
+pub mod prefix;
 pub mod reset;
+pub mod rollback;
 pub mod run;
+pub mod search;
 pub mod uninstall;
+pub mod upgrade;
+pub mod uses;
+pub mod verify;
+pub mod which;
@@ -0,0 +1,48 @@
+use console::{Term, style};
+
+pub async fn execute(
+    installer: &mut zb_io::install::Installer,
+    json: bool,
+) -> Result<(), zb_core::Error> {
+    let outdated = installer.outdated().await?;
+
+    if json {
+        let kegs: Vec<_> = outdated
+            .iter()
+            .map(|keg| {
+                serde_json::json!({
+                    "name": keg.name,
+                    "installed": keg.installed,
+                    "available": keg.available,
+                    "pinned": keg.pinned,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(kegs));
+        if outdated.is_empty() {
+            return Ok(());
+        }
+        std::process::exit(1);
+    }
+
+    if outdated.is_empty() {
+        // Keep piped output empty so scripts can rely on it
+        if Term::stdout().is_term() {
+            println!("All formulas are up to date.");
+        }
+        return Ok(());
+    }
+
+    for keg in &outdated {
+        println!(
+            "{} {} -> {}{}",
+            style(&keg.name).bold(),
+            style(&keg.installed).dim(),
+            style(&keg.available).green(),
+            if keg.pinned { " (pinned)" } else { "" }
+        );
+    }
+
+    // Non-zero exit lets scripts and CI detect pending upgrades
+    std::process::exit(1);
+}
@@ -0,0 +1,47 @@
+use console::style;
+
+use crate::utils::installed_keg;
+
+/// Hold a formula at its installed version so `zb upgrade` skips it
+pub fn execute_pin(
+    installer: &mut zb_io::install::Installer,
+    formula: String,
+) -> Result<(), zb_core::Error> {
+    let name = installed_keg(installer, &formula)?.name;
+    let newly_pinned = installer.pin(&name)?;
+    let version = installer
+        .get_installed(&name)
+        .map(|keg| keg.version)
+        .unwrap_or_default();
+
+    if newly_pinned {
+        println!(
+            "{} Pinned {} at {}",
+            style("==>").cyan().bold(),
+            style(&name).bold(),
+            style(version).green()
+        );
+    } else {
+        println!("{} is already pinned at {version}.", style(&name).bold());
+    }
+    Ok(())
+}
+
+/// Let a pinned formula be upgraded again
+pub fn execute_unpin(
+    installer: &mut zb_io::install::Installer,
+    formula: String,
+) -> Result<(), zb_core::Error> {
+    let name = installed_keg(installer, &formula)?.name;
+
+    if installer.unpin(&name)? {
+        println!(
+            "{} Unpinned {}",
+            style("==>").cyan().bold(),
+            style(&name).bold()
+        );
+    } else {
+        println!("{} isn't pinned.", style(&name).bold());
+    }
+    Ok(())
+}
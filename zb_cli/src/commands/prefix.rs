@@ -0,0 +1,23 @@
+use std::path::Path;
+
+use crate::utils::installed_keg;
+
+pub fn execute(
+    installer: &mut zb_io::install::Installer,
+    prefix: &Path,
+    formula: Option<String>,
+) -> Result<(), zb_core::Error> {
+    let Some(formula) = formula else {
+        println!("{}", prefix.display());
+        return Ok(());
+    };
+
+    let installed = installed_keg(installer, &formula)?;
+    println!(
+        "{}",
+        installer
+            .keg_path(&installed.name, &installed.version)
+            .display()
+    );
+    Ok(())
+}
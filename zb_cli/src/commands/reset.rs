@@ -5,12 +5,23 @@ use std::process::Command;
 
 use crate::init::{InitError, run_init};
 
-pub fn execute(root: &Path, prefix: &Path, yes: bool) -> Result<(), zb_core::Error> {
+pub fn execute(
+    root: &Path,
+    prefix: &Path,
+    yes: bool,
+    interactive: bool,
+) -> Result<(), zb_core::Error> {
     if !root.exists() && !prefix.exists() {
         println!("Nothing to reset - directories do not exist.");
         return Ok(());
     }
 
+    if !yes && !interactive {
+        return Err(zb_core::Error::InvalidArgument {
+            message: "refusing to reset without --yes in non-interactive mode".to_string(),
+        });
+    }
+
     if !yes {
         println!(
             "{} This will delete all zerobrew data at:",
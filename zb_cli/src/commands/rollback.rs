@@ -0,0 +1,27 @@
+use console::style;
+
+use crate::utils::installed_keg;
+
+pub fn execute(
+    installer: &mut zb_io::install::Installer,
+    formula: String,
+) -> Result<(), zb_core::Error> {
+    let name = installed_keg(installer, &formula)?.name;
+
+    println!(
+        "{} Rolling back {}...",
+        style("==>").cyan().bold(),
+        style(&name).bold()
+    );
+    let (from, to) = installer.rollback(&name)?;
+
+    println!(
+        "    {} {} {} -> {}",
+        style("✓").green(),
+        style(&name).bold(),
+        style(from).dim(),
+        style(to).green()
+    );
+
+    Ok(())
+}
@@ -1,21 +1,36 @@
 use console::style;
-use std::os::unix::process::CommandExt;
-use std::path::PathBuf;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use zb_io::install::Installer;
 
-use crate::utils::normalize_formula_name;
+use crate::utils::{keg_executables, normalize_tap_formula_name};
 
 /// Prepare a package for execution by ensuring it's installed
-/// Returns the path to the executable
+/// Returns the path to the executable: `binary` if given, otherwise the one
+/// named after the formula, or the formula's only executable
 pub async fn prepare_execution(
     installer: &mut Installer,
     formula: &str,
+    binary: Option<&str>,
 ) -> Result<PathBuf, zb_core::Error> {
-    let normalized = normalize_formula_name(formula)?;
+    prepare(installer, formula, binary)
+        .await
+        .map(|(bin_path, _)| bin_path)
+}
 
-    let was_installed = installer.is_installed(&normalized);
+/// Like [`prepare_execution`], also returning the formulas installed to
+/// make the run possible, in install order
+async fn prepare(
+    installer: &mut Installer,
+    formula: &str,
+    binary: Option<&str>,
+) -> Result<(PathBuf, Vec<String>), zb_core::Error> {
+    let normalized = normalize_tap_formula_name(formula, |tap| installer.has_tap(tap))?;
 
+    let was_installed = installer.get_installed(&normalized).is_some();
+
+    let mut installed_now = Vec::new();
     if !was_installed {
         println!(
             "{} Installing {} temporarily...",
@@ -24,7 +39,8 @@ pub async fn prepare_execution(
         );
 
         let plan = installer.plan(std::slice::from_ref(&normalized)).await?;
-        installer.execute(plan, false).await?;
+        let result = installer.execute(plan, false).await?;
+        installed_now = result.packages.into_iter().map(|p| p.name).collect();
     }
 
     let installed =
@@ -34,38 +50,134 @@ pub async fn prepare_execution(
                 name: normalized.clone(),
             })?;
 
-    let keg_path = installer.keg_path(&normalized, &installed.version);
-    let bin_path = keg_path.join("bin").join(&normalized);
+    let keg = installer.keg_path(&installed.name, &installed.version);
+    let bin_path = select_executable(&keg, &installed.name, binary)?;
+    Ok((bin_path, installed_now))
+}
 
-    if !bin_path.exists() {
-        return Err(zb_core::Error::ExecutionError {
-            message: format!(
-                "executable '{}' not found in package '{}'",
-                normalized, normalized
-            ),
-        });
+/// Run the executable as a child process and wait for it, then uninstall
+/// whatever had to be installed for the run. Their store entries are left
+/// for `zb gc`.
+pub async fn run_and_remove(
+    installer: &mut Installer,
+    formula: &str,
+    binary: Option<&str>,
+    args: &[String],
+) -> Result<std::process::ExitStatus, zb_core::Error> {
+    let (bin_path, installed_now) = prepare(installer, formula, binary).await?;
+
+    // Ctrl-C is for the child; this process stays to clean up after it
+    let _sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt()).ok();
+    let status = tokio::process::Command::new(&bin_path)
+        .args(args)
+        .status()
+        .await;
+
+    // Dependents go before what they depend on; failures show up below
+    if !installed_now.is_empty() {
+        let _ = installer.cache_installed_formulas().await;
+    }
+    for name in installed_now.iter().rev() {
+        if let Err(e) = installer.uninstall(name, false) {
+            eprintln!(
+                "{} Could not remove {}: {e}",
+                style("warning:").yellow().bold(),
+                style(name).bold()
+            );
+        }
     }
 
-    Ok(bin_path)
+    status.map_err(|e| zb_core::Error::ExecutionError {
+        message: format!("failed to execute '{formula}': {e}"),
+    })
+}
+
+/// Pick the executable to run from `keg`, listing what's there when the
+/// choice can't be made
+fn select_executable(
+    keg: &Path,
+    formula: &str,
+    binary: Option<&str>,
+) -> Result<PathBuf, zb_core::Error> {
+    let available: Vec<String> = keg_executables(keg)
+        .iter()
+        .filter_map(|path| path.file_name()?.to_str().map(str::to_string))
+        .collect();
+
+    let chosen = match binary {
+        Some(binary) => available.iter().find(|name| *name == binary),
+        None => available
+            .iter()
+            .find(|name| *name == formula)
+            .or(match available.as_slice() {
+                [only] => Some(only),
+                _ => None,
+            }),
+    };
+    if let Some(name) = chosen {
+        return Ok(keg.join("bin").join(name));
+    }
+
+    let wanted = binary.unwrap_or(formula);
+    let message = if available.is_empty() {
+        format!("executable '{wanted}' not found in package '{formula}', which has none")
+    } else {
+        format!(
+            "executable '{wanted}' not found in package '{formula}' (available: {}); \
+             pick one with `zb run {formula}:<name>`",
+            available.join(", ")
+        )
+    };
+    Err(zb_core::Error::ExecutionError { message })
+}
+
+/// The exit code a shell would report for `status`: 128 plus the signal
+/// number when the child was killed by one
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
 }
 
 pub async fn execute(
     installer: &mut Installer,
     formula: String,
+    binary: Option<String>,
     args: Vec<String>,
+    remove: bool,
 ) -> Result<(), zb_core::Error> {
+    let (formula, binary) = match formula.split_once(':') {
+        Some(_) if binary.is_some() => {
+            return Err(zb_core::Error::InvalidArgument {
+                message: "give the executable as formula:binary or with --bin, not both"
+                    .to_string(),
+            });
+        }
+        Some((formula, binary)) => (formula.to_string(), Some(binary.to_string())),
+        None => (formula, binary),
+    };
+
     println!(
         "{} Running {}...",
         style("==>").cyan().bold(),
         style(&formula).bold()
     );
 
-    let bin_path = prepare_execution(installer, &formula).await?;
+    if remove {
+        let status = run_and_remove(installer, &formula, binary.as_deref(), &args).await?;
+        if !status.success() {
+            std::process::exit(exit_code(status));
+        }
+        return Ok(());
+    }
+
+    let bin_path = prepare_execution(installer, &formula, binary.as_deref()).await?;
 
     println!(
         "{} Executing {}...",
         style("==>").cyan().bold(),
-        style(&formula).green()
+        style(bin_path.file_name().unwrap_or_default().to_string_lossy()).green()
     );
 
     let err = Command::new(&bin_path).args(&args).exec();
@@ -90,6 +202,10 @@ mod tests {
     use zb_io::store::Store;
 
     fn create_bottle_tarball(formula_name: &str) -> Vec<u8> {
+        create_bottle_tarball_with_bins(formula_name, &[formula_name])
+    }
+
+    fn create_bottle_tarball_with_bins(formula_name: &str, bins: &[&str]) -> Vec<u8> {
         use flate2::Compression;
         use flate2::write::GzEncoder;
         use std::io::Write;
@@ -97,18 +213,20 @@ mod tests {
 
         let mut builder = Builder::new(Vec::new());
 
-        let content = format!("#!/bin/sh\necho {}", formula_name);
-        let content_bytes = content.as_bytes();
+        for bin in bins {
+            let content = format!("#!/bin/sh\necho {}", bin);
+            let content_bytes = content.as_bytes();
 
-        let mut header = tar::Header::new_gnu();
-        header
-            .set_path(format!("{}/1.0.0/bin/{}", formula_name, formula_name))
-            .unwrap();
-        header.set_size(content_bytes.len() as u64);
-        header.set_mode(0o755);
-        header.set_cksum();
+            let mut header = tar::Header::new_gnu();
+            header
+                .set_path(format!("{}/1.0.0/bin/{}", formula_name, bin))
+                .unwrap();
+            header.set_size(content_bytes.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
 
-        builder.append(&header, content_bytes).unwrap();
+            builder.append(&header, content_bytes).unwrap();
+        }
 
         let tar_data = builder.into_inner().unwrap();
 
@@ -124,12 +242,8 @@ mod tests {
         format!("{:x}", hasher.finalize())
     }
 
-    fn get_test_bottle_tag() -> &'static str {
-        if cfg!(target_os = "linux") {
-            "x86_64_linux"
-        } else {
-            "arm64_sonoma"
-        }
+    fn get_test_bottle_tag() -> String {
+        zb_core::Platform::current().bottle_tags().remove(0)
     }
 
     #[tokio::test]
@@ -189,7 +303,9 @@ mod tests {
 
         assert!(!installer.is_installed("testrun"));
 
-        let bin_path = prepare_execution(&mut installer, "testrun").await.unwrap();
+        let bin_path = prepare_execution(&mut installer, "testrun", None)
+            .await
+            .unwrap();
 
         assert!(installer.is_installed("testrun"));
         assert!(!prefix.join("bin/testrun").exists());
@@ -263,7 +379,7 @@ mod tests {
             .unwrap();
         assert!(installer.is_installed("alreadyinstalled"));
 
-        let bin_path = prepare_execution(&mut installer, "alreadyinstalled")
+        let bin_path = prepare_execution(&mut installer, "alreadyinstalled", None)
             .await
             .unwrap();
 
@@ -302,7 +418,220 @@ mod tests {
 
         let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
 
-        let result = prepare_execution(&mut installer, "nonexistent").await;
+        let result = prepare_execution(&mut installer, "nonexistent", None).await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn run_accepts_formulas_from_a_configured_tap() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let bottle = create_bottle_tarball("taptool");
+        let tag = get_test_bottle_tag();
+        let formula_json = serde_json::json!({
+            "name": "taptool",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/taptool.tar.gz", mock_server.uri()),
+                    "sha256": sha256_hex(&bottle),
+                }
+            } } }
+        });
+
+        // Only the tap serves it, not homebrew/core
+        Mock::given(method("GET"))
+            .and(path("/tools/taptool.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/bottles/taptool.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/core", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db)
+            .with_tap("mycompany/tools", &format!("{}/tools", mock_server.uri()));
+
+        let bin_path = prepare_execution(&mut installer, "mycompany/tools/taptool", None)
+            .await
+            .unwrap();
+        assert!(bin_path.ends_with("taptool/1.0.0/bin/taptool"));
+
+        // Run again, now that it's installed
+        let again = prepare_execution(&mut installer, "mycompany/tools/taptool", None)
+            .await
+            .unwrap();
+        assert_eq!(again, bin_path);
+
+        let keg = crate::utils::installed_keg(&installer, "mycompany/tools/taptool").unwrap();
+        assert_eq!(keg.name, "taptool");
+        assert!(matches!(
+            crate::utils::installed_keg(&installer, "other/tap/taptool"),
+            Err(zb_core::Error::UnsupportedTap { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn run_selects_among_several_executables() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let bottle = create_bottle_tarball_with_bins("multibin", &["multibin", "multibin-shell"]);
+        let tag = get_test_bottle_tag();
+        let formula_json = serde_json::json!({
+            "name": "multibin",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/multibin.tar.gz", mock_server.uri()),
+                    "sha256": sha256_hex(&bottle),
+                }
+            } } }
+        });
+
+        Mock::given(method("GET"))
+            .and(path("/multibin.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/bottles/multibin.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let default = prepare_execution(&mut installer, "multibin", None)
+            .await
+            .unwrap();
+        assert!(default.ends_with("bin/multibin"));
+
+        let shell = prepare_execution(&mut installer, "multibin", Some("multibin-shell"))
+            .await
+            .unwrap();
+        assert!(shell.ends_with("bin/multibin-shell"));
+
+        let err = prepare_execution(&mut installer, "multibin", Some("nope"))
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("available: multibin, multibin-shell")
+        );
+    }
+
+    #[tokio::test]
+    async fn run_with_rm_uninstalls_what_it_installed() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let bottle = create_bottle_tarball("ephemeral");
+        let tag = get_test_bottle_tag();
+        let formula_json = serde_json::json!({
+            "name": "ephemeral",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/ephemeral.tar.gz", mock_server.uri()),
+                    "sha256": sha256_hex(&bottle),
+                }
+            } } }
+        });
+
+        Mock::given(method("GET"))
+            .and(path("/ephemeral.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/bottles/ephemeral.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let status = run_and_remove(&mut installer, "ephemeral", None, &[])
+            .await
+            .unwrap();
+
+        assert!(status.success());
+        assert!(!installer.is_installed("ephemeral"));
+        assert!(!root.join("cellar/ephemeral").exists());
+    }
+
+    #[test]
+    fn signalled_children_exit_like_a_shell_reports_them() {
+        let killed = Command::new("sh")
+            .args(["-c", "kill -TERM $$"])
+            .status()
+            .unwrap();
+        assert_eq!(exit_code(killed), 128 + 15);
+
+        let failed = Command::new("sh").args(["-c", "exit 3"]).status().unwrap();
+        assert_eq!(exit_code(failed), 3);
+    }
+
+    #[test]
+    fn sole_executable_is_used_whatever_its_name() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let bin_dir = tmp.path().join("bin");
+        fs::create_dir_all(&bin_dir).unwrap();
+        for name in ["rg", "README"] {
+            fs::write(bin_dir.join(name), "").unwrap();
+        }
+        fs::set_permissions(bin_dir.join("rg"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let chosen = select_executable(tmp.path(), "ripgrep", None).unwrap();
+        assert_eq!(chosen, bin_dir.join("rg"));
+
+        fs::write(bin_dir.join("rg-extra"), "").unwrap();
+        fs::set_permissions(bin_dir.join("rg-extra"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(select_executable(tmp.path(), "ripgrep", None).is_err());
+    }
 }
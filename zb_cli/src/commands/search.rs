@@ -0,0 +1,42 @@
+use console::style;
+
+/// Most results printed before the list is cut short
+const MAX_RESULTS: usize = 50;
+
+pub async fn execute(
+    installer: &mut zb_io::install::Installer,
+    query: String,
+) -> Result<(), zb_core::Error> {
+    let matches = installer.search(&query).await?;
+
+    if matches.is_empty() {
+        return Err(zb_core::Error::ExecutionError {
+            message: format!("no formulas match '{query}'"),
+        });
+    }
+
+    for formula in matches.iter().take(MAX_RESULTS) {
+        print!(
+            "{} {}",
+            style(&formula.name).bold(),
+            style(&formula.versions.stable).dim()
+        );
+        match &formula.desc {
+            Some(desc) => println!("  {desc}"),
+            None => println!(),
+        }
+    }
+
+    if matches.len() > MAX_RESULTS {
+        println!(
+            "{}",
+            style(format!(
+                "... and {} more; refine your query to narrow the results",
+                matches.len() - MAX_RESULTS
+            ))
+            .dim()
+        );
+    }
+
+    Ok(())
+}
@@ -1,9 +1,10 @@
 use console::style;
 
-pub fn execute(
+pub async fn execute(
     installer: &mut zb_io::install::Installer,
     formulas: Vec<String>,
     all: bool,
+    force: bool,
 ) -> Result<(), zb_core::Error> {
     let formulas = if all {
         let installed = installer.list_installed()?;
@@ -13,8 +14,17 @@ pub fn execute(
         }
         installed.into_iter().map(|k| k.name).collect()
     } else {
+        // Accept names qualified with the tap they were installed from
         formulas
+            .into_iter()
+            .map(|name| installer.get_installed(&name).map_or(name, |keg| keg.name))
+            .collect()
     };
+    if !force {
+        // Dependents are checked against cached formula JSON
+        installer.cache_installed_formulas().await?;
+    }
+    let formulas = removal_order(installer, formulas);
 
     println!(
         "{} Uninstalling {}...",
@@ -27,7 +37,7 @@ pub fn execute(
     if formulas.len() > 1 {
         for name in &formulas {
             print!("    {} {}...", style("○").dim(), name);
-            match installer.uninstall(name) {
+            match installer.uninstall(name, force) {
                 Ok(()) => println!(" {}", style("✓").green()),
                 Err(e) => {
                     println!(" {}", style("✗").red());
@@ -35,7 +45,7 @@ pub fn execute(
                 }
             }
         }
-    } else if let Err(e) = installer.uninstall(&formulas[0]) {
+    } else if let Err(e) = installer.uninstall(&formulas[0], force) {
         errors.push((formulas[0].clone(), e));
     }
 
@@ -50,7 +60,37 @@ pub fn execute(
                 err
             );
         }
+        if errors
+            .iter()
+            .any(|(_, e)| matches!(e, zb_core::Error::DependencyConflict { .. }))
+        {
+            eprintln!(
+                "    Uninstall the dependents first, or pass {} to remove anyway.",
+                style("--force").bold()
+            );
+        }
         // Return just the first error up. TODO: don't return errors from this fn?
         Err(errors.remove(0).1)
     }
 }
+
+/// Order `formulas` so each one comes before anything it depends on. That way
+/// removing a formula together with its dependencies isn't blocked by the
+/// formulas being removed in the same run.
+fn removal_order(installer: &zb_io::install::Installer, formulas: Vec<String>) -> Vec<String> {
+    let mut pending = formulas;
+    let mut ordered = Vec::with_capacity(pending.len());
+
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|name| {
+            installer
+                .installed_dependents(name)
+                .map(|dependents| dependents.iter().all(|d| !pending.contains(d)))
+                .unwrap_or(true)
+        });
+        // Anything left in a cycle keeps its original order
+        ordered.push(pending.remove(ready.unwrap_or(0)));
+    }
+
+    ordered
+}
@@ -0,0 +1,101 @@
+use console::style;
+use std::time::Instant;
+
+use crate::utils::normalize_tap_formula_name;
+
+pub async fn execute(
+    installer: &mut zb_io::install::Installer,
+    formula: Option<String>,
+    plain: bool,
+) -> Result<(), zb_core::Error> {
+    let start = Instant::now();
+
+    let requested = match formula {
+        Some(formula) => {
+            let name = normalize_tap_formula_name(&formula, |tap| installer.has_tap(tap))?;
+            match installer.get_installed(&name) {
+                Some(keg) => Some(keg.name),
+                None => return Err(zb_core::Error::NotInstalled { name }),
+            }
+        }
+        None => None,
+    };
+
+    println!(
+        "{} Checking for outdated formulas...",
+        style("==>").cyan().bold()
+    );
+
+    let mut outdated = installer.outdated().await?;
+    if let Some(name) = &requested {
+        outdated.retain(|keg| &keg.name == name);
+    }
+
+    let (pinned, outdated): (Vec<_>, Vec<_>) = outdated.into_iter().partition(|keg| keg.pinned);
+    for keg in &pinned {
+        println!(
+            "{} Skipping {} (pinned at {}, {} available; `zb unpin {}` to upgrade)",
+            style("==>").yellow().bold(),
+            style(&keg.name).bold(),
+            keg.installed,
+            keg.available,
+            keg.name
+        );
+    }
+
+    if outdated.is_empty() {
+        if !pinned.is_empty() {
+            return Ok(());
+        }
+        match &requested {
+            Some(name) => println!("{} is already up to date.", style(name).bold()),
+            None => println!("All formulas are up to date."),
+        }
+        return Ok(());
+    }
+
+    println!(
+        "{} Upgrading {} {}...",
+        style("==>").cyan().bold(),
+        outdated.len(),
+        if outdated.len() == 1 {
+            "formula"
+        } else {
+            "formulas"
+        }
+    );
+    for keg in &outdated {
+        println!(
+            "    {} {} -> {}",
+            style(&keg.name).green(),
+            style(&keg.installed).dim(),
+            keg.available
+        );
+    }
+
+    let names: Vec<String> = outdated.iter().map(|keg| keg.name.clone()).collect();
+    let plan = installer.plan_upgrade(&names).await?;
+
+    let options = super::install::InstallOptions {
+        plain,
+        ..Default::default()
+    };
+    let failed = super::install::execute_plan(installer, plan, options, start).await?;
+    super::install::report_failed(&failed)?;
+
+    println!("{} Upgraded:", style("==>").cyan().bold());
+    for keg in &outdated {
+        println!(
+            "    {} {} -> {}",
+            style(&keg.name).bold(),
+            keg.installed,
+            style(&keg.available).green()
+        );
+    }
+    println!(
+        "    {}",
+        style("Previous versions are kept until cleaned up.").dim()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use console::style;
+
+use crate::utils::normalize_tap_formula_name;
+
+pub async fn execute(
+    installer: &mut zb_io::install::Installer,
+    formula: String,
+    all: bool,
+) -> Result<(), zb_core::Error> {
+    let name = normalize_tap_formula_name(&formula, |tap| installer.has_tap(tap))?;
+    // Dependencies are listed by plain name, whichever tap provides them
+    let plain = name
+        .rsplit_once('/')
+        .map_or(name.as_str(), |(_, formula)| formula);
+    let dependents = installer.uses(plain, all).await?;
+
+    if dependents.is_empty() {
+        let scope = if all {
+            "No formulas"
+        } else {
+            "No installed formulas"
+        };
+        println!("{scope} use {}.", style(&name).bold());
+        return Ok(());
+    }
+
+    for dependent in dependents {
+        println!("{dependent}");
+    }
+
+    Ok(())
+}
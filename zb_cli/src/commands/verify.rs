@@ -0,0 +1,56 @@
+use console::style;
+
+use crate::utils::installed_keg;
+
+pub fn execute(
+    installer: &mut zb_io::install::Installer,
+    formula: Option<String>,
+) -> Result<(), zb_core::Error> {
+    let name = formula
+        .map(|formula| installed_keg(installer, &formula).map(|keg| keg.name))
+        .transpose()?;
+    let reports = installer.verify(name.as_deref())?;
+
+    if reports.is_empty() {
+        println!("No formulas installed.");
+        return Ok(());
+    }
+
+    println!(
+        "{} Verifying {} formula{}...",
+        style("==>").cyan().bold(),
+        reports.len(),
+        if reports.len() == 1 { "" } else { "s" }
+    );
+
+    let mut broken = 0;
+    for report in &reports {
+        if report.problems.is_empty() {
+            let note = if report.store_checked {
+                String::new()
+            } else {
+                format!(" {}", style("(no manifest; store not rehashed)").dim())
+            };
+            println!("    {} {}{note}", style("✓").green(), report.name);
+            continue;
+        }
+
+        broken += 1;
+        println!("    {} {}", style("✗").red(), report.name);
+        for problem in &report.problems {
+            println!("        {problem}");
+        }
+    }
+
+    if broken == 0 {
+        println!("{} No problems found.", style("==>").cyan().bold());
+        return Ok(());
+    }
+
+    Err(zb_core::Error::ExecutionError {
+        message: format!(
+            "verify found problems in {broken} formula{}; reinstall to repair",
+            if broken == 1 { "" } else { "s" }
+        ),
+    })
+}
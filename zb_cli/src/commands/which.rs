@@ -0,0 +1,22 @@
+use crate::utils::{installed_keg, keg_executables};
+
+pub fn execute(
+    installer: &mut zb_io::install::Installer,
+    formula: String,
+) -> Result<(), zb_core::Error> {
+    let installed = installed_keg(installer, &formula)?;
+    let name = installed.name;
+
+    let keg = installer.keg_path(&name, &installed.version);
+    let executables = keg_executables(&keg);
+    if executables.is_empty() {
+        return Err(zb_core::Error::ExecutionError {
+            message: format!("'{name}' does not provide any executables"),
+        });
+    }
+
+    for path in executables {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::cli::Cli;
+use crate::utils::{parse_byte_rate, parse_byte_size};
+
+/// Upper bound for `auto` concurrency, whatever the core count
+pub const MAX_AUTO_CONCURRENCY: usize = 48;
+
+/// How many network operations to run at once: a fixed number, or `auto` to
+/// size it from the machine's available parallelism
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "ConcurrencyValue")]
+pub enum Concurrency {
+    #[default]
+    Auto,
+    Fixed(usize),
+}
+
+impl Concurrency {
+    /// The number of concurrent operations to allow; `auto` is four per core,
+    /// between 4 and [`MAX_AUTO_CONCURRENCY`]
+    pub fn resolve(self) -> usize {
+        match self {
+            Self::Fixed(n) => n,
+            Self::Auto => std::thread::available_parallelism()
+                .map_or(1, |n| n.get())
+                .saturating_mul(4)
+                .clamp(4, MAX_AUTO_CONCURRENCY),
+        }
+    }
+}
+
+impl std::str::FromStr for Concurrency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        match s.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(Self::Fixed(n)),
+            _ => Err(format!(
+                "invalid concurrency '{s}' (expected a positive number or auto)"
+            )),
+        }
+    }
+}
+
+/// `concurrency = 8` and `concurrency = "auto"` are both accepted in the file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConcurrencyValue {
+    Number(usize),
+    Text(String),
+}
+
+impl TryFrom<ConcurrencyValue> for Concurrency {
+    type Error = String;
+
+    fn try_from(value: ConcurrencyValue) -> Result<Self, Self::Error> {
+        match value {
+            ConcurrencyValue::Number(n) => n.to_string().parse(),
+            ConcurrencyValue::Text(s) => s.parse(),
+        }
+    }
+}
+
+/// Defaults read from `<root>/config.toml`. Every field is optional; flags and
+/// environment variables given on the command line win over the file, so a
+/// mode the file turns on is turned off again with e.g. `--offline=false`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub prefix: Option<PathBuf>,
+    pub concurrency: Option<Concurrency>,
+    pub api_concurrency: Option<usize>,
+    pub download_concurrency: Option<usize>,
+    /// Bytes per second, with the same suffixes as `--max-rate`
+    pub max_rate: Option<String>,
+    /// Seconds, like `--timeout`
+    pub timeout: Option<u64>,
+    /// Bytes, with the same suffixes as `--cache-max-size`
+    pub cache_max_size: Option<String>,
+    pub offline: Option<bool>,
+    pub verify_cached: Option<bool>,
+    pub stream_extract: Option<bool>,
+    pub api_cache_in_db: Option<bool>,
+    pub api_url: Option<String>,
+    pub trusted_keys: Vec<String>,
+    pub require_signatures: Option<bool>,
+    pub bottle_mirror: Option<String>,
+    pub fallback_mirrors: Vec<String>,
+    pub proxy: Option<String>,
+    pub max_redirects: Option<usize>,
+    pub mirror_same_host: Option<bool>,
+    pub materialize_mode: Option<String>,
+    /// Tap name (`user/repo`) to the base URL serving its formula JSON
+    pub taps: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Read `config.toml` from `root`; a missing file yields the defaults
+    pub fn load(root: &Path) -> Result<Self, zb_core::Error> {
+        let path = root.join("config.toml");
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(zb_core::Error::FileError {
+                    message: format!("failed to read {}: {e}", path.display()),
+                });
+            }
+        };
+
+        toml::from_str(&contents).map_err(|e| zb_core::Error::InvalidArgument {
+            message: format!("invalid {}: {e}", path.display()),
+        })
+    }
+
+    /// Fill in every setting the command line left unset
+    pub fn apply(self, cli: &mut Cli) -> Result<(), zb_core::Error> {
+        let invalid = |message: String| zb_core::Error::InvalidArgument {
+            message: format!("config.toml: {message}"),
+        };
+
+        if cli.prefix.is_none() {
+            cli.prefix = self.prefix;
+        }
+        if cli.concurrency.is_none() {
+            cli.concurrency = self.concurrency;
+        }
+        if cli.api_concurrency.is_none() {
+            cli.api_concurrency = self.api_concurrency;
+        }
+        if cli.download_concurrency.is_none() {
+            cli.download_concurrency = self.download_concurrency;
+        }
+        if cli.max_rate.is_none()
+            && let Some(rate) = self.max_rate
+        {
+            cli.max_rate = Some(parse_byte_rate(&rate).map_err(invalid)?);
+        }
+        if cli.timeout.is_none() {
+            cli.timeout = self.timeout;
+        }
+        if cli.cache_max_size.is_none()
+            && let Some(size) = self.cache_max_size
+        {
+            cli.cache_max_size = Some(parse_byte_size(&size).map_err(invalid)?);
+        }
+        if cli.api_url.is_none() {
+            cli.api_url = self.api_url;
+        }
+        if cli.bottle_mirror.is_none() {
+            cli.bottle_mirror = self.bottle_mirror;
+        }
+        if cli.proxy.is_none() {
+            cli.proxy = self.proxy;
+        }
+        if self.max_redirects == Some(0) {
+            return Err(invalid("max_redirects must be at least 1".to_string()));
+        }
+        if cli.max_redirects.is_none() {
+            cli.max_redirects = self.max_redirects;
+        }
+        // --no-cache can't be combined with --offline, so it turns off the
+        // file's offline mode like `--offline=false` would
+        if cli.offline.is_none() && !cli.no_cache {
+            cli.offline = self.offline;
+        }
+        if cli.verify_cached.is_none() {
+            cli.verify_cached = self.verify_cached;
+        }
+        if cli.stream_extract.is_none() {
+            cli.stream_extract = self.stream_extract;
+        }
+        if cli.api_cache_in_db.is_none() {
+            cli.api_cache_in_db = self.api_cache_in_db;
+        }
+        if cli.require_signatures.is_none() {
+            cli.require_signatures = self.require_signatures;
+        }
+        if cli.mirror_same_host.is_none() {
+            cli.mirror_same_host = self.mirror_same_host;
+        }
+        if cli.trusted_keys.is_empty() {
+            cli.trusted_keys = self.trusted_keys;
+        }
+        if cli.fallback_mirrors.is_empty() {
+            cli.fallback_mirrors = self.fallback_mirrors;
+        }
+        if cli.taps.is_empty() {
+            cli.taps = self.taps.into_iter().collect();
+        }
+        if cli.materialize_mode.is_none()
+            && let Some(mode) = self.materialize_mode
+        {
+            cli.materialize_mode = Some(mode.parse().map_err(invalid)?);
+        }
+
+        // Only mirror downloads are pinned to their host
+        if cli.mirror_same_host == Some(true) && cli.bottle_mirror.is_none() {
+            return Err(zb_core::Error::InvalidArgument {
+                message: "--mirror-same-host only applies with --bottle-mirror".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+    use tempfile::TempDir;
+
+    /// Parse `args` as the command line, ignoring whatever variables the
+    /// environment running the tests sets for flags
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        fn without_env(command: clap::Command) -> clap::Command {
+            command
+                .mut_args(|arg| arg.env(None::<&str>))
+                .mut_subcommands(without_env)
+        }
+        let matches = without_env(Cli::command()).try_get_matches_from(args)?;
+        Cli::from_arg_matches(&matches)
+    }
+
+    #[test]
+    fn file_overrides_defaults_but_loses_to_flags() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(
+            tmp.path().join("config.toml"),
+            "concurrency = 8\ncache_max_size = \"1G\"\noffline = true\nmaterialize_mode = \"copy\"\n\n[taps]\n\"mycompany/tools\" = \"https://taps.example.com/tools\"\n",
+        )
+        .unwrap();
+
+        let mut cli = parse(&["zb", "--concurrency", "4", "list"]).unwrap();
+        Config::load(tmp.path()).unwrap().apply(&mut cli).unwrap();
+
+        assert_eq!(cli.concurrency, Some(Concurrency::Fixed(4)));
+        assert_eq!(cli.cache_max_size, Some(1024 * 1024 * 1024));
+        assert_eq!(cli.offline, Some(true));
+        assert_eq!(cli.materialize_mode, Some(zb_io::MaterializeMode::Copy));
+        assert_eq!(cli.max_rate, None);
+        assert_eq!(
+            cli.taps,
+            [(
+                "mycompany/tools".to_string(),
+                "https://taps.example.com/tools".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn auto_concurrency_resolves_and_explicit_values_pass_through() {
+        assert!(Concurrency::Auto.resolve() >= 4);
+        assert!(Concurrency::Auto.resolve() <= MAX_AUTO_CONCURRENCY);
+        assert_eq!("auto".parse(), Ok(Concurrency::Auto));
+        assert_eq!("100".parse::<Concurrency>().unwrap().resolve(), 100);
+        assert!("0".parse::<Concurrency>().is_err());
+
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(tmp.path().join("config.toml"), "concurrency = \"auto\"\n").unwrap();
+        let config = Config::load(tmp.path()).unwrap();
+        assert_eq!(config.concurrency, Some(Concurrency::Auto));
+    }
+
+    #[test]
+    fn redirect_settings_agree_between_flags_and_file() {
+        let tmp = TempDir::new().unwrap();
+        assert!(parse(&["zb", "--max-redirects", "0", "list"]).is_err());
+
+        std::fs::write(tmp.path().join("config.toml"), "max_redirects = 0\n").unwrap();
+        let mut cli = parse(&["zb", "list"]).unwrap();
+        assert!(matches!(
+            Config::load(tmp.path()).unwrap().apply(&mut cli),
+            Err(zb_core::Error::InvalidArgument { .. })
+        ));
+
+        std::fs::write(tmp.path().join("config.toml"), "mirror_same_host = true\n").unwrap();
+        let mut cli = parse(&["zb", "list"]).unwrap();
+        assert!(matches!(
+            Config::load(tmp.path()).unwrap().apply(&mut cli),
+            Err(zb_core::Error::InvalidArgument { .. })
+        ));
+        let mut cli = parse(&["zb", "--bottle-mirror", "https://m.example.com", "list"]).unwrap();
+        Config::load(tmp.path()).unwrap().apply(&mut cli).unwrap();
+        assert_eq!(cli.mirror_same_host, Some(true));
+    }
+
+    #[test]
+    fn explicit_false_beats_true_in_the_file() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(
+            tmp.path().join("config.toml"),
+            "offline = true\nverify_cached = true\nstream_extract = true\n",
+        )
+        .unwrap();
+
+        // `=0` is what ZEROBREW_OFFLINE=0 parses as, through the same parser
+        let mut cli = parse(&[
+            "zb",
+            "--offline=0",
+            "--verify-cached=false",
+            "--stream-extract",
+            "list",
+        ])
+        .unwrap();
+        Config::load(tmp.path()).unwrap().apply(&mut cli).unwrap();
+        assert_eq!(cli.offline, Some(false));
+        assert_eq!(cli.verify_cached, Some(false));
+        // A bare flag doesn't take the subcommand after it as its value
+        assert_eq!(cli.stream_extract, Some(true));
+        assert!(matches!(cli.command, crate::cli::Commands::List { .. }));
+
+        // --no-cache turns the file's offline mode off instead of clashing
+        let mut cli = parse(&["zb", "--no-cache", "list"]).unwrap();
+        Config::load(tmp.path()).unwrap().apply(&mut cli).unwrap();
+        assert_eq!(cli.offline, None);
+        assert_eq!(cli.verify_cached, Some(true));
+    }
+
+    #[test]
+    fn missing_file_is_empty_and_bad_file_is_an_error() {
+        let tmp = TempDir::new().unwrap();
+        assert!(Config::load(tmp.path()).unwrap().concurrency.is_none());
+
+        std::fs::write(tmp.path().join("config.toml"), "concurency = 8\n").unwrap();
+        assert!(matches!(
+            Config::load(tmp.path()),
+            Err(zb_core::Error::InvalidArgument { .. })
+        ));
+    }
+}
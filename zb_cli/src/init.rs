@@ -1,5 +1,5 @@
 use console::style;
-use std::io::Write;
+use std::io::{BufRead, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -8,10 +8,24 @@ pub enum InitError {
     Message(String),
 }
 
+/// Every directory a working install needs, parents first
+fn required_dirs(root: &Path, prefix: &Path) -> Vec<PathBuf> {
+    vec![
+        root.to_path_buf(),
+        root.join("store"),
+        root.join("db"),
+        root.join("cache"),
+        root.join("locks"),
+        prefix.to_path_buf(),
+        prefix.join("bin"),
+        prefix.join("Cellar"),
+    ]
+}
+
 pub fn needs_init(root: &Path, prefix: &Path) -> bool {
-    let root_ok = root.exists() && is_writable(root);
-    let prefix_ok = prefix.exists() && is_writable(prefix);
-    !(root_ok && prefix_ok)
+    required_dirs(root, prefix)
+        .iter()
+        .any(|dir| !is_writable(dir))
 }
 
 pub fn is_writable(path: &Path) -> bool {
@@ -41,24 +55,21 @@ pub fn run_init(root: &Path, prefix: &Path, no_modify_path: bool) -> Result<(),
     };
     let zerobrew_bin = format!("{}/bin", zerobrew_dir);
 
-    let dirs_to_create: Vec<PathBuf> = vec![
-        root.to_path_buf(),
-        root.join("store"),
-        root.join("db"),
-        root.join("cache"),
-        root.join("locks"),
-        prefix.to_path_buf(),
-        prefix.join("bin"),
-        prefix.join("Cellar"),
-    ];
+    // Only repair what's missing or unwritable so a re-run leaves a healthy
+    // layout alone
+    let dirs_to_create: Vec<PathBuf> = required_dirs(root, prefix)
+        .into_iter()
+        .filter(|d| !is_writable(d))
+        .collect();
 
     let need_sudo = dirs_to_create.iter().any(|d| {
         if d.exists() {
             !is_writable(d)
         } else {
-            d.parent()
-                .map(|p| p.exists() && !is_writable(p))
-                .unwrap_or(true)
+            d.ancestors()
+                .skip(1)
+                .find(|p| p.exists())
+                .is_none_or(|p| !is_writable(p))
         }
     });
 
@@ -89,28 +100,20 @@ pub fn run_init(root: &Path, prefix: &Path, no_modify_path: bool) -> Result<(),
             .map(|s| s.trim().to_string())
             .unwrap_or_else(|| std::env::var("USER").unwrap_or_else(|_| "root".to_string()));
 
-        let status = Command::new("sudo")
-            .args(["chown", "-R", &user, &root.to_string_lossy()])
-            .status()
-            .map_err(|e| InitError::Message(format!("Failed to run sudo chown: {}", e)))?;
-
-        if !status.success() {
-            return Err(InitError::Message(format!(
-                "Failed to set ownership on {}",
-                root.display()
-            )));
-        }
-
-        let status = Command::new("sudo")
-            .args(["chown", "-R", &user, &prefix.to_string_lossy()])
-            .status()
-            .map_err(|e| InitError::Message(format!("Failed to run sudo chown: {}", e)))?;
+        // Take back the whole tree, including anything an earlier sudo run
+        // left owned by root
+        for dir in [root, prefix] {
+            let status = Command::new("sudo")
+                .args(["chown", "-R", &user, &dir.to_string_lossy()])
+                .status()
+                .map_err(|e| InitError::Message(format!("Failed to run sudo chown: {}", e)))?;
 
-        if !status.success() {
-            return Err(InitError::Message(format!(
-                "Failed to set ownership on {}",
-                prefix.display()
-            )));
+            if !status.success() {
+                return Err(InitError::Message(format!(
+                    "Failed to set ownership on {}",
+                    dir.display()
+                )));
+            }
         }
     } else {
         for dir in &dirs_to_create {
@@ -127,49 +130,133 @@ pub fn run_init(root: &Path, prefix: &Path, no_modify_path: bool) -> Result<(),
     Ok(())
 }
 
-fn add_to_path(
-    prefix: &Path,
-    zerobrew_dir: &str,
-    zerobrew_bin: &str,
-    root: &Path,
-    no_modify_path: bool,
-) -> Result<(), InitError> {
-    let shell = std::env::var("SHELL").unwrap_or_default();
-    let home = std::env::var("HOME").map_err(|_| InitError::Message("HOME not set".to_string()))?;
-
-    let config_file = if shell.contains("zsh") {
-        let zdotdir = std::env::var("ZDOTDIR").unwrap_or_else(|_| home.clone());
-        let zshenv = format!("{}/.zshenv", zdotdir);
+/// Shells whose config files `add_to_path` knows how to write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shell {
+    Zsh,
+    Bash,
+    Fish,
+    Nu,
+    /// Anything else gets POSIX syntax in ~/.profile
+    Other,
+}
 
-        if std::path::Path::new(&zshenv).exists() {
-            zshenv
-        } else {
-            format!("{}/.zshrc", zdotdir)
+impl Shell {
+    fn detect(shell: &str) -> Self {
+        let name = Path::new(shell)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(shell);
+        match name {
+            "fish" => Shell::Fish,
+            "nu" => Shell::Nu,
+            _ if shell.contains("zsh") => Shell::Zsh,
+            _ if shell.contains("bash") => Shell::Bash,
+            _ => Shell::Other,
         }
-    } else if shell.contains("bash") {
-        let bash_profile = format!("{}/.bash_profile", home);
-        if std::path::Path::new(&bash_profile).exists() {
-            bash_profile
-        } else {
-            format!("{}/.bashrc", home)
+    }
+
+    /// The file to append zerobrew's configuration to
+    fn config_file(self, home: &str) -> String {
+        let config_home =
+            std::env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| format!("{}/.config", home));
+        match self {
+            Shell::Zsh => {
+                let zdotdir = std::env::var("ZDOTDIR").unwrap_or_else(|_| home.to_string());
+                let zshenv = format!("{}/.zshenv", zdotdir);
+
+                if std::path::Path::new(&zshenv).exists() {
+                    zshenv
+                } else {
+                    format!("{}/.zshrc", zdotdir)
+                }
+            }
+            Shell::Bash => {
+                let bash_profile = format!("{}/.bash_profile", home);
+                if std::path::Path::new(&bash_profile).exists() {
+                    bash_profile
+                } else {
+                    format!("{}/.bashrc", home)
+                }
+            }
+            Shell::Fish => format!("{}/fish/config.fish", config_home),
+            // Nushell only follows XDG_CONFIG_HOME on macOS when it's set
+            Shell::Nu if cfg!(target_os = "macos") && std::env::var("XDG_CONFIG_HOME").is_err() => {
+                format!("{}/Library/Application Support/nushell/env.nu", home)
+            }
+            Shell::Nu => format!("{}/nushell/env.nu", config_home),
+            Shell::Other => format!("{}/.profile", home),
         }
-    } else {
-        format!("{}/.profile", home)
-    };
+    }
 
-    let prefix_bin = prefix.join("bin");
+    /// The line that puts `dir` on PATH, which also tells whether a config
+    /// file has been set up already
+    fn path_line(self, dir: &str) -> String {
+        match self {
+            Shell::Fish => format!("fish_add_path {}", dir),
+            Shell::Nu => format!(
+                "$env.PATH = ($env.PATH | split row (char esep) | prepend '{}' | uniq)",
+                dir
+            ),
+            Shell::Zsh | Shell::Bash | Shell::Other => format!("_zb_path_append {}", dir),
+        }
+    }
 
-    // Check if zerobrew is already configured
-    let already_added = if let Ok(contents) = std::fs::read_to_string(&config_file) {
-        contents.contains("# zerobrew")
-    } else {
-        false
-    };
+    /// Whether `contents` already configures zerobrew for this shell
+    fn is_configured(self, contents: &str, prefix_bin: &str) -> bool {
+        let path_line = self.path_line(prefix_bin);
+        contents.contains("# zerobrew") || contents.lines().any(|l| l.trim() == path_line)
+    }
 
-    if !no_modify_path && !already_added {
-        // Build the shell configuration content
-        let config_content = format!(
-            "\n# zerobrew
+    /// The block appended to the config file, in this shell's syntax
+    fn config_content(
+        self,
+        zerobrew_dir: &str,
+        zerobrew_bin: &str,
+        root: &Path,
+        prefix: &Path,
+    ) -> String {
+        let prefix_bin = prefix.join("bin");
+        let prefix_bin = prefix_bin.to_string_lossy();
+        match self {
+            Shell::Fish => format!(
+                "\n# zerobrew
+set -gx ZEROBREW_DIR {}
+set -gx ZEROBREW_BIN {}
+set -gx ZEROBREW_ROOT {}
+set -gx ZEROBREW_PREFIX {}
+set -gx PKG_CONFIG_PATH {}/lib/pkgconfig $PKG_CONFIG_PATH
+{}
+{}
+",
+                zerobrew_dir,
+                zerobrew_bin,
+                root.display(),
+                prefix.display(),
+                prefix.display(),
+                self.path_line(zerobrew_bin),
+                self.path_line(&prefix_bin)
+            ),
+            Shell::Nu => format!(
+                "\n# zerobrew
+$env.ZEROBREW_DIR = '{}'
+$env.ZEROBREW_BIN = '{}'
+$env.ZEROBREW_ROOT = '{}'
+$env.ZEROBREW_PREFIX = '{}'
+$env.PKG_CONFIG_PATH = ($env.PKG_CONFIG_PATH? | default [] | split row (char esep) | prepend '{}/lib/pkgconfig' | str join (char esep))
+{}
+{}
+",
+                zerobrew_dir,
+                zerobrew_bin,
+                root.display(),
+                prefix.display(),
+                prefix.display(),
+                self.path_line(zerobrew_bin),
+                self.path_line(&prefix_bin)
+            ),
+            Shell::Zsh | Shell::Bash | Shell::Other => format!(
+                "\n# zerobrew
 export ZEROBREW_DIR={}
 export ZEROBREW_BIN={}
 export ZEROBREW_ROOT={}
@@ -182,22 +269,55 @@ _zb_path_append() {{
         *) export PATH=\"$argpath:$PATH\" ;;
     esac;
 }}
-_zb_path_append {}
-_zb_path_append {}
+{}
+{}
 ",
-            zerobrew_dir,
-            zerobrew_bin,
-            root.display(),
-            prefix.display(),
-            prefix.display(),
-            zerobrew_bin,
-            prefix_bin.display()
-        );
+                zerobrew_dir,
+                zerobrew_bin,
+                root.display(),
+                prefix.display(),
+                prefix.display(),
+                self.path_line(zerobrew_bin),
+                self.path_line(&prefix_bin)
+            ),
+        }
+    }
+}
+
+fn add_to_path(
+    prefix: &Path,
+    zerobrew_dir: &str,
+    zerobrew_bin: &str,
+    root: &Path,
+    no_modify_path: bool,
+) -> Result<(), InitError> {
+    let shell = Shell::detect(&std::env::var("SHELL").unwrap_or_default());
+    let home = std::env::var("HOME").map_err(|_| InitError::Message("HOME not set".to_string()))?;
+    let config_file = shell.config_file(&home);
 
-        let write_result = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&config_file)
+    let prefix_bin = prefix.join("bin");
+
+    // Check if zerobrew is already configured
+    let already_added = if let Ok(contents) = std::fs::read_to_string(&config_file) {
+        shell.is_configured(&contents, &prefix_bin.to_string_lossy())
+    } else {
+        false
+    };
+
+    if !no_modify_path && !already_added {
+        let config_content = shell.config_content(zerobrew_dir, zerobrew_bin, root, prefix);
+
+        // fish and nushell keep their config in directories that may not
+        // exist yet
+        let write_result = Path::new(&config_file)
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| {
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&config_file)
+            })
             .and_then(|mut f| f.write_all(config_content.as_bytes()));
 
         if let Err(e) = write_result {
@@ -242,11 +362,31 @@ _zb_path_append {}
     Ok(())
 }
 
-pub fn ensure_init(root: &Path, prefix: &Path) -> Result<(), zb_core::Error> {
+/// Initialize on first use, asking first. Without `interactive` this fails
+/// straight away instead of waiting on stdin.
+pub fn ensure_init(root: &Path, prefix: &Path, interactive: bool) -> Result<(), zb_core::Error> {
+    ensure_init_with(root, prefix, interactive, &mut std::io::stdin().lock())
+}
+
+fn ensure_init_with(
+    root: &Path,
+    prefix: &Path,
+    interactive: bool,
+    input: &mut impl BufRead,
+) -> Result<(), zb_core::Error> {
     if !needs_init(root, prefix) {
         return Ok(());
     }
 
+    if !interactive {
+        return Err(zb_core::Error::StoreCorruption {
+            message: format!(
+                "zerobrew is not initialized at {}. Run 'zb init' first.",
+                root.display()
+            ),
+        });
+    }
+
     println!(
         "{} Zerobrew needs to be initialized first.",
         style("Note:").yellow().bold()
@@ -259,9 +399,9 @@ pub fn ensure_init(root: &Path, prefix: &Path) -> Result<(), zb_core::Error> {
     print!("Initialize now? [Y/n] ");
     std::io::stdout().flush().unwrap();
 
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-    let input = input.trim();
+    let mut answer = String::new();
+    input.read_line(&mut answer).unwrap();
+    let input = answer.trim();
 
     if !input.is_empty() && !input.eq_ignore_ascii_case("y") && !input.eq_ignore_ascii_case("yes") {
         return Err(zb_core::Error::StoreCorruption {
@@ -321,12 +461,65 @@ mod tests {
         let root = tmp.path().join("root");
         let prefix = tmp.path().join("prefix");
 
-        fs::create_dir(&root).unwrap();
-        fs::create_dir(&prefix).unwrap();
+        for dir in required_dirs(&root, &prefix) {
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        assert!(!needs_init(&root, &prefix));
+    }
+
+    #[test]
+    fn init_repairs_only_the_missing_subdirectory() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        let prefix = tmp.path().join("prefix");
+
+        run_init(&root, &prefix, true).unwrap();
+        assert!(!needs_init(&root, &prefix));
+
+        fs::write(root.join("store/marker"), b"keep").unwrap();
+        fs::remove_dir(root.join("db")).unwrap();
+        assert!(needs_init(&root, &prefix));
 
+        run_init(&root, &prefix, true).unwrap();
+        assert!(root.join("db").is_dir());
+        assert_eq!(fs::read(root.join("store/marker")).unwrap(), b"keep");
         assert!(!needs_init(&root, &prefix));
     }
 
+    /// Panics if anything tries to read a prompt answer
+    struct NoStdin;
+
+    impl std::io::Read for NoStdin {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            panic!("stdin was read in non-interactive mode");
+        }
+    }
+
+    impl BufRead for NoStdin {
+        fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+            panic!("stdin was read in non-interactive mode");
+        }
+
+        fn consume(&mut self, _: usize) {}
+    }
+
+    #[test]
+    fn non_interactive_ensure_init_fails_without_reading_stdin() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        let prefix = tmp.path().join("prefix");
+
+        let err = ensure_init_with(&root, &prefix, false, &mut NoStdin).unwrap_err();
+        assert!(err.to_string().contains("zb init"));
+        assert!(!root.exists());
+
+        for dir in required_dirs(&root, &prefix) {
+            fs::create_dir_all(dir).unwrap();
+        }
+        ensure_init_with(&root, &prefix, false, &mut NoStdin).unwrap();
+    }
+
     #[test]
     fn is_writable_returns_true_for_writable_dir() {
         let tmp = TempDir::new().unwrap();
@@ -615,7 +808,7 @@ mod tests {
             std::env::set_var("HOME", home.to_str().unwrap());
         }
         unsafe {
-            std::env::set_var("SHELL", "/bin/fish");
+            std::env::set_var("SHELL", "/bin/ksh");
         }
 
         add_to_path(&prefix, zerobrew_dir, zerobrew_bin, &root, false).unwrap();
@@ -657,4 +850,77 @@ mod tests {
         let content = fs::read_to_string(&shell_config).unwrap();
         assert!(content.contains("# zerobrew"));
     }
+
+    #[test]
+    fn add_to_path_writes_fish_config_once() {
+        let tmp = TempDir::new().unwrap();
+        let home = tmp.path();
+        let prefix = tmp.path().join("prefix");
+        let root = tmp.path().join("root");
+        let config_fish = home.join(".config/fish/config.fish");
+        let zerobrew_dir = "/home/user/.zerobrew";
+        let zerobrew_bin = "/home/user/.zerobrew/bin";
+
+        fs::create_dir(&prefix).unwrap();
+        fs::create_dir(&root).unwrap();
+
+        unsafe {
+            std::env::set_var("HOME", home.to_str().unwrap());
+            std::env::set_var("SHELL", "/usr/bin/fish");
+            std::env::remove_var("XDG_CONFIG_HOME");
+        }
+
+        add_to_path(&prefix, zerobrew_dir, zerobrew_bin, &root, false).unwrap();
+        add_to_path(&prefix, zerobrew_dir, zerobrew_bin, &root, false).unwrap();
+
+        let content = fs::read_to_string(&config_fish).unwrap();
+        assert_eq!(content.matches("# zerobrew").count(), 1);
+        assert!(content.contains(&format!("fish_add_path {}", prefix.join("bin").display())));
+        assert!(!content.contains("export "));
+        assert!(!home.join(".profile").exists());
+    }
+
+    #[test]
+    fn config_content_uses_each_shells_syntax() {
+        let root = Path::new("/opt/zb");
+        let prefix = Path::new("/opt/zb/prefix");
+        let content = |shell: Shell| shell.config_content("/d", "/d/bin", root, prefix);
+
+        let bash = content(Shell::Bash);
+        assert!(bash.contains("export ZEROBREW_ROOT=/opt/zb\n"));
+        assert!(bash.contains("_zb_path_append /opt/zb/prefix/bin\n"));
+
+        let fish = content(Shell::Fish);
+        assert!(fish.contains("set -gx ZEROBREW_ROOT /opt/zb\n"));
+        assert!(fish.contains("fish_add_path /d/bin\nfish_add_path /opt/zb/prefix/bin\n"));
+        assert!(!fish.contains("export"));
+
+        let nu = content(Shell::Nu);
+        assert!(nu.contains("$env.ZEROBREW_ROOT = '/opt/zb'\n"));
+        assert!(nu.contains(
+            "$env.PATH = ($env.PATH | split row (char esep) | prepend '/opt/zb/prefix/bin' | uniq)\n"
+        ));
+        assert!(!nu.contains("export"));
+    }
+
+    #[test]
+    fn detects_shells_by_name() {
+        assert_eq!(Shell::detect("/usr/local/bin/fish"), Shell::Fish);
+        assert_eq!(Shell::detect("/usr/bin/nu"), Shell::Nu);
+        assert_eq!(Shell::detect("/bin/zsh"), Shell::Zsh);
+        assert_eq!(Shell::detect("/opt/homebrew/bin/bash"), Shell::Bash);
+        assert_eq!(Shell::detect("/bin/ksh"), Shell::Other);
+    }
+
+    #[test]
+    fn is_configured_matches_the_shells_own_path_line() {
+        let fish_line = "fish_add_path /opt/zb/prefix/bin\n";
+        assert!(Shell::Fish.is_configured(fish_line, "/opt/zb/prefix/bin"));
+        assert!(!Shell::Nu.is_configured(fish_line, "/opt/zb/prefix/bin"));
+        assert!(
+            !Shell::Fish
+                .is_configured("_zb_path_append /opt/zb/prefix/bin\n", "/opt/zb/prefix/bin")
+        );
+        assert!(Shell::Bash.is_configured("# zerobrew\n", "/opt/zb/prefix/bin"));
+    }
 }
@@ -1,16 +1,27 @@
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 
-pub fn normalize_formula_name(name: &str) -> Result<String, zb_core::Error> {
+/// Strip a `homebrew/core/` prefix from `name`. Names from taps `has_tap`
+/// accepts keep their tap so they're fetched from its configured URL; any
+/// other tap is unsupported.
+pub fn normalize_tap_formula_name(
+    name: &str,
+    has_tap: impl Fn(&str) -> bool,
+) -> Result<String, zb_core::Error> {
     let trimmed = name.trim();
     if let Some((tap, formula)) = trimmed.rsplit_once('/') {
+        if formula.is_empty() && (tap == "homebrew/core" || has_tap(tap)) {
+            return Err(zb_core::Error::MissingFormula {
+                name: trimmed.to_string(),
+                suggestions: Vec::new(),
+            });
+        }
         if tap == "homebrew/core" {
-            if formula.is_empty() {
-                return Err(zb_core::Error::MissingFormula {
-                    name: trimmed.to_string(),
-                });
-            }
             return Ok(formula.to_string());
         }
+        if has_tap(tap) {
+            return Ok(trimmed.to_string());
+        }
         return Err(zb_core::Error::UnsupportedTap {
             name: trimmed.to_string(),
         });
@@ -19,6 +30,162 @@ pub fn normalize_formula_name(name: &str) -> Result<String, zb_core::Error> {
     Ok(trimmed.to_string())
 }
 
+/// The installed keg `name` refers to, which may be qualified with the tap
+/// it came from
+pub fn installed_keg(
+    installer: &zb_io::install::Installer,
+    name: &str,
+) -> Result<zb_io::db::InstalledKeg, zb_core::Error> {
+    let name = normalize_tap_formula_name(name, |tap| installer.has_tap(tap))?;
+    installer
+        .get_installed(&name)
+        .ok_or(zb_core::Error::NotInstalled { name })
+}
+
+/// Executable files in a keg's `bin/`, sorted by path
+pub fn keg_executables(keg: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(keg.join("bin")) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            std::fs::metadata(path)
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Parse a `user/repo=URL` tap mapping
+pub fn parse_tap_mapping(value: &str) -> Result<(String, String), String> {
+    let (tap, url) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid tap mapping '{value}' (expected user/repo=URL)"))?;
+    let tap = tap.trim().trim_matches('/');
+    let url = url.trim();
+    if tap.split('/').count() != 2 || tap.split('/').any(str::is_empty) || url.is_empty() {
+        return Err(format!(
+            "invalid tap mapping '{value}' (expected user/repo=URL)"
+        ));
+    }
+    Ok((tap.to_string(), url.to_string()))
+}
+
+/// Whether we're running under CI, which exports `CI=true`
+pub fn is_ci() -> bool {
+    std::env::var("CI").is_ok_and(|value| value.eq_ignore_ascii_case("true") || value == "1")
+}
+
+/// Whether to print one line per step instead of drawing progress bars:
+/// when prompting is off, or when stdout isn't a terminal the bars could
+/// redraw in place (piped into a file, they'd leave carriage returns behind)
+pub fn use_plain_output(interactive: bool, stdout_is_terminal: bool) -> bool {
+    !interactive || !stdout_is_terminal
+}
+
+/// Log filter for a `-v` count: nothing by default, zerobrew's own debug
+/// events at `-v`, and everything down to trace (including the HTTP stack) at `-vv`
+pub fn log_filter(verbosity: u8) -> &'static str {
+    match verbosity {
+        0 => "off",
+        1 => "zb_io=debug,zb_cli=debug",
+        _ => "debug,zb_io=trace,zb_cli=trace",
+    }
+}
+
+/// Send tracing output to stderr so it never mixes with `--json` on stdout.
+/// `ZEROBREW_LOG` takes a full filter directive and overrides `-v`.
+pub fn init_logging(verbosity: u8) {
+    let filter = std::env::var("ZEROBREW_LOG")
+        .ok()
+        .and_then(|directive| tracing_subscriber::EnvFilter::try_new(directive).ok())
+        .unwrap_or_else(|| tracing_subscriber::EnvFilter::new(log_filter(verbosity)));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(console::colors_enabled_stderr())
+        .try_init();
+}
+
+/// Format a unix timestamp as ISO-8601 in UTC, e.g. `2024-05-01T12:00:00Z`
+pub fn format_iso8601(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+/// Parse a byte rate such as `500k` or `2M` into bytes per second.
+/// Suffixes are binary multiples (k = 1024) and case-insensitive.
+pub fn parse_byte_rate(value: &str) -> Result<u64, String> {
+    parse_bytes(value, "rate")
+}
+
+/// Parse a size such as `512M` or `10G` into bytes, with the same suffixes
+/// as [`parse_byte_rate`].
+pub fn parse_byte_size(value: &str) -> Result<u64, String> {
+    parse_bytes(value, "size")
+}
+
+/// Parse a duration such as `300`, `300s`, `5m` or `1h`; plain numbers are seconds
+pub fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let trimmed = value.trim();
+    let (digits, multiplier) = match trimmed.char_indices().last() {
+        Some((idx, c)) if c.is_ascii_alphabetic() => {
+            let multiplier = match c.to_ascii_lowercase() {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                _ => return Err(format!("unknown duration suffix '{c}' in '{value}'")),
+            };
+            (&trimmed[..idx], multiplier)
+        }
+        _ => (trimmed, 1),
+    };
+
+    let amount: u64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration '{value}'"))?;
+    if amount == 0 {
+        return Err("duration must be greater than zero".to_string());
+    }
+    amount
+        .checked_mul(multiplier)
+        .map(std::time::Duration::from_secs)
+        .ok_or_else(|| format!("duration '{value}' is too large"))
+}
+
+fn parse_bytes(value: &str, what: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let (digits, multiplier) = match trimmed.char_indices().last() {
+        Some((idx, c)) if c.is_ascii_alphabetic() => {
+            let multiplier = match c.to_ascii_lowercase() {
+                'k' => 1024,
+                'm' => 1024 * 1024,
+                'g' => 1024 * 1024 * 1024,
+                _ => return Err(format!("unknown {what} suffix '{c}' in '{value}'")),
+            };
+            (&trimmed[..idx], multiplier)
+        }
+        _ => (trimmed, 1),
+    };
+
+    let amount: u64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid {what} '{value}'"))?;
+    if amount == 0 {
+        return Err(format!("{what} must be greater than zero"));
+    }
+
+    amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("{what} '{value}' is too large"))
+}
+
 pub fn get_root_path(cli_root: Option<PathBuf>) -> PathBuf {
     if let Some(root) = cli_root {
         return root;
@@ -47,3 +214,119 @@ pub fn get_root_path(cli_root: Option<PathBuf>) -> PathBuf {
         xdg_data_home.join("zerobrew")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_output_is_used_unless_interactive_on_a_terminal() {
+        assert!(!use_plain_output(true, true));
+        assert!(use_plain_output(true, false));
+        assert!(use_plain_output(false, true));
+        assert!(use_plain_output(false, false));
+    }
+
+    #[test]
+    fn every_verbosity_has_a_valid_log_filter() {
+        for verbosity in 0..4 {
+            tracing_subscriber::EnvFilter::try_new(log_filter(verbosity)).unwrap();
+        }
+        let cli = <crate::cli::Cli as clap::Parser>::try_parse_from(["zb", "-vv", "list"]).unwrap();
+        assert_eq!(cli.verbose, 2);
+    }
+
+    #[test]
+    fn configured_taps_keep_their_prefix_and_others_are_rejected() {
+        let has_tap = |tap: &str| tap == "mycompany/tools";
+        assert_eq!(
+            normalize_tap_formula_name("mycompany/tools/foo", has_tap).unwrap(),
+            "mycompany/tools/foo"
+        );
+        assert_eq!(
+            normalize_tap_formula_name("homebrew/core/foo", has_tap).unwrap(),
+            "foo"
+        );
+        assert!(matches!(
+            normalize_tap_formula_name("other/tap/foo", has_tap),
+            Err(zb_core::Error::UnsupportedTap { .. })
+        ));
+        assert!(matches!(
+            normalize_tap_formula_name("mycompany/tools/foo", |_| false),
+            Err(zb_core::Error::UnsupportedTap { .. })
+        ));
+
+        assert_eq!(
+            parse_tap_mapping("mycompany/tools=https://taps.example.com/tools").unwrap(),
+            (
+                "mycompany/tools".to_string(),
+                "https://taps.example.com/tools".to_string()
+            )
+        );
+        assert!(parse_tap_mapping("mycompany=https://example.com").is_err());
+        assert!(parse_tap_mapping("mycompany/tools").is_err());
+    }
+
+    #[test]
+    fn keg_executables_lists_only_executable_files() {
+        use std::fs;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let bin = tmp.path().join("bin");
+        fs::create_dir_all(bin.join("subdir")).unwrap();
+
+        for (name, mode) in [("zed", 0o755), ("alpha", 0o555), ("notes.txt", 0o644)] {
+            fs::write(bin.join(name), b"#!/bin/sh\n").unwrap();
+            fs::set_permissions(bin.join(name), fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        assert_eq!(
+            keg_executables(tmp.path()),
+            [bin.join("alpha"), bin.join("zed")]
+        );
+        assert!(keg_executables(&tmp.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn format_iso8601_uses_utc() {
+        assert_eq!(format_iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_iso8601(1_714_564_800), "2024-05-01T12:00:00Z");
+    }
+
+    #[test]
+    fn parse_byte_rate_accepts_suffixes() {
+        assert_eq!(parse_byte_rate("1500").unwrap(), 1500);
+        assert_eq!(parse_byte_rate("500k").unwrap(), 500 * 1024);
+        assert_eq!(parse_byte_rate("2M").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_byte_rate("1g").unwrap(), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn parse_byte_rate_rejects_garbage() {
+        assert!(parse_byte_rate("").is_err());
+        assert!(parse_byte_rate("0").is_err());
+        assert!(parse_byte_rate("fast").is_err());
+        assert!(parse_byte_rate("10x").is_err());
+    }
+
+    #[test]
+    fn parse_duration_accepts_suffixes() {
+        use std::time::Duration;
+        assert_eq!(parse_duration("300").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("300s").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1H").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("5d").is_err());
+    }
+
+    #[test]
+    fn parse_byte_size_reports_size_errors() {
+        assert_eq!(parse_byte_size("10G").unwrap(), 10 * 1024 * 1024 * 1024);
+        assert_eq!(
+            parse_byte_size("0").unwrap_err(),
+            "size must be greater than zero"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::platform::Platform;
 use crate::{Error, Formula};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -5,114 +6,34 @@ pub struct SelectedBottle {
     pub tag: String,
     pub url: String,
     pub sha256: String,
+    pub size: Option<u64>,
 }
 
+/// Pick the best bottle for the running platform
 pub fn select_bottle(formula: &Formula) -> Result<SelectedBottle, Error> {
-    // Prefer macOS ARM bottles in order of preference (newest first)
-    #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-    {
-        let macos_tags = [
-            "arm64_tahoe",
-            "arm64_sequoia",
-            "arm64_sonoma",
-            "arm64_ventura",
-        ];
-
-        for preferred_tag in macos_tags {
-            if let Some(file) = formula.bottle.stable.files.get(preferred_tag) {
-                return Ok(SelectedBottle {
-                    tag: preferred_tag.to_string(),
-                    url: file.url.clone(),
-                    sha256: file.sha256.clone(),
-                });
-            }
-        }
-    }
-
-    // Prefer macOS Intel bottles in order of preference (newest first)
-    #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
-    {
-        let macos_tags = [
-            "x86_64_tahoe",
-            "x86_64_sequoia",
-            "x86_64_sonoma",
-            "x86_64_ventura",
-        ];
-
-        for preferred_tag in macos_tags {
-            if let Some(file) = formula.bottle.stable.files.get(preferred_tag) {
-                return Ok(SelectedBottle {
-                    tag: preferred_tag.to_string(),
-                    url: file.url.clone(),
-                    sha256: file.sha256.clone(),
-                });
-            }
-        }
-    }
+    select_bottle_for(formula, &Platform::current().bottle_tags())
+}
 
-    // Prefer Linux x86_64 bottles
-    #[cfg(target_os = "linux")]
-    {
-        let linux_tags = ["x86_64_linux"];
-        for preferred_tag in linux_tags {
-            if let Some(file) = formula.bottle.stable.files.get(preferred_tag) {
-                return Ok(SelectedBottle {
-                    tag: preferred_tag.to_string(),
+/// Pick the first of `tags` that the formula has a bottle for
+pub fn select_bottle_for(formula: &Formula, tags: &[String]) -> Result<SelectedBottle, Error> {
+    tags.iter()
+        .find_map(|tag| {
+            formula
+                .bottle
+                .stable
+                .files
+                .get(tag)
+                .map(|file| SelectedBottle {
+                    tag: tag.clone(),
                     url: file.url.clone(),
                     sha256: file.sha256.clone(),
-                });
-            }
-        }
-    }
-
-    // Check for universal "all" bottle (platform-independent packages like ca-certificates)
-    if let Some(file) = formula.bottle.stable.files.get("all") {
-        return Ok(SelectedBottle {
-            tag: "all".to_string(),
-            url: file.url.clone(),
-            sha256: file.sha256.clone(),
-        });
-    }
-
-    // Fallback: any arm64 macOS bottle (but not linux)
-    #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-    for (tag, file) in &formula.bottle.stable.files {
-        if tag.starts_with("arm64_") && !tag.contains("linux") {
-            return Ok(SelectedBottle {
-                tag: tag.clone(),
-                url: file.url.clone(),
-                sha256: file.sha256.clone(),
-            });
-        }
-    }
-
-    // Fallback: any x86_64 macOS bottle (but not linux)
-    #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
-    for (tag, file) in &formula.bottle.stable.files {
-        if tag.starts_with("x86_64_") && !tag.contains("linux") {
-            return Ok(SelectedBottle {
-                tag: tag.clone(),
-                url: file.url.clone(),
-                sha256: file.sha256.clone(),
-            });
-        }
-    }
-
-    // Fallback for Linux: any linux bottle
-    #[cfg(target_os = "linux")]
-    for (tag, file) in &formula.bottle.stable.files {
-        if tag.contains("linux") {
-            return Ok(SelectedBottle {
-                tag: tag.clone(),
-                url: file.url.clone(),
-                sha256: file.sha256.clone(),
-            });
-        }
-    }
-
-    Err(Error::UnsupportedBottle {
-        name: formula.name.clone(),
-    })
+                    size: file.size,
+                })
+        })
+        .ok_or_else(|| Error::NoBottleForPlatform {
+            name: formula.name.clone(),
+            tags_tried: tags.to_vec(),
+        })
 }
 
 #[cfg(test)]
@@ -177,6 +98,7 @@ mod tests {
                 url: "https://ghcr.io/v2/homebrew/core/ca-certificates/blobs/sha256:abc123"
                     .to_string(),
                 sha256: "abc123".to_string(),
+                size: None,
             },
         );
 
@@ -186,10 +108,19 @@ mod tests {
                 stable: "2024-01-01".to_string(),
             },
             dependencies: Vec::new(),
+            build_dependencies: Vec::new(),
             bottle: Bottle {
                 stable: BottleStable { files, rebuild: 0 },
             },
             revision: 0,
+            keg_only: false,
+            keg_only_reason: None,
+            caveats: None,
+            desc: None,
+            homepage: None,
+            license: None,
+            requires_license_acceptance: false,
+            tap: None,
         };
 
         let selected = select_bottle(&formula).unwrap();
@@ -207,6 +138,7 @@ mod tests {
                 url: "https://example.com/legacy.tar.gz".to_string(),
                 sha256: "cccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc"
                     .to_string(),
+                size: None,
             },
         );
 
@@ -216,16 +148,25 @@ mod tests {
                 stable: "0.1.0".to_string(),
             },
             dependencies: Vec::new(),
+            build_dependencies: Vec::new(),
             bottle: Bottle {
                 stable: BottleStable { files, rebuild: 0 },
             },
             revision: 0,
+            keg_only: false,
+            keg_only_reason: None,
+            caveats: None,
+            desc: None,
+            homepage: None,
+            license: None,
+            requires_license_acceptance: false,
+            tap: None,
         };
 
         let err = select_bottle(&formula).unwrap_err();
         assert!(matches!(
             err,
-            Error::UnsupportedBottle { name } if name == "legacy"
+            Error::NoBottleForPlatform { name, .. } if name == "legacy"
         ));
     }
 
@@ -239,6 +180,7 @@ mod tests {
                 url: "https://example.com/legacy.tar.gz".to_string(),
                 sha256: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
                     .to_string(),
+                size: None,
             },
         );
 
@@ -248,16 +190,25 @@ mod tests {
                 stable: "0.1.0".to_string(),
             },
             dependencies: Vec::new(),
+            build_dependencies: Vec::new(),
             bottle: Bottle {
                 stable: BottleStable { files, rebuild: 0 },
             },
             revision: 0,
+            keg_only: false,
+            keg_only_reason: None,
+            caveats: None,
+            desc: None,
+            homepage: None,
+            license: None,
+            requires_license_acceptance: false,
+            tap: None,
         };
 
         let err = select_bottle(&formula).unwrap_err();
         assert!(matches!(
             err,
-            Error::UnsupportedBottle { name } if name == "legacy"
+            Error::NoBottleForPlatform { name, .. } if name == "legacy"
         ));
     }
 }
@@ -0,0 +1,140 @@
+use std::fmt;
+
+use crate::Version;
+
+/// What a non-runtime dependency is needed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+    Build,
+    Test,
+    Optional,
+    Recommended,
+}
+
+/// A parsed dependency entry such as `zlib`, `cmake :build` or `openssl@3 >= 3.2`.
+/// A spec with no kind is a plain runtime dependency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencySpec {
+    pub name: String,
+    pub kind: Option<DependencyKind>,
+    pub requirement: Option<VersionReq>,
+}
+
+impl DependencySpec {
+    pub fn parse(spec: &str) -> Self {
+        let mut tokens = spec.split_whitespace();
+        let name = tokens.next().unwrap_or_default().to_string();
+        let mut kind = None;
+        let mut requirement = None;
+
+        while let Some(token) = tokens.next() {
+            match token {
+                ":build" => kind = Some(DependencyKind::Build),
+                ":test" => kind = Some(DependencyKind::Test),
+                ":optional" => kind = Some(DependencyKind::Optional),
+                ":recommended" => kind = Some(DependencyKind::Recommended),
+                _ => {
+                    if let Some(op) = Op::parse(token)
+                        && let Some(version) = tokens.next()
+                    {
+                        requirement = Some(VersionReq {
+                            op,
+                            version: Version::parse(version),
+                        });
+                    }
+                }
+            }
+        }
+
+        Self {
+            name,
+            kind,
+            requirement,
+        }
+    }
+
+    /// Whether a bottle install needs this dependency. Recommended dependencies
+    /// are on by default in Homebrew, so they count too.
+    pub fn is_runtime(&self) -> bool {
+        matches!(self.kind, None | Some(DependencyKind::Recommended))
+    }
+}
+
+/// A version requirement like `>= 3.2` attached to a dependency
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    op: Op,
+    version: Version,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Op {
+    fn parse(token: &str) -> Option<Self> {
+        match token {
+            "=" | "==" => Some(Op::Eq),
+            ">" => Some(Op::Gt),
+            ">=" => Some(Op::Ge),
+            "<" => Some(Op::Lt),
+            "<=" => Some(Op::Le),
+            _ => None,
+        }
+    }
+}
+
+impl VersionReq {
+    pub fn matches(&self, version: &Version) -> bool {
+        match self.op {
+            Op::Eq => version == &self.version,
+            Op::Gt => version > &self.version,
+            Op::Ge => version >= &self.version,
+            Op::Lt => version < &self.version,
+            Op::Le => version <= &self.version,
+        }
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            Op::Eq => "=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+        };
+        write!(f, "{op} {}", self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bare_names_tags_and_requirements() {
+        let bare = DependencySpec::parse("zlib");
+        assert_eq!(bare.name, "zlib");
+        assert_eq!(bare.kind, None);
+        assert!(bare.is_runtime());
+
+        let build = DependencySpec::parse("cmake :build");
+        assert_eq!(build.name, "cmake");
+        assert_eq!(build.kind, Some(DependencyKind::Build));
+        assert!(!build.is_runtime());
+
+        let constrained = DependencySpec::parse("openssl@3 >= 3.2");
+        assert_eq!(constrained.name, "openssl@3");
+        let req = constrained.requirement.unwrap();
+        assert!(req.matches(&Version::parse("3.2.1")));
+        assert!(!req.matches(&Version::parse("3.1.9")));
+        assert_eq!(req.to_string(), ">= 3.2");
+    }
+}
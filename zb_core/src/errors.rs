@@ -3,18 +3,115 @@ use std::path::PathBuf;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
-    UnsupportedBottle { name: String },
-    ChecksumMismatch { expected: String, actual: String },
-    LinkConflict { path: PathBuf },
-    StoreCorruption { message: String },
-    NetworkFailure { message: String },
-    MissingFormula { name: String },
-    UnsupportedTap { name: String },
-    DependencyCycle { cycle: Vec<String> },
-    NotInstalled { name: String },
-    FileError { message: String },
-    InvalidArgument { message: String },
-    ExecutionError { message: String },
+    UnsupportedBottle {
+        name: String,
+    },
+    NoBottleForPlatform {
+        name: String,
+        tags_tried: Vec<String>,
+    },
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+    LinkConflict {
+        path: PathBuf,
+        /// Formula already linked at `path`, or `None` for a file zerobrew doesn't manage
+        existing: Option<String>,
+        incoming: String,
+    },
+    StoreCorruption {
+        message: String,
+    },
+    NetworkFailure {
+        message: String,
+    },
+    /// The server answered with a non-success status
+    HttpStatus {
+        code: u16,
+    },
+    /// A request or body read took longer than the client allows
+    Timeout,
+    /// The connection dropped before the response finished
+    ConnectionReset,
+    /// `url` kept redirecting past the client's limit of `limit` hops,
+    /// most likely in a loop
+    TooManyRedirects {
+        url: String,
+        limit: usize,
+    },
+    /// A request to the bottle mirror at `from` was redirected to another
+    /// host, which the redirect policy forbids
+    CrossHostRedirect {
+        from: String,
+        to: String,
+    },
+    MissingFormula {
+        name: String,
+        /// Known formula names close to `name`, best first
+        suggestions: Vec<String>,
+    },
+    UnsupportedTap {
+        name: String,
+    },
+    /// `name@version` matched neither a versioned formula nor the current
+    /// stable version of `name`
+    VersionUnavailable {
+        name: String,
+        version: String,
+        available: String,
+    },
+    /// `name` was requested at HEAD, which only a source build can provide
+    HeadUnsupported {
+        name: String,
+    },
+    /// What the formula API serves for `name` differs from what a lockfile
+    /// recorded; `locked` and `served` describe each side
+    LockMismatch {
+        name: String,
+        locked: String,
+        served: String,
+    },
+    /// `name` needs its license accepted before it can be installed
+    LicenseNotAccepted {
+        name: String,
+        license: Option<String>,
+    },
+    DependencyCycle {
+        cycle: Vec<String>,
+    },
+    NotInstalled {
+        name: String,
+    },
+    FileError {
+        message: String,
+    },
+    InvalidArgument {
+        message: String,
+    },
+    ExecutionError {
+        message: String,
+    },
+    ExtractionError {
+        message: String,
+    },
+    DependencyConflict {
+        name: String,
+        dependents: Vec<String>,
+    },
+    OfflineMiss {
+        resource: String,
+    },
+    SignatureInvalid {
+        resource: String,
+        message: String,
+    },
+    DependencyFailed {
+        name: String,
+        dependency: String,
+    },
+    /// Interrupted by the user before it finished
+    Cancelled,
 }
 
 impl fmt::Display for Error {
@@ -23,21 +120,84 @@ impl fmt::Display for Error {
             Error::UnsupportedBottle { name } => {
                 write!(f, "unsupported bottle for formula '{name}'")
             }
+            Error::NoBottleForPlatform { name, tags_tried } => {
+                let rendered = tags_tried.join(", ");
+                write!(
+                    f,
+                    "no bottle of formula '{name}' fits this platform (tried {rendered})"
+                )
+            }
             Error::ChecksumMismatch { expected, actual } => {
                 write!(f, "checksum mismatch (expected {expected}, got {actual})")
             }
-            Error::LinkConflict { path } => {
-                write!(f, "link conflict at '{}'", path.to_string_lossy())
+            Error::LinkConflict {
+                path,
+                existing,
+                incoming,
+            } => {
+                let owner = match existing {
+                    Some(existing) => format!("already linked by '{existing}'"),
+                    None => "an existing file is in the way".to_string(),
+                };
+                write!(
+                    f,
+                    "link conflict at '{}' installing '{incoming}': {owner}",
+                    path.to_string_lossy()
+                )
             }
             Error::StoreCorruption { message } => write!(f, "store corruption: {message}"),
             Error::NetworkFailure { message } => write!(f, "network failure: {message}"),
-            Error::MissingFormula { name } => write!(f, "missing formula '{name}'"),
+            Error::HttpStatus { code } => write!(f, "server returned HTTP {code}"),
+            Error::Timeout => write!(f, "network request timed out"),
+            Error::ConnectionReset => {
+                write!(f, "connection was reset before the download finished")
+            }
+            Error::TooManyRedirects { url, limit } => {
+                write!(f, "too many redirects (more than {limit}) fetching '{url}'")
+            }
+            Error::CrossHostRedirect { from, to } => {
+                write!(
+                    f,
+                    "refused redirect from mirror '{from}' to another host: '{to}'"
+                )
+            }
+            Error::MissingFormula { name, .. } => write!(f, "missing formula '{name}'"),
             Error::UnsupportedTap { name } => {
                 write!(
                     f,
-                    "tap formula '{name}' is not supported (only homebrew/core)"
+                    "tap formula '{name}' is not supported (only homebrew/core and configured taps)"
                 )
             }
+            Error::VersionUnavailable {
+                name,
+                version,
+                available,
+            } => write!(
+                f,
+                "no bottle of '{name}' at version {version} (there is no '{name}@{version}' formula and the current version is {available})"
+            ),
+            Error::HeadUnsupported { name } => write!(
+                f,
+                "'{name}' can't be installed from HEAD: zerobrew only installs prebuilt bottles"
+            ),
+            Error::LockMismatch {
+                name,
+                locked,
+                served,
+            } => write!(
+                f,
+                "'{name}' doesn't match the lockfile: locked {locked}, but the API serves {served}"
+            ),
+            Error::LicenseNotAccepted { name, license } => match license {
+                Some(license) => write!(
+                    f,
+                    "'{name}' requires accepting its license ({license}) before installing"
+                ),
+                None => write!(
+                    f,
+                    "'{name}' requires accepting its license before installing"
+                ),
+            },
             Error::DependencyCycle { cycle } => {
                 let rendered = cycle.join(" -> ");
                 write!(f, "dependency cycle detected: {rendered}")
@@ -46,6 +206,36 @@ impl fmt::Display for Error {
             Error::FileError { message } => write!(f, "file error: {message}"),
             Error::InvalidArgument { message } => write!(f, "invalid argument: {message}"),
             Error::ExecutionError { message } => write!(f, "{message}"),
+            Error::ExtractionError { message } => write!(f, "extraction failed: {message}"),
+            Error::DependencyConflict { name, dependents } => {
+                let rendered = dependents.join(", ");
+                write!(f, "formula '{name}' is required by: {rendered}")
+            }
+            Error::OfflineMiss { resource } => {
+                write!(f, "'{resource}' is not cached and offline mode is on")
+            }
+            Error::SignatureInvalid { resource, message } => {
+                write!(f, "signature check failed for '{resource}': {message}")
+            }
+            Error::DependencyFailed { name, dependency } => {
+                write!(
+                    f,
+                    "skipped '{name}' because its dependency '{dependency}' failed"
+                )
+            }
+            Error::Cancelled => write!(f, "install cancelled"),
+        }
+    }
+}
+
+impl Error {
+    /// Whether trying the same request again could succeed: dropped
+    /// connections, timeouts and 5xx responses may; client errors won't.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::HttpStatus { code } => *code >= 500,
+            Error::Timeout | Error::ConnectionReset => true,
+            _ => false,
         }
     }
 }
@@ -64,4 +254,15 @@ mod tests {
 
         assert!(err.to_string().contains("libheif"));
     }
+
+    #[test]
+    fn only_server_side_http_errors_are_transient() {
+        assert!(Error::HttpStatus { code: 503 }.is_transient());
+        assert!(!Error::HttpStatus { code: 404 }.is_transient());
+        assert!(Error::Timeout.is_transient());
+        assert_eq!(
+            Error::HttpStatus { code: 404 }.to_string(),
+            "server returned HTTP 404"
+        );
+    }
 }
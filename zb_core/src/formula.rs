@@ -1,14 +1,43 @@
 use serde::Deserialize;
 use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::dependency::{DependencyKind, DependencySpec};
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct Formula {
     pub name: String,
     pub versions: Versions,
     pub dependencies: Vec<String>,
+    /// Needed only to build from source; bottles don't require them
+    #[serde(default)]
+    pub build_dependencies: Vec<String>,
     pub bottle: Bottle,
     #[serde(default)]
     pub revision: u32,
+    /// Installed into the cellar but never linked into the prefix
+    #[serde(default)]
+    pub keg_only: bool,
+    #[serde(default)]
+    pub keg_only_reason: Option<KegOnlyReason>,
+    /// Notes to show the user after installing, as Homebrew does
+    #[serde(default)]
+    pub caveats: Option<String>,
+    #[serde(default)]
+    pub desc: Option<String>,
+    #[serde(default)]
+    pub homepage: Option<String>,
+    /// SPDX license expression, e.g. `MIT`
+    #[serde(default)]
+    pub license: Option<String>,
+    /// The license must be accepted explicitly (`--accept-license`) before
+    /// installing. Homebrew never sets this; taps and formula files can.
+    #[serde(default)]
+    pub requires_license_acceptance: bool,
+    /// The tap (`user/repo`) the formula came from, or `None` for
+    /// homebrew/core. Set by whoever resolved the name, never by the JSON.
+    #[serde(skip)]
+    pub tap: Option<String>,
 }
 
 impl Formula {
@@ -22,6 +51,58 @@ impl Formula {
             self.versions.stable.clone()
         }
     }
+
+    /// Every dependency, parsed. Entries in `build_dependencies` are always build-only.
+    pub fn dependency_specs(&self) -> Vec<DependencySpec> {
+        let build = self.build_dependencies.iter().map(|dep| DependencySpec {
+            kind: Some(DependencyKind::Build),
+            ..DependencySpec::parse(dep)
+        });
+        self.dependencies
+            .iter()
+            .map(|dep| DependencySpec::parse(dep))
+            .chain(build)
+            .collect()
+    }
+
+    /// The dependencies an install pulls in: runtime ones, plus build-only
+    /// ones when `include_build` is set. Test and optional ones never are.
+    pub fn install_dependencies(&self, include_build: bool) -> Vec<DependencySpec> {
+        self.dependency_specs()
+            .into_iter()
+            .filter(|dep| {
+                dep.is_runtime() || (include_build && dep.kind == Some(DependencyKind::Build))
+            })
+            .collect()
+    }
+}
+
+/// Why a formula is keg-only, e.g. `:provided_by_macos` or `:versioned_formula`
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct KegOnlyReason {
+    pub reason: String,
+    #[serde(default)]
+    pub explanation: String,
+}
+
+impl fmt::Display for KegOnlyReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.explanation.is_empty() {
+            return f.write_str(&self.explanation);
+        }
+        f.write_str(&self.reason.trim_start_matches(':').replace('_', " "))
+    }
+}
+
+/// An entry in the formula index: just enough to search and compare versions
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct FormulaSummary {
+    pub name: String,
+    #[serde(default)]
+    pub desc: Option<String>,
+    pub versions: Versions,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -47,6 +128,9 @@ pub struct BottleStable {
 pub struct BottleFile {
     pub url: String,
     pub sha256: String,
+    /// Download size in bytes, when the API provides it
+    #[serde(default)]
+    pub size: Option<u64>,
 }
 
 #[cfg(test)]
@@ -107,4 +191,24 @@ mod tests {
         let formula: Formula = serde_json::from_str(fixture).unwrap();
         assert_eq!(formula.revision, 0);
     }
+
+    #[test]
+    fn keg_only_reason_is_parsed_and_displayed() {
+        let mut value: serde_json::Value =
+            serde_json::from_str(include_str!("../fixtures/formula_foo.json")).unwrap();
+        value["keg_only"] = serde_json::json!(true);
+        value["keg_only_reason"] =
+            serde_json::json!({ "reason": ":provided_by_macos", "explanation": "" });
+        let formula: Formula = serde_json::from_value(value).unwrap();
+
+        assert!(formula.keg_only);
+        let reason = formula.keg_only_reason.unwrap();
+        assert_eq!(reason.to_string(), "provided by macos");
+
+        let explained = KegOnlyReason {
+            reason: ":versioned_formula".to_string(),
+            explanation: "this is an alternate version".to_string(),
+        };
+        assert_eq!(explained.to_string(), "this is an alternate version");
+    }
 }
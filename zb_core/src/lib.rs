@@ -1,11 +1,17 @@
 pub mod bottle;
 pub mod context;
+pub mod dependency;
 pub mod errors;
 pub mod formula;
+pub mod platform;
 pub mod resolve;
+pub mod version;
 
-pub use bottle::{SelectedBottle, select_bottle};
+pub use bottle::{SelectedBottle, select_bottle, select_bottle_for};
 pub use context::{ConcurrencyLimits, Context, LogLevel, LoggerHandle, Paths};
+pub use dependency::{DependencyKind, DependencySpec, VersionReq};
 pub use errors::Error;
 pub use formula::Formula;
-pub use resolve::resolve_closure;
+pub use platform::{Arch, Os, Platform};
+pub use resolve::{resolve_closure, resolve_closure_with};
+pub use version::Version;
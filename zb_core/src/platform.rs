@@ -0,0 +1,132 @@
+use std::sync::OnceLock;
+
+/// macOS releases that have bottles, newest first, by major version
+const MACOS_RELEASES: [(u32, &str); 6] = [
+    (26, "tahoe"),
+    (15, "sequoia"),
+    (14, "sonoma"),
+    (13, "ventura"),
+    (12, "monterey"),
+    (11, "big_sur"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    Arm64,
+    X86_64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Os {
+    /// macOS with the given major version, e.g. 15 for Sequoia
+    MacOs(u32),
+    Linux,
+}
+
+/// The OS and CPU a bottle has to run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Platform {
+    pub os: Os,
+    pub arch: Arch,
+}
+
+impl Platform {
+    /// The platform zerobrew is running on, detected once per process
+    pub fn current() -> Self {
+        static CURRENT: OnceLock<Platform> = OnceLock::new();
+        *CURRENT.get_or_init(|| {
+            let arch = if cfg!(target_arch = "aarch64") {
+                Arch::Arm64
+            } else {
+                Arch::X86_64
+            };
+            let os = if cfg!(target_os = "macos") {
+                Os::MacOs(macos_major_version().unwrap_or(MACOS_RELEASES[0].0))
+            } else {
+                Os::Linux
+            };
+            Self { os, arch }
+        })
+    }
+
+    /// Bottle tags that run here, best first. macOS bottles built for the
+    /// running release or any older one work; newer ones may not.
+    pub fn bottle_tags(&self) -> Vec<String> {
+        let mut tags = Vec::new();
+        match self.os {
+            Os::MacOs(major) => {
+                for (_, name) in MACOS_RELEASES.iter().filter(|(v, _)| *v <= major) {
+                    match self.arch {
+                        Arch::Arm64 => tags.push(format!("arm64_{name}")),
+                        // Intel bottles are tagged both ways across Homebrew's history
+                        Arch::X86_64 => {
+                            tags.push(format!("x86_64_{name}"));
+                            tags.push(name.to_string());
+                        }
+                    }
+                }
+            }
+            Os::Linux => match self.arch {
+                Arch::Arm64 => tags.push("arm64_linux".to_string()),
+                Arch::X86_64 => tags.push("x86_64_linux".to_string()),
+            },
+        }
+        tags.push("all".to_string());
+        tags
+    }
+}
+
+fn macos_major_version() -> Option<u32> {
+    let output = std::process::Command::new("sw_vers")
+        .arg("-productVersion")
+        .output()
+        .ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    let major: u32 = version.trim().split('.').next()?.parse().ok()?;
+    // Big Sur also reports itself as 10.16 to older tools
+    Some(if major == 10 { 11 } else { major })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_ventura_falls_back_through_older_releases() {
+        let platform = Platform {
+            os: Os::MacOs(13),
+            arch: Arch::X86_64,
+        };
+        assert_eq!(
+            platform.bottle_tags(),
+            [
+                "x86_64_ventura",
+                "ventura",
+                "x86_64_monterey",
+                "monterey",
+                "x86_64_big_sur",
+                "big_sur",
+                "all"
+            ]
+        );
+    }
+
+    #[test]
+    fn apple_silicon_sequoia_skips_newer_releases() {
+        let platform = Platform {
+            os: Os::MacOs(15),
+            arch: Arch::Arm64,
+        };
+        assert_eq!(
+            platform.bottle_tags(),
+            [
+                "arm64_sequoia",
+                "arm64_sonoma",
+                "arm64_ventura",
+                "arm64_monterey",
+                "arm64_big_sur",
+                "all"
+            ]
+        );
+    }
+}
@@ -1,15 +1,26 @@
-use crate::{Error, Formula};
+use crate::{Error, Formula, Version, VersionReq};
 use std::collections::{BTreeMap, BTreeSet};
 
 type InDegreeMap = BTreeMap<String, usize>;
 type AdjacencyMap = BTreeMap<String, BTreeSet<String>>;
 
+/// Every runtime dependency of `roots` (and the roots), dependencies first
 pub fn resolve_closure(
     roots: &[String],
     formulas: &BTreeMap<String, Formula>,
 ) -> Result<Vec<String>, Error> {
-    let closure = compute_closure(roots, formulas)?;
-    let (mut indegree, adjacency) = build_graph(&closure, formulas)?;
+    resolve_closure_with(roots, formulas, false)
+}
+
+/// Like [`resolve_closure`], optionally following build-only dependencies too
+pub fn resolve_closure_with(
+    roots: &[String],
+    formulas: &BTreeMap<String, Formula>,
+    include_build: bool,
+) -> Result<Vec<String>, Error> {
+    let closure = compute_closure(roots, formulas, include_build)?;
+    check_requirements(&closure, formulas, include_build)?;
+    let (mut indegree, adjacency) = build_graph(&closure, formulas, include_build)?;
 
     let mut ready: BTreeSet<String> = indegree
         .iter()
@@ -52,6 +63,7 @@ pub fn resolve_closure(
 fn compute_closure(
     roots: &[String],
     formulas: &BTreeMap<String, Formula>,
+    include_build: bool,
 ) -> Result<BTreeSet<String>, Error> {
     let mut closure = BTreeSet::new();
     let mut stack = roots.to_vec();
@@ -61,11 +73,12 @@ fn compute_closure(
             continue;
         }
 
-        let formula = formulas
-            .get(&name)
-            .ok_or_else(|| Error::MissingFormula { name: name.clone() })?;
+        let formula = formulas.get(&name).ok_or_else(|| Error::MissingFormula {
+            name: name.clone(),
+            suggestions: Vec::new(),
+        })?;
 
-        let mut deps = formula.dependencies.clone();
+        let mut deps = dependency_names(formula, include_build);
         deps.sort();
         for dep in deps {
             // Skip dependencies that aren't in the formulas map
@@ -86,15 +99,17 @@ fn compute_closure(
 fn build_graph(
     closure: &BTreeSet<String>,
     formulas: &BTreeMap<String, Formula>,
+    include_build: bool,
 ) -> Result<(InDegreeMap, AdjacencyMap), Error> {
     let mut indegree: InDegreeMap = closure.iter().map(|name| (name.clone(), 0)).collect();
     let mut adjacency: AdjacencyMap = BTreeMap::new();
 
     for name in closure {
-        let formula = formulas
-            .get(name)
-            .ok_or_else(|| Error::MissingFormula { name: name.clone() })?;
-        let mut deps = formula.dependencies.clone();
+        let formula = formulas.get(name).ok_or_else(|| Error::MissingFormula {
+            name: name.clone(),
+            suggestions: Vec::new(),
+        })?;
+        let mut deps = dependency_names(formula, include_build);
         deps.sort();
         for dep in deps {
             if !closure.contains(&dep) {
@@ -110,6 +125,61 @@ fn build_graph(
     Ok((indegree, adjacency))
 }
 
+/// Each dependency once, even when a formula lists it more than once (say as
+/// both a runtime and a build dependency); a repeated edge would otherwise
+/// leave the dependent's in-degree above zero and look like a cycle
+fn dependency_names(formula: &Formula, include_build: bool) -> Vec<String> {
+    let mut names: Vec<String> = formula
+        .install_dependencies(include_build)
+        .into_iter()
+        .map(|dep| dep.name)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Fail if any formula in the closure asks for a version of a dependency
+/// that the version we would install doesn't satisfy
+fn check_requirements(
+    closure: &BTreeSet<String>,
+    formulas: &BTreeMap<String, Formula>,
+    include_build: bool,
+) -> Result<(), Error> {
+    let mut requirements: BTreeMap<String, Vec<(String, VersionReq)>> = BTreeMap::new();
+    for name in closure {
+        let Some(formula) = formulas.get(name) else {
+            continue;
+        };
+        for dep in formula.install_dependencies(include_build) {
+            if let Some(req) = dep.requirement {
+                requirements
+                    .entry(dep.name)
+                    .or_default()
+                    .push((name.clone(), req));
+            }
+        }
+    }
+
+    for (dep, reqs) in requirements {
+        let Some(formula) = formulas.get(&dep) else {
+            continue;
+        };
+        let available = Version::parse(&formula.effective_version());
+        if reqs.iter().any(|(_, req)| !req.matches(&available)) {
+            return Err(Error::DependencyConflict {
+                name: dep,
+                dependents: reqs
+                    .iter()
+                    .map(|(requirer, req)| format!("{requirer} ({req})"))
+                    .collect(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,6 +193,7 @@ mod tests {
             BottleFile {
                 url: format!("https://example.com/{name}.tar.gz"),
                 sha256: "deadbeef".repeat(8),
+                size: None,
             },
         );
 
@@ -132,10 +203,19 @@ mod tests {
                 stable: "1.0.0".to_string(),
             },
             dependencies: deps.iter().map(|dep| dep.to_string()).collect(),
+            build_dependencies: Vec::new(),
             bottle: Bottle {
                 stable: BottleStable { files, rebuild: 0 },
             },
             revision: 0,
+            keg_only: false,
+            keg_only_reason: None,
+            caveats: None,
+            desc: None,
+            homepage: None,
+            license: None,
+            requires_license_acceptance: false,
+            tap: None,
         }
     }
 
@@ -187,4 +267,65 @@ mod tests {
         // Should successfully resolve with just git and gettext
         assert_eq!(order, vec!["gettext", "git"]);
     }
+
+    #[test]
+    fn build_and_test_dependencies_are_excluded_by_default() {
+        let mut formulas = BTreeMap::new();
+        let mut app = formula("app", &["lib", "cmake :build", "bats :test"]);
+        app.build_dependencies = vec!["pkgconf".to_string()];
+        formulas.insert("app".to_string(), app);
+        for name in ["lib", "cmake", "bats", "pkgconf"] {
+            formulas.insert(name.to_string(), formula(name, &[]));
+        }
+
+        let order = resolve_closure(&["app".to_string()], &formulas).unwrap();
+        assert_eq!(order, vec!["lib", "app"]);
+
+        let order = resolve_closure_with(&["app".to_string()], &formulas, true).unwrap();
+        assert_eq!(order, vec!["cmake", "lib", "pkgconf", "app"]);
+    }
+
+    #[test]
+    fn diamond_dependency_is_installed_once() {
+        let mut formulas = BTreeMap::new();
+        formulas.insert("top".to_string(), formula("top", &["left", "right"]));
+        formulas.insert("left".to_string(), formula("left", &["base >= 1.0"]));
+        formulas.insert("right".to_string(), formula("right", &["base < 2"]));
+        formulas.insert("base".to_string(), formula("base", &[]));
+
+        let order = resolve_closure(&["top".to_string()], &formulas).unwrap();
+        assert_eq!(order, vec!["base", "left", "right", "top"]);
+    }
+
+    #[test]
+    fn repeated_dependency_entries_are_one_edge() {
+        let mut formulas = BTreeMap::new();
+        let mut app = formula("app", &["lib", "lib >= 1.0"]);
+        app.build_dependencies = vec!["lib".to_string()];
+        formulas.insert("app".to_string(), app);
+        formulas.insert("lib".to_string(), formula("lib", &[]));
+
+        let order = resolve_closure(&["app".to_string()], &formulas).unwrap();
+        assert_eq!(order, vec!["lib", "app"]);
+        let order = resolve_closure_with(&["app".to_string()], &formulas, true).unwrap();
+        assert_eq!(order, vec!["lib", "app"]);
+    }
+
+    #[test]
+    fn incompatible_version_requirements_conflict() {
+        let mut formulas = BTreeMap::new();
+        formulas.insert("top".to_string(), formula("top", &["left", "right"]));
+        formulas.insert("left".to_string(), formula("left", &["base >= 1.0"]));
+        formulas.insert("right".to_string(), formula("right", &["base >= 2.0"]));
+        formulas.insert("base".to_string(), formula("base", &[]));
+
+        let err = resolve_closure(&["top".to_string()], &formulas).unwrap_err();
+        assert_eq!(
+            err,
+            Error::DependencyConflict {
+                name: "base".to_string(),
+                dependents: vec!["left (>= 1.0)".to_string(), "right (>= 2.0)".to_string()],
+            }
+        );
+    }
 }
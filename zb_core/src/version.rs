@@ -0,0 +1,162 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A Homebrew version such as `1.2.3` or `3.0.13_1`.
+///
+/// Numeric components compare numerically (so `1.10` > `1.9`), a trailing
+/// `_N` revision breaks ties between otherwise equal versions, and a
+/// pre-release tag like `rc1` sorts before the release it precedes. Other
+/// letter suffixes sort after it, so `1.1.1` < `1.1.1a` < `1.1.1w`.
+#[derive(Debug, Clone)]
+pub struct Version {
+    raw: String,
+    segments: Vec<Segment>,
+    revision: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Number(u64),
+    Text(String),
+}
+
+impl Segment {
+    /// Whether this is a tag that marks a version before its release
+    fn is_prerelease(&self) -> bool {
+        matches!(self, Segment::Text(tag) if PRERELEASE_TAGS.contains(&tag.as_str()))
+    }
+}
+
+const PRERELEASE_TAGS: &[&str] = &["alpha", "beta", "rc", "pre", "dev"];
+
+impl Version {
+    pub fn parse(raw: &str) -> Self {
+        let (base, revision) = match raw.rsplit_once('_') {
+            Some((base, rev)) if !rev.is_empty() && rev.bytes().all(|b| b.is_ascii_digit()) => {
+                (base, rev.parse().unwrap_or(0))
+            }
+            _ => (raw, 0),
+        };
+
+        let mut segments = Vec::new();
+        for part in base.split(['.', '-', '+']) {
+            let mut rest = part;
+            while let Some(first) = rest.chars().next() {
+                let is_digit = first.is_ascii_digit();
+                let end = rest
+                    .find(|c: char| c.is_ascii_digit() != is_digit)
+                    .unwrap_or(rest.len());
+                let (token, tail) = rest.split_at(end);
+                segments.push(match token.parse() {
+                    Ok(n) if is_digit => Segment::Number(n),
+                    _ => Segment::Text(token.to_ascii_lowercase()),
+                });
+                rest = tail;
+            }
+        }
+
+        Self {
+            raw: raw.to_string(),
+            segments,
+            revision,
+        }
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.segments.len().max(other.segments.len());
+        for i in 0..len {
+            let ordering = match (self.segments.get(i), other.segments.get(i)) {
+                (Some(Segment::Number(a)), Some(Segment::Number(b))) => a.cmp(b),
+                (Some(Segment::Text(a)), Some(Segment::Text(b))) => a.cmp(b),
+                (Some(Segment::Number(_)), Some(Segment::Text(_))) => Ordering::Greater,
+                (Some(Segment::Text(_)), Some(Segment::Number(_))) => Ordering::Less,
+                // Missing trailing components count as zero: 1.2 == 1.2.0
+                (Some(Segment::Number(n)), None) => n.cmp(&0),
+                (None, Some(Segment::Number(n))) => 0.cmp(n),
+                // A pre-release tag sorts before the release, any other
+                // suffix after it: 1.0rc1 < 1.0 < 1.0a
+                (Some(tag @ Segment::Text(_)), None) if tag.is_prerelease() => Ordering::Less,
+                (Some(Segment::Text(_)), None) => Ordering::Greater,
+                (None, Some(tag @ Segment::Text(_))) if tag.is_prerelease() => Ordering::Greater,
+                (None, Some(Segment::Text(_))) => Ordering::Less,
+                (None, None) => Ordering::Equal,
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        self.revision.cmp(&other.revision)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s)
+    }
+
+    #[test]
+    fn numeric_components_compare_numerically() {
+        assert!(v("1.10") > v("1.9"));
+        assert!(v("2.0.0") > v("1.99.99"));
+        assert_eq!(v("1.2"), v("1.2.0"));
+    }
+
+    #[test]
+    fn revision_suffix_breaks_ties() {
+        assert!(v("1.2.3_1") > v("1.2.3"));
+        assert!(v("1.2.3_2") > v("1.2.3_1"));
+        assert!(v("1.2.4") > v("1.2.3_5"));
+        assert_eq!(v("3.0.13_1").revision(), 1);
+    }
+
+    #[test]
+    fn prerelease_tags_sort_before_release() {
+        assert!(v("1.0rc1") < v("1.0"));
+        assert!(v("1.0rc2") > v("1.0rc1"));
+        assert!(v("9e") > v("9d"));
+        assert!(v("2.0beta") < v("2.0"));
+        assert!(v("2.0") > v("2.0-dev"));
+    }
+
+    #[test]
+    fn letter_suffixes_sort_after_release() {
+        assert!(v("1.1.1") < v("1.1.1a"));
+        assert!(v("1.1.1a") < v("1.1.1w"));
+        assert!(v("1.1.1w") > v("1.1.1"));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        assert_eq!(v("2024-01-05_1").to_string(), "2024-01-05_1");
+    }
+}
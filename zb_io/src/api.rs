@@ -1,10 +1,66 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Semaphore;
+
 use crate::cache::{ApiCache, CacheEntry};
+use crate::download::{
+    DEFAULT_CONNECT_TIMEOUT, DEFAULT_READ_TIMEOUT, RedirectPolicy, RetryPolicy, is_retryable,
+    request_error, status_error, with_proxy,
+};
+use zb_core::formula::FormulaSummary;
 use zb_core::{Error, Formula};
 
+/// Cached API responses younger than this are used without asking the server
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
 pub struct ApiClient {
     base_url: String,
     client: reqwest::Client,
     cache: Option<ApiCache>,
+    semaphore: Option<Arc<Semaphore>>,
+    offline: bool,
+    no_cache: bool,
+    cache_ttl: Duration,
+    retry_policy: RetryPolicy,
+    local: HashMap<String, Formula>,
+    taps: HashMap<String, String>,
+    timeout: Duration,
+    proxy: Option<reqwest::Proxy>,
+    redirect_policy: RedirectPolicy,
+}
+
+fn http_client(
+    timeout: Duration,
+    proxy: Option<&reqwest::Proxy>,
+    redirect_policy: RedirectPolicy,
+) -> reqwest::Client {
+    // Use HTTP/2 with connection pooling for better multiplexing of parallel requests
+    let builder = reqwest::Client::builder()
+        .user_agent("zerobrew/0.1")
+        .redirect(redirect_policy.to_reqwest(None))
+        .connect_timeout(DEFAULT_CONNECT_TIMEOUT.min(timeout))
+        .read_timeout(timeout)
+        .pool_max_idle_per_host(20);
+    with_proxy(builder, proxy)
+        .build()
+        .unwrap_or_else(|_| reqwest::Client::new())
+}
+
+/// The tap a `user/repo/formula` name refers to, or `None` for plain names
+/// and homebrew/core
+pub(crate) fn tap_of(name: &str) -> Option<&str> {
+    name.rsplit_once('/')
+        .map(|(tap, _)| tap)
+        .filter(|tap| *tap != "homebrew/core")
+}
+
+fn parse_formula(body: &str) -> Result<Formula, Error> {
+    serde_json::from_str(body).map_err(|e| Error::NetworkFailure {
+        message: format!("failed to parse formula JSON: {e}"),
+    })
 }
 
 impl ApiClient {
@@ -13,65 +69,252 @@ impl ApiClient {
     }
 
     pub fn with_base_url(base_url: String) -> Self {
-        // Use HTTP/2 with connection pooling for better multiplexing of parallel requests
-        let client = reqwest::Client::builder()
-            .user_agent("zerobrew/0.1")
-            .pool_max_idle_per_host(20)
-            .build()
-            .unwrap_or_else(|_| reqwest::Client::new());
-
         Self {
             base_url,
-            client,
+            client: http_client(DEFAULT_READ_TIMEOUT, None, RedirectPolicy::default()),
             cache: None,
+            semaphore: None,
+            offline: false,
+            no_cache: false,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            retry_policy: RetryPolicy::default(),
+            local: HashMap::new(),
+            taps: HashMap::new(),
+            timeout: DEFAULT_READ_TIMEOUT,
+            proxy: None,
+            redirect_policy: RedirectPolicy::default(),
         }
     }
 
+    /// Fetch formulas from `base_url` instead, e.g. a mirror of the Homebrew API
+    pub fn with_api_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
     pub fn with_cache(mut self, cache: ApiCache) -> Self {
         self.cache = Some(cache);
         self
     }
 
-    pub async fn get_formula(&self, name: &str) -> Result<Formula, Error> {
-        let url = format!("{}/{}.json", self.base_url, name);
+    /// How long a cached response is used as-is before being revalidated
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Retry metadata fetches that fail with a 5xx or a connection error,
+    /// as bottle downloads are
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
 
-        let cached_entry = self.cache.as_ref().and_then(|c| c.get(&url));
+    /// Serve every request from the cache and never touch the network
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
 
-        let mut request = self.client.get(&url);
+    /// Always ask the server, ignoring cached responses; fresh ones are still cached
+    pub fn with_no_cache(mut self, no_cache: bool) -> Self {
+        self.no_cache = no_cache;
+        self
+    }
 
-        if let Some(ref entry) = cached_entry {
-            if let Some(ref etag) = entry.etag {
-                request = request.header("If-None-Match", etag.as_str());
+    /// Give up on a request that stalls for longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.client = http_client(timeout, self.proxy.as_ref(), self.redirect_policy);
+        self
+    }
+
+    /// Send every request through `proxy` (see [`crate::parse_proxy`])
+    /// instead of any proxy configured in the environment
+    pub fn with_proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self.client = http_client(self.timeout, self.proxy.as_ref(), self.redirect_policy);
+        self
+    }
+
+    /// Cap how many redirects a metadata request follows
+    pub fn with_redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect_policy = policy;
+        self.client = http_client(self.timeout, self.proxy.as_ref(), policy);
+        self
+    }
+
+    /// Limit the number of formula fetches that may be in flight at once
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.semaphore = Some(Arc::new(Semaphore::new(concurrency.max(1))));
+        self
+    }
+
+    /// Answer requests for `formula.name` with `formula` instead of asking the API
+    pub fn with_local_formula(mut self, formula: Formula) -> Self {
+        self.local.insert(formula.name.clone(), formula);
+        self
+    }
+
+    /// Fetch `user/repo/formula` names from `base_url`, which serves the
+    /// tap's formula JSON the way the Homebrew API does. Names from taps
+    /// without a mapping are rejected.
+    pub fn with_tap(mut self, tap: &str, base_url: &str) -> Self {
+        self.taps.insert(
+            tap.trim_matches('/').to_string(),
+            base_url.trim_end_matches('/').to_string(),
+        );
+        self
+    }
+
+    /// Whether names from `tap` can be resolved
+    pub fn has_tap(&self, tap: &str) -> bool {
+        tap == "homebrew/core" || self.taps.contains_key(tap)
+    }
+
+    /// The JSON URL for `name`, which may be prefixed with a registered tap
+    fn formula_url(&self, name: &str) -> Result<String, Error> {
+        let Some((tap, formula)) = name.rsplit_once('/') else {
+            return Ok(format!("{}/{}.json", self.base_url, name));
+        };
+        let base_url = if tap == "homebrew/core" {
+            &self.base_url
+        } else {
+            self.taps.get(tap).ok_or_else(|| Error::UnsupportedTap {
+                name: name.to_string(),
+            })?
+        };
+        Ok(format!("{base_url}/{formula}.json"))
+    }
+
+    /// Read a formula JSON file in the same format the API serves
+    pub fn formula_from_file(path: &Path) -> Result<Formula, Error> {
+        let body = std::fs::read_to_string(path).map_err(|e| Error::FileError {
+            message: format!("failed to read {}: {e}", path.display()),
+        })?;
+        parse_formula(&body)
+    }
+
+    pub async fn get_formula(&self, name: &str) -> Result<Formula, Error> {
+        if let Some(formula) = self.local.get(name) {
+            return Ok(formula.clone());
+        }
+
+        let _permit = match &self.semaphore {
+            Some(semaphore) => {
+                Some(
+                    semaphore
+                        .acquire()
+                        .await
+                        .map_err(|e| Error::NetworkFailure {
+                            message: format!("semaphore error: {e}"),
+                        })?,
+                )
             }
-            if let Some(ref last_modified) = entry.last_modified {
-                request = request.header("If-Modified-Since", last_modified.as_str());
+            None => None,
+        };
+
+        let url = self.formula_url(name)?;
+
+        let body = self
+            .fetch_cached(&url)
+            .await?
+            .ok_or_else(|| Error::MissingFormula {
+                name: name.to_string(),
+                suggestions: Vec::new(),
+            })?;
+
+        let mut formula = parse_formula(&body)?;
+        formula.tap = tap_of(name).map(str::to_string);
+        Ok(formula)
+    }
+
+    /// Look up a formula in the local cache only, without touching the network
+    pub fn cached_formula(&self, name: &str) -> Option<Formula> {
+        let url = self.formula_url(name).ok()?;
+        let entry = self.cache.as_ref()?.get(&url)?;
+        let mut formula: Formula = serde_json::from_str(&entry.body).ok()?;
+        formula.tap = tap_of(name).map(str::to_string);
+        Some(formula)
+    }
+
+    /// Fetch the list of every formula (name, description and version).
+    /// Falls back to the last cached copy when the API can't be reached.
+    pub async fn get_formula_index(&self) -> Result<Vec<FormulaSummary>, Error> {
+        let url = format!("{}.json", self.base_url.trim_end_matches('/'));
+
+        let body = match self.fetch_cached(&url).await {
+            Ok(Some(body)) => body,
+            Ok(None) => {
+                return Err(Error::NetworkFailure {
+                    message: "formula index not found (HTTP 404)".to_string(),
+                });
             }
+            Err(e) => match self.cache.as_ref().and_then(|c| c.get(&url)) {
+                Some(entry) => entry.body,
+                None => return Err(e),
+            },
+        };
+
+        serde_json::from_str(&body).map_err(|e| Error::NetworkFailure {
+            message: format!("failed to parse formula index JSON: {e}"),
+        })
+    }
+
+    /// GET `url`, revalidating any cached copy with its ETag/Last-Modified.
+    /// Returns `None` if the server responds 404.
+    async fn fetch_cached(&self, url: &str) -> Result<Option<String>, Error> {
+        let cached = self
+            .cache
+            .as_ref()
+            .filter(|_| !self.no_cache)
+            .and_then(|c| c.get_with_age(url));
+        tracing::debug!(url, cached = cached.is_some(), "fetching formula metadata");
+
+        // Offline, even a stale entry beats nothing
+        if self.offline {
+            return match cached {
+                Some((entry, _)) => Ok(Some(entry.body)),
+                None => Err(Error::OfflineMiss {
+                    resource: url.to_string(),
+                }),
+            };
         }
 
-        let response = request.send().await.map_err(|e| Error::NetworkFailure {
-            message: e.to_string(),
-        })?;
+        let cached_entry = match cached {
+            Some((entry, age)) if age < self.cache_ttl => {
+                tracing::debug!(url, ?age, "formula metadata cache hit");
+                return Ok(Some(entry.body));
+            }
+            Some((entry, _)) => Some(entry),
+            None => None,
+        };
+
+        let mut attempt = 0;
+        let response = loop {
+            match self.send(url, cached_entry.as_ref()).await {
+                Ok(response) => break response,
+                Err(e) if attempt < self.retry_policy.max_retries && is_retryable(&e) => {
+                    tracing::debug!(url, attempt, error = %e, "retrying formula metadata fetch");
+                    tokio::time::sleep(self.retry_policy.delay_for(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
 
         if response.status() == reqwest::StatusCode::NOT_MODIFIED
             && let Some(entry) = cached_entry
         {
-            let formula: Formula =
-                serde_json::from_str(&entry.body).map_err(|e| Error::NetworkFailure {
-                    message: format!("failed to parse cached formula JSON: {e}"),
-                })?;
-            return Ok(formula);
+            tracing::debug!(url, "formula metadata not modified");
+            if let Some(ref cache) = self.cache {
+                let _ = cache.touch(url);
+            }
+            return Ok(Some(entry.body));
         }
 
         if response.status() == reqwest::StatusCode::NOT_FOUND {
-            return Err(Error::MissingFormula {
-                name: name.to_string(),
-            });
-        }
-
-        if !response.status().is_success() {
-            return Err(Error::NetworkFailure {
-                message: format!("HTTP {}", response.status()),
-            });
+            return Ok(None);
         }
 
         let etag = response
@@ -96,14 +339,41 @@ impl ApiClient {
                 last_modified,
                 body: body.clone(),
             };
-            let _ = cache.put(&url, &entry);
+            let _ = cache.put(url, &entry);
         }
 
-        let formula: Formula = serde_json::from_str(&body).map_err(|e| Error::NetworkFailure {
-            message: format!("failed to parse formula JSON: {e}"),
-        })?;
+        Ok(Some(body))
+    }
 
-        Ok(formula)
+    /// One GET of `url`, conditional on `cached` if there is one. Statuses
+    /// other than success, 304 and 404 come back as errors so they can be
+    /// retried.
+    async fn send(
+        &self,
+        url: &str,
+        cached: Option<&CacheEntry>,
+    ) -> Result<reqwest::Response, Error> {
+        let mut request = self.client.get(url);
+
+        if let Some(entry) = cached {
+            if let Some(ref etag) = entry.etag {
+                request = request.header("If-None-Match", etag.as_str());
+            }
+            if let Some(ref last_modified) = entry.last_modified {
+                request = request.header("If-Modified-Since", last_modified.as_str());
+            }
+        }
+
+        let response = request.send().await.map_err(|e| request_error("", e))?;
+        let status = response.status();
+        if status.is_success()
+            || (status == reqwest::StatusCode::NOT_MODIFIED && cached.is_some())
+            || status == reqwest::StatusCode::NOT_FOUND
+        {
+            Ok(response)
+        } else {
+            Err(status_error(status))
+        }
     }
 }
 
@@ -138,6 +408,99 @@ mod tests {
         assert_eq!(formula.versions.stable, "1.2.3");
     }
 
+    #[tokio::test]
+    async fn registered_tap_resolves_from_its_own_base_url() {
+        let core = MockServer::start().await;
+        let tap = MockServer::start().await;
+        let fixture = include_str!("../../zb_core/fixtures/formula_foo.json");
+
+        Mock::given(method("GET"))
+            .and(path("/tools/foo.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fixture))
+            .expect(1)
+            .mount(&tap)
+            .await;
+
+        let client = ApiClient::with_base_url(core.uri())
+            .with_tap("mycompany/tools", &format!("{}/tools/", tap.uri()));
+        let formula = client.get_formula("mycompany/tools/foo").await.unwrap();
+        assert_eq!(formula.name, "foo");
+        assert!(client.has_tap("mycompany/tools"));
+
+        let err = client.get_formula("other/tap/foo").await.unwrap_err();
+        assert!(matches!(err, Error::UnsupportedTap { name } if name == "other/tap/foo"));
+        assert!(core.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn configured_proxy_carries_metadata_requests() {
+        let proxy = MockServer::start().await;
+        let fixture = include_str!("../../zb_core/fixtures/formula_foo.json");
+
+        // A plain HTTP proxy receives the request for the origin's path
+        Mock::given(method("GET"))
+            .and(path("/api/foo.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fixture))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let client = ApiClient::with_base_url("http://formulae.invalid/api".to_string())
+            .with_proxy(crate::download::proxy_bypassing(&proxy.uri(), None).unwrap());
+        let formula = client.get_formula("foo").await.unwrap();
+        assert_eq!(formula.name, "foo");
+
+        let request = &proxy.received_requests().await.unwrap()[0];
+        assert_eq!(request.url.host_str(), Some("formulae.invalid"));
+    }
+
+    fn quick_retries() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_until_the_formula_arrives() {
+        let mock_server = MockServer::start().await;
+        let fixture = include_str!("../../zb_core/fixtures/formula_foo.json");
+
+        Mock::given(method("GET"))
+            .and(path("/foo.json"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/foo.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fixture))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = ApiClient::with_base_url(mock_server.uri()).with_retry_policy(quick_retries());
+        let formula = client.get_formula("foo").await.unwrap();
+        assert_eq!(formula.name, "foo");
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_a_missing_formula() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/nope.json"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = ApiClient::with_base_url(mock_server.uri()).with_retry_policy(quick_retries());
+        let err = client.get_formula("nope").await.unwrap_err();
+        assert!(matches!(err, Error::MissingFormula { .. }));
+    }
+
     #[tokio::test]
     async fn returns_missing_formula_on_404() {
         let mock_server = MockServer::start().await;
@@ -153,7 +516,7 @@ mod tests {
 
         assert!(matches!(
             err,
-            Error::MissingFormula { name } if name == "nonexistent"
+            Error::MissingFormula { name, .. } if name == "nonexistent"
         ));
     }
 
@@ -204,7 +567,10 @@ mod tests {
             .await;
 
         let cache = ApiCache::in_memory().unwrap();
-        let client = ApiClient::with_base_url(mock_server.uri()).with_cache(cache);
+        // Every entry is stale straight away, so each fetch revalidates
+        let client = ApiClient::with_base_url(mock_server.uri())
+            .with_cache(cache)
+            .with_cache_ttl(Duration::ZERO);
 
         // First request
         let _ = client.get_formula("foo").await.unwrap();
@@ -242,7 +608,9 @@ mod tests {
             .await;
 
         let cache = ApiCache::in_memory().unwrap();
-        let client = ApiClient::with_base_url(mock_server.uri()).with_cache(cache);
+        let client = ApiClient::with_base_url(mock_server.uri())
+            .with_cache(cache)
+            .with_cache_ttl(Duration::ZERO);
 
         // First request populates cache
         let _ = client.get_formula("foo").await.unwrap();
@@ -262,4 +630,227 @@ mod tests {
         assert_eq!(formula.name, "foo");
         assert_eq!(formula.versions.stable, "1.2.3");
     }
+
+    #[tokio::test]
+    async fn concurrency_limit_serializes_fetches() {
+        let mock_server = MockServer::start().await;
+        let fixture = include_str!("../../zb_core/fixtures/formula_foo.json");
+
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(fixture)
+                    .set_delay(std::time::Duration::from_millis(100)),
+            )
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let client = ApiClient::with_base_url(mock_server.uri()).with_concurrency(1);
+
+        let start = std::time::Instant::now();
+        let results = futures::future::join_all([
+            client.get_formula("a"),
+            client.get_formula("b"),
+            client.get_formula("c"),
+        ])
+        .await;
+        let elapsed = start.elapsed();
+
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert!(
+            elapsed >= std::time::Duration::from_millis(300),
+            "fetches overlapped despite a limit of 1 ({elapsed:?})"
+        );
+    }
+
+    #[tokio::test]
+    async fn formula_index_is_served_from_cache_when_offline() {
+        let mock_server = MockServer::start().await;
+        let index = r#"[
+            {"name": "jq", "desc": "Lightweight JSON processor", "versions": {"stable": "1.7.1"}},
+            {"name": "wget", "desc": null, "versions": {"stable": "1.24.5"}}
+        ]"#;
+
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(index))
+            .mount(&mock_server)
+            .await;
+
+        let cache = ApiCache::in_memory().unwrap();
+        let client =
+            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).with_cache(cache);
+
+        let formulas = client.get_formula_index().await.unwrap();
+        assert_eq!(formulas.len(), 2);
+        assert_eq!(formulas[0].name, "jq");
+        assert_eq!(formulas[0].versions.stable, "1.7.1");
+        assert_eq!(formulas[1].desc, None);
+
+        // With the server gone, the cached index is still usable
+        drop(mock_server);
+        let formulas = client.get_formula_index().await.unwrap();
+        assert_eq!(formulas.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn cached_formula_reads_without_network() {
+        let mock_server = MockServer::start().await;
+        let fixture = include_str!("../../zb_core/fixtures/formula_foo.json");
+
+        Mock::given(method("GET"))
+            .and(path("/foo.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fixture))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let cache = ApiCache::in_memory().unwrap();
+        let client = ApiClient::with_base_url(mock_server.uri()).with_cache(cache);
+
+        assert!(client.cached_formula("foo").is_none());
+        client.get_formula("foo").await.unwrap();
+
+        let formula = client.cached_formula("foo").unwrap();
+        assert_eq!(formula.name, "foo");
+    }
+
+    #[tokio::test]
+    async fn fresh_entries_skip_the_network_and_stale_ones_revalidate() {
+        let mock_server = MockServer::start().await;
+        let fixture = include_str!("../../zb_core/fixtures/formula_foo.json");
+        let tmp = tempfile::TempDir::new().unwrap();
+        let cache_path = tmp.path().join("api_cache.sqlite3");
+
+        Mock::given(method("GET"))
+            .and(path("/foo.json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(fixture)
+                    .insert_header("etag", "\"abc123\""),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = ApiClient::with_base_url(mock_server.uri())
+            .with_cache(ApiCache::open(&cache_path).unwrap());
+        client.get_formula("foo").await.unwrap();
+        // Within the TTL: served from the cache with no request at all
+        client.get_formula("foo").await.unwrap();
+        mock_server.verify().await;
+        mock_server.reset().await;
+
+        Mock::given(method("GET"))
+            .and(path("/foo.json"))
+            .and(header("If-None-Match", "\"abc123\""))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // A client with a two-second TTL sees the same entry as expired
+        // once it is older than that
+        let expiring = ApiClient::with_base_url(mock_server.uri())
+            .with_cache(ApiCache::open(&cache_path).unwrap())
+            .with_cache_ttl(Duration::from_secs(2));
+        tokio::time::pause();
+        tokio::time::advance(Duration::from_millis(3100)).await;
+        tokio::time::resume();
+        let formula = expiring.get_formula("foo").await.unwrap();
+        assert_eq!(formula.name, "foo");
+
+        // The 304 refreshed the timestamp, so the next fetch stays local
+        expiring.get_formula("foo").await.unwrap();
+        let (_, age) = ApiCache::open(&cache_path)
+            .unwrap()
+            .get_with_age(&format!("{}/foo.json", mock_server.uri()))
+            .unwrap();
+        assert!(age < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn database_backed_cache_round_trips_and_expires_with_the_ttl() {
+        let mock_server = MockServer::start().await;
+        let fixture = include_str!("../../zb_core/fixtures/formula_foo.json");
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db_path = tmp.path().join("zb.sqlite3");
+        let url = format!("{}/foo.json", mock_server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/foo.json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(fixture)
+                    .insert_header("etag", "\"abc123\""),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = ApiClient::with_base_url(mock_server.uri())
+            .with_cache(ApiCache::in_database(&db_path).unwrap())
+            .with_cache_ttl(Duration::from_secs(60));
+        client.get_formula("foo").await.unwrap();
+        client.get_formula("foo").await.unwrap();
+        mock_server.verify().await;
+        mock_server.reset().await;
+
+        // The entry shares the file with the install records
+        let reopened = ApiCache::in_database(&db_path).unwrap();
+        let (entry, _) = reopened.get_with_age(&url).unwrap();
+        assert_eq!(entry.etag.as_deref(), Some("\"abc123\""));
+        assert_eq!(entry.body, fixture);
+        crate::db::Database::open(&db_path).unwrap();
+
+        // Once older than the TTL it is revalidated rather than reused
+        rusqlite::Connection::open(&db_path)
+            .unwrap()
+            .execute("UPDATE formula_cache SET cached_at = cached_at - 3600", [])
+            .unwrap();
+        Mock::given(method("GET"))
+            .and(path("/foo.json"))
+            .and(header("If-None-Match", "\"abc123\""))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        assert_eq!(client.get_formula("foo").await.unwrap().name, "foo");
+
+        let (_, age) = reopened.get_with_age(&url).unwrap();
+        assert!(age < Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn no_cache_fetches_even_a_fresh_entry_and_stores_the_result() {
+        let mock_server = MockServer::start().await;
+        let fixture = include_str!("../../zb_core/fixtures/formula_foo.json");
+        let tmp = tempfile::TempDir::new().unwrap();
+        let cache_path = tmp.path().join("api_cache.sqlite3");
+
+        Mock::given(method("GET"))
+            .and(path("/foo.json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(fixture)
+                    .insert_header("etag", "\"fresh\""),
+            )
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let client = ApiClient::with_base_url(mock_server.uri())
+            .with_cache(ApiCache::open(&cache_path).unwrap())
+            .with_no_cache(true);
+        client.get_formula("foo").await.unwrap();
+        // The entry just written is fresh, but is neither used nor revalidated
+        client.get_formula("foo").await.unwrap();
+
+        let (entry, _) = ApiCache::open(&cache_path)
+            .unwrap()
+            .get_with_age(&format!("{}/foo.json", mock_server.uri()))
+            .unwrap();
+        assert_eq!(entry.etag.as_deref(), Some("\"fresh\""));
+    }
 }
@@ -1,13 +1,108 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{self, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
+use fs4::fs_std::FileExt;
+use rusqlite::{Connection, params};
 use zb_core::Error;
 
+use crate::lock::process_alive;
+
 #[derive(Clone)]
 pub struct BlobCache {
     blobs_dir: PathBuf,
     tmp_dir: PathBuf,
+    /// Set on this clone only: cached and partial blobs are ignored, and new
+    /// ones replace them once committed
+    refresh: bool,
+    /// Shared by every clone, so limits and pins apply to all of them
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    /// Maximum total size of the blobs directory; 0 means unlimited
+    max_size: AtomicU64,
+    pinned: Mutex<HashMap<String, usize>>,
+    /// Where pins are shared with other processes, if anywhere
+    pin_db: Mutex<Option<Connection>>,
+}
+
+impl Shared {
+    /// Record in the pin database, if there is one, that this process has
+    /// taken or let go of its pin on a blob. Pins are best effort: a failed
+    /// write only leaves the blob unprotected from other processes.
+    fn share_pin(&self, sha256: &str, held: bool) {
+        let pin_db = self.pin_db.lock().unwrap();
+        let Some(conn) = pin_db.as_ref() else {
+            return;
+        };
+        let sql = if held {
+            "INSERT OR IGNORE INTO blob_pins (sha256, pid) VALUES (?1, ?2)"
+        } else {
+            "DELETE FROM blob_pins WHERE sha256 = ?1 AND pid = ?2"
+        };
+        let _ = conn.execute(sql, params![sha256, std::process::id()]);
+    }
+
+    /// Blobs pinned by any process still running, forgetting the pins of
+    /// those that exited without letting go
+    fn shared_pins(&self) -> HashSet<String> {
+        let pin_db = self.pin_db.lock().unwrap();
+        let Some(conn) = pin_db.as_ref() else {
+            return HashSet::new();
+        };
+        let rows: Vec<(String, u32)> = conn
+            .prepare("SELECT sha256, pid FROM blob_pins")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut pinned = HashSet::new();
+        for (sha256, pid) in rows {
+            if process_alive(pid) {
+                pinned.insert(sha256);
+            } else {
+                let _ = conn.execute("DELETE FROM blob_pins WHERE pid = ?1", params![pid]);
+            }
+        }
+        pinned
+    }
+}
+
+/// Keeps a blob from being evicted while held
+pub struct BlobPin {
+    shared: Arc<Shared>,
+    sha256: String,
+}
+
+impl Drop for BlobPin {
+    fn drop(&mut self) {
+        let mut pinned = self.shared.pinned.lock().unwrap();
+        if let Some(count) = pinned.get_mut(&self.sha256) {
+            *count -= 1;
+            if *count == 0 {
+                pinned.remove(&self.sha256);
+                drop(pinned);
+                self.shared.share_pin(&self.sha256, false);
+            }
+        }
+    }
+}
+
+/// A downloaded bottle sitting in the cache
+#[derive(Debug, Clone)]
+pub struct CachedBlob {
+    pub sha256: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
 }
 
 impl BlobCache {
@@ -18,15 +113,136 @@ impl BlobCache {
         fs::create_dir_all(&blobs_dir)?;
         fs::create_dir_all(&tmp_dir)?;
 
-        Ok(Self { blobs_dir, tmp_dir })
+        Ok(Self {
+            blobs_dir,
+            tmp_dir,
+            refresh: false,
+            shared: Arc::default(),
+        })
+    }
+
+    /// Treat every blob as missing, so each is written afresh. What the cache
+    /// holds is left in place until a new blob is committed over it, and
+    /// other clones keep using it meanwhile.
+    pub fn with_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Cap the cache at `max_bytes`, evicting least recently used blobs on
+    /// commit. Applies to every clone of this cache.
+    pub fn with_max_size(self, max_bytes: u64) -> Self {
+        self.shared.max_size.store(max_bytes, Ordering::Relaxed);
+        self
+    }
+
+    /// Share pins through the install database at `db_path`, so zb processes
+    /// running at once don't evict or collect blobs each other are installing
+    /// from. Applies to every clone of this cache.
+    pub fn with_shared_pins(self, db_path: &Path) -> Result<Self, Error> {
+        let conn = crate::db::connect(db_path)?;
+        *self.shared.pin_db.lock().unwrap() = Some(conn);
+        Ok(self)
+    }
+
+    /// Protect a blob from eviction until the returned pin is dropped
+    pub fn pin(&self, sha256: &str) -> BlobPin {
+        let first = {
+            let mut pinned = self.shared.pinned.lock().unwrap();
+            let count = pinned.entry(sha256.to_string()).or_default();
+            *count += 1;
+            *count == 1
+        };
+        if first {
+            self.shared.share_pin(sha256, true);
+        }
+        BlobPin {
+            shared: self.shared.clone(),
+            sha256: sha256.to_string(),
+        }
+    }
+
+    /// Whether a pin is currently held on the blob, here or by another
+    /// process sharing pins
+    pub fn is_pinned(&self, sha256: &str) -> bool {
+        self.shared.pinned.lock().unwrap().contains_key(sha256)
+            || self.shared.shared_pins().contains(sha256)
+    }
+
+    /// Record a cache hit so the blob counts as recently used
+    pub fn touch(&self, sha256: &str) {
+        if let Ok(file) = fs::File::options().write(true).open(self.blob_path(sha256)) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+    }
+
+    /// Drop least recently used blobs until the cache fits its size cap.
+    /// Pinned blobs and `keep` are never evicted.
+    fn evict(&self, keep: &str) -> io::Result<()> {
+        let max_size = self.shared.max_size.load(Ordering::Relaxed);
+        if max_size == 0 {
+            return Ok(());
+        }
+
+        let mut blobs = self.list_blobs()?;
+        let mut total: u64 = blobs.iter().map(|b| b.size).sum();
+        if total <= max_size {
+            return Ok(());
+        }
+
+        blobs.sort_by_key(|b| b.modified);
+        let shared_pins = self.shared.shared_pins();
+        let pinned = self.shared.pinned.lock().unwrap();
+        for blob in blobs {
+            if total <= max_size {
+                break;
+            }
+            if blob.sha256 == keep
+                || pinned.contains_key(&blob.sha256)
+                || shared_pins.contains(&blob.sha256)
+            {
+                continue;
+            }
+            if fs::remove_file(&blob.path).is_ok() {
+                let _ = fs::remove_file(self.signature_path(&blob.sha256));
+                total -= blob.size;
+            }
+        }
+        Ok(())
     }
 
     pub fn blob_path(&self, sha256: &str) -> PathBuf {
         self.blobs_dir.join(format!("{sha256}.tar.gz"))
     }
 
+    /// Where the detached signature a blob was verified against is kept, so
+    /// cache hits can be checked again without the network
+    pub fn signature_path(&self, sha256: &str) -> PathBuf {
+        self.blobs_dir.join(format!("{sha256}.minisig"))
+    }
+
     pub fn has_blob(&self, sha256: &str) -> bool {
-        self.blob_path(sha256).exists()
+        !self.refresh && self.blob_path(sha256).exists()
+    }
+
+    /// Every complete blob in the cache
+    pub fn list_blobs(&self) -> io::Result<Vec<CachedBlob>> {
+        let mut blobs = Vec::new();
+        for entry in fs::read_dir(&self.blobs_dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(sha256) = file_name.to_str().and_then(|n| n.strip_suffix(".tar.gz")) else {
+                continue;
+            };
+            let metadata = entry.metadata()?;
+            blobs.push(CachedBlob {
+                sha256: sha256.to_string(),
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+        Ok(blobs)
     }
 
     /// Remove a blob from the cache (used when extraction fails due to corruption)
@@ -34,6 +250,7 @@ impl BlobCache {
         let path = self.blob_path(sha256);
         if path.exists() {
             fs::remove_file(&path)?;
+            let _ = fs::remove_file(self.signature_path(sha256));
             Ok(true)
         } else {
             Ok(false)
@@ -41,13 +258,15 @@ impl BlobCache {
     }
 
     pub fn start_write(&self, sha256: &str) -> io::Result<BlobWriter> {
+        // Writers for the same blob may run in other processes or on the same
+        // thread, so every temp file gets a name of its own
+        static NEXT_WRITER: AtomicU64 = AtomicU64::new(0);
         let final_path = self.blob_path(sha256);
-        // Use unique temp filename to avoid corruption from concurrent racing downloads
-        let unique_id = std::process::id();
-        let thread_id = std::thread::current().id();
-        let tmp_path = self
-            .tmp_dir
-            .join(format!("{sha256}.{unique_id}.{thread_id:?}.tar.gz.part"));
+        let tmp_path = self.tmp_dir.join(format!(
+            "{sha256}.{}.{}.tar.gz.part",
+            std::process::id(),
+            NEXT_WRITER.fetch_add(1, Ordering::Relaxed)
+        ));
 
         let file = fs::File::create(&tmp_path)?;
 
@@ -56,6 +275,95 @@ impl BlobCache {
             tmp_path,
             final_path,
             committed: false,
+            keep_partial: false,
+            resume_offset: 0,
+            cache: self.clone(),
+            sha256: sha256.to_string(),
+        })
+    }
+
+    /// Path of the resumable partial download for a blob
+    pub fn partial_path(&self, sha256: &str) -> PathBuf {
+        self.tmp_dir.join(format!("{sha256}.tar.gz.part"))
+    }
+
+    /// Number of bytes already downloaded into the resumable partial for a
+    /// blob; 0 while another writer is appending to it, as a new download
+    /// then goes to a file of its own
+    pub fn partial_len(&self, sha256: &str) -> u64 {
+        if self.refresh {
+            return 0;
+        }
+        let Ok(file) = fs::File::open(self.partial_path(sha256)) else {
+            return 0;
+        };
+        if !matches!(file.try_lock_exclusive(), Ok(true)) {
+            return 0;
+        }
+        file.metadata().map(|m| m.len()).unwrap_or(0)
+    }
+
+    /// Remove the resumable partial for a blob, unless a writer (here or in
+    /// another process) holds its lock, along with any temp files this
+    /// process was writing it through. Returns how many files were removed.
+    pub fn remove_partials(&self, sha256: &str) -> io::Result<usize> {
+        let ours = format!("{sha256}.{}.", std::process::id());
+        let resumable = format!("{sha256}.tar.gz.part");
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.tmp_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let removable = if name == resumable {
+                // Removed while the lock is held, so no writer can take it
+                // in between
+                let Ok(file) = fs::File::open(entry.path()) else {
+                    continue;
+                };
+                matches!(file.try_lock_exclusive(), Ok(true))
+                    && fs::remove_file(entry.path()).is_ok()
+            } else {
+                name.starts_with(&ours) && fs::remove_file(entry.path()).is_ok()
+            };
+            if removable {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Open a writer that appends to any existing partial download for this blob.
+    /// Unlike `start_write`, the partial file is kept if the writer is dropped
+    /// without committing, so an interrupted download can be resumed later.
+    /// The partial is locked while written; if another writer (in this process
+    /// or another) holds it, this falls back to a fresh `start_write`.
+    pub fn start_resumable_write(&self, sha256: &str) -> io::Result<BlobWriter> {
+        if self.refresh {
+            return self.start_write(sha256);
+        }
+        let final_path = self.blob_path(sha256);
+        let tmp_path = self.partial_path(sha256);
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&tmp_path)?;
+        if !file.try_lock_exclusive()? {
+            return self.start_write(sha256);
+        }
+        let resume_offset = file.metadata()?.len();
+
+        Ok(BlobWriter {
+            file,
+            tmp_path,
+            final_path,
+            committed: false,
+            keep_partial: true,
+            resume_offset,
+            cache: self.clone(),
+            sha256: sha256.to_string(),
         })
     }
 }
@@ -65,6 +373,10 @@ pub struct BlobWriter {
     tmp_path: PathBuf,
     final_path: PathBuf,
     committed: bool,
+    keep_partial: bool,
+    cache: BlobCache,
+    sha256: String,
+    resume_offset: u64,
 }
 
 impl BlobWriter {
@@ -72,11 +384,45 @@ impl BlobWriter {
         self.file.seek(pos)
     }
 
+    /// Bytes already present in the partial file when the writer was opened
+    pub fn resume_offset(&self) -> u64 {
+        self.resume_offset
+    }
+
+    /// Path of the in-progress temp file
+    pub fn tmp_path(&self) -> &Path {
+        &self.tmp_path
+    }
+
+    /// Drop everything after `len` bytes, so writing continues from that offset
+    pub fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)?;
+        self.resume_offset = self.resume_offset.min(len);
+        Ok(())
+    }
+
+    /// Delete the temp file without committing (e.g. after a checksum mismatch)
+    pub fn discard(mut self) {
+        self.keep_partial = false;
+    }
+
+    /// Commit under a different blob name than the writer was started with,
+    /// for content whose digest is only known once it has all been written
+    pub fn commit_as(mut self, sha256: &str) -> Result<PathBuf, Error> {
+        self.final_path = self.cache.blob_path(sha256);
+        self.sha256 = sha256.to_string();
+        self.commit()
+    }
+
     pub fn commit(mut self) -> Result<PathBuf, Error> {
         self.file.flush().map_err(|e| Error::NetworkFailure {
             message: format!("failed to flush blob: {e}"),
         })?;
 
+        if self.cache.refresh {
+            return self.replace();
+        }
+
         // Another racing download may have already created the final blob.
         // In that case, just clean up our temp file and return success.
         if self.final_path.exists() {
@@ -101,6 +447,26 @@ impl BlobWriter {
         }
 
         self.committed = true;
+        // Going over the cap only costs disk space, so don't fail the download
+        let _ = self.cache.evict(&self.sha256);
+        Ok(self.final_path.clone())
+    }
+
+    /// Commit over whatever blob the cache holds, then drop the resumable
+    /// partial the new blob supersedes unless a writer still holds it
+    fn replace(mut self) -> Result<PathBuf, Error> {
+        fs::rename(&self.tmp_path, &self.final_path).map_err(|e| Error::NetworkFailure {
+            message: format!("failed to rename blob: {e}"),
+        })?;
+        self.committed = true;
+
+        let partial = self.cache.partial_path(&self.sha256);
+        if let Ok(file) = fs::File::open(&partial)
+            && matches!(file.try_lock_exclusive(), Ok(true))
+        {
+            let _ = fs::remove_file(&partial);
+        }
+        let _ = self.cache.evict(&self.sha256);
         Ok(self.final_path.clone())
     }
 }
@@ -117,7 +483,7 @@ impl Write for BlobWriter {
 
 impl Drop for BlobWriter {
     fn drop(&mut self) {
-        if !self.committed && self.tmp_path.exists() {
+        if !self.committed && !self.keep_partial && self.tmp_path.exists() {
             let _ = fs::remove_file(&self.tmp_path);
         }
     }
@@ -202,4 +568,210 @@ mod tests {
         let removed = cache.remove_blob("nonexistent").unwrap();
         assert!(!removed);
     }
+
+    #[test]
+    fn resumable_write_keeps_partial_on_drop() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap();
+
+        let sha = "resume123";
+        {
+            let mut writer = cache.start_resumable_write(sha).unwrap();
+            assert_eq!(writer.resume_offset(), 0);
+            writer.write_all(b"hello ").unwrap();
+        }
+
+        assert_eq!(cache.partial_len(sha), 6);
+
+        let mut writer = cache.start_resumable_write(sha).unwrap();
+        assert_eq!(writer.resume_offset(), 6);
+        writer.write_all(b"world").unwrap();
+        let final_path = writer.commit().unwrap();
+
+        assert_eq!(fs::read_to_string(final_path).unwrap(), "hello world");
+        assert_eq!(cache.partial_len(sha), 0);
+    }
+
+    #[test]
+    fn partials_in_use_are_not_removed() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap();
+
+        let sha = "inuse";
+        let mut writer = cache.start_resumable_write(sha).unwrap();
+        writer.write_all(b"partial").unwrap();
+        assert_eq!(cache.remove_partials(sha).unwrap(), 0);
+        assert!(cache.partial_path(sha).exists());
+
+        drop(writer);
+        assert_eq!(cache.remove_partials(sha).unwrap(), 1);
+        assert!(!cache.partial_path(sha).exists());
+    }
+
+    #[test]
+    fn refreshing_writes_replace_the_blob_only_once_committed() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap();
+        let sha = "refresh";
+
+        let mut writer = cache.start_write(sha).unwrap();
+        writer.write_all(b"old").unwrap();
+        writer.commit().unwrap();
+        let mut partial = cache.start_resumable_write(sha).unwrap();
+        partial.write_all(b"par").unwrap();
+        drop(partial);
+
+        let refreshing = cache.clone().with_refresh(true);
+        assert!(!refreshing.has_blob(sha));
+        assert_eq!(refreshing.partial_len(sha), 0);
+
+        // An abandoned write leaves the cache as it was
+        let mut writer = refreshing.start_resumable_write(sha).unwrap();
+        writer.write_all(b"new").unwrap();
+        drop(writer);
+        assert_eq!(fs::read(cache.blob_path(sha)).unwrap(), b"old");
+        assert_eq!(cache.partial_len(sha), 3);
+
+        let mut writer = refreshing.start_resumable_write(sha).unwrap();
+        writer.write_all(b"new").unwrap();
+        writer.commit().unwrap();
+        assert_eq!(fs::read(cache.blob_path(sha)).unwrap(), b"new");
+        assert!(!cache.partial_path(sha).exists());
+    }
+
+    #[test]
+    fn discarded_resumable_write_removes_partial() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap();
+
+        let sha = "discardme";
+        let mut writer = cache.start_resumable_write(sha).unwrap();
+        writer.write_all(b"bad bytes").unwrap();
+        writer.discard();
+
+        assert!(!cache.partial_path(sha).exists());
+        assert!(!cache.has_blob(sha));
+    }
+
+    #[test]
+    fn concurrent_writers_of_one_blob_never_share_a_temp_file() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap();
+        let sha = "shared";
+        let content: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+
+        // Two writers on one thread used to get the same temp name
+        let first = cache.start_write(sha).unwrap();
+        let second = cache.start_write(sha).unwrap();
+        assert_ne!(first.tmp_path(), second.tmp_path());
+        drop((first, second));
+
+        // A second resumable writer can't append to a partial in use
+        let holder = cache.start_resumable_write(sha).unwrap();
+        assert_eq!(cache.partial_len(sha), 0);
+        drop(holder);
+
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                let cache = cache.clone();
+                let content = content.clone();
+                std::thread::spawn(move || {
+                    let mut writer = cache.start_resumable_write(sha).unwrap();
+                    for chunk in content.chunks(1024) {
+                        writer.write_all(chunk).unwrap();
+                        std::thread::yield_now();
+                    }
+                    writer.commit().unwrap()
+                })
+            })
+            .collect();
+        for writer in writers {
+            let path = writer.join().unwrap();
+            assert_eq!(path, cache.blob_path(sha));
+        }
+
+        assert_eq!(fs::read(cache.blob_path(sha)).unwrap(), content);
+    }
+
+    #[test]
+    fn pins_are_shared_between_caches_through_the_database() {
+        let tmp = TempDir::new().unwrap();
+        let db_path = tmp.path().join("zb.sqlite3");
+        let pinning = BlobCache::new(tmp.path())
+            .unwrap()
+            .with_shared_pins(&db_path)
+            .unwrap();
+        // Another process, as far as pins go
+        let evicting = BlobCache::new(tmp.path())
+            .unwrap()
+            .with_shared_pins(&db_path)
+            .unwrap()
+            .with_max_size(10);
+
+        let commit = |cache: &BlobCache, sha: &str| {
+            let mut writer = cache.start_write(sha).unwrap();
+            writer.write_all(&[0u8; 10]).unwrap();
+            writer.commit().unwrap();
+        };
+
+        let pin = pinning.pin("in_use");
+        commit(&pinning, "in_use");
+        assert!(evicting.is_pinned("in_use"));
+        commit(&evicting, "other");
+        assert!(evicting.has_blob("in_use"));
+
+        drop(pin);
+        assert!(!evicting.is_pinned("in_use"));
+        commit(&evicting, "another");
+        assert!(!evicting.has_blob("in_use"));
+
+        // A pin left by a process that has exited doesn't count
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute(
+            "INSERT INTO blob_pins (sha256, pid) VALUES ('orphan', ?1)",
+            params![i32::MAX],
+        )
+        .unwrap();
+        assert!(!evicting.is_pinned("orphan"));
+    }
+
+    #[test]
+    fn size_cap_evicts_least_recently_used_unpinned_blobs() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap().with_max_size(30);
+
+        // Last used `age` seconds from now, negative for the past
+        let now = SystemTime::now();
+        let commit = |sha: &str, age: i64| {
+            let mut writer = cache.start_write(sha).unwrap();
+            writer.write_all(&[0u8; 10]).unwrap();
+            let path = writer.commit().unwrap();
+            let offset = std::time::Duration::from_secs(age.unsigned_abs());
+            let modified = if age < 0 { now - offset } else { now + offset };
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_modified(modified))
+                .unwrap();
+        };
+
+        let _pin = cache.pin("pinned");
+        commit("pinned", -100);
+        commit("oldest", -90);
+        commit("older", -80);
+        cache.touch("oldest");
+        commit("newest", 60);
+
+        // "older" was least recently used; the pinned blob is never evicted
+        assert!(!cache.has_blob("older"));
+        assert!(cache.has_blob("pinned"));
+        assert!(cache.has_blob("oldest"));
+        assert!(cache.has_blob("newest"));
+
+        commit("newer_still", 70);
+        assert!(!cache.has_blob("oldest"));
+        assert!(cache.has_blob("pinned"));
+        assert!(cache.has_blob("newest"));
+        assert!(cache.has_blob("newer_still"));
+    }
 }
@@ -1,8 +1,14 @@
 use rusqlite::{Connection, params};
 use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use zb_core::Error;
 
 pub struct ApiCache {
     conn: Connection,
+    /// `api_cache` in a standalone cache file, `formula_cache` in the main database
+    table: &'static str,
+    clock: Clock,
 }
 
 #[derive(Debug, Clone)]
@@ -16,13 +22,31 @@ impl ApiCache {
     pub fn open(path: &Path) -> Result<Self, rusqlite::Error> {
         let conn = Connection::open(path)?;
         Self::init_schema(&conn)?;
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            table: "api_cache",
+            clock: Clock::new(),
+        })
     }
 
     pub fn in_memory() -> Result<Self, rusqlite::Error> {
         let conn = Connection::open_in_memory()?;
         Self::init_schema(&conn)?;
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            table: "api_cache",
+            clock: Clock::new(),
+        })
+    }
+
+    /// Keep the cache in the `formula_cache` table of the install database at
+    /// `path` rather than a file of its own, migrating it first if needed
+    pub fn in_database(path: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            conn: crate::db::connect(path)?,
+            table: "formula_cache",
+            clock: Clock::new(),
+        })
     }
 
     fn init_schema(conn: &Connection) -> Result<(), rusqlite::Error> {
@@ -40,36 +64,85 @@ impl ApiCache {
     }
 
     pub fn get(&self, url: &str) -> Option<CacheEntry> {
+        self.get_with_age(url).map(|(entry, _)| entry)
+    }
+
+    /// The cached entry along with how long ago it was fetched or last revalidated
+    pub fn get_with_age(&self, url: &str) -> Option<(CacheEntry, Duration)> {
         self.conn
             .query_row(
-                "SELECT etag, last_modified, body FROM api_cache WHERE url = ?1",
+                &format!(
+                    "SELECT etag, last_modified, body, cached_at FROM {} WHERE url = ?1",
+                    self.table
+                ),
                 params![url],
                 |row| {
-                    Ok(CacheEntry {
-                        etag: row.get(0)?,
-                        last_modified: row.get(1)?,
-                        body: row.get(2)?,
-                    })
+                    let cached_at: i64 = row.get(3)?;
+                    let age = (self.clock.now_secs() - cached_at).max(0) as u64;
+                    Ok((
+                        CacheEntry {
+                            etag: row.get(0)?,
+                            last_modified: row.get(1)?,
+                            body: row.get(2)?,
+                        },
+                        Duration::from_secs(age),
+                    ))
                 },
             )
             .ok()
     }
 
     pub fn put(&self, url: &str, entry: &CacheEntry) -> Result<(), rusqlite::Error> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        self.conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (url, etag, last_modified, body, cached_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                self.table
+            ),
+            params![
+                url,
+                entry.etag,
+                entry.last_modified,
+                entry.body,
+                self.clock.now_secs()
+            ],
+        )?;
+        Ok(())
+    }
 
+    /// Mark an entry as freshly validated (e.g. after a 304) without changing it
+    pub fn touch(&self, url: &str) -> Result<(), rusqlite::Error> {
         self.conn.execute(
-            "INSERT OR REPLACE INTO api_cache (url, etag, last_modified, body, cached_at)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![url, entry.etag, entry.last_modified, entry.body, now],
+            &format!("UPDATE {} SET cached_at = ?2 WHERE url = ?1", self.table),
+            params![url, self.clock.now_secs()],
         )?;
         Ok(())
     }
 }
 
+/// The wall clock, advanced by tokio's clock from when the cache was opened,
+/// so tests can age entries with `tokio::time::advance`
+struct Clock {
+    wall: SystemTime,
+    instant: tokio::time::Instant,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Self {
+            wall: SystemTime::now(),
+            instant: tokio::time::Instant::now(),
+        }
+    }
+
+    fn now_secs(&self) -> i64 {
+        (self.wall + self.instant.elapsed())
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,4 +169,27 @@ mod tests {
         let cache = ApiCache::in_memory().unwrap();
         assert!(cache.get("https://example.com/nonexistent.json").is_none());
     }
+
+    #[test]
+    fn touch_resets_entry_age() {
+        let cache = ApiCache::in_memory().unwrap();
+        let url = "https://example.com/foo.json";
+        let entry = CacheEntry {
+            etag: None,
+            last_modified: None,
+            body: "{}".to_string(),
+        };
+        cache.put(url, &entry).unwrap();
+        cache
+            .conn
+            .execute("UPDATE api_cache SET cached_at = cached_at - 3600", [])
+            .unwrap();
+
+        let (_, age) = cache.get_with_age(url).unwrap();
+        assert!(age >= Duration::from_secs(3600));
+
+        cache.touch(url).unwrap();
+        let (_, age) = cache.get_with_age(url).unwrap();
+        assert!(age < Duration::from_secs(60));
+    }
 }
@@ -0,0 +1,142 @@
+use std::fmt;
+use std::io;
+
+use sha2::{Digest, Sha256};
+
+/// Stands in for a missing checksum to have the digest computed on first
+/// download instead, when the downloader trusts on first use
+pub const TRUST_ON_FIRST_USE: &str = "tofu";
+
+/// Expected digest of a download, tagged with the algorithm that produced it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Checksum {
+    Sha256(String),
+    Blake3(String),
+}
+
+impl Checksum {
+    /// Parse `blake3:<hex>` or `sha256:<hex>`; an untagged digest is sha256
+    pub fn parse(value: &str) -> Self {
+        match value.split_once(':') {
+            Some(("blake3", hex)) => Self::Blake3(hex.to_lowercase()),
+            Some(("sha256", hex)) => Self::Sha256(hex.to_lowercase()),
+            _ => Self::Sha256(value.to_lowercase()),
+        }
+    }
+
+    pub fn hex(&self) -> &str {
+        match self {
+            Self::Sha256(hex) | Self::Blake3(hex) => hex,
+        }
+    }
+
+    /// No digest to check against: empty, or [`TRUST_ON_FIRST_USE`]
+    pub fn is_unknown(&self) -> bool {
+        let hex = self.hex();
+        hex.is_empty() || hex == TRUST_ON_FIRST_USE
+    }
+
+    /// The same algorithm with `hex` as its digest
+    pub(crate) fn with_hex(&self, hex: String) -> Self {
+        match self {
+            Self::Sha256(_) => Self::Sha256(hex),
+            Self::Blake3(_) => Self::Blake3(hex),
+        }
+    }
+
+    /// Name of the blob in the cache. Sha256 blobs keep their bare digest so
+    /// existing caches stay valid; other algorithms are prefixed to avoid clashes.
+    pub fn cache_key(&self) -> String {
+        match self {
+            Self::Sha256(hex) => hex.clone(),
+            Self::Blake3(hex) => format!("blake3-{hex}"),
+        }
+    }
+
+    pub(crate) fn hasher(&self) -> Hasher {
+        match self {
+            Self::Sha256(_) => Hasher::Sha256(Sha256::new()),
+            Self::Blake3(_) => Hasher::Blake3(Box::default()),
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256(hex) => write!(f, "sha256:{hex}"),
+            Self::Blake3(hex) => write!(f, "blake3:{hex}"),
+        }
+    }
+}
+
+/// Streaming hasher for whichever algorithm a [`Checksum`] uses
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    pub(crate) fn finalize_hex(self) -> String {
+        match self {
+            Self::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tagged_and_bare_digests() {
+        assert_eq!(Checksum::parse("ABC"), Checksum::Sha256("abc".into()));
+        assert_eq!(
+            Checksum::parse("blake3:abc"),
+            Checksum::Blake3("abc".into())
+        );
+        assert_eq!(Checksum::parse("blake3:abc").cache_key(), "blake3-abc");
+        assert_eq!(Checksum::parse("sha256:abc").cache_key(), "abc");
+        assert!(Checksum::parse("").is_unknown());
+        assert!(Checksum::parse("blake3:tofu").is_unknown());
+        assert!(!Checksum::parse("abc").is_unknown());
+    }
+
+    #[test]
+    fn hashes_with_the_tagged_algorithm() {
+        let mut sha = Checksum::Sha256(String::new()).hasher();
+        sha.update(b"hello world");
+        assert_eq!(
+            sha.finalize_hex(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+
+        let mut blake = Checksum::Blake3(String::new()).hasher();
+        blake.update(b"hello world");
+        assert_eq!(
+            blake.finalize_hex(),
+            blake3::hash(b"hello world").to_hex().as_str()
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use std::path::Path;
+use std::time::Duration;
 
 use rusqlite::{Connection, Transaction, params};
 
+use crate::migrations;
 use zb_core::Error;
 
 pub struct Database {
@@ -14,60 +16,67 @@ pub struct InstalledKeg {
     pub version: String,
     pub store_key: String,
     pub installed_at: i64,
+    /// Held at its version by `zb pin`; upgrades skip it
+    pub pinned: bool,
+    /// The formula's description when it was installed
+    pub description: Option<String>,
+    /// The tap it was installed from, or `None` for homebrew/core
+    pub tap: Option<String>,
 }
 
-impl Database {
-    pub fn open(path: &Path) -> Result<Self, Error> {
-        let conn = Connection::open(path).map_err(|e| Error::StoreCorruption {
-            message: format!("failed to open database: {e}"),
+impl InstalledKeg {
+    /// The name to look the formula up by, qualified with its tap if it has one
+    pub fn qualified_name(&self) -> String {
+        match &self.tap {
+            Some(tap) => format!("{tap}/{}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// One recorded change to a formula. `old_*` is empty for a fresh install and
+/// `new_*` is empty for an uninstall.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub name: String,
+    pub old_version: Option<String>,
+    pub old_store_key: Option<String>,
+    pub new_version: Option<String>,
+    pub new_store_key: Option<String>,
+    pub recorded_at: i64,
+}
+
+/// Open the install database at `path` and bring its schema up to date
+pub(crate) fn connect(path: &Path) -> Result<Connection, Error> {
+    let mut conn = Connection::open(path).map_err(|e| Error::StoreCorruption {
+        message: format!("failed to open database: {e}"),
+    })?;
+    // Readers may briefly overlap another zb process's write
+    conn.busy_timeout(Duration::from_secs(5))
+        .map_err(|e| Error::StoreCorruption {
+            message: format!("failed to configure database: {e}"),
         })?;
 
-        Self::init_schema(&conn)?;
+    migrations::migrate(&mut conn)?;
+    Ok(conn)
+}
 
+impl Database {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let conn = connect(path)?;
         Ok(Self { conn })
     }
 
     pub fn in_memory() -> Result<Self, Error> {
-        let conn = Connection::open_in_memory().map_err(|e| Error::StoreCorruption {
+        let mut conn = Connection::open_in_memory().map_err(|e| Error::StoreCorruption {
             message: format!("failed to open in-memory database: {e}"),
         })?;
 
-        Self::init_schema(&conn)?;
+        migrations::migrate(&mut conn)?;
 
         Ok(Self { conn })
     }
 
-    fn init_schema(conn: &Connection) -> Result<(), Error> {
-        conn.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS installed_kegs (
-                name TEXT PRIMARY KEY,
-                version TEXT NOT NULL,
-                store_key TEXT NOT NULL,
-                installed_at INTEGER NOT NULL
-            );
-
-            CREATE TABLE IF NOT EXISTS store_refs (
-                store_key TEXT PRIMARY KEY,
-                refcount INTEGER NOT NULL DEFAULT 1
-            );
-
-            CREATE TABLE IF NOT EXISTS keg_files (
-                name TEXT NOT NULL,
-                version TEXT NOT NULL,
-                linked_path TEXT NOT NULL,
-                target_path TEXT NOT NULL,
-                PRIMARY KEY (name, linked_path)
-            );
-            ",
-        )
-        .map_err(|e| Error::StoreCorruption {
-            message: format!("failed to initialize schema: {e}"),
-        })?;
-
-        Ok(())
-    }
-
     pub fn transaction(&mut self) -> Result<InstallTransaction<'_>, Error> {
         let tx = self
             .conn
@@ -82,7 +91,10 @@ impl Database {
     pub fn get_installed(&self, name: &str) -> Option<InstalledKeg> {
         self.conn
             .query_row(
-                "SELECT name, version, store_key, installed_at FROM installed_kegs WHERE name = ?1",
+                "SELECT k.name, k.version, k.store_key, k.installed_at, p.name IS NOT NULL,
+                        k.description, k.tap
+                 FROM installed_kegs k LEFT JOIN pinned p ON p.name = k.name
+                 WHERE k.name = ?1",
                 params![name],
                 |row| {
                     Ok(InstalledKeg {
@@ -90,6 +102,9 @@ impl Database {
                         version: row.get(1)?,
                         store_key: row.get(2)?,
                         installed_at: row.get(3)?,
+                        pinned: row.get(4)?,
+                        description: row.get(5)?,
+                        tap: row.get(6)?,
                     })
                 },
             )
@@ -100,7 +115,10 @@ impl Database {
         let mut stmt = self
             .conn
             .prepare(
-                "SELECT name, version, store_key, installed_at FROM installed_kegs ORDER BY name",
+                "SELECT k.name, k.version, k.store_key, k.installed_at, p.name IS NOT NULL,
+                        k.description, k.tap
+                 FROM installed_kegs k LEFT JOIN pinned p ON p.name = k.name
+                 ORDER BY k.name",
             )
             .map_err(|e| Error::StoreCorruption {
                 message: format!("failed to prepare statement: {e}"),
@@ -113,6 +131,9 @@ impl Database {
                     version: row.get(1)?,
                     store_key: row.get(2)?,
                     installed_at: row.get(3)?,
+                    pinned: row.get(4)?,
+                    description: row.get(5)?,
+                    tap: row.get(6)?,
                 })
             })
             .map_err(|e| Error::StoreCorruption {
@@ -126,6 +147,68 @@ impl Database {
         Ok(kegs)
     }
 
+    /// Hold `name` at its installed version. Returns false if it already was.
+    pub fn pin(&self, name: &str) -> Result<bool, Error> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO pinned (name, pinned_at) VALUES (?1, ?2)",
+                params![name, now],
+            )
+            .map(|changed| changed > 0)
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to pin {name}: {e}"),
+            })
+    }
+
+    /// Let `name` be upgraded again. Returns false if it wasn't pinned.
+    pub fn unpin(&self, name: &str) -> Result<bool, Error> {
+        self.conn
+            .execute("DELETE FROM pinned WHERE name = ?1", params![name])
+            .map(|changed| changed > 0)
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to unpin {name}: {e}"),
+            })
+    }
+
+    /// Recorded changes to `name`, newest first
+    pub fn history(&self, name: &str) -> Result<Vec<HistoryEntry>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, old_version, old_store_key, new_version, new_store_key, recorded_at
+                 FROM transactions WHERE name = ?1 ORDER BY id DESC",
+            )
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to prepare statement: {e}"),
+            })?;
+
+        let entries = stmt
+            .query_map(params![name], |row| {
+                Ok(HistoryEntry {
+                    name: row.get(0)?,
+                    old_version: row.get(1)?,
+                    old_store_key: row.get(2)?,
+                    new_version: row.get(3)?,
+                    new_store_key: row.get(4)?,
+                    recorded_at: row.get(5)?,
+                })
+            })
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to query history: {e}"),
+            })?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to collect results: {e}"),
+            })?;
+
+        Ok(entries)
+    }
+
     pub fn get_store_refcount(&self, store_key: &str) -> i64 {
         self.conn
             .query_row(
@@ -171,8 +254,9 @@ impl<'a> InstallTransaction<'a> {
 
         self.tx
             .execute(
-                "INSERT OR REPLACE INTO installed_kegs (name, version, store_key, installed_at)
-                 VALUES (?1, ?2, ?3, ?4)",
+                "INSERT INTO installed_kegs (name, version, store_key, installed_at)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(name) DO UPDATE SET version = ?2, store_key = ?3, installed_at = ?4",
                 params![name, version, store_key, now],
             )
             .map_err(|e| Error::StoreCorruption {
@@ -193,6 +277,92 @@ impl<'a> InstallTransaction<'a> {
         Ok(())
     }
 
+    /// Store the description of installed formula `name`
+    pub fn record_description(&self, name: &str, description: Option<&str>) -> Result<(), Error> {
+        self.tx
+            .execute(
+                "UPDATE installed_kegs SET description = ?2 WHERE name = ?1",
+                params![name, description],
+            )
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to record description: {e}"),
+            })?;
+
+        Ok(())
+    }
+
+    pub fn record_tap(&self, name: &str, tap: Option<&str>) -> Result<(), Error> {
+        self.tx
+            .execute(
+                "UPDATE installed_kegs SET tap = ?2 WHERE name = ?1",
+                params![name, tap],
+            )
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to record tap: {e}"),
+            })?;
+
+        Ok(())
+    }
+
+    /// Release the previous install of a formula that is being replaced by a
+    /// new version: drop its linked-file records and its store reference.
+    /// The old keg itself stays on disk for cleanup to reclaim.
+    pub fn record_replaced(&self, old: &InstalledKeg) -> Result<(), Error> {
+        self.tx
+            .execute(
+                "DELETE FROM keg_files WHERE name = ?1 AND version = ?2",
+                params![old.name, old.version],
+            )
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to remove keg files records: {e}"),
+            })?;
+
+        self.tx
+            .execute(
+                "UPDATE store_refs SET refcount = refcount - 1 WHERE store_key = ?1",
+                params![old.store_key],
+            )
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to decrement store ref: {e}"),
+            })?;
+
+        Ok(())
+    }
+
+    /// Append to the history of `name`. `old` and `new` are `(version, store_key)`
+    /// of the install before and after the change.
+    pub fn record_history(
+        &self,
+        name: &str,
+        old: Option<(&str, &str)>,
+        new: Option<(&str, &str)>,
+    ) -> Result<(), Error> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.tx
+            .execute(
+                "INSERT INTO transactions
+                 (name, old_version, old_store_key, new_version, new_store_key, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    name,
+                    old.map(|(version, _)| version),
+                    old.map(|(_, key)| key),
+                    new.map(|(version, _)| version),
+                    new.map(|(_, key)| key),
+                    now
+                ],
+            )
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to record history: {e}"),
+            })?;
+
+        Ok(())
+    }
+
     pub fn record_linked_file(
         &self,
         name: &str,
@@ -238,6 +408,13 @@ impl<'a> InstallTransaction<'a> {
                 message: format!("failed to remove keg files records: {e}"),
             })?;
 
+        // A pin only means something while the formula is installed
+        self.tx
+            .execute("DELETE FROM pinned WHERE name = ?1", params![name])
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to remove pin: {e}"),
+            })?;
+
         // Decrement store ref if we had one
         if let Some(ref key) = store_key {
             self.tx
@@ -283,6 +460,29 @@ mod tests {
         assert_eq!(installed[0].store_key, "abc123");
     }
 
+    #[test]
+    fn pins_show_on_installed_kegs_and_go_away_on_uninstall() {
+        let mut db = Database::in_memory().unwrap();
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", "1.0.0", "abc123").unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert!(db.pin("foo").unwrap());
+        assert!(db.get_installed("foo").unwrap().pinned);
+        assert!(db.list_installed().unwrap()[0].pinned);
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_uninstall("foo").unwrap();
+            tx.record_install("foo", "1.0.0", "abc123").unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(!db.get_installed("foo").unwrap().pinned);
+        assert!(!db.unpin("foo").unwrap());
+    }
+
     #[test]
     fn rollback_leaves_no_partial_state() {
         let mut db = Database::in_memory().unwrap();
@@ -349,6 +549,28 @@ mod tests {
         assert!(unreferenced.contains(&"key2".to_string()));
     }
 
+    #[test]
+    fn history_is_listed_newest_first() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_history("foo", None, Some(("1.0.0", "key1")))
+                .unwrap();
+            tx.record_history("foo", Some(("1.0.0", "key1")), Some(("2.0.0", "key2")))
+                .unwrap();
+            tx.record_history("bar", None, Some(("1.0.0", "key3")))
+                .unwrap();
+            tx.commit().unwrap();
+        }
+
+        let history = db.history("foo").unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].old_version.as_deref(), Some("1.0.0"));
+        assert_eq!(history[0].new_store_key.as_deref(), Some("key2"));
+        assert_eq!(history[1].old_version, None);
+    }
+
     #[test]
     fn linked_files_are_recorded() {
         let mut db = Database::in_memory().unwrap();
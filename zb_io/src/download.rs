@@ -1,21 +1,24 @@
 use std::collections::{BTreeMap, HashMap};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 
+use crate::checksum::Checksum;
+use crate::signature::{SignatureCheck, SignaturePolicy, SignatureVerifier};
+use crate::store::{StagedExtraction, Store};
 use futures_util::StreamExt;
 use futures_util::future::select_all;
 use reqwest::StatusCode;
 use reqwest::header::{
-    ACCEPT_RANGES, AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, HeaderValue, WWW_AUTHENTICATE,
+    ACCEPT_RANGES, AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, HeaderValue, RANGE,
+    WWW_AUTHENTICATE,
 };
 use serde::Deserialize;
-use sha2::{Digest, Sha256};
-use tokio::sync::{Mutex, Notify, RwLock, Semaphore, mpsc};
+use tokio::sync::{Mutex, Notify, OwnedSemaphorePermit, RwLock, Semaphore, mpsc, watch};
 
-use crate::blob::BlobCache;
+use crate::blob::{BlobCache, BlobWriter};
 use crate::progress::InstallProgress;
 use zb_core::Error;
 
@@ -36,6 +39,16 @@ const GLOBAL_DOWNLOAD_CONCURRENCY: usize = 20;
 /// With 20 global concurrency, we can have 3-4 large files downloading concurrently.
 const MAX_CONCURRENT_CHUNKS: usize = 6;
 
+/// Default cap on concurrent connections to any single host, room for eight
+/// downloads racing their connections
+/// Keeps a large global limit from hammering one registry (ghcr.io rate-limits
+/// aggressive clients) while still allowing fan-out across mirrors.
+const DEFAULT_PER_HOST_CONNECTIONS: usize = 8 * RACING_CONNECTIONS;
+
+/// Downloads in one batch that may fail for good before the rest are
+/// cancelled; see [`FailureBudget`]
+const BATCH_FAILURE_BUDGET: u32 = 3;
+
 /// Maximum retry attempts for failed chunk downloads
 const MAX_CHUNK_RETRIES: u32 = 3;
 
@@ -58,6 +71,8 @@ struct ChunkDownloadContext<'a> {
     name: Option<String>,
     file_size: u64,
     total_downloaded: Arc<AtomicU64>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    failure_budget: Option<FailureBudget>,
 }
 
 /// Context for chunked download operations
@@ -66,14 +81,226 @@ struct ChunkedDownloadContext<'a> {
     client: &'a reqwest::Client,
     token_cache: &'a TokenCache,
     url: &'a str,
-    expected_sha256: &'a str,
+    checksum: &'a Checksum,
     name: Option<String>,
     progress: Option<DownloadProgressCallback>,
     file_size: u64,
     global_semaphore: &'a Arc<Semaphore>,
+    connections: Option<usize>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    signature: Option<&'a SignatureCheck>,
+    failure_budget: Option<&'a FailureBudget>,
 }
 // FIXME: extract timeout and HTTP/2 window size constants to config file
 
+/// Retry behaviour for transient download failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each subsequent retry
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `attempt` (0-based), with up to one base delay of jitter
+    pub(crate) fn delay_for(&self, attempt: u32) -> Duration {
+        if self.base_delay.is_zero() {
+            return Duration::ZERO;
+        }
+
+        let backoff = self.base_delay.saturating_mul(1 << attempt.min(16));
+        let jitter_range = self.base_delay.as_millis().max(1) as u64;
+        backoff + Duration::from_millis(jitter_seed() % jitter_range)
+    }
+}
+
+/// How many downloads of one batch may run out their retries before the
+/// batch gives up. Each download follows its own [`RetryPolicy`]; once
+/// `limit` of them have failed for good, nothing in the batch retries again
+/// and the downloads still running are cancelled, so a wide batch on a dead
+/// network fails in seconds instead of every download backing off in turn.
+#[derive(Debug, Clone)]
+pub(crate) struct FailureBudget {
+    failed: Arc<watch::Sender<u32>>,
+    limit: u32,
+}
+
+impl FailureBudget {
+    pub(crate) fn new(limit: u32) -> Self {
+        Self {
+            failed: Arc::new(watch::Sender::new(0)),
+            limit: limit.max(1),
+        }
+    }
+
+    /// Count a download that failed after all its retries
+    fn record_failure(&self) {
+        self.failed.send_modify(|failed| *failed += 1);
+    }
+
+    fn is_spent(&self) -> bool {
+        *self.failed.borrow() >= self.limit
+    }
+
+    /// Resolve once enough downloads have failed to give up on the batch
+    async fn spent(&self) {
+        let mut failed = self.failed.subscribe();
+        // The sender lives as long as `self`, so this can't close early
+        let _ = failed.wait_for(|failed| *failed >= self.limit).await;
+    }
+
+    fn cancelled_error(&self) -> Error {
+        Error::NetworkFailure {
+            message: format!(
+                "cancelled after {} other downloads failed",
+                *self.failed.borrow()
+            ),
+        }
+    }
+}
+
+/// Cheap per-call randomness for backoff jitter, without pulling in an RNG crate
+fn jitter_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default(),
+    );
+    hasher.finish()
+}
+
+/// Whether a failed attempt is worth retrying: connection errors, dropped
+/// streams and 5xx responses are; client errors and checksum mismatches are not.
+pub(crate) fn is_retryable(err: &Error) -> bool {
+    matches!(err, Error::NetworkFailure { .. }) || err.is_transient()
+}
+
+/// Classify a reqwest failure so retry logic and the CLI can tell timeouts
+/// and dropped connections apart from everything else
+pub(crate) fn request_error(context: &str, e: reqwest::Error) -> Error {
+    if e.is_timeout() {
+        return Error::Timeout;
+    }
+    if e.is_redirect()
+        && let Some(stop) =
+            std::error::Error::source(&e).and_then(|source| source.downcast_ref::<RedirectStop>())
+    {
+        return match stop {
+            RedirectStop::TooMany { limit } => Error::TooManyRedirects {
+                url: e.url().map(|url| url.to_string()).unwrap_or_default(),
+                limit: *limit,
+            },
+            RedirectStop::CrossHost { from, to } => Error::CrossHostRedirect {
+                from: from.clone(),
+                to: to.clone(),
+            },
+        };
+    }
+    if let Some(status) = e.status() {
+        return Error::HttpStatus {
+            code: status.as_u16(),
+        };
+    }
+
+    let mut source = std::error::Error::source(&e);
+    while let Some(err) = source {
+        if let Some(io) = err.downcast_ref::<std::io::Error>() {
+            match io.kind() {
+                std::io::ErrorKind::TimedOut => return Error::Timeout,
+                std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::UnexpectedEof => return Error::ConnectionReset,
+                _ => {}
+            }
+        }
+        source = err.source();
+    }
+
+    Error::NetworkFailure {
+        message: if context.is_empty() {
+            e.to_string()
+        } else {
+            format!("{context}: {e}")
+        },
+    }
+}
+
+pub(crate) fn status_error(status: StatusCode) -> Error {
+    Error::HttpStatus {
+        code: status.as_u16(),
+    }
+}
+
+/// Token-bucket bandwidth limiter shared by every download that holds it.
+/// Allows up to one second of burst; larger reads go into debt so later
+/// callers queue behind them and the long-run rate stays at the limit.
+struct RateLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<TokenBucket>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        Self {
+            bytes_per_sec,
+            bucket: Mutex::new(TokenBucket {
+                tokens: bytes_per_sec,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until `bytes` may be consumed without exceeding the rate
+    async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.bytes_per_sec;
+            bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_sec);
+            bucket.last_refill = now;
+            bucket.tokens -= bytes as f64;
+
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Pace a chunk of `bytes` through the limiter, if one is configured
+async fn throttle(rate_limiter: Option<&RateLimiter>, bytes: usize) {
+    if let Some(limiter) = rate_limiter {
+        limiter.acquire(bytes).await;
+    }
+}
+
 /// Callback for download progress updates
 pub type DownloadProgressCallback = Arc<dyn Fn(InstallProgress) + Send + Sync>;
 
@@ -114,9 +341,19 @@ struct TokenResponse {
 #[derive(Debug, Clone)]
 pub struct DownloadResult {
     pub name: String,
-    pub sha256: String,
+    pub checksum: Checksum,
     pub blob_path: PathBuf,
     pub index: usize,
+    /// From the request being queued until the blob was ready, including any
+    /// wait for a download slot
+    pub elapsed: Duration,
+}
+
+/// A streamed download that failed, with the index of its request
+#[derive(Debug)]
+pub struct FailedDownload {
+    pub index: usize,
+    pub error: Error,
 }
 
 /// Cached auth token with expiry
@@ -127,6 +364,144 @@ struct CachedToken {
 
 type TokenCache = Arc<RwLock<HashMap<String, CachedToken>>>;
 
+/// How long to wait for a connection to open
+pub(crate) const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a response may go without delivering a byte before it's abandoned.
+/// Applies to each read, so a slow but steady download is never cut off.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Parse `url` as a proxy for every request, HTTP and HTTPS alike. Hosts
+/// listed in `NO_PROXY` still bypass it.
+pub fn parse_proxy(url: &str) -> Result<reqwest::Proxy, Error> {
+    proxy_bypassing(url, reqwest::NoProxy::from_env())
+}
+
+/// Like [`parse_proxy`], with the hosts that bypass it given
+pub(crate) fn proxy_bypassing(
+    url: &str,
+    no_proxy: Option<reqwest::NoProxy>,
+) -> Result<reqwest::Proxy, Error> {
+    reqwest::Proxy::all(url)
+        .map(|proxy| proxy.no_proxy(no_proxy))
+        .map_err(|e| Error::InvalidArgument {
+            message: format!("invalid proxy URL '{url}': {e}"),
+        })
+}
+
+/// Route `builder`'s requests through `proxy`. Without one, reqwest honors
+/// `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY` from the environment.
+pub(crate) fn with_proxy(
+    builder: reqwest::ClientBuilder,
+    proxy: Option<&reqwest::Proxy>,
+) -> reqwest::ClientBuilder {
+    match proxy {
+        Some(proxy) => builder.proxy(proxy.clone()),
+        None => builder,
+    }
+}
+
+/// Redirects a request may follow before failing, the same as reqwest's default
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Which redirects a request follows. ghcr.io answers blob requests with a
+/// redirect to its CDN, so by default any host is allowed and only the
+/// number of hops is capped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    /// Hops after which a request fails with [`Error::TooManyRedirects`]
+    pub max_redirects: usize,
+    /// Fail requests to the [`Downloader::with_url_rewrite`] mirror with
+    /// [`Error::CrossHostRedirect`] if they're redirected to another host
+    pub pin_mirror_host: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            pin_mirror_host: false,
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// The reqwest policy enforcing this one, given the host requests are
+    /// rewritten to, if any
+    pub(crate) fn to_reqwest(self, mirror_host: Option<String>) -> reqwest::redirect::Policy {
+        let pinned = mirror_host.filter(|_| self.pin_mirror_host);
+        reqwest::redirect::Policy::custom(move |attempt| {
+            // `previous` starts with the original URL, so it holds one more
+            // entry than the hops taken so far
+            if attempt.previous().len() > self.max_redirects {
+                let limit = self.max_redirects;
+                return attempt.error(RedirectStop::TooMany { limit });
+            }
+            if let Some(pinned) = &pinned {
+                let from = attempt.previous().first().and_then(|url| url.host_str());
+                if from == Some(pinned.as_str()) && attempt.url().host_str() != from {
+                    let to = attempt.url().to_string();
+                    return attempt.error(RedirectStop::CrossHost {
+                        from: pinned.clone(),
+                        to,
+                    });
+                }
+            }
+            attempt.follow()
+        })
+    }
+}
+
+/// Why a [`RedirectPolicy`] stopped a request, carried through reqwest's
+/// error so [`request_error`] can report it
+#[derive(Debug)]
+enum RedirectStop {
+    TooMany { limit: usize },
+    CrossHost { from: String, to: String },
+}
+
+impl std::fmt::Display for RedirectStop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooMany { limit } => write!(f, "more than {limit} redirects"),
+            Self::CrossHost { from, to } => write!(f, "redirect from {from} to {to}"),
+        }
+    }
+}
+
+impl std::error::Error for RedirectStop {}
+
+/// Settings shared by the pooled client and the per-race isolated ones
+fn client_builder(
+    read_timeout: Duration,
+    proxy: Option<&reqwest::Proxy>,
+    redirect: reqwest::redirect::Policy,
+) -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder()
+        .user_agent("zerobrew/0.1")
+        .redirect(redirect)
+        .tcp_nodelay(true)
+        .tcp_keepalive(Duration::from_secs(60))
+        .connect_timeout(DEFAULT_CONNECT_TIMEOUT.min(read_timeout))
+        .read_timeout(read_timeout)
+        .http2_adaptive_window(true)
+        .http2_initial_stream_window_size(Some(2 * 1024 * 1024))
+        .http2_initial_connection_window_size(Some(4 * 1024 * 1024));
+    with_proxy(builder, proxy)
+}
+
+fn pooled_client(
+    read_timeout: Duration,
+    proxy: Option<&reqwest::Proxy>,
+    redirect: reqwest::redirect::Policy,
+) -> reqwest::Client {
+    // Use HTTP/2 with connection pooling for better performance
+    client_builder(read_timeout, proxy, redirect)
+        .pool_max_idle_per_host(10)
+        .build()
+        .unwrap_or_else(|_| reqwest::Client::new())
+}
+
 fn build_rustls_config() -> rustls::ClientConfig {
     let provider = rustls::crypto::aws_lc_rs::default_provider();
 
@@ -143,12 +518,27 @@ fn build_rustls_config() -> rustls::ClientConfig {
         .with_no_client_auth()
 }
 
+#[derive(Clone)]
 pub struct Downloader {
     client: reqwest::Client,
     blob_cache: BlobCache,
     token_cache: TokenCache,
     global_semaphore: Option<Arc<Semaphore>>,
+    /// Connections each download may open to its host; unlimited if unset
+    connections: Option<usize>,
     tls_config: Arc<rustls::ClientConfig>,
+    retry_policy: RetryPolicy,
+    failure_budget: Option<FailureBudget>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    offline: bool,
+    url_rewrite: Option<(String, String)>,
+    verify_cached: bool,
+    signatures: Option<Arc<SignatureVerifier>>,
+    stream_into: Option<Store>,
+    read_timeout: Duration,
+    proxy: Option<reqwest::Proxy>,
+    redirect_policy: RedirectPolicy,
+    trust_on_first_use: bool,
 }
 
 impl Downloader {
@@ -156,43 +546,169 @@ impl Downloader {
         Self::with_semaphore(blob_cache, None)
     }
 
+    /// Create a Downloader that retries transient failures according to `retry_policy`
+    pub fn with_retry_policy(blob_cache: BlobCache, retry_policy: RetryPolicy) -> Self {
+        let mut downloader = Self::new(blob_cache);
+        downloader.retry_policy = retry_policy;
+        downloader
+    }
+
     pub fn with_semaphore(blob_cache: BlobCache, semaphore: Option<Arc<Semaphore>>) -> Self {
-        // Use HTTP/2 with connection pooling for better performance
         let tls_config = Arc::new(build_rustls_config());
 
         Self {
-            client: reqwest::Client::builder()
-                .user_agent("zerobrew/0.1")
-                .pool_max_idle_per_host(10)
-                .tcp_nodelay(true)
-                .tcp_keepalive(Duration::from_secs(60))
-                .connect_timeout(Duration::from_secs(30))
-                .timeout(Duration::from_secs(300))
-                .http2_adaptive_window(true)
-                .http2_initial_stream_window_size(Some(2 * 1024 * 1024))
-                .http2_initial_connection_window_size(Some(4 * 1024 * 1024))
-                .build()
-                .unwrap_or_else(|_| reqwest::Client::new()),
+            client: pooled_client(
+                DEFAULT_READ_TIMEOUT,
+                None,
+                RedirectPolicy::default().to_reqwest(None),
+            ),
             blob_cache,
             token_cache: Arc::new(RwLock::new(HashMap::new())),
             global_semaphore: semaphore,
+            connections: None,
             tls_config,
+            retry_policy: RetryPolicy::default(),
+            failure_budget: None,
+            rate_limiter: None,
+            offline: false,
+            url_rewrite: None,
+            verify_cached: false,
+            signatures: None,
+            stream_into: None,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            proxy: None,
+            redirect_policy: RedirectPolicy::default(),
+            trust_on_first_use: false,
+        }
+    }
+
+    /// Give up on a connection that takes longer than `timeout` to open, or a
+    /// response that goes `timeout` without sending any data
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self.client = self.pooled_client();
+        self
+    }
+
+    /// Send every request through `proxy` (see [`parse_proxy`]) instead of
+    /// any proxy configured in the environment
+    pub fn with_proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self.client = self.pooled_client();
+        self
+    }
+
+    /// Cap how many redirects a download follows, and optionally keep
+    /// requests to the mirror from being redirected elsewhere
+    pub fn with_redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect_policy = policy;
+        self.client = self.pooled_client();
+        self
+    }
+
+    /// Open at most `connections` at a time for each download, racing fewer
+    /// connections and fetching fewer chunks at once
+    pub(crate) fn with_connections(mut self, connections: usize) -> Self {
+        self.connections = Some(connections.max(1));
+        self
+    }
+
+    /// Stop retrying once `budget` is spent
+    pub(crate) fn with_failure_budget(mut self, budget: FailureBudget) -> Self {
+        self.failure_budget = Some(budget);
+        self
+    }
+
+    /// Cap download bandwidth at `bytes_per_sec`, shared across every download
+    /// made through this Downloader (and its clones)
+    pub fn with_max_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(bytes_per_sec)));
+        self
+    }
+
+    /// Only serve blobs already in the cache; anything else is an error
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Re-hash cached blobs before using them, redownloading any that no
+    /// longer match their checksum. Costs a full read of every cache hit.
+    pub fn with_verify_cached(mut self, verify: bool) -> Self {
+        self.verify_cached = verify;
+        self
+    }
+
+    /// Download every bottle again, replacing whatever the cache holds for it
+    /// once the new blob checks out
+    pub fn with_no_cache(mut self, no_cache: bool) -> Self {
+        self.blob_cache = self.blob_cache.with_refresh(no_cache);
+        self
+    }
+
+    /// Require new downloads to carry a detached minisign signature (`<url>.minisig`
+    /// or `<url>.sig`) from a trusted key. A blob that fails is removed again.
+    pub fn with_signature_verifier(mut self, verifier: SignatureVerifier) -> Self {
+        self.signatures = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Unpack bottles into `store` while they download instead of reading the
+    /// blob back afterwards. The unpacked entry is only kept once the checksum
+    /// (and signature, if required) checks out.
+    pub fn with_streaming_extraction(mut self, store: Store) -> Self {
+        self.stream_into = Some(store);
+        self
+    }
+
+    /// Accept downloads whose expected checksum is empty or
+    /// [`TRUST_ON_FIRST_USE`](crate::checksum::TRUST_ON_FIRST_USE): the blob is
+    /// hashed as it arrives and cached under the digest computed, which is
+    /// printed so it can be added to the formula. Without this such downloads
+    /// are refused.
+    pub fn with_trust_on_first_use(mut self, trust: bool) -> Self {
+        self.trust_on_first_use = trust;
+        self
+    }
+
+    /// Fetch URLs starting with `from` from `to` instead, e.g. to send ghcr.io
+    /// bottle requests to an internal mirror
+    pub fn with_url_rewrite(mut self, from: &str, to: &str) -> Self {
+        self.url_rewrite = Some((from.to_string(), to.to_string()));
+        self.client = self.pooled_client();
+        self
+    }
+
+    fn rewrite_url(&self, url: &str) -> String {
+        match &self.url_rewrite {
+            Some((from, to)) if url.starts_with(from.as_str()) => {
+                format!("{to}{}", &url[from.len()..])
+            }
+            _ => url.to_string(),
         }
     }
 
-    // FIXME: extract timeout and HTTP/2 window size constants to config file
+    /// The redirect policy for this downloader's clients, pinned to the
+    /// mirror's host if the policy asks for that
+    fn redirects(&self) -> reqwest::redirect::Policy {
+        let mirror_host = self
+            .url_rewrite
+            .as_ref()
+            .and_then(|(_, to)| reqwest::Url::parse(to).ok())
+            .and_then(|url| url.host_str().map(str::to_string));
+        self.redirect_policy.to_reqwest(mirror_host)
+    }
+
+    fn pooled_client(&self) -> reqwest::Client {
+        pooled_client(self.read_timeout, self.proxy.as_ref(), self.redirects())
+    }
+
     fn create_isolated_client(&self) -> reqwest::Client {
-        reqwest::Client::builder()
-            .user_agent("zerobrew/0.1")
-            .use_preconfigured_tls(self.tls_config.clone())
+        // reqwest only recognises a bare `rustls::ClientConfig` here; handing it the
+        // Arc fails the build and would silently drop every setting above
+        client_builder(self.read_timeout, self.proxy.as_ref(), self.redirects())
+            .use_preconfigured_tls(rustls::ClientConfig::clone(&self.tls_config))
             .pool_max_idle_per_host(0)
-            .tcp_nodelay(true)
-            .tcp_keepalive(Duration::from_secs(60))
-            .connect_timeout(Duration::from_secs(30))
-            .timeout(Duration::from_secs(300))
-            .http2_adaptive_window(true)
-            .http2_initial_stream_window_size(Some(2 * 1024 * 1024))
-            .http2_initial_connection_window_size(Some(4 * 1024 * 1024))
             .build()
             .unwrap_or_else(|_| reqwest::Client::new())
     }
@@ -203,18 +719,41 @@ impl Downloader {
     }
 
     pub async fn download(&self, url: &str, expected_sha256: &str) -> Result<PathBuf, Error> {
-        self.download_with_progress(url, expected_sha256, None, None)
+        let checksum = Checksum::Sha256(expected_sha256.to_string());
+        self.download_with_progress(url, &checksum, None, None)
             .await
     }
 
+    #[tracing::instrument(name = "download", skip_all, fields(%url, %checksum))]
     pub async fn download_with_progress(
         &self,
         url: &str,
-        expected_sha256: &str,
+        checksum: &Checksum,
         name: Option<String>,
         progress: Option<DownloadProgressCallback>,
     ) -> Result<PathBuf, Error> {
-        if self.blob_cache.has_blob(expected_sha256) {
+        if checksum.is_unknown() {
+            let (blob_path, _) = self
+                .download_trusting_first_use(url, checksum, name, progress)
+                .await?;
+            return Ok(blob_path);
+        }
+
+        let key = checksum.cache_key();
+        if self.blob_cache.has_blob(&key)
+            && self.verify_cached
+            && !self.cached_blob_matches(checksum).await
+        {
+            eprintln!("cached bottle {checksum} is corrupt, downloading it again");
+            self.blob_cache.remove_blob(&key).ok();
+        }
+
+        if self.blob_cache.has_blob(&key) {
+            tracing::debug!("bottle cache hit");
+            if let Some(verifier) = &self.signatures {
+                self.verify_cached_signature(verifier, url, &key).await?;
+            }
+            self.blob_cache.touch(&key);
             // Report as already complete
             if let (Some(cb), Some(n)) = (&progress, &name) {
                 cb(InstallProgress::DownloadCompleted {
@@ -222,80 +761,470 @@ impl Downloader {
                     total_bytes: 0,
                 });
             }
-            return Ok(self.blob_cache.blob_path(expected_sha256));
+            return Ok(self.blob_cache.blob_path(&key));
+        }
+
+        if let Some(path) = local_path(url) {
+            tracing::debug!(path = %path.display(), "copying local bottle");
+            return self
+                .copy_local_bottle(url, path, checksum, name, progress)
+                .await;
+        }
+
+        if self.offline {
+            return Err(Error::OfflineMiss {
+                resource: url.to_string(),
+            });
+        }
+
+        let url = self.rewrite_url(url);
+        tracing::debug!(%url, "downloading bottle");
+
+        let signature = match &self.signatures {
+            Some(verifier) => Some(self.fetch_signature(verifier, &url).await?),
+            None => None,
+        };
+
+        if let Some(store) = &self.stream_into
+            && let Some(staged) = store.begin_extraction(&key)?
+        {
+            match self
+                .download_extracting(
+                    &url,
+                    checksum,
+                    name.clone(),
+                    progress.clone(),
+                    staged,
+                    signature.as_ref(),
+                )
+                .await
+            {
+                Ok(blob_path) => {
+                    self.keep_signature(&key, signature.as_ref());
+                    return Ok(blob_path);
+                }
+                Err(e @ (Error::ChecksumMismatch { .. } | Error::SignatureInvalid { .. })) => {
+                    return Err(e);
+                }
+                // Anything else gets a second chance through the usual path
+                Err(_) => {}
+            }
         }
 
         // Get alternate mirror URLs (user-configured)
-        let alternates = get_alternate_urls(url);
+        let alternates = get_alternate_urls(&url);
 
         // Always use racing to hit different CDN edges for faster downloads
-        self.download_with_racing(url, &alternates, expected_sha256, name, progress)
-            .await
+        let blob_path = self
+            .download_with_racing(
+                &url,
+                &alternates,
+                checksum,
+                name,
+                progress,
+                signature.as_ref(),
+            )
+            .await?;
+        tracing::debug!(path = %blob_path.display(), "bottle downloaded");
+        self.keep_signature(&key, signature.as_ref());
+
+        Ok(blob_path)
     }
 
-    /// Download with racing: start multiple parallel connections to the same URL
-    /// (hits different CDN edges) and optionally alternate mirrors.
-    /// First successful download wins, others are cancelled.
-    async fn download_with_racing(
+    /// Download from `url`, moving on to each of `fallbacks` in turn when it
+    /// fails. Only errors, with the last mirror's error, once all have failed.
+    pub async fn download_with_fallbacks(
         &self,
-        primary_url: &str,
-        alternate_urls: &[String],
-        expected_sha256: &str,
+        url: &str,
+        fallbacks: &[String],
+        checksum: &Checksum,
         name: Option<String>,
         progress: Option<DownloadProgressCallback>,
     ) -> Result<PathBuf, Error> {
-        let (use_chunked, file_size) = {
-            let cached_token =
-                get_cached_token_for_url_internal(&self.token_cache, primary_url).await;
+        let mut result = self
+            .download_with_progress(url, checksum, name.clone(), progress.clone())
+            .await;
+        let mut failed_url = url;
+        for fallback in fallbacks {
+            let Err(e) = &result else { break };
+            tracing::warn!(url = %failed_url, error = %e, %fallback, "download failed, trying the next mirror");
+            result = self
+                .download_with_progress(fallback, checksum, name.clone(), progress.clone())
+                .await;
+            failed_url = fallback;
+        }
+        result
+    }
 
-            let mut request = self.client.head(primary_url);
-            if let Some(token) = &cached_token {
-                request = request.header(
-                    AUTHORIZATION,
-                    HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
-                );
-            }
+    /// Download `url` with nothing to check it against, caching the blob under
+    /// the digest computed with `checksum`'s algorithm and returning that
+    /// digest. Refused unless the downloader trusts on first use.
+    pub async fn download_trusting_first_use(
+        &self,
+        url: &str,
+        checksum: &Checksum,
+        name: Option<String>,
+        progress: Option<DownloadProgressCallback>,
+    ) -> Result<(PathBuf, Checksum), Error> {
+        if !self.trust_on_first_use {
+            return Err(Error::InvalidArgument {
+                message: format!(
+                    "no checksum to verify {url} against, and trust on first use is off"
+                ),
+            });
+        }
 
-            match request.send().await {
-                Ok(response) if response.status().is_success() => {
-                    let content_length = response
-                        .headers()
-                        .get(CONTENT_LENGTH)
-                        .and_then(|v| v.to_str().ok())
-                        .and_then(|s| s.parse::<u64>().ok());
+        let (blob_path, computed, total_bytes) = self.download_unverified(url, checksum).await?;
 
-                    let supports_ranges = server_supports_ranges(&response);
+        eprintln!("trusting {url} on first use: {computed}");
+        if let (Some(cb), Some(n)) = (&progress, &name) {
+            cb(InstallProgress::DownloadCompleted {
+                name: n.clone(),
+                total_bytes,
+            });
+        }
+        Ok((blob_path, computed))
+    }
 
-                    if let Some(size) = content_length {
-                        (
-                            supports_ranges && size >= CHUNKED_DOWNLOAD_THRESHOLD,
-                            Some(size),
-                        )
-                    } else {
-                        (false, None)
-                    }
+    /// Download `url` into the cache without verifying it, hashing it with
+    /// `checksum`'s algorithm as it arrives and caching it under the digest
+    /// computed. Returns the blob, its digest and its size. Signatures are
+    /// still checked when a verifier is set.
+    pub async fn download_unverified(
+        &self,
+        url: &str,
+        checksum: &Checksum,
+    ) -> Result<(PathBuf, Checksum, u64), Error> {
+        let local = local_path(url);
+        if self.offline && local.is_none() {
+            return Err(Error::OfflineMiss {
+                resource: url.to_string(),
+            });
+        }
+
+        let url = self.rewrite_url(url);
+        let signature = match &self.signatures {
+            Some(verifier) => Some(self.fetch_signature(verifier, &url).await?),
+            None => None,
+        };
+        let write_failed = |e: std::io::Error| Error::NetworkFailure {
+            message: format!("failed to write download: {e}"),
+        };
+        let mut writer = self
+            .blob_cache
+            .start_write("unverified")
+            .map_err(write_failed)?;
+        let mut hasher = checksum.hasher();
+        let mut total_bytes = 0u64;
+
+        if let Some(path) = local {
+            use tokio::io::AsyncReadExt;
+
+            let read_failed = |e: std::io::Error| Error::FileError {
+                message: format!("failed to read bottle {}: {e}", path.display()),
+            };
+            let mut file = tokio::fs::File::open(&path).await.map_err(read_failed)?;
+            let mut buf = vec![0u8; 256 * 1024];
+            loop {
+                let n = file.read(&mut buf).await.map_err(read_failed)?;
+                if n == 0 {
+                    break;
                 }
-                _ => (false, None),
+                hasher.update(&buf[..n]);
+                writer.write_all(&buf[..n]).map_err(write_failed)?;
+                total_bytes += n as u64;
             }
-        };
+        } else {
+            let response =
+                fetch_download_response_internal(&self.client, &self.token_cache, &url, 0).await?;
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk.map_err(|e| request_error("failed to read chunk", e))?;
+                throttle(self.rate_limiter.as_deref(), chunk.len()).await;
+                hasher.update(&chunk);
+                writer.write_all(&chunk).map_err(write_failed)?;
+                total_bytes += chunk.len() as u64;
+            }
+        }
 
-        if use_chunked && let Some(size) = file_size {
-            // Use global semaphore if available, otherwise create a temporary one
-            let semaphore = self
-                .global_semaphore
-                .clone()
-                .unwrap_or_else(|| Arc::new(Semaphore::new(GLOBAL_DOWNLOAD_CONCURRENCY)));
+        let computed = checksum.with_hex(hasher.finalize_hex());
+        writer.flush().map_err(write_failed)?;
+        if let Some(signature) = &signature
+            && let Err(e) = signature.verify(writer.tmp_path()).await
+        {
+            writer.discard();
+            return Err(e);
+        }
+        let blob_path = writer.commit_as(&computed.cache_key())?;
+        self.keep_signature(&computed.cache_key(), signature.as_ref());
+        Ok((blob_path, computed, total_bytes))
+    }
 
-            let ctx = ChunkedDownloadContext {
-                blob_cache: &self.blob_cache,
-                client: &self.client,
-                token_cache: &self.token_cache,
+    /// Download in a single stream, teeing the bytes into `staged` as they're
+    /// written to the blob cache, and commit the extraction once verified
+    async fn download_extracting(
+        &self,
+        url: &str,
+        checksum: &Checksum,
+        name: Option<String>,
+        progress: Option<DownloadProgressCallback>,
+        mut staged: StagedExtraction,
+        signature: Option<&SignatureCheck>,
+    ) -> Result<PathBuf, Error> {
+        let response =
+            fetch_download_response_internal(&self.client, &self.token_cache, url, 0).await?;
+        let writer = download_response_internal(
+            &self.blob_cache,
+            response,
+            0,
+            checksum,
+            progress.zip(name),
+            self.rate_limiter.as_deref(),
+            Some(&mut staged),
+        )
+        .await?;
+        let blob_path = commit_signed(writer, signature).await?;
+
+        // A failed extraction isn't fatal: the verified blob is cached and the
+        // store unpacks it again the usual way
+        if let Some(store) = &self.stream_into {
+            let _ = store.spawn_blocking(move || staged.commit()).await;
+        }
+        Ok(blob_path)
+    }
+
+    /// Fetch the detached signature (`<url>.minisig`, then `<url>.sig`) ahead
+    /// of the download. A 404 means there is none; any other failure is an
+    /// error when signatures are required, rather than counting as unsigned.
+    async fn fetch_signature(
+        &self,
+        verifier: &Arc<SignatureVerifier>,
+        url: &str,
+    ) -> Result<SignatureCheck, Error> {
+        let mut signature = None;
+        for extension in ["minisig", "sig"] {
+            let signature_url = format!("{url}.{extension}");
+            let fetched = match local_path(&signature_url) {
+                Some(path) => match tokio::fs::read_to_string(&path).await {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    read => read.map_err(|e| Error::FileError {
+                        message: format!("failed to read {}: {e}", path.display()),
+                    }),
+                },
+                None => match self.client.get(&signature_url).send().await {
+                    Ok(response) if response.status() == StatusCode::NOT_FOUND => continue,
+                    Ok(response) if response.status().is_success() => {
+                        response.text().await.map_err(|e| request_error("", e))
+                    }
+                    Ok(response) => Err(status_error(response.status())),
+                    Err(e) => Err(request_error("", e)),
+                },
+            };
+
+            match fetched {
+                Ok(text) => {
+                    signature = Some(text);
+                    break;
+                }
+                Err(e) if verifier.policy() == SignaturePolicy::Strict => {
+                    return Err(Error::SignatureInvalid {
+                        resource: url.to_string(),
+                        message: format!("failed to fetch {signature_url}: {e}"),
+                    });
+                }
+                Err(e) => eprintln!("warning: failed to fetch {signature_url}: {e}"),
+            }
+        }
+
+        Ok(SignatureCheck::new(verifier.clone(), url, signature))
+    }
+
+    /// Check a cached blob against the signature kept beside it, fetching one
+    /// if it was cached without. A blob that fails is removed from the cache.
+    async fn verify_cached_signature(
+        &self,
+        verifier: &Arc<SignatureVerifier>,
+        url: &str,
+        key: &str,
+    ) -> Result<(), Error> {
+        let kept = self.blob_cache.signature_path(key);
+        let check = match tokio::fs::read_to_string(&kept).await {
+            Ok(text) => SignatureCheck::new(verifier.clone(), url, Some(text)),
+            Err(_) if self.offline => SignatureCheck::new(verifier.clone(), url, None),
+            Err(_) => {
+                self.fetch_signature(verifier, &self.rewrite_url(url))
+                    .await?
+            }
+        };
+
+        if let Err(e) = check.verify(&self.blob_cache.blob_path(key)).await {
+            self.blob_cache.remove_blob(key).ok();
+            return Err(e);
+        }
+        self.keep_signature(key, Some(&check));
+        Ok(())
+    }
+
+    /// Keep the signature a blob was verified against next to it in the cache
+    fn keep_signature(&self, key: &str, check: Option<&SignatureCheck>) {
+        let Some(signature) = check.and_then(SignatureCheck::signature) else {
+            return;
+        };
+        let path = self.blob_cache.signature_path(key);
+        if !path.exists() {
+            let _ = std::fs::write(path, signature);
+        }
+    }
+
+    /// Bring a `file://` bottle into the cache, checking it (and its
+    /// signature, if required) like any download
+    async fn copy_local_bottle(
+        &self,
+        url: &str,
+        path: PathBuf,
+        checksum: &Checksum,
+        name: Option<String>,
+        progress: Option<DownloadProgressCallback>,
+    ) -> Result<PathBuf, Error> {
+        let signature = match &self.signatures {
+            Some(verifier) => Some(self.fetch_signature(verifier, url).await?),
+            None => None,
+        };
+
+        let key = checksum.cache_key();
+        let blob_cache = self.blob_cache.clone();
+        let checksum = checksum.clone();
+        let (writer, bytes) = tokio::task::spawn_blocking(move || {
+            let failed = |e: std::io::Error| Error::FileError {
+                message: format!("failed to read bottle {}: {e}", path.display()),
+            };
+            let mut file = std::fs::File::open(&path).map_err(failed)?;
+            let mut writer = blob_cache
+                .start_write(&checksum.cache_key())
+                .map_err(failed)?;
+            let mut hasher = checksum.hasher();
+            let mut buf = vec![0u8; 256 * 1024];
+            let mut bytes = 0u64;
+            loop {
+                let n = file.read(&mut buf).map_err(failed)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                writer.write_all(&buf[..n]).map_err(failed)?;
+                bytes += n as u64;
+            }
+
+            let actual = hasher.finalize_hex();
+            if actual != checksum.hex() {
+                writer.discard();
+                return Err(Error::ChecksumMismatch {
+                    expected: checksum.hex().to_string(),
+                    actual,
+                });
+            }
+            writer.flush().map_err(failed)?;
+            Ok((writer, bytes))
+        })
+        .await
+        .map_err(|e| Error::FileError {
+            message: format!("bottle copy task failed: {e}"),
+        })??;
+        let blob_path = commit_signed(writer, signature.as_ref()).await?;
+        self.keep_signature(&key, signature.as_ref());
+
+        if let (Some(cb), Some(n)) = (&progress, &name) {
+            cb(InstallProgress::DownloadCompleted {
+                name: n.clone(),
+                total_bytes: bytes,
+            });
+        }
+        Ok(blob_path)
+    }
+
+    async fn cached_blob_matches(&self, checksum: &Checksum) -> bool {
+        let path = self.blob_cache.blob_path(&checksum.cache_key());
+        let checksum = checksum.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut file = std::fs::File::open(path)?;
+            let mut hasher = checksum.hasher();
+            std::io::copy(&mut file, &mut hasher)?;
+            Ok::<_, std::io::Error>(hasher.finalize_hex() == checksum.hex())
+        })
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .unwrap_or(false)
+    }
+
+    /// Download with racing: start multiple parallel connections to the same URL
+    /// (hits different CDN edges) and optionally alternate mirrors.
+    /// First successful download wins, others are cancelled.
+    async fn download_with_racing(
+        &self,
+        primary_url: &str,
+        alternate_urls: &[String],
+        checksum: &Checksum,
+        name: Option<String>,
+        progress: Option<DownloadProgressCallback>,
+        signature: Option<&SignatureCheck>,
+    ) -> Result<PathBuf, Error> {
+        let (use_chunked, file_size) = {
+            let cached_token =
+                get_cached_token_for_url_internal(&self.token_cache, primary_url).await;
+
+            let mut request = self.client.head(primary_url);
+            if let Some(token) = &cached_token {
+                request = request.header(
+                    AUTHORIZATION,
+                    HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+                );
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    let content_length = response
+                        .headers()
+                        .get(CONTENT_LENGTH)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|s| s.parse::<u64>().ok());
+
+                    let supports_ranges = server_supports_ranges(&response);
+
+                    if let Some(size) = content_length {
+                        (
+                            supports_ranges && size >= CHUNKED_DOWNLOAD_THRESHOLD,
+                            Some(size),
+                        )
+                    } else {
+                        (false, None)
+                    }
+                }
+                _ => (false, None),
+            }
+        };
+
+        if use_chunked && let Some(size) = file_size {
+            // Use global semaphore if available, otherwise create a temporary one
+            let semaphore = self
+                .global_semaphore
+                .clone()
+                .unwrap_or_else(|| Arc::new(Semaphore::new(GLOBAL_DOWNLOAD_CONCURRENCY)));
+
+            let ctx = ChunkedDownloadContext {
+                blob_cache: &self.blob_cache,
+                client: &self.client,
+                token_cache: &self.token_cache,
                 url: primary_url,
-                expected_sha256,
+                checksum,
                 name,
                 progress,
                 file_size: size,
                 global_semaphore: &semaphore,
+                connections: self.connections,
+                rate_limiter: self.rate_limiter.clone(),
+                signature,
+                failure_budget: self.failure_budget.as_ref(),
             };
 
             return download_with_chunks(&ctx).await;
@@ -312,7 +1241,10 @@ impl Downloader {
         let mut all_urls: Vec<String> = Vec::new();
 
         // Add primary URL multiple times for CDN edge racing
-        for _ in 0..RACING_CONNECTIONS {
+        let racing_connections = self
+            .connections
+            .map_or(RACING_CONNECTIONS, |n| n.min(RACING_CONNECTIONS));
+        for _ in 0..racing_connections {
             all_urls.push(primary_url.to_string());
         }
 
@@ -321,99 +1253,56 @@ impl Downloader {
 
         let mut handles = Vec::new();
         for (idx, url) in all_urls.into_iter().enumerate() {
-            let downloader_client = if idx < RACING_CONNECTIONS {
+            let client = if idx < racing_connections {
                 self.create_isolated_client()
             } else {
                 self.client.clone()
             };
-            let blob_cache = self.blob_cache.clone();
-            let token_cache = self.token_cache.clone();
-            let expected_sha256 = expected_sha256.to_string();
-            let name = name.clone();
-            let progress = progress.clone();
-            let done = done.clone();
-            let done_notify = done_notify.clone();
-            let body_download_gate = body_download_gate.clone();
+            let racer = Racer {
+                client,
+                blob_cache: self.blob_cache.clone(),
+                token_cache: self.token_cache.clone(),
+                url,
+                checksum: checksum.clone(),
+                name: name.clone(),
+                progress: progress.clone(),
+                done: done.clone(),
+                done_notify: done_notify.clone(),
+                body_download_gate: body_download_gate.clone(),
+                rate_limiter: self.rate_limiter.clone(),
+                signature: signature.cloned(),
+            };
+            let retry_policy = self.retry_policy;
+            let failure_budget = self.failure_budget.clone();
 
             let delay = Duration::from_millis(idx as u64 * RACING_STAGGER_MS);
 
             let handle = tokio::spawn(async move {
                 tokio::time::sleep(delay).await;
 
-                if done.load(Ordering::Acquire) {
-                    return Err(Error::NetworkFailure {
-                        message: "cancelled: another download finished first".to_string(),
-                    });
-                }
-
-                // Another racing task may have already created the final blob.
-                if blob_cache.has_blob(&expected_sha256) {
-                    if let (Some(cb), Some(n)) = (&progress, &name) {
-                        cb(InstallProgress::DownloadCompleted {
-                            name: n.clone(),
-                            total_bytes: 0,
-                        });
-                    }
-
-                    done.store(true, Ordering::Release);
-                    done_notify.notify_waiters();
-                    return Ok(blob_cache.blob_path(&expected_sha256));
-                }
-
-                let response =
-                    fetch_download_response_internal(&downloader_client, &token_cache, &url)
-                        .await?;
-
-                let _permit = tokio::select! {
-                    permit = body_download_gate.acquire_owned() => permit.map_err(|_| Error::NetworkFailure {
-                        message: "download permit closed unexpectedly".to_string(),
-                    })?,
-                    _ = done_notify.notified() => {
-                        return Err(Error::NetworkFailure {
-                            message: "cancelled: another download finished first".to_string(),
-                        });
-                    }
-                };
-
-                if done.load(Ordering::Acquire) {
-                    return Err(Error::NetworkFailure {
-                        message: "cancelled: another download finished first".to_string(),
-                    });
-                }
-
-                // Another racing task may have created the blob while we waited for the permit.
-                if blob_cache.has_blob(&expected_sha256) {
-                    if let (Some(cb), Some(n)) = (&progress, &name) {
-                        cb(InstallProgress::DownloadCompleted {
-                            name: n.clone(),
-                            total_bytes: 0,
-                        });
+                let mut attempt = 0;
+                loop {
+                    match racer.run().await {
+                        Err(e)
+                            if attempt < retry_policy.max_retries
+                                && is_retryable(&e)
+                                && !racer.done.load(Ordering::Acquire)
+                                && !failure_budget
+                                    .as_ref()
+                                    .is_some_and(FailureBudget::is_spent) =>
+                        {
+                            tracing::debug!(url = %racer.url, attempt, error = %e, "retrying download");
+                            tokio::time::sleep(retry_policy.delay_for(attempt)).await;
+                            attempt += 1;
+                        }
+                        result => return result,
                     }
-
-                    done.store(true, Ordering::Release);
-                    done_notify.notify_waiters();
-                    return Ok(blob_cache.blob_path(&expected_sha256));
-                }
-
-                let result = download_response_internal(
-                    &blob_cache,
-                    response,
-                    &expected_sha256,
-                    name,
-                    progress,
-                )
-                .await;
-
-                if result.is_ok() {
-                    done.store(true, Ordering::Release);
-                    done_notify.notify_waiters();
                 }
-
-                result
             });
 
             handles.push(handle);
         }
+        let _abort_on_drop = AbortOnDrop(handles.iter().map(|h| h.abort_handle()).collect());
 
         // Race all handles - return first success, keep trying on failures
         let mut pending = handles;
@@ -445,16 +1334,138 @@ impl Downloader {
     }
 }
 
+/// Stops the tasks behind these handles when dropped, so abandoning a
+/// download (e.g. on Ctrl-C) doesn't leave its connections writing
+struct AbortOnDrop(Vec<tokio::task::AbortHandle>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for handle in &self.0 {
+            handle.abort();
+        }
+    }
+}
+
+/// One racing connection for a single-stream download
+struct Racer {
+    client: reqwest::Client,
+    blob_cache: BlobCache,
+    token_cache: TokenCache,
+    url: String,
+    checksum: Checksum,
+    name: Option<String>,
+    progress: Option<DownloadProgressCallback>,
+    done: Arc<AtomicBool>,
+    done_notify: Arc<Notify>,
+    body_download_gate: Arc<Semaphore>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    signature: Option<SignatureCheck>,
+}
+
+impl Racer {
+    /// Make a single attempt. A fresh blob writer and hasher are used each time,
+    /// so a failed attempt never leaves partial data behind.
+    async fn run(&self) -> Result<PathBuf, Error> {
+        if self.done.load(Ordering::Acquire) {
+            return Err(Error::NetworkFailure {
+                message: "cancelled: another download finished first".to_string(),
+            });
+        }
+
+        let key = self.checksum.cache_key();
+
+        // Another racing task may have already created the final blob.
+        if self.blob_cache.has_blob(&key) {
+            if let (Some(cb), Some(n)) = (&self.progress, &self.name) {
+                cb(InstallProgress::DownloadCompleted {
+                    name: n.clone(),
+                    total_bytes: 0,
+                });
+            }
+
+            self.done.store(true, Ordering::Release);
+            self.done_notify.notify_waiters();
+            return Ok(self.blob_cache.blob_path(&key));
+        }
+
+        // Resume from any partial download left behind by an earlier attempt
+        let range_start = self.blob_cache.partial_len(&key);
+
+        let response = fetch_download_response_internal(
+            &self.client,
+            &self.token_cache,
+            &self.url,
+            range_start,
+        )
+        .await?;
+
+        let _permit = tokio::select! {
+            permit = self.body_download_gate.clone().acquire_owned() => permit.map_err(|_| Error::NetworkFailure {
+                message: "download permit closed unexpectedly".to_string(),
+            })?,
+            _ = self.done_notify.notified() => {
+                return Err(Error::NetworkFailure {
+                    message: "cancelled: another download finished first".to_string(),
+                });
+            }
+        };
+
+        if self.done.load(Ordering::Acquire) {
+            return Err(Error::NetworkFailure {
+                message: "cancelled: another download finished first".to_string(),
+            });
+        }
+
+        // Another racing task may have created the blob while we waited for the permit.
+        if self.blob_cache.has_blob(&key) {
+            if let (Some(cb), Some(n)) = (&self.progress, &self.name) {
+                cb(InstallProgress::DownloadCompleted {
+                    name: n.clone(),
+                    total_bytes: 0,
+                });
+            }
+
+            self.done.store(true, Ordering::Release);
+            self.done_notify.notify_waiters();
+            return Ok(self.blob_cache.blob_path(&key));
+        }
+
+        let writer = download_response_internal(
+            &self.blob_cache,
+            response,
+            range_start,
+            &self.checksum,
+            self.progress.clone().zip(self.name.clone()),
+            self.rate_limiter.as_deref(),
+            None,
+        )
+        .await?;
+        let result = commit_signed(writer, self.signature.as_ref()).await;
+
+        if result.is_ok() {
+            self.done.store(true, Ordering::Release);
+            self.done_notify.notify_waiters();
+        }
+
+        result
+    }
+}
+
 /// Fetch a successful download response with GHCR auth handling.
+/// A non-zero `range_start` requests only the bytes from that offset onwards.
 async fn fetch_download_response_internal(
     client: &reqwest::Client,
     token_cache: &TokenCache,
     url: &str,
+    range_start: u64,
 ) -> Result<reqwest::Response, Error> {
     // Try with cached token first (for GHCR URLs)
     let cached_token = get_cached_token_for_url_internal(token_cache, url).await;
 
     let mut request = client.get(url);
+    if range_start > 0 {
+        request = request.header(RANGE, format!("bytes={range_start}-"));
+    }
     if let Some(token) = &cached_token {
         request = request.header(
             AUTHORIZATION,
@@ -462,20 +1473,16 @@ async fn fetch_download_response_internal(
         );
     }
 
-    let response = request.send().await.map_err(|e| Error::NetworkFailure {
-        message: e.to_string(),
-    })?;
+    let response = request.send().await.map_err(|e| request_error("", e))?;
 
     let response = if response.status() == StatusCode::UNAUTHORIZED {
-        handle_auth_challenge_internal(client, token_cache, url, response).await?
+        handle_auth_challenge_internal(client, token_cache, url, range_start, response).await?
     } else {
         response
     };
 
     if !response.status().is_success() {
-        return Err(Error::NetworkFailure {
-            message: format!("HTTP {}", response.status()),
-        });
+        return Err(status_error(response.status()));
     }
 
     Ok(response)
@@ -498,6 +1505,7 @@ async fn handle_auth_challenge_internal(
     client: &reqwest::Client,
     token_cache: &TokenCache,
     url: &str,
+    range_start: u64,
     response: reqwest::Response,
 ) -> Result<reqwest::Response, Error> {
     let www_auth_header = response.headers().get(WWW_AUTHENTICATE);
@@ -517,22 +1525,19 @@ async fn handle_auth_challenge_internal(
 
     let token = fetch_bearer_token_internal(client, token_cache, www_auth).await?;
 
-    let response = client
-        .get(url)
-        .header(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
-        )
-        .send()
-        .await
-        .map_err(|e| Error::NetworkFailure {
-            message: e.to_string(),
-        })?;
+    let mut request = client.get(url).header(
+        AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+    );
+    if range_start > 0 {
+        request = request.header(RANGE, format!("bytes={range_start}-"));
+    }
+
+    let response = request.send().await.map_err(|e| request_error("", e))?;
 
     if response.status() == StatusCode::UNAUTHORIZED {
-        return Err(Error::NetworkFailure {
-            message: "authentication failed: token was rejected by server".to_string(),
-        });
+        // The token we were just issued was rejected; retrying won't help
+        return Err(status_error(response.status()));
     }
 
     Ok(response)
@@ -566,14 +1571,10 @@ async fn fetch_bearer_token_internal(
         .get(token_url)
         .send()
         .await
-        .map_err(|e| Error::NetworkFailure {
-            message: format!("token request failed: {e}"),
-        })?;
+        .map_err(|e| request_error("token request failed", e))?;
 
     if !response.status().is_success() {
-        return Err(Error::NetworkFailure {
-            message: format!("token request returned HTTP {}", response.status()),
-        });
+        return Err(status_error(response.status()));
     }
 
     let token_response: TokenResponse =
@@ -610,10 +1611,11 @@ fn server_supports_ranges(response: &reqwest::Response) -> bool {
         .unwrap_or(false)
 }
 
-fn calculate_chunk_ranges(file_size: u64) -> Vec<ChunkRange> {
-    let chunk_size = calculate_chunk_size(file_size);
+/// Split `start..file_size` into chunks sized for the bytes still to fetch
+fn calculate_chunk_ranges(start: u64, file_size: u64) -> Vec<ChunkRange> {
+    let chunk_size = calculate_chunk_size(file_size - start);
     let mut chunks = Vec::new();
-    let mut offset = 0;
+    let mut offset = start;
 
     while offset < file_size {
         let remaining = file_size - offset;
@@ -628,15 +1630,28 @@ fn calculate_chunk_ranges(file_size: u64) -> Vec<ChunkRange> {
     chunks
 }
 
+/// Whether a chunk that failed on `attempt` should be fetched again
+fn can_retry_chunk(ctx: &ChunkDownloadContext<'_>, attempt: u32) -> bool {
+    attempt < MAX_CHUNK_RETRIES
+        && !ctx
+            .failure_budget
+            .as_ref()
+            .is_some_and(FailureBudget::is_spent)
+}
+
 async fn download_chunk(
     ctx: &ChunkDownloadContext<'_>,
     chunk: &ChunkRange,
 ) -> Result<Vec<u8>, Error> {
-    let range_header = format!("bytes={}-{}", chunk.offset, chunk.offset + chunk.size - 1);
-
+    let chunk_end = chunk.offset + chunk.size - 1;
+    // Bytes received so far survive a failed attempt, so a retry only asks
+    // for the rest of the chunk
+    let mut chunk_data = Vec::with_capacity(chunk.size as usize);
     let mut last_error = None;
 
     for attempt in 0..=MAX_CHUNK_RETRIES {
+        let range_start = chunk.offset + chunk_data.len() as u64;
+        let range_header = format!("bytes={range_start}-{chunk_end}");
         let cached_token = get_cached_token_for_url_internal(ctx.token_cache, ctx.url).await;
 
         let mut request = ctx
@@ -683,41 +1698,38 @@ async fn download_chunk(
 
                 if let Some(content_range) = response.headers().get(CONTENT_RANGE) {
                     let range_str = content_range.to_str().unwrap_or("");
-                    if !range_str.contains(&format!(
-                        "{}-{}",
-                        chunk.offset,
-                        chunk.offset + chunk.size - 1
-                    )) {
+                    if !range_str.contains(&format!("{range_start}-{chunk_end}")) {
                         return Err(Error::NetworkFailure {
                             message: format!(
-                                "invalid content-range: expected bytes {}-{}, got: {}",
-                                chunk.offset,
-                                chunk.offset + chunk.size - 1,
-                                range_str
+                                "invalid content-range: expected bytes {range_start}-{chunk_end}, got: {range_str}"
                             ),
                         });
                     }
                 }
 
                 if !response.status().is_success() {
-                    last_error = Some(Error::NetworkFailure {
-                        message: format!("chunk download returned HTTP {}", response.status()),
-                    });
+                    last_error = Some(status_error(response.status()));
 
-                    if response.status().is_server_error() && attempt < MAX_CHUNK_RETRIES {
+                    if response.status().is_server_error() && can_retry_chunk(ctx, attempt) {
                         tokio::time::sleep(Duration::from_millis(100 * (1 << attempt))).await;
                         continue;
                     }
                     return Err(last_error.unwrap());
                 }
 
-                let mut chunk_data = Vec::with_capacity(chunk.size as usize);
                 let mut stream = response.bytes_stream();
+                let mut interrupted = None;
 
                 while let Some(item) = stream.next().await {
-                    let bytes = item.map_err(|e| Error::NetworkFailure {
-                        message: format!("failed to read chunk bytes: {e}"),
-                    })?;
+                    let bytes = match item {
+                        Ok(bytes) => bytes,
+                        Err(e) => {
+                            interrupted = Some(request_error("failed to read chunk bytes", e));
+                            break;
+                        }
+                    };
+
+                    throttle(ctx.rate_limiter.as_deref(), bytes.len()).await;
 
                     chunk_data.extend_from_slice(&bytes);
 
@@ -733,6 +1745,15 @@ async fn download_chunk(
                     }
                 }
 
+                if let Some(e) = interrupted {
+                    last_error = Some(e);
+                    if can_retry_chunk(ctx, attempt) {
+                        tokio::time::sleep(Duration::from_millis(100 * (1 << attempt))).await;
+                        continue;
+                    }
+                    return Err(last_error.unwrap());
+                }
+
                 if chunk_data.len() != chunk.size as usize {
                     return Err(Error::NetworkFailure {
                         message: format!(
@@ -746,12 +1767,10 @@ async fn download_chunk(
                 return Ok(chunk_data);
             }
             Err(e) => {
-                last_error = Some(Error::NetworkFailure {
-                    message: format!("chunk download failed: {e}"),
-                });
+                last_error = Some(request_error("chunk download failed", e));
 
                 // Retry on network errors
-                if attempt < MAX_CHUNK_RETRIES {
+                if can_retry_chunk(ctx, attempt) {
                     tokio::time::sleep(Duration::from_millis(100 * (1 << attempt))).await;
                     continue;
                 }
@@ -766,7 +1785,32 @@ async fn download_chunk(
 
 /// Download a file using parallel chunk requests
 async fn download_with_chunks(ctx: &ChunkedDownloadContext<'_>) -> Result<PathBuf, Error> {
-    let chunks = calculate_chunk_ranges(ctx.file_size);
+    // Create output file early for streaming writes. Chunks are written in
+    // order, so a partial left by an earlier attempt is a prefix of the blob
+    // and only the bytes after it need fetching.
+    let mut writer = ctx
+        .blob_cache
+        .start_resumable_write(&ctx.checksum.cache_key())
+        .map_err(|e| Error::NetworkFailure {
+            message: format!("failed to create blob writer: {e}"),
+        })?;
+    let resume_offset = match writer.resume_offset() {
+        offset if offset <= ctx.file_size => offset,
+        _ => 0,
+    };
+
+    let mut hasher = ctx.checksum.hasher();
+    writer
+        .truncate(resume_offset)
+        .and_then(|_| {
+            let mut partial = std::fs::File::open(writer.tmp_path())?;
+            std::io::copy(&mut partial, &mut hasher)
+        })
+        .map_err(|e| Error::NetworkFailure {
+            message: format!("failed to read partial download: {e}"),
+        })?;
+
+    let chunks = calculate_chunk_ranges(resume_offset, ctx.file_size);
 
     if let (Some(cb), Some(n)) = (&ctx.progress, &ctx.name) {
         cb(InstallProgress::DownloadStarted {
@@ -775,14 +1819,6 @@ async fn download_with_chunks(ctx: &ChunkedDownloadContext<'_>) -> Result<PathBu
         });
     }
 
-    // Create output file early for streaming writes
-    let mut writer = ctx
-        .blob_cache
-        .start_write(ctx.expected_sha256)
-        .map_err(|e| Error::NetworkFailure {
-            message: format!("failed to create blob writer: {e}"),
-        })?;
-
     // Track expected chunk sizes for validation
     let expected_chunks: BTreeMap<u64, u64> = chunks.iter().map(|c| (c.offset, c.size)).collect();
     let total_chunks = chunks.len();
@@ -790,11 +1826,15 @@ async fn download_with_chunks(ctx: &ChunkedDownloadContext<'_>) -> Result<PathBu
     // Channel to receive completed chunks
     let (chunk_tx, mut chunk_rx) = mpsc::unbounded_channel::<(Vec<u8>, u64)>();
 
-    let total_downloaded = Arc::new(AtomicU64::new(0));
+    let total_downloaded = Arc::new(AtomicU64::new(resume_offset));
+
+    // Chunks beyond the connections this download may open wait their turn
+    let connection_slots = ctx.connections.map(|n| Arc::new(Semaphore::new(n)));
 
     // Spawn download tasks and collect handles
     let mut handles = Vec::new();
     for chunk in chunks {
+        let connection_slots = connection_slots.clone();
         let client = ctx.client.clone();
         let token_cache = ctx.token_cache.clone();
         let url = ctx.url.to_string();
@@ -804,8 +1844,16 @@ async fn download_with_chunks(ctx: &ChunkedDownloadContext<'_>) -> Result<PathBu
         let name = ctx.name.clone();
         let chunk_tx = chunk_tx.clone();
         let file_size = ctx.file_size;
+        let rate_limiter = ctx.rate_limiter.clone();
+        let failure_budget = ctx.failure_budget.cloned();
 
         let handle = tokio::spawn(async move {
+            let _slot = match &connection_slots {
+                Some(slots) => Some(slots.acquire().await.map_err(|e| Error::NetworkFailure {
+                    message: format!("connection semaphore error: {e}"),
+                })?),
+                None => None,
+            };
             // Acquire permit from global semaphore
             let _permit = global_semaphore
                 .acquire()
@@ -822,6 +1870,8 @@ async fn download_with_chunks(ctx: &ChunkedDownloadContext<'_>) -> Result<PathBu
                 name: name.clone(),
                 file_size,
                 total_downloaded: total_downloaded.clone(),
+                rate_limiter,
+                failure_budget,
             };
 
             let chunk_data = download_chunk(&chunk_ctx, &chunk).await?;
@@ -842,10 +1892,9 @@ async fn download_with_chunks(ctx: &ChunkedDownloadContext<'_>) -> Result<PathBu
     drop(chunk_tx);
 
     // Track next expected offset for streaming writes
-    let mut next_expected_offset: u64 = 0;
+    let mut next_expected_offset = resume_offset;
     let mut received_chunks = BTreeMap::new(); // Only buffer out-of-order chunks
     let mut chunks_written = 0u64;
-    let mut hasher = Sha256::new();
 
     while let Some((chunk_data, offset)) = chunk_rx.recv().await {
         // Validate chunk size matches expected
@@ -914,11 +1963,13 @@ async fn download_with_chunks(ctx: &ChunkedDownloadContext<'_>) -> Result<PathBu
         });
     }
 
-    let actual_hash = format!("{:x}", hasher.finalize());
+    let actual_hash = hasher.finalize_hex();
 
-    if actual_hash != ctx.expected_sha256 {
+    if actual_hash != ctx.checksum.hex() {
+        // A corrupt partial must not be resumed from
+        writer.discard();
         return Err(Error::ChecksumMismatch {
-            expected: ctx.expected_sha256.to_string(),
+            expected: ctx.checksum.hex().to_string(),
             actual: actual_hash,
         });
     }
@@ -934,44 +1985,98 @@ async fn download_with_chunks(ctx: &ChunkedDownloadContext<'_>) -> Result<PathBu
         });
     }
 
+    commit_signed(writer, ctx.signature).await
+}
+
+/// Commit a fully written blob, but only once `signature` (if any) checks
+/// out, so an unverified blob never enters the cache
+async fn commit_signed(
+    writer: BlobWriter,
+    signature: Option<&SignatureCheck>,
+) -> Result<PathBuf, Error> {
+    if let Some(signature) = signature
+        && let Err(e) = signature.verify(writer.tmp_path()).await
+    {
+        writer.discard();
+        return Err(e);
+    }
     writer.commit()
 }
 
+/// Stream a response body into the blob cache, verifying its checksum, and
+/// return the writer for the caller to commit. If the request asked for bytes from `range_start` and the server honoured it
+/// with a 206, the body is appended to the existing partial download; a plain 200
+/// means the server ignored the range, so the partial is discarded and rewritten.
 async fn download_response_internal(
     blob_cache: &BlobCache,
     response: reqwest::Response,
-    expected_sha256: &str,
-    name: Option<String>,
-    progress: Option<DownloadProgressCallback>,
-) -> Result<PathBuf, Error> {
-    let total_bytes = response
+    range_start: u64,
+    checksum: &Checksum,
+    reporter: Option<(DownloadProgressCallback, String)>,
+    rate_limiter: Option<&RateLimiter>,
+    mut tee: Option<&mut StagedExtraction>,
+) -> Result<BlobWriter, Error> {
+    let content_length = response
         .headers()
         .get(CONTENT_LENGTH)
         .and_then(|v| v.to_str().ok())
         .and_then(|s| s.parse::<u64>().ok());
 
-    if let (Some(cb), Some(n)) = (&progress, &name) {
-        cb(InstallProgress::DownloadStarted {
-            name: n.clone(),
-            total_bytes,
+    let mut writer = blob_cache
+        .start_resumable_write(&checksum.cache_key())
+        .map_err(|e| Error::NetworkFailure {
+            message: format!("failed to create blob writer: {e}"),
+        })?;
+
+    let resumed = range_start > 0
+        && response.status() == StatusCode::PARTIAL_CONTENT
+        && content_range_start(&response) == Some(range_start)
+        && writer.resume_offset() >= range_start;
+
+    if response.status() == StatusCode::PARTIAL_CONTENT && !resumed {
+        // The tail we were sent doesn't line up with what's on disk; start over
+        writer.discard();
+        return Err(Error::NetworkFailure {
+            message: "partial response did not match the resumed download".to_string(),
         });
     }
 
-    let mut writer =
-        blob_cache
-            .start_write(expected_sha256)
+    let mut hasher = checksum.hasher();
+    let mut downloaded: u64 = 0;
+
+    if resumed {
+        // Another attempt may have appended past our range start in the meantime
+        writer
+            .truncate(range_start)
+            .and_then(|_| {
+                let mut partial = std::fs::File::open(writer.tmp_path())?;
+                std::io::copy(&mut partial, &mut hasher)
+            })
             .map_err(|e| Error::NetworkFailure {
-                message: format!("failed to create blob writer: {e}"),
+                message: format!("failed to read partial download: {e}"),
             })?;
+        downloaded = range_start;
+    } else {
+        writer.truncate(0).map_err(|e| Error::NetworkFailure {
+            message: format!("failed to reset partial download: {e}"),
+        })?;
+    }
+
+    let total_bytes = content_length.map(|len| len + downloaded);
+
+    if let Some((cb, n)) = &reporter {
+        cb(InstallProgress::DownloadStarted {
+            name: n.clone(),
+            total_bytes,
+        });
+    }
 
-    let mut hasher = Sha256::new();
     let mut stream = response.bytes_stream();
-    let mut downloaded: u64 = 0;
 
     while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(|e| Error::NetworkFailure {
-            message: format!("failed to read chunk: {e}"),
-        })?;
+        let chunk = chunk.map_err(|e| request_error("failed to read chunk", e))?;
+
+        throttle(rate_limiter, chunk.len()).await;
 
         downloaded += chunk.len() as u64;
         hasher.update(&chunk);
@@ -980,8 +2085,11 @@ async fn download_response_internal(
             .map_err(|e| Error::NetworkFailure {
                 message: format!("failed to write chunk: {e}"),
             })?;
+        if let Some(staged) = tee.as_deref_mut() {
+            staged.feed(&chunk).await;
+        }
 
-        if let (Some(cb), Some(n)) = (&progress, &name) {
+        if let Some((cb, n)) = &reporter {
             cb(InstallProgress::DownloadProgress {
                 name: n.clone(),
                 downloaded,
@@ -990,11 +2098,13 @@ async fn download_response_internal(
         }
     }
 
-    let actual_hash = format!("{:x}", hasher.finalize());
+    let actual_hash = hasher.finalize_hex();
 
-    if actual_hash != expected_sha256 {
+    if actual_hash != checksum.hex() {
+        // A corrupt partial must not be resumed from
+        writer.discard();
         return Err(Error::ChecksumMismatch {
-            expected: expected_sha256.to_string(),
+            expected: checksum.hex().to_string(),
             actual: actual_hash,
         });
     }
@@ -1004,14 +2114,28 @@ async fn download_response_internal(
         message: format!("failed to flush download: {e}"),
     })?;
 
-    if let (Some(cb), Some(n)) = (&progress, &name) {
+    if let Some((cb, n)) = &reporter {
         cb(InstallProgress::DownloadCompleted {
             name: n.clone(),
             total_bytes: downloaded,
         });
     }
 
-    writer.commit()
+    Ok(writer)
+}
+
+/// Parse the first byte offset out of a `Content-Range: bytes <start>-<end>/<total>` header
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .parse()
+        .ok()
 }
 
 /// Extract scope prefix from a GHCR URL for token cache matching.
@@ -1066,28 +2190,120 @@ fn parse_www_authenticate(header: &str) -> Result<(String, String, String), Erro
 
 pub struct DownloadRequest {
     pub url: String,
-    pub sha256: String,
+    /// Mirrors to try in order when `url` fails; they serve the same bottle,
+    /// so the checksum covers them all
+    pub fallback_urls: Vec<String>,
+    pub checksum: Checksum,
     pub name: String,
 }
 
-type InflightMap = HashMap<String, Arc<tokio::sync::broadcast::Sender<Result<PathBuf, String>>>>;
+type InflightMap = HashMap<String, Arc<tokio::sync::broadcast::Sender<Result<PathBuf, Error>>>>;
+
+/// Takes a download's inflight entry back out if the download is abandoned
+/// before finishing, so later requests for the blob start it again instead
+/// of waiting on it forever
+struct InflightEntry {
+    inflight: Arc<Mutex<InflightMap>>,
+    key: Option<String>,
+}
+
+impl Drop for InflightEntry {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        if let Ok(mut map) = self.inflight.try_lock() {
+            map.remove(&key);
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let inflight = self.inflight.clone();
+            runtime.spawn(async move {
+                inflight.lock().await.remove(&key);
+            });
+        }
+    }
+}
+
+/// Per-host connection limiter, lazily creating one semaphore per `host:port`
+#[derive(Clone)]
+struct HostLimiter {
+    limit: usize,
+    semaphores: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl HostLimiter {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            semaphores: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Reserve `connections` to the host of `url`
+    async fn acquire(&self, url: &str, connections: usize) -> Result<OwnedSemaphorePermit, Error> {
+        let key = host_key(url);
+        let semaphore = {
+            let mut map = self.semaphores.lock().await;
+            map.entry(key)
+                .or_insert_with(|| Arc::new(Semaphore::new(self.limit)))
+                .clone()
+        };
+
+        semaphore
+            .acquire_many_owned(connections.min(self.limit) as u32)
+            .await
+            .map_err(|e| Error::NetworkFailure {
+                message: format!("host semaphore error: {e}"),
+            })
+    }
+}
+
+/// The filesystem path behind a `file://` URL
+fn local_path(url: &str) -> Option<PathBuf> {
+    let url = reqwest::Url::parse(url).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+/// Key used to group downloads by server; unparseable URLs share one bucket
+fn host_key(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| {
+            let host = u.host_str()?.to_string();
+            Some(match u.port_or_known_default() {
+                Some(port) => format!("{host}:{port}"),
+                None => host,
+            })
+        })
+        .unwrap_or_default()
+}
 
+#[derive(Clone)]
 pub struct ParallelDownloader {
     downloader: Arc<Downloader>,
     semaphore: Arc<Semaphore>,
+    host_limiter: HostLimiter,
     inflight: Arc<Mutex<InflightMap>>,
+    /// Downloads of a batch that may fail before the rest are cancelled
+    batch_failures: u32,
 }
 
 impl ParallelDownloader {
     pub fn new(blob_cache: BlobCache) -> Self {
         let semaphore = Arc::new(Semaphore::new(GLOBAL_DOWNLOAD_CONCURRENCY));
         Self {
-            downloader: Arc::new(Downloader::with_semaphore(
-                blob_cache,
-                Some(semaphore.clone()),
-            )),
+            downloader: Arc::new(
+                Downloader::with_semaphore(blob_cache, Some(semaphore.clone()))
+                    .with_connections(RACING_CONNECTIONS),
+            ),
             semaphore,
+            host_limiter: HostLimiter::new(DEFAULT_PER_HOST_CONNECTIONS),
             inflight: Arc::new(Mutex::new(HashMap::new())),
+            batch_failures: BATCH_FAILURE_BUDGET,
         }
     }
 
@@ -1096,20 +2312,142 @@ impl ParallelDownloader {
     pub fn with_concurrency(blob_cache: BlobCache, concurrency: usize) -> Self {
         let semaphore = Arc::new(Semaphore::new(concurrency));
         Self {
-            downloader: Arc::new(Downloader::with_semaphore(
-                blob_cache,
-                Some(semaphore.clone()),
-            )),
+            downloader: Arc::new(
+                Downloader::with_semaphore(blob_cache, Some(semaphore.clone()))
+                    .with_connections(RACING_CONNECTIONS),
+            ),
             semaphore,
+            host_limiter: HostLimiter::new(DEFAULT_PER_HOST_CONNECTIONS),
             inflight: Arc::new(Mutex::new(HashMap::new())),
+            batch_failures: BATCH_FAILURE_BUDGET,
         }
     }
 
+    /// Cap the number of concurrent connections to any single host, counting
+    /// each connection a download races or each chunk it fetches at once.
+    /// The global concurrency limit still applies across all hosts.
+    pub fn with_per_host_limit(mut self, limit: usize) -> Self {
+        self.host_limiter = HostLimiter::new(limit);
+        let downloader = Downloader::clone(&self.downloader)
+            .with_connections(limit.clamp(1, RACING_CONNECTIONS));
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Cancel the rest of a `download_streaming` batch once `failures` of its
+    /// downloads have failed after all their retries (at least one)
+    pub fn with_batch_failure_budget(mut self, failures: u32) -> Self {
+        self.batch_failures = failures;
+        self
+    }
+
+    /// Cap combined download bandwidth at `bytes_per_sec`. The limit is global
+    /// across all concurrent downloads, not applied per file.
+    pub fn with_max_rate(mut self, bytes_per_sec: u64) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_max_rate(bytes_per_sec);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Only serve blobs already in the cache
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_offline(offline);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Redownload every blob, ignoring the cache
+    pub fn with_no_cache(mut self, no_cache: bool) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_no_cache(no_cache);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Re-hash cached blobs before using them
+    pub fn with_verify_cached(mut self, verify: bool) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_verify_cached(verify);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Unpack bottles into `store` while they download
+    pub fn with_streaming_extraction(mut self, store: Store) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_streaming_extraction(store);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Connect and stall timeout for every download
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_timeout(timeout);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Send every download through `proxy`
+    pub fn with_proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_proxy(proxy);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Redirect limits for every download
+    pub fn with_redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_redirect_policy(policy);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Require trusted signatures on new downloads
+    pub fn with_signature_verifier(mut self, verifier: SignatureVerifier) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_signature_verifier(verifier);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Fetch URLs starting with `from` from `to` instead
+    pub fn with_url_rewrite(mut self, from: &str, to: &str) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_url_rewrite(from, to);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
+    /// Accept downloads without a checksum, caching them under the digest
+    /// computed on first download
+    pub fn with_trust_on_first_use(mut self, trust: bool) -> Self {
+        let downloader = Downloader::clone(&self.downloader).with_trust_on_first_use(trust);
+        self.downloader = Arc::new(downloader);
+        self
+    }
+
     /// Remove a blob from the cache (used when extraction fails due to corruption)
     pub fn remove_blob(&self, sha256: &str) -> bool {
         self.downloader.remove_blob(sha256)
     }
 
+    /// Download `url` into the cache with nothing to check it against,
+    /// returning the blob and its sha256
+    pub async fn download_unverified(&self, url: &str) -> Result<(PathBuf, Checksum), Error> {
+        let (path, checksum, _) = self
+            .downloader
+            .download_unverified(url, &Checksum::Sha256(String::new()))
+            .await?;
+        Ok((path, checksum))
+    }
+
+    /// Download `url`, which has no checksum to verify, returning the blob and
+    /// the digest computed with `checksum`'s algorithm; see
+    /// [`Downloader::download_trusting_first_use`]
+    pub async fn download_trusting_first_use(
+        &self,
+        url: &str,
+        checksum: &Checksum,
+    ) -> Result<(PathBuf, Checksum), Error> {
+        self.downloader
+            .download_trusting_first_use(url, checksum, None, None)
+            .await
+    }
+
     /// Download a single file (used for retries after corruption)
     pub async fn download_single(
         &self,
@@ -1119,6 +2457,7 @@ impl ParallelDownloader {
         Self::download_with_dedup(
             self.downloader.clone(),
             self.semaphore.clone(),
+            self.host_limiter.clone(),
             self.inflight.clone(),
             request,
             progress,
@@ -1143,21 +2482,38 @@ impl ParallelDownloader {
             .map(|req| {
                 let downloader = self.downloader.clone();
                 let semaphore = self.semaphore.clone();
+                let host_limiter = self.host_limiter.clone();
                 let inflight = self.inflight.clone();
                 let progress = progress.clone();
 
                 tokio::spawn(async move {
-                    Self::download_with_dedup(downloader, semaphore, inflight, req, progress).await
+                    Self::download_with_dedup(
+                        downloader,
+                        semaphore,
+                        host_limiter,
+                        inflight,
+                        req,
+                        progress,
+                    )
+                    .await
                 })
             })
             .collect();
 
-        let mut results = Vec::with_capacity(handles.len());
-        for handle in handles {
-            let result = handle.await.map_err(|e| Error::NetworkFailure {
+        // The batch fails as soon as any download does, so stop the rest
+        // rather than letting them run out their retries
+        let _abort_on_drop = AbortOnDrop(handles.iter().map(|h| h.abort_handle()).collect());
+        let mut pending: futures_util::stream::FuturesUnordered<_> = handles
+            .into_iter()
+            .enumerate()
+            .map(|(index, handle)| async move { (index, handle.await) })
+            .collect();
+
+        let mut results = vec![PathBuf::new(); pending.len()];
+        while let Some((index, result)) = pending.next().await {
+            results[index] = result.map_err(|e| Error::NetworkFailure {
                 message: format!("task join error: {e}"),
             })??;
-            results.push(result);
         }
 
         Ok(results)
@@ -1170,189 +2526,957 @@ impl ParallelDownloader {
         &self,
         requests: Vec<DownloadRequest>,
         progress: Option<DownloadProgressCallback>,
-    ) -> mpsc::Receiver<Result<DownloadResult, Error>> {
+    ) -> mpsc::Receiver<Result<DownloadResult, FailedDownload>> {
         let (tx, rx) = mpsc::channel(requests.len().max(1));
 
+        let budget = FailureBudget::new(self.batch_failures);
+        let batch =
+            Arc::new(Downloader::clone(&self.downloader).with_failure_budget(budget.clone()));
         for (index, req) in requests.into_iter().enumerate() {
-            let downloader = self.downloader.clone();
+            let downloader = batch.clone();
+            let budget = budget.clone();
             let semaphore = self.semaphore.clone();
+            let host_limiter = self.host_limiter.clone();
             let inflight = self.inflight.clone();
             let progress = progress.clone();
             let tx = tx.clone();
             let name = req.name.clone();
-            let sha256 = req.sha256.clone();
+            let checksum = req.checksum.clone();
+
+            tokio::spawn(async move {
+                let started = Instant::now();
+                let download = Self::download_with_dedup(
+                    downloader,
+                    semaphore,
+                    host_limiter,
+                    inflight,
+                    req,
+                    progress,
+                );
+                // Nobody is waiting for the result any more once the receiver
+                // is dropped, so stop downloading; once too many of the batch
+                // have failed, give up on this one too
+                let result = tokio::select! {
+                    result = download => {
+                        if result.is_err() {
+                            budget.record_failure();
+                        }
+                        result
+                    }
+                    () = budget.spent() => Err(budget.cancelled_error()),
+                    () = tx.closed() => return,
+                };
+                let _ = tx
+                    .send(
+                        result
+                            .map(|blob_path| DownloadResult {
+                                name,
+                                checksum,
+                                blob_path,
+                                index,
+                                elapsed: started.elapsed(),
+                            })
+                            .map_err(|error| FailedDownload { index, error }),
+                    )
+                    .await;
+            });
+        }
+
+        rx
+    }
+
+    async fn download_with_dedup(
+        downloader: Arc<Downloader>,
+        semaphore: Arc<Semaphore>,
+        host_limiter: HostLimiter,
+        inflight: Arc<Mutex<InflightMap>>,
+        req: DownloadRequest,
+        progress: Option<DownloadProgressCallback>,
+    ) -> Result<PathBuf, Error> {
+        // Check if there's already an inflight request for this blob
+        let key = req.checksum.cache_key();
+        let mut receiver = {
+            let mut map = inflight.lock().await;
+
+            if let Some(sender) = map.get(&key) {
+                // Subscribe to existing inflight request
+                Some(sender.subscribe())
+            } else {
+                // Create a new broadcast channel for this request
+                let (tx, _) = tokio::sync::broadcast::channel(1);
+                map.insert(key.clone(), Arc::new(tx));
+                None
+            }
+        };
+
+        if let Some(ref mut rx) = receiver {
+            // Wait for the inflight request to complete
+            let result = rx.recv().await.map_err(|e| Error::NetworkFailure {
+                message: format!("broadcast recv error: {e}"),
+            })?;
+
+            return result;
+        }
+
+        // We're the first request for this sha256, do the actual download
+        let mut entry = InflightEntry {
+            inflight: inflight.clone(),
+            key: Some(key.clone()),
+        };
+        // Wait on the host before taking a global slot, so downloads queued
+        // behind a busy host don't keep other hosts waiting
+        let _host_permit = host_limiter
+            .acquire(
+                &req.url,
+                downloader.connections.unwrap_or(RACING_CONNECTIONS),
+            )
+            .await?;
+        let _permit = semaphore
+            .acquire()
+            .await
+            .map_err(|e| Error::NetworkFailure {
+                message: format!("semaphore error: {e}"),
+            })?;
+
+        let result = downloader
+            .download_with_fallbacks(
+                &req.url,
+                &req.fallback_urls,
+                &req.checksum,
+                Some(req.name),
+                progress,
+            )
+            .await;
+
+        // Notify waiters and clean up
+        entry.key = None;
+        {
+            let mut map = inflight.lock().await;
+            if let Some(sender) = map.remove(&key) {
+                let _ = sender.send(result.clone());
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn valid_checksum_passes() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = Downloader::new(blob_cache);
+
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let result = downloader.download(&url, sha256).await;
+
+        assert!(result.is_ok());
+        let blob_path = result.unwrap();
+        assert!(blob_path.exists());
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn url_rewrite_fetches_from_mirror() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/mirror/v2/homebrew/core/test/blobs/sha256:abc"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .expect(1..)
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let mirror = format!("{}/mirror/", mock_server.uri());
+        let downloader = Downloader::new(blob_cache).with_url_rewrite("https://ghcr.io/", &mirror);
+
+        let result = downloader
+            .download(
+                "https://ghcr.io/v2/homebrew/core/test/blobs/sha256:abc",
+                sha256,
+            )
+            .await;
+
+        assert_eq!(std::fs::read(result.unwrap()).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn mismatch_deletes_blob_and_errors() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let wrong_sha256 = "0000000000000000000000000000000000000000000000000000000000000000";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = Downloader::new(blob_cache);
+
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let result = downloader.download(&url, wrong_sha256).await;
+
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch { .. }));
+
+        let blob_path = tmp
+            .path()
+            .join("blobs")
+            .join(format!("{wrong_sha256}.tar.gz"));
+        assert!(!blob_path.exists());
+
+        let tmp_path = tmp
+            .path()
+            .join("tmp")
+            .join(format!("{wrong_sha256}.tar.gz.part"));
+        assert!(!tmp_path.exists());
+    }
+
+    #[tokio::test]
+    async fn trust_on_first_use_names_the_blob_by_its_computed_hash() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+
+        // Never the default
+        let err = Downloader::new(blob_cache.clone())
+            .download(&url, "")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument { .. }), "{err:?}");
+
+        let downloader = Downloader::new(blob_cache).with_trust_on_first_use(true);
+        let (blob_path, computed) = downloader
+            .download_trusting_first_use(&url, &Checksum::parse("tofu"), None, None)
+            .await
+            .unwrap();
+        assert_eq!(computed, Checksum::Sha256(sha256.to_string()));
+        assert_eq!(
+            blob_path,
+            tmp.path().join("blobs").join(format!("{sha256}.tar.gz"))
+        );
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
+        assert!(!tmp.path().join("blobs/unverified.tar.gz").exists());
+
+        // The blob is now found by its pinned checksum like any other
+        assert_eq!(downloader.download(&url, sha256).await.unwrap(), blob_path);
+        assert_eq!(downloader.download(&url, "").await.unwrap(), blob_path);
+    }
+
+    fn gzipped_tarball(content: &[u8]) -> Vec<u8> {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_path("pkg/hello.txt").unwrap();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, content).unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn streaming_extraction_populates_the_store_entry() {
+        let mock_server = MockServer::start().await;
+        let tarball = gzipped_tarball(b"hello");
+        let sha256 = format!("{:x}", Sha256::digest(&tarball));
+
+        Mock::given(method("GET"))
+            .and(path("/bottle.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(tarball.clone()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(&tmp.path().join("cache")).unwrap();
+        let store = Store::new(tmp.path()).unwrap();
+        let downloader = Downloader::new(blob_cache).with_streaming_extraction(store.clone());
+
+        let url = format!("{}/bottle.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, &sha256).await.unwrap();
+
+        assert_eq!(std::fs::read(blob_path).unwrap(), tarball);
+        let entry = store.entry_path(&sha256);
+        assert_eq!(
+            std::fs::read(entry.join("pkg/hello.txt")).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn streaming_extraction_leaves_nothing_behind_on_mismatch() {
+        let mock_server = MockServer::start().await;
+        let tarball = gzipped_tarball(b"hello");
+        let wrong_sha256 = "0000000000000000000000000000000000000000000000000000000000000000";
+
+        Mock::given(method("GET"))
+            .and(path("/bottle.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(tarball))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(&tmp.path().join("cache")).unwrap();
+        let store = Store::new(tmp.path()).unwrap();
+        let downloader = Downloader::new(blob_cache).with_streaming_extraction(store);
+
+        let url = format!("{}/bottle.tar.gz", mock_server.uri());
+        let err = downloader.download(&url, wrong_sha256).await.unwrap_err();
+
+        assert!(matches!(err, Error::ChecksumMismatch { .. }));
+        let leftovers: Vec<_> = std::fs::read_dir(tmp.path().join("store"))
+            .unwrap()
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn signed_download_is_kept_and_tampered_signature_is_rejected() {
+        use crate::signature::{SignaturePolicy, TEST_PUBLIC_KEY, TEST_SIGNATURE};
+
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        let tampered = TEST_SIGNATURE.replace("2BMlveyB", "2BMlveyC");
+
+        for (file, signature) in [("good", TEST_SIGNATURE), ("bad", tampered.as_str())] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{file}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/{file}.tar.gz.minisig")))
+                .respond_with(ResponseTemplate::new(200).set_body_string(signature))
+                .mount(&mock_server)
+                .await;
+        }
+
+        let make_downloader = |dir: &std::path::Path| {
+            let verifier =
+                SignatureVerifier::new(&[TEST_PUBLIC_KEY.to_string()], SignaturePolicy::Strict)
+                    .unwrap();
+            Downloader::new(BlobCache::new(dir).unwrap()).with_signature_verifier(verifier)
+        };
+
+        let good_dir = TempDir::new().unwrap();
+        let good = make_downloader(good_dir.path())
+            .download(&format!("{}/good.tar.gz", mock_server.uri()), sha256)
+            .await;
+        assert!(good.unwrap().exists());
+
+        let bad_dir = TempDir::new().unwrap();
+        let bad = make_downloader(bad_dir.path())
+            .download(&format!("{}/bad.tar.gz", mock_server.uri()), sha256)
+            .await;
+        assert!(matches!(bad, Err(Error::SignatureInvalid { .. })));
+        assert!(!BlobCache::new(bad_dir.path()).unwrap().has_blob(sha256));
+    }
+
+    #[tokio::test]
+    async fn local_bottles_are_held_to_required_signatures() {
+        use crate::signature::{SignaturePolicy, TEST_PUBLIC_KEY, TEST_SIGNATURE};
+
+        let bottles = TempDir::new().unwrap();
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        let tampered = TEST_SIGNATURE.replace("2BMlveyB", "2BMlveyC");
+        for (file, signature) in [("good", TEST_SIGNATURE), ("bad", tampered.as_str())] {
+            std::fs::write(
+                bottles.path().join(format!("{file}.tar.gz")),
+                b"hello world",
+            )
+            .unwrap();
+            std::fs::write(
+                bottles.path().join(format!("{file}.tar.gz.minisig")),
+                signature,
+            )
+            .unwrap();
+        }
+
+        let make_downloader = |dir: &std::path::Path| {
+            let verifier =
+                SignatureVerifier::new(&[TEST_PUBLIC_KEY.to_string()], SignaturePolicy::Strict)
+                    .unwrap();
+            Downloader::new(BlobCache::new(dir).unwrap()).with_signature_verifier(verifier)
+        };
+        let url = |file: &str| format!("file://{}/{file}.tar.gz", bottles.path().display());
+
+        let good_dir = TempDir::new().unwrap();
+        let good = make_downloader(good_dir.path())
+            .download(&url("good"), sha256)
+            .await;
+        assert!(good.unwrap().exists());
+
+        let bad_dir = TempDir::new().unwrap();
+        let bad = make_downloader(bad_dir.path())
+            .download(&url("bad"), sha256)
+            .await;
+        assert!(
+            matches!(bad, Err(Error::SignatureInvalid { .. })),
+            "{bad:?}"
+        );
+        assert!(!BlobCache::new(bad_dir.path()).unwrap().has_blob(sha256));
+    }
+
+    #[tokio::test]
+    async fn required_signature_survives_neither_fetch_errors_nor_cache_hits() {
+        use crate::signature::{SignaturePolicy, TEST_PUBLIC_KEY};
+
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        Mock::given(method("GET"))
+            .and(path("/flaky.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/flaky.tar.gz.minisig"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let strict = || {
+            let verifier =
+                SignatureVerifier::new(&[TEST_PUBLIC_KEY.to_string()], SignaturePolicy::Strict)
+                    .unwrap();
+            Downloader::new(blob_cache.clone()).with_signature_verifier(verifier)
+        };
+        let url = format!("{}/flaky.tar.gz", mock_server.uri());
+
+        // A failed signature fetch isn't "unsigned", and nothing is cached
+        let result = strict().download(&url, sha256).await;
+        assert!(
+            matches!(result, Err(Error::SignatureInvalid { .. })),
+            "{result:?}"
+        );
+        assert!(!blob_cache.has_blob(sha256));
+
+        // A blob cached without signatures is checked again on a hit
+        Downloader::new(blob_cache.clone())
+            .download(&url, sha256)
+            .await
+            .unwrap();
+        let result = strict().with_offline(true).download(&url, sha256).await;
+        assert!(
+            matches!(result, Err(Error::SignatureInvalid { .. })),
+            "{result:?}"
+        );
+        assert!(!blob_cache.has_blob(sha256));
+    }
+
+    #[tokio::test]
+    async fn blake3_checksum_validates_and_keys_the_cache() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = Downloader::new(blob_cache.clone());
+
+        let checksum = Checksum::Blake3(blake3::hash(content).to_hex().to_string());
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let blob_path = downloader
+            .download_with_progress(&url, &checksum, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
+        assert!(blob_cache.has_blob(&checksum.cache_key()));
+    }
+
+    #[tokio::test]
+    async fn blake3_mismatch_errors() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"hello world".to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = Downloader::new(blob_cache.clone());
+
+        // The sha256 of the content is not its blake3 digest
+        let checksum = Checksum::Blake3(
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9".to_string(),
+        );
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let result = downloader
+            .download_with_progress(&url, &checksum, None, None)
+            .await;
+
+        assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+        assert!(!blob_cache.has_blob(&checksum.cache_key()));
+    }
+
+    #[tokio::test]
+    async fn skips_download_if_blob_exists() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+
+        let mut writer = blob_cache.start_write(sha256).unwrap();
+        writer.write_all(content).unwrap();
+        writer.commit().unwrap();
+
+        let downloader = Downloader::new(blob_cache);
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let result = downloader.download(&url, sha256).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn verify_cached_redownloads_corrupt_blob() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .expect(1..)
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+
+        let mut writer = blob_cache.start_write(sha256).unwrap();
+        writer.write_all(b"hello wor").unwrap();
+        writer.commit().unwrap();
+
+        let downloader = Downloader::new(blob_cache).with_verify_cached(true);
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, sha256).await.unwrap();
+
+        assert_eq!(std::fs::read(blob_path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn no_cache_redownloads_a_valid_cached_blob() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .expect(1..)
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let mut writer = blob_cache.start_write(sha256).unwrap();
+        writer.write_all(content).unwrap();
+        writer.commit().unwrap();
+
+        let downloader = Downloader::new(blob_cache.clone()).with_no_cache(true);
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, sha256).await.unwrap();
+
+        assert_eq!(std::fs::read(blob_path).unwrap(), content);
+        assert!(blob_cache.has_blob(sha256));
+    }
+
+    #[tokio::test]
+    async fn retries_transient_server_errors() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/flaky.tar.gz"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/flaky.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = Downloader::with_retry_policy(
+            blob_cache,
+            RetryPolicy {
+                max_retries: 2,
+                base_delay: Duration::ZERO,
+            },
+        );
+
+        let url = format!("{}/flaky.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, sha256).await.unwrap();
+
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
+        let blobs: Vec<_> = std::fs::read_dir(tmp.path().join("blobs"))
+            .unwrap()
+            .collect();
+        assert_eq!(blobs.len(), 1);
+        let leftover_parts = std::fs::read_dir(tmp.path().join("tmp")).unwrap().count();
+        assert_eq!(leftover_parts, 0);
+    }
+
+    #[tokio::test]
+    async fn configured_proxy_carries_bottle_downloads() {
+        let proxy = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/bottles/proxied.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&proxy)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        // Whatever NO_PROXY the test runner has mustn't matter
+        let downloader =
+            Downloader::new(blob_cache).with_proxy(proxy_bypassing(&proxy.uri(), None).unwrap());
+
+        let blob_path = downloader
+            .download("http://bottles.invalid/bottles/proxied.tar.gz", sha256)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(blob_path).unwrap(), content);
+    }
+
+    #[test]
+    fn invalid_proxy_url_is_rejected() {
+        assert!(matches!(
+            parse_proxy("not a url"),
+            Err(Error::InvalidArgument { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn redirect_loop_fails_once_the_cap_is_reached() {
+        let mock_server = MockServer::start().await;
+        for (from, to) in [
+            ("/loop-a.tar.gz", "/loop-b.tar.gz"),
+            ("/loop-b.tar.gz", "/loop-a.tar.gz"),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(from))
+                .respond_with(ResponseTemplate::new(302).insert_header("Location", to))
+                .mount(&mock_server)
+                .await;
+        }
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = Downloader::new(blob_cache).with_redirect_policy(RedirectPolicy {
+            max_redirects: 3,
+            ..RedirectPolicy::default()
+        });
+
+        let result = downloader
+            .download(&format!("{}/loop-a.tar.gz", mock_server.uri()), "abc")
+            .await;
+        assert!(
+            matches!(result, Err(Error::TooManyRedirects { limit: 3, .. })),
+            "{result:?}"
+        );
+
+        // The loop isn't retried: each racing connection makes one request
+        // and follows three hops before giving up
+        let gets = mock_server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.method == wiremock::http::Method::GET)
+            .count();
+        assert_eq!(gets, 4 * RACING_CONNECTIONS);
+    }
 
-            tokio::spawn(async move {
-                let result =
-                    Self::download_with_dedup(downloader, semaphore, inflight, req, progress).await;
-                let _ = tx
-                    .send(result.map(|blob_path| DownloadResult {
-                        name,
-                        sha256,
-                        blob_path,
-                        index,
-                    }))
-                    .await;
+    #[tokio::test]
+    async fn pinned_mirror_refuses_redirects_to_other_hosts() {
+        let mock_server = MockServer::start().await;
+        // Same server, but under a different host name
+        let elsewhere = mock_server.uri().replace("127.0.0.1", "localhost");
+        Mock::given(method("GET"))
+            .and(path("/mirror/v2/homebrew/core/test/blobs/sha256:abc"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("Location", format!("{elsewhere}/cdn/test.tar.gz")),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let mirror = format!("{}/mirror/", mock_server.uri());
+        let downloader = Downloader::new(blob_cache)
+            .with_url_rewrite("https://ghcr.io/", &mirror)
+            .with_redirect_policy(RedirectPolicy {
+                pin_mirror_host: true,
+                ..RedirectPolicy::default()
             });
-        }
 
-        rx
+        let result = downloader
+            .download(
+                "https://ghcr.io/v2/homebrew/core/test/blobs/sha256:abc",
+                "abc",
+            )
+            .await;
+        assert!(
+            matches!(&result, Err(Error::CrossHostRedirect { to, .. }) if to.starts_with(&elsewhere)),
+            "{result:?}"
+        );
     }
 
-    async fn download_with_dedup(
-        downloader: Arc<Downloader>,
-        semaphore: Arc<Semaphore>,
-        inflight: Arc<Mutex<InflightMap>>,
-        req: DownloadRequest,
-        progress: Option<DownloadProgressCallback>,
-    ) -> Result<PathBuf, Error> {
-        // Check if there's already an inflight request for this sha256
-        let mut receiver = {
-            let mut map = inflight.lock().await;
+    #[tokio::test]
+    async fn unresponsive_server_times_out_instead_of_hanging() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/stalled.tar.gz"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_bytes(b"never sent".to_vec())
+                    .set_delay(Duration::from_secs(60)),
+            )
+            .mount(&mock_server)
+            .await;
 
-            if let Some(sender) = map.get(&req.sha256) {
-                // Subscribe to existing inflight request
-                Some(sender.subscribe())
-            } else {
-                // Create a new broadcast channel for this request
-                let (tx, _) = tokio::sync::broadcast::channel(1);
-                map.insert(req.sha256.clone(), Arc::new(tx));
-                None
-            }
-        };
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = Downloader::with_retry_policy(
+            blob_cache,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        )
+        .with_timeout(Duration::from_millis(300));
 
-        if let Some(ref mut rx) = receiver {
-            // Wait for the inflight request to complete
-            let result = rx.recv().await.map_err(|e| Error::NetworkFailure {
-                message: format!("broadcast recv error: {e}"),
-            })?;
+        let url = format!("{}/stalled.tar.gz", mock_server.uri());
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            downloader.download(&url, &"0".repeat(64)),
+        )
+        .await
+        .expect("download hung past its timeout");
 
-            return result.map_err(|msg| Error::NetworkFailure { message: msg });
-        }
+        assert!(matches!(result, Err(Error::Timeout)), "{result:?}");
+    }
 
-        // We're the first request for this sha256, do the actual download
-        let _permit = semaphore
-            .acquire()
-            .await
-            .map_err(|e| Error::NetworkFailure {
-                message: format!("semaphore error: {e}"),
-            })?;
+    #[tokio::test]
+    async fn resumes_partial_download_with_range_request() {
+        use wiremock::matchers::header;
 
-        let result = downloader
-            .download_with_progress(&req.url, &req.sha256, Some(req.name), progress)
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/resume.tar.gz"))
+            .and(header("Range", "bytes=6-"))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .append_header("Content-Range", "bytes 6-10/11")
+                    .set_body_bytes(content[6..].to_vec()),
+            )
+            .expect(1..)
+            .mount(&mock_server)
             .await;
 
-        // Notify waiters and clean up
-        {
-            let mut map = inflight.lock().await;
-            if let Some(sender) = map.remove(&req.sha256) {
-                let broadcast_result = match &result {
-                    Ok(path) => Ok(path.clone()),
-                    Err(e) => Err(e.to_string()),
-                };
-                let _ = sender.send(broadcast_result);
-            }
-        }
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        std::fs::write(blob_cache.partial_path(sha256), &content[..6]).unwrap();
 
-        result
-    }
-}
+        let downloader = Downloader::new(blob_cache.clone());
+        let url = format!("{}/resume.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, sha256).await.unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::time::Duration;
-    use tempfile::TempDir;
-    use wiremock::matchers::{method, path};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
+        assert!(!blob_cache.partial_path(sha256).exists());
+    }
 
     #[tokio::test]
-    async fn valid_checksum_passes() {
+    async fn restarts_when_server_ignores_range() {
         let mock_server = MockServer::start().await;
         let content = b"hello world";
         let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
 
         Mock::given(method("GET"))
-            .and(path("/test.tar.gz"))
+            .and(path("/norange.tar.gz"))
             .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
             .mount(&mock_server)
             .await;
 
         let tmp = TempDir::new().unwrap();
         let blob_cache = BlobCache::new(tmp.path()).unwrap();
-        let downloader = Downloader::new(blob_cache);
+        std::fs::write(blob_cache.partial_path(sha256), b"stale partial").unwrap();
 
-        let url = format!("{}/test.tar.gz", mock_server.uri());
-        let result = downloader.download(&url, sha256).await;
+        let downloader = Downloader::new(blob_cache);
+        let url = format!("{}/norange.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, sha256).await.unwrap();
 
-        assert!(result.is_ok());
-        let blob_path = result.unwrap();
-        assert!(blob_path.exists());
         assert_eq!(std::fs::read(&blob_path).unwrap(), content);
     }
 
     #[tokio::test]
-    async fn mismatch_deletes_blob_and_errors() {
-        let mock_server = MockServer::start().await;
+    async fn falls_back_to_the_next_mirror_when_one_fails() {
+        let primary = MockServer::start().await;
+        let fallback = MockServer::start().await;
         let content = b"hello world";
-        let wrong_sha256 = "0000000000000000000000000000000000000000000000000000000000000000";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
 
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1..)
+            .mount(&primary)
+            .await;
         Mock::given(method("GET"))
             .and(path("/test.tar.gz"))
             .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
-            .mount(&mock_server)
+            .expect(1..)
+            .mount(&fallback)
             .await;
 
         let tmp = TempDir::new().unwrap();
         let blob_cache = BlobCache::new(tmp.path()).unwrap();
-        let downloader = Downloader::new(blob_cache);
-
-        let url = format!("{}/test.tar.gz", mock_server.uri());
-        let result = downloader.download(&url, wrong_sha256).await;
+        let downloader = Downloader::with_retry_policy(
+            blob_cache,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        );
 
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        assert!(matches!(err, Error::ChecksumMismatch { .. }));
+        let blob_path = downloader
+            .download_with_fallbacks(
+                &format!("{}/test.tar.gz", primary.uri()),
+                &[
+                    format!("{}/test.tar.gz", fallback.uri()),
+                    "http://127.0.0.1:1/never-tried.tar.gz".to_string(),
+                ],
+                &Checksum::Sha256(sha256.to_string()),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
 
-        let blob_path = tmp
-            .path()
-            .join("blobs")
-            .join(format!("{wrong_sha256}.tar.gz"));
-        assert!(!blob_path.exists());
+        assert_eq!(
+            blob_path,
+            tmp.path().join("blobs").join(format!("{sha256}.tar.gz"))
+        );
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
 
-        let tmp_path = tmp
-            .path()
-            .join("tmp")
-            .join(format!("{wrong_sha256}.tar.gz.part"));
-        assert!(!tmp_path.exists());
+        // With every mirror down, the last one's error is returned
+        let err = downloader
+            .download_with_fallbacks(
+                &format!("{}/test.tar.gz", primary.uri()),
+                &[format!("{}/test.tar.gz", primary.uri())],
+                &Checksum::Sha256("0".repeat(64)),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err, Error::HttpStatus { code: 503 });
     }
 
     #[tokio::test]
-    async fn skips_download_if_blob_exists() {
+    async fn does_not_retry_client_errors() {
         let mock_server = MockServer::start().await;
-        let content = b"hello world";
-        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
 
         Mock::given(method("GET"))
-            .and(path("/test.tar.gz"))
-            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
-            .expect(0)
+            .and(path("/missing.tar.gz"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(RACING_CONNECTIONS as u64)
             .mount(&mock_server)
             .await;
 
         let tmp = TempDir::new().unwrap();
         let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = Downloader::with_retry_policy(
+            blob_cache,
+            RetryPolicy {
+                max_retries: 5,
+                base_delay: Duration::ZERO,
+            },
+        );
 
-        let mut writer = blob_cache.start_write(sha256).unwrap();
-        writer.write_all(content).unwrap();
-        writer.commit().unwrap();
-
-        let downloader = Downloader::new(blob_cache);
-        let url = format!("{}/test.tar.gz", mock_server.uri());
-        let result = downloader.download(&url, sha256).await;
+        let url = format!("{}/missing.tar.gz", mock_server.uri());
+        let sha256 = "0".repeat(64);
+        let err = downloader.download(&url, &sha256).await.unwrap_err();
 
-        assert!(result.is_ok());
+        assert_eq!(err, Error::HttpStatus { code: 404 });
     }
 
     #[tokio::test]
@@ -1389,7 +3513,8 @@ mod tests {
                 let sha256 = format!("{:064x}", i);
                 DownloadRequest {
                     url: format!("{}/file{i}.tar.gz", mock_server.uri()),
-                    sha256,
+                    fallback_urls: Vec::new(),
+                    checksum: Checksum::Sha256(sha256),
                     name: format!("pkg{i}"),
                 }
             })
@@ -1404,6 +3529,175 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn per_host_limit_caps_each_host_independently() {
+        use std::sync::Mutex as StdMutex;
+
+        type Arrivals = Arc<StdMutex<Vec<(String, Instant)>>>;
+
+        let delay = Duration::from_millis(100);
+        let servers = [MockServer::start().await, MockServer::start().await];
+        let arrivals: Vec<Arrivals> = (0..servers.len())
+            .map(|_| Arc::new(StdMutex::new(Vec::new())))
+            .collect();
+
+        let body_for = |host: usize, path: &str| format!("host{host}{path}").into_bytes();
+        for (host, (server, arrivals)) in servers.iter().zip(&arrivals).enumerate() {
+            let arrivals = arrivals.clone();
+            Mock::given(method("GET"))
+                .respond_with(move |req: &wiremock::Request| {
+                    arrivals
+                        .lock()
+                        .unwrap()
+                        .push((req.url.path().to_string(), Instant::now()));
+                    ResponseTemplate::new(200)
+                        .set_body_bytes(body_for(host, req.url.path()))
+                        .set_delay(delay)
+                })
+                .mount(server)
+                .await;
+        }
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader =
+            ParallelDownloader::with_concurrency(blob_cache, 16).with_per_host_limit(2);
+
+        let requests: Vec<_> = servers
+            .iter()
+            .enumerate()
+            .flat_map(|(host, server)| {
+                (0..6).map(move |i| {
+                    let path = format!("/file{i}.tar.gz");
+                    let mut hasher = Sha256::new();
+                    hasher.update(body_for(host, &path));
+                    DownloadRequest {
+                        url: format!("{}{path}", server.uri()),
+                        fallback_urls: Vec::new(),
+                        checksum: Checksum::Sha256(format!("{:x}", hasher.finalize())),
+                        name: format!("pkg{host}-{i}"),
+                    }
+                })
+            })
+            .collect();
+
+        let results = downloader.download_all(requests).await.unwrap();
+        assert_eq!(results.len(), 12);
+
+        for arrivals in &arrivals {
+            let arrivals = arrivals.lock().unwrap();
+            // A download may race several connections; its span runs from the
+            // first connection's arrival until at least `delay` after the last
+            let mut spans: HashMap<&str, (Instant, Instant)> = HashMap::new();
+            for (path, at) in arrivals.iter() {
+                let span = spans.entry(path).or_insert((*at, *at));
+                span.0 = span.0.min(*at);
+                span.1 = span.1.max(*at + delay);
+            }
+            assert_eq!(spans.len(), 6);
+
+            let peak = spans
+                .values()
+                .map(|(start, _)| {
+                    spans
+                        .values()
+                        .filter(|(s, e)| s <= start && e > start)
+                        .count()
+                })
+                .max()
+                .unwrap();
+            // Each download reserves both connections it may race, so a
+            // host serves one download at a time
+            assert!(peak <= 1, "per-host peak was {peak}, expected <= 1");
+        }
+    }
+
+    #[tokio::test]
+    async fn max_rate_paces_downloads() {
+        let mock_server = MockServer::start().await;
+        let content = vec![0x5a; 200 * 1024];
+        let sha256 = {
+            let mut hasher = Sha256::new();
+            hasher.update(&content);
+            format!("{:x}", hasher.finalize())
+        };
+
+        Mock::given(method("GET"))
+            .and(path("/throttled.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.clone()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = ParallelDownloader::new(blob_cache).with_max_rate(100 * 1024);
+
+        let start = Instant::now();
+        let path = downloader
+            .download_single(
+                DownloadRequest {
+                    url: format!("{}/throttled.tar.gz", mock_server.uri()),
+                    fallback_urls: Vec::new(),
+                    checksum: Checksum::Sha256(sha256),
+                    name: "throttled".to_string(),
+                },
+                None,
+            )
+            .await
+            .unwrap();
+
+        // One second of burst is free; the remaining 100 KiB takes a second
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(950),
+            "download finished in {elapsed:?}, faster than the rate limit allows"
+        );
+        assert_eq!(std::fs::read(path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn batch_is_cancelled_once_its_failure_budget_is_spent() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/stalled"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(60)))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let downloader = ParallelDownloader::new(blob_cache).with_batch_failure_budget(2);
+
+        let requests: Vec<_> = ["missing0", "missing1", "stalled0", "stalled1", "stalled2"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| DownloadRequest {
+                url: format!("{}/{name}.tar.gz", mock_server.uri()),
+                fallback_urls: Vec::new(),
+                checksum: Checksum::Sha256(format!("{i:064x}")),
+                name: name.to_string(),
+            })
+            .collect();
+
+        // The two failures spend the budget, so the stalled downloads are
+        // cancelled instead of waiting out their responses
+        let mut rx = downloader.download_streaming(requests, None);
+        let mut failed = 0;
+        while let Some(result) = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("batch was not cancelled")
+        {
+            assert!(result.is_err());
+            failed += 1;
+        }
+        assert_eq!(failed, 5);
+    }
+
     #[tokio::test]
     async fn same_blob_requested_multiple_times_fetches_once() {
         let mock_server = MockServer::start().await;
@@ -1435,7 +3729,8 @@ mod tests {
         let requests: Vec<_> = (0..5)
             .map(|i| DownloadRequest {
                 url: format!("{}/dedup.tar.gz", mock_server.uri()),
-                sha256: actual_sha256.clone(),
+                fallback_urls: Vec::new(),
+                checksum: Checksum::Sha256(actual_sha256.clone()),
                 name: format!("dedup{i}"),
             })
             .collect();
@@ -1524,6 +3819,74 @@ mod tests {
         assert_eq!(downloaded_content, large_content);
     }
 
+    #[tokio::test]
+    async fn chunked_download_resumes_from_partial_file() {
+        let mock_server = MockServer::start().await;
+
+        let large_content: Vec<u8> = (0..15 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let actual_sha256 = {
+            let mut hasher = Sha256::new();
+            hasher.update(&large_content);
+            format!("{:x}", hasher.finalize())
+        };
+
+        Mock::given(method("HEAD"))
+            .and(path("/large.tar.gz"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .append_header("Accept-Ranges", "bytes")
+                    .append_header("Content-Length", large_content.len().to_string()),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let range_starts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let range_starts_clone = range_starts.clone();
+        let content = large_content.clone();
+        Mock::given(method("GET"))
+            .and(path("/large.tar.gz"))
+            .respond_with(move |req: &wiremock::Request| {
+                let range = req.headers.get("Range").unwrap().to_str().unwrap();
+                let (start, end) = range
+                    .strip_prefix("bytes=")
+                    .unwrap()
+                    .split_once('-')
+                    .unwrap();
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                range_starts_clone.lock().unwrap().push(start);
+
+                ResponseTemplate::new(206)
+                    .append_header(
+                        "Content-Range",
+                        format!("bytes {start}-{end}/{}", content.len()),
+                    )
+                    .set_body_bytes(content[start..=end].to_vec())
+            })
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let blob_cache = BlobCache::new(tmp.path()).unwrap();
+        let already_written = 7 * 1024 * 1024;
+        std::fs::write(
+            blob_cache.partial_path(&actual_sha256),
+            &large_content[..already_written],
+        )
+        .unwrap();
+        let downloader = Downloader::new(blob_cache);
+
+        let url = format!("{}/large.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, &actual_sha256).await.unwrap();
+
+        assert_eq!(std::fs::read(&blob_path).unwrap(), large_content);
+        let range_starts = range_starts.lock().unwrap();
+        assert!(!range_starts.is_empty());
+        assert!(
+            range_starts.iter().all(|&start| start >= already_written),
+            "refetched bytes already on disk: {range_starts:?}"
+        );
+    }
+
     #[tokio::test]
     async fn fallback_to_normal_download_when_ranges_not_supported() {
         let mock_server = MockServer::start().await;
@@ -1,6 +1,8 @@
+use std::cell::Cell;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use flate2::read::GzDecoder;
 use tar::Archive;
@@ -27,65 +29,154 @@ fn detect_compression(path: &Path) -> Result<CompressionFormat, Error> {
         message: format!("failed to read magic bytes: {e}"),
     })?;
 
+    Ok(compression_from_magic(&magic[..bytes_read]))
+}
+
+fn compression_from_magic(magic: &[u8]) -> CompressionFormat {
+    let bytes_read = magic.len();
     if bytes_read < 2 {
-        return Ok(CompressionFormat::Unknown);
+        return CompressionFormat::Unknown;
     }
 
     // Gzip: 1f 8b
     if magic[0] == 0x1f && magic[1] == 0x8b {
-        return Ok(CompressionFormat::Gzip);
+        return CompressionFormat::Gzip;
     }
 
     // XZ: fd 37 7a 58 5a 00 (FD 7zXZ\0)
     if bytes_read >= 6 && magic[0..6] == [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00] {
-        return Ok(CompressionFormat::Xz);
+        return CompressionFormat::Xz;
     }
 
     // Zstd: 28 b5 2f fd
     if bytes_read >= 4 && magic[0..4] == [0x28, 0xb5, 0x2f, 0xfd] {
-        return Ok(CompressionFormat::Zstd);
+        return CompressionFormat::Zstd;
     }
 
-    Ok(CompressionFormat::Unknown)
+    CompressionFormat::Unknown
 }
 
+/// Called during extraction with the bytes decompressed so far and, when it
+/// can be told up front, how many there will be in all
+pub type ExtractProgress<'a> = &'a (dyn Fn(u64, Option<u64>) + Sync);
+
 pub fn extract_tarball(tarball_path: &Path, dest_dir: &Path) -> Result<(), Error> {
+    extract_tarball_with_progress(tarball_path, dest_dir, None)
+}
+
+/// Like [`extract_tarball`], reporting progress after each archive entry
+pub fn extract_tarball_with_progress(
+    tarball_path: &Path,
+    dest_dir: &Path,
+    progress: Option<ExtractProgress<'_>>,
+) -> Result<(), Error> {
     let format = detect_compression(tarball_path)?;
 
-    let file = File::open(tarball_path).map_err(|e| Error::StoreCorruption {
+    let mut file = File::open(tarball_path).map_err(|e| Error::StoreCorruption {
         message: format!("failed to open tarball: {e}"),
     })?;
-    let reader = BufReader::new(file);
+    let total = match (format, progress) {
+        (CompressionFormat::Gzip, Some(_)) => gzip_decompressed_size(&mut file),
+        _ => None,
+    };
+    extract_compressed(
+        BufReader::new(file),
+        format,
+        &tarball_path.display().to_string(),
+        dest_dir,
+        progress.map(|report| (report, total)),
+    )
+}
 
+/// The decompressed size gzip records in its trailer. It's only kept modulo
+/// 4 GiB, so a size below the compressed one is taken as wrapped and ignored.
+fn gzip_decompressed_size(file: &mut File) -> Option<u64> {
+    let compressed = file.metadata().ok()?.len();
+    let mut trailer = [0u8; 4];
+    file.seek(SeekFrom::End(-4)).ok()?;
+    file.read_exact(&mut trailer).ok()?;
+    file.seek(SeekFrom::Start(0)).ok()?;
+
+    let size = u64::from(u32::from_le_bytes(trailer));
+    (size >= compressed).then_some(size)
+}
+
+/// Extract a tarball as it is read, detecting the compression from its first bytes
+pub fn extract_tarball_stream<R: Read>(mut reader: R, dest_dir: &Path) -> Result<(), Error> {
+    let mut magic = Vec::with_capacity(6);
+    (&mut reader)
+        .take(6)
+        .read_to_end(&mut magic)
+        .map_err(|e| Error::StoreCorruption {
+            message: format!("failed to read magic bytes: {e}"),
+        })?;
+
+    let format = compression_from_magic(&magic);
+    let reader = BufReader::new(std::io::Cursor::new(magic).chain(reader));
+    extract_compressed(reader, format, "download stream", dest_dir, None)
+}
+
+fn extract_compressed<R: Read>(
+    reader: R,
+    format: CompressionFormat,
+    source: &str,
+    dest_dir: &Path,
+    progress: Option<(ExtractProgress<'_>, Option<u64>)>,
+) -> Result<(), Error> {
     match format {
         CompressionFormat::Gzip => {
             let decoder = GzDecoder::new(reader);
-            extract_tar_archive(decoder, dest_dir)
+            extract_tar_archive(decoder, dest_dir, progress)
         }
         CompressionFormat::Xz => {
             let decoder = XzDecoder::new(reader);
-            extract_tar_archive(decoder, dest_dir)
+            extract_tar_archive(decoder, dest_dir, progress)
         }
         CompressionFormat::Zstd => {
-            let decoder = ZstdDecoder::new(reader).map_err(|e| Error::StoreCorruption {
+            let decoder = ZstdDecoder::new(reader).map_err(|e| Error::ExtractionError {
                 message: format!("failed to create zstd decoder: {e}"),
             })?;
-            extract_tar_archive(decoder, dest_dir)
-        }
-        CompressionFormat::Unknown => {
-            // Try gzip as fallback
-            let decoder = GzDecoder::new(reader);
-            extract_tar_archive(decoder, dest_dir)
+            extract_tar_archive(decoder, dest_dir, progress)
         }
+        CompressionFormat::Unknown => Err(Error::ExtractionError {
+            message: format!("unrecognized compression format: {source}"),
+        }),
     }
 }
 
-fn extract_tar_archive<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error> {
-    let mut archive = Archive::new(reader);
+/// Counts the bytes read through it into a cell shared with its creator,
+/// which can't reach the reader once the archive owns it
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+fn extract_tar_archive<R: Read>(
+    reader: R,
+    dest_dir: &Path,
+    progress: Option<(ExtractProgress<'_>, Option<u64>)>,
+) -> Result<(), Error> {
+    let extracted = Rc::new(Cell::new(0));
+    let mut archive = Archive::new(CountingReader {
+        inner: reader,
+        count: extracted.clone(),
+    });
 
     archive.set_preserve_permissions(true);
     archive.set_unpack_xattrs(true);
 
+    // Symlinks whose targets resolve outside dest_dir. Writing any later entry
+    // through one of these would land outside the destination.
+    let mut escaping_links: Vec<PathBuf> = Vec::new();
+
     for entry in archive.entries().map_err(|e| Error::StoreCorruption {
         message: format!("failed to read archive entries: {e}"),
     })? {
@@ -93,9 +184,12 @@ fn extract_tar_archive<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error>
             message: format!("failed to read archive entry: {e}"),
         })?;
 
-        let entry_path = entry.path().map_err(|e| Error::StoreCorruption {
-            message: format!("failed to read entry path: {e}"),
-        })?;
+        let entry_path = entry
+            .path()
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to read entry path: {e}"),
+            })?
+            .into_owned();
 
         // Store path as owned string for error message
         let path_display = entry_path.display().to_string();
@@ -103,11 +197,50 @@ fn extract_tar_archive<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error>
         // Security check: validate path doesn't escape destination
         validate_path(&entry_path, dest_dir)?;
 
+        if let Some(link) = escaping_links
+            .iter()
+            .find(|link| entry_path.starts_with(link) && entry_path != **link)
+        {
+            return Err(Error::ExtractionError {
+                message: format!(
+                    "entry {path_display} would be written through symlink {} which points outside the destination",
+                    link.display()
+                ),
+            });
+        }
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry
+                .link_name()
+                .map_err(|e| Error::StoreCorruption {
+                    message: format!("failed to read link target of {path_display}: {e}"),
+                })?
+                .map(|target| target.into_owned())
+                .unwrap_or_default();
+
+            if entry_type.is_hard_link() {
+                // Hard link targets are archive paths and must stay inside, like any entry
+                validate_path(&target, dest_dir)?;
+            } else if !link_stays_within(&entry_path, &target) {
+                escaping_links.push(entry_path.clone());
+            }
+        }
+
         entry
             .unpack_in(dest_dir)
             .map_err(|e| Error::StoreCorruption {
                 message: format!("failed to unpack entry {path_display}: {e}"),
             })?;
+
+        if let Some((report, total)) = progress {
+            report(extracted.get(), total);
+        }
+    }
+
+    if let Some((report, total)) = progress {
+        // Account for the end-of-archive padding after the last entry
+        report(total.unwrap_or(extracted.get()).max(extracted.get()), total);
     }
 
     Ok(())
@@ -125,7 +258,7 @@ fn extract_tar_archive<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error>
 fn validate_path(path: &Path, dest_dir: &Path) -> Result<(), Error> {
     // Reject absolute paths
     if path.is_absolute() {
-        return Err(Error::StoreCorruption {
+        return Err(Error::ExtractionError {
             message: format!("absolute path in archive: {}", path.display()),
         });
     }
@@ -133,7 +266,7 @@ fn validate_path(path: &Path, dest_dir: &Path) -> Result<(), Error> {
     // Reject paths with .. components
     for component in path.components() {
         if let std::path::Component::ParentDir = component {
-            return Err(Error::StoreCorruption {
+            return Err(Error::ExtractionError {
                 message: format!("path traversal in archive: {}", path.display()),
             });
         }
@@ -149,7 +282,7 @@ fn validate_path(path: &Path, dest_dir: &Path) -> Result<(), Error> {
     let normalized_dest = normalize_path(dest_dir);
 
     if !normalized.starts_with(&normalized_dest) {
-        return Err(Error::StoreCorruption {
+        return Err(Error::ExtractionError {
             message: format!(
                 "path escapes destination directory: {} (normalized: {}) not within {}",
                 path.display(),
@@ -162,6 +295,23 @@ fn validate_path(path: &Path, dest_dir: &Path) -> Result<(), Error> {
     Ok(())
 }
 
+/// Whether a symlink at archive path `link` pointing at `target` resolves to a
+/// location inside the archive root. Checked lexically, relative to the link's
+/// parent directory.
+fn link_stays_within(link: &Path, target: &Path) -> bool {
+    use std::path::Component;
+
+    if target.is_absolute() {
+        return false;
+    }
+
+    let parent = link.parent().unwrap_or(Path::new(""));
+    !matches!(
+        normalize_path(&parent.join(target)).components().next(),
+        Some(Component::ParentDir)
+    )
+}
+
 /// Normalize a path by resolving . and .. components without filesystem access.
 ///
 /// This is safer than `canonicalize()` because:
@@ -221,7 +371,7 @@ fn normalize_path(path: &Path) -> PathBuf {
 /// For file-based extraction with auto-detection, use `extract_tarball` instead.
 pub fn extract_tarball_from_reader<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error> {
     let decoder = GzDecoder::new(reader);
-    extract_tar_archive(decoder, dest_dir)
+    extract_tar_archive(decoder, dest_dir, None)
 }
 
 #[cfg(test)]
@@ -291,6 +441,82 @@ mod tests {
         assert_eq!(content, "Hello, World!");
     }
 
+    #[test]
+    fn reports_progress_monotonically_up_to_the_decompressed_size() {
+        let tmp = TempDir::new().unwrap();
+        let big = vec![b'x'; 100_000];
+        let tarball = create_test_tarball(vec![
+            ("a.txt", b"first", None),
+            ("b.bin", &big, None),
+            ("c.txt", b"last", None),
+        ]);
+        let decompressed = {
+            let mut tar = Vec::new();
+            GzDecoder::new(&tarball[..]).read_to_end(&mut tar).unwrap();
+            tar.len() as u64
+        };
+
+        let tarball_path = tmp.path().join("test.tar.gz");
+        fs::write(&tarball_path, &tarball).unwrap();
+        let dest = tmp.path().join("extracted");
+        fs::create_dir(&dest).unwrap();
+
+        let calls = std::sync::Mutex::new(Vec::new());
+        let report = |extracted, total| calls.lock().unwrap().push((extracted, total));
+        extract_tarball_with_progress(&tarball_path, &dest, Some(&report)).unwrap();
+
+        let calls = calls.into_inner().unwrap();
+        assert!(calls.len() >= 3, "{calls:?}");
+        assert!(calls.windows(2).all(|w| w[0].0 <= w[1].0), "{calls:?}");
+        assert!(calls.iter().all(|(_, total)| *total == Some(decompressed)));
+        assert_eq!(calls.last().unwrap().0, decompressed);
+        assert!(calls[0].0 < decompressed);
+        assert_eq!(fs::read(dest.join("b.bin")).unwrap(), big);
+    }
+
+    #[test]
+    fn extracts_zstd_tarball() {
+        let tmp = TempDir::new().unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+        let content = b"compressed with zstd";
+        let mut header = tar::Header::new_gnu();
+        header.set_path("zstd.txt").unwrap();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, &content[..]).unwrap();
+        let tar_data = builder.into_inner().unwrap();
+
+        let tarball = zstd::stream::encode_all(&tar_data[..], 0).unwrap();
+        assert_eq!(&tarball[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+
+        let tarball_path = tmp.path().join("test.tar.zst");
+        fs::write(&tarball_path, &tarball).unwrap();
+
+        let dest = tmp.path().join("extracted");
+        fs::create_dir(&dest).unwrap();
+
+        extract_tarball(&tarball_path, &dest).unwrap();
+
+        let extracted = fs::read(dest.join("zstd.txt")).unwrap();
+        assert_eq!(extracted, content);
+    }
+
+    #[test]
+    fn rejects_unknown_compression() {
+        let tmp = TempDir::new().unwrap();
+
+        let tarball_path = tmp.path().join("plain.bin");
+        fs::write(&tarball_path, b"definitely not a tarball").unwrap();
+
+        let dest = tmp.path().join("extracted");
+        fs::create_dir(&dest).unwrap();
+
+        let err = extract_tarball(&tarball_path, &dest).unwrap_err();
+        assert!(matches!(err, Error::ExtractionError { .. }));
+    }
+
     #[test]
     fn preserves_executable_bit() {
         let tmp = TempDir::new().unwrap();
@@ -421,6 +647,83 @@ mod tests {
         assert!(err.to_string().contains("absolute path"));
     }
 
+    #[test]
+    fn rejects_traversal_and_absolute_members_without_writing() {
+        let tmp = TempDir::new().unwrap();
+        let dest = tmp.path().join("extracted");
+        fs::create_dir(&dest).unwrap();
+
+        let absolute_target = tmp.path().join("absolute-evil");
+        for member in [
+            b"../evil".to_vec(),
+            absolute_target.to_str().unwrap().as_bytes().to_vec(),
+        ] {
+            let tarball_path = tmp.path().join("evil.tar.gz");
+            fs::write(&tarball_path, create_malicious_tarball(&member)).unwrap();
+
+            let err = extract_tarball(&tarball_path, &dest).unwrap_err();
+            assert!(matches!(err, Error::ExtractionError { .. }), "{err}");
+        }
+
+        assert!(!tmp.path().join("evil").exists());
+        assert!(!absolute_target.exists());
+        assert_eq!(fs::read_dir(&dest).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn rejects_writes_through_escaping_symlink() {
+        let tmp = TempDir::new().unwrap();
+        let outside = tmp.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_size(0);
+        link.set_mode(0o777);
+        builder
+            .append_link(&mut link, "pkg/escape", "../../outside")
+            .unwrap();
+
+        let content = b"evil";
+        let mut file = tar::Header::new_gnu();
+        file.set_path("pkg/escape/evil.txt").unwrap();
+        file.set_size(content.len() as u64);
+        file.set_mode(0o644);
+        file.set_cksum();
+        builder.append(&file, &content[..]).unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+        let tarball_path = tmp.path().join("symlink.tar.gz");
+        fs::write(&tarball_path, encoder.finish().unwrap()).unwrap();
+
+        let dest = tmp.path().join("extracted");
+        fs::create_dir(&dest).unwrap();
+
+        let err = extract_tarball(&tarball_path, &dest).unwrap_err();
+        assert!(matches!(err, Error::ExtractionError { .. }), "{err}");
+        assert!(!outside.join("evil.txt").exists());
+    }
+
+    #[test]
+    fn allows_symlinks_that_stay_inside() {
+        assert!(link_stays_within(
+            Path::new("pkg/1.0/lib/libfoo.dylib"),
+            Path::new("libfoo.1.dylib")
+        ));
+        assert!(link_stays_within(
+            Path::new("pkg/1.0/bin/tool"),
+            Path::new("../libexec/tool")
+        ));
+        assert!(!link_stays_within(
+            Path::new("pkg/escape"),
+            Path::new("../../outside")
+        ));
+        assert!(!link_stays_within(Path::new("pkg/abs"), Path::new("/etc")));
+    }
+
     #[test]
     fn normalize_path_removes_dot_components() {
         let path = PathBuf::from("/foo/./bar/./baz");
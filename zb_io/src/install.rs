@@ -1,30 +1,99 @@
-use std::collections::BTreeMap;
-use std::path::Path;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use crate::api::ApiClient;
 use crate::blob::BlobCache;
+use crate::cache::ApiCache;
+use crate::checksum::Checksum;
 use crate::db::Database;
 use crate::download::{
     DownloadProgressCallback, DownloadRequest, DownloadResult, ParallelDownloader,
 };
-use crate::link::{LinkedFile, Linker};
-use crate::materialize::Cellar;
+use crate::link::{LinkOutcome, LinkRequest, LinkedFile, Linker};
+use crate::lock::FileLock;
+use crate::materialize::{Cellar, MaterializeMode};
 use crate::progress::{InstallProgress, ProgressCallback};
+use crate::signature::SignatureVerifier;
 use crate::store::Store;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
 
-use zb_core::{Error, Formula, SelectedBottle, resolve_closure, select_bottle};
+use zb_core::formula::{Bottle, BottleFile, BottleStable, FormulaSummary, Versions};
+use zb_core::{
+    DependencySpec, Error, Formula, SelectedBottle, Version, resolve_closure_with, select_bottle,
+    select_bottle_for,
+};
+
+/// What `cleanup` removed, or would remove on a dry run
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    pub blobs: Vec<PathBuf>,
+    pub kegs: Vec<PathBuf>,
+    pub bytes_reclaimed: u64,
+}
+
+/// What `gc` removed
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub store_entries: Vec<String>,
+    pub kegs: Vec<PathBuf>,
+    pub blobs: Vec<PathBuf>,
+    pub bytes_reclaimed: u64,
+}
+
+/// Total size of the regular files under `path`
+fn dir_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
 
 /// Maximum number of retries for corrupted downloads
 const MAX_CORRUPTION_RETRIES: usize = 3;
 
+/// Most "did you mean" names offered for a formula that doesn't exist
+const MAX_SUGGESTIONS: usize = 3;
+
+/// How long `gc` leaves an unreferenced blob alone after it was last used
+const GC_BLOB_GRACE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many times, 20ms apart, a stopped install looks for its abandoned
+/// downloads to release their resumable partials
+const PARTIAL_RELEASE_CHECKS: usize = 50;
+
 pub struct Installer {
     api_client: ApiClient,
+    blob_cache: BlobCache,
     downloader: ParallelDownloader,
     store: Store,
     cellar: Cellar,
     linker: Linker,
     db: Database,
+    include_build: bool,
+    /// Plan only the requested formulas, never their dependencies
+    ignore_dependencies: bool,
+    /// Install formulas whose license must be accepted first
+    accept_licenses: bool,
+    keep_going: bool,
+    /// Install bottles published without a checksum, keyed by their digest
+    trust_on_first_use: bool,
+    /// URL prefixes serving ghcr.io's bottles, tried in turn when a download fails
+    fallback_mirrors: Vec<String>,
+    /// Installed formulas the next `execute` builds fresh kegs for
+    reinstall: HashSet<String>,
+    /// Bottle tag to install instead of the detected platform's
+    bottle_tag: Option<String>,
+    /// When planning and installing give up with [`Error::Timeout`]
+    deadline: Option<tokio::time::Instant>,
+    unpack_concurrency: usize,
+    #[cfg(test)]
+    unpack_hook: Option<Arc<dyn Fn() + Send + Sync>>,
 }
 
 pub struct InstallPlan {
@@ -32,8 +101,206 @@ pub struct InstallPlan {
     pub bottles: Vec<SelectedBottle>,
 }
 
+impl InstallPlan {
+    /// Total bytes to download, or `None` if any bottle's size is unknown
+    pub fn download_size(&self) -> Option<u64> {
+        self.bottles.iter().map(|bottle| bottle.size).sum()
+    }
+
+    /// `(name, caveats)` for every planned formula that has any
+    pub fn caveats(&self) -> Vec<(&str, &str)> {
+        self.formulas
+            .iter()
+            .filter_map(|formula| {
+                let caveats = formula.caveats.as_deref()?.trim();
+                (!caveats.is_empty()).then_some((formula.name.as_str(), caveats))
+            })
+            .collect()
+    }
+}
+
+/// The first dependency of `formula` that is in `broken`
+fn broken_dependency(
+    formula: &Formula,
+    broken: &HashSet<&str>,
+    include_build: bool,
+) -> Option<String> {
+    formula
+        .install_dependencies(include_build)
+        .into_iter()
+        .find(|dep| broken.contains(dep.name.as_str()))
+        .map(|dep| dep.name)
+}
+
+/// Running byte counts across all downloads in one `execute`, throttled so the
+/// overall event fires at most every [`DownloadTally::INTERVAL`]
+#[derive(Default)]
+struct DownloadTally {
+    downloaded: HashMap<String, u64>,
+    completed: HashSet<String>,
+    last_report: Option<std::time::Instant>,
+}
+
+impl DownloadTally {
+    const INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+    fn total(&self) -> u64 {
+        self.downloaded.values().sum()
+    }
+
+    /// Record progress, returning `(downloaded, finished)` when it's time to report
+    fn progress(&mut self, name: &str, downloaded: u64) -> Option<(u64, bool)> {
+        self.downloaded.insert(name.to_string(), downloaded);
+        let now = std::time::Instant::now();
+        if self
+            .last_report
+            .is_some_and(|last| now.duration_since(last) < Self::INTERVAL)
+        {
+            return None;
+        }
+        self.last_report = Some(now);
+        Some((self.total(), false))
+    }
+
+    /// Record a finished download; always reports
+    fn complete(&mut self, name: &str, bytes: u64, packages: usize) -> Option<(u64, bool)> {
+        self.downloaded.insert(name.to_string(), bytes);
+        self.completed.insert(name.to_string());
+        Some((self.total(), self.completed.len() >= packages))
+    }
+}
+
 pub struct ExecuteResult {
     pub installed: usize,
+    /// What was installed, in plan order
+    pub packages: Vec<InstalledPackage>,
+    /// Packages already installed from the same bottle, which were left alone
+    pub already_installed: Vec<InstalledPackage>,
+    /// Packages that failed or were skipped, in plan order (only with keep-going)
+    pub failed: Vec<(String, Error)>,
+    /// How long each installed package spent in each phase, by name
+    pub timings: BTreeMap<String, PhaseTimings>,
+}
+
+/// Wall-clock time one package spent in each phase of an install. Phases of
+/// different packages overlap, so these don't add up to the total.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PhaseTimings {
+    /// Until the bottle was on disk, including any wait for a download slot
+    #[serde(serialize_with = "as_secs")]
+    pub download: Duration,
+    /// Unpacking the bottle into the store
+    #[serde(serialize_with = "as_secs")]
+    pub extract: Duration,
+    /// Populating the keg from the store entry
+    #[serde(serialize_with = "as_secs")]
+    pub materialize: Duration,
+    /// Linking the keg into the prefix
+    #[serde(serialize_with = "as_secs")]
+    pub link: Duration,
+}
+
+fn as_secs<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// One formula in an [`ExecuteResult`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    pub store_key: String,
+}
+
+/// What `verify` found for one installed formula
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub name: String,
+    pub problems: Vec<String>,
+    /// False when the store entry has no manifest to rehash against
+    pub store_checked: bool,
+}
+
+/// An installed formula with a newer stable version available
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutdatedKeg {
+    pub name: String,
+    pub installed: String,
+    pub available: String,
+    /// Pinned with `zb pin`, so upgrades leave it alone
+    pub pinned: bool,
+}
+
+/// Every formula a plan resolved to, with the exact version and bottle, as
+/// written by `zb freeze`. `zb install --locked` installs exactly this set.
+///
+/// Bottles differ between platforms, so a lockfile only records the ones
+/// for the platform (or `--bottle-tag`) it was frozen for. Installing it
+/// where another bottle would be picked fails with [`Error::LockMismatch`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub formulas: Vec<LockedFormula>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedFormula {
+    pub name: String,
+    /// The tap the formula came from, if not homebrew/core
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap: Option<String>,
+    pub version: String,
+    /// Bottle tag of the platform the plan selected a bottle for
+    pub tag: String,
+    /// sha256 of the bottle the plan selected
+    pub sha256: String,
+}
+
+impl LockedFormula {
+    /// The name to resolve the formula by, qualified with its tap if it has one
+    fn qualified_name(&self) -> String {
+        match &self.tap {
+            Some(tap) => format!("{tap}/{}", self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("{} ({} bottle {})", self.version, self.tag, self.sha256)
+    }
+}
+
+/// The installed formulas written by `zb export`, which `zb import`
+/// installs again on another machine
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportManifest {
+    pub formulas: Vec<ExportedFormula>,
+}
+
+/// One formula in an [`ExportManifest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedFormula {
+    pub name: String,
+    /// Installed version to ask for again; the current one is used without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+}
+
+/// What `zb import` will install
+pub struct ImportPlan {
+    pub plan: InstallPlan,
+    /// Formulas whose recorded version can't be found any more, planned at
+    /// their current version instead
+    pub unavailable: Vec<UnavailableVersion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnavailableVersion {
+    pub name: String,
+    pub recorded: String,
+    /// The version that will be installed
+    pub current: String,
 }
 
 /// Internal struct for tracking processed packages during streaming install
@@ -42,53 +309,95 @@ struct ProcessedPackage {
     name: String,
     version: String,
     store_key: String,
+    description: Option<String>,
+    tap: Option<String>,
     linked_files: Vec<LinkedFile>,
+    timings: PhaseTimings,
 }
 
-impl Installer {
-    pub fn new(
-        api_client: ApiClient,
-        blob_cache: BlobCache,
-        store: Store,
-        cellar: Cellar,
-        linker: Linker,
-        db: Database,
-    ) -> Self {
-        Self {
-            api_client,
-            downloader: ParallelDownloader::new(blob_cache),
-            store,
-            cellar,
-            linker,
-            db,
-        }
-    }
-
-    /// Resolve dependencies and plan the install
-    pub async fn plan(&self, names: &[String]) -> Result<InstallPlan, Error> {
-        // Recursively fetch all formulas we need
-        let formulas = self.fetch_all_formulas(names).await?;
-
-        // Resolve in topological order
-        let ordered = resolve_closure(names, &formulas)?;
+/// Everything needed to unpack a downloaded bottle away from the install
+/// loop, so several can be extracted and materialized at once
+#[derive(Clone)]
+struct Unpacker {
+    store: Store,
+    cellar: Cellar,
+    downloader: ParallelDownloader,
+    fallback_mirrors: Vec<String>,
+    #[cfg(test)]
+    hook: Option<Arc<dyn Fn() + Send + Sync>>,
+}
 
-        // Build list of formulas in order
-        let all_formulas: Vec<Formula> = ordered
-            .iter()
-            .map(|n| formulas.get(n).cloned().unwrap())
-            .collect();
+/// A package extracted into the store and materialized, waiting to be linked
+struct Unpacked {
+    keg_path: PathBuf,
+    /// Built beside an installed keg of the same version, to be swapped in
+    /// for it just before linking
+    replaces: bool,
+    /// Files relocation rewrote
+    relocated: Vec<PathBuf>,
+    /// Download, extract and materialize times; linking is timed later
+    timings: PhaseTimings,
+}
 
-        // Select bottles for each formula
-        let mut bottles = Vec::new();
-        for formula in &all_formulas {
-            let bottle = select_bottle(formula)?;
-            bottles.push(bottle);
+impl Unpacker {
+    /// Extract `download` into the store and materialize its keg. With
+    /// `replace`, the store entry is extracted afresh and the keg is only
+    /// staged, leaving the installed one in place.
+    async fn unpack(
+        &self,
+        download: &DownloadResult,
+        formula: &Formula,
+        bottle: &SelectedBottle,
+        progress: Option<DownloadProgressCallback>,
+        replace: bool,
+    ) -> Result<Unpacked, Error> {
+        // Hardlinked kegs share files with the store, so whatever was wrong
+        // with the keg may be wrong with the entry too
+        if replace {
+            self.store.remove_entry(&bottle.sha256)?;
         }
 
-        Ok(InstallPlan {
-            formulas: all_formulas,
-            bottles,
-        })
+        // Try extraction with retry logic for corrupted downloads
+        let extract_started = std::time::Instant::now();
+        let store_entry = self
+            .extract_with_retry(download, formula, bottle, progress)
+            .await?;
+        let mut timings = PhaseTimings {
+            download: download.elapsed,
+            extract: extract_started.elapsed(),
+            ..PhaseTimings::default()
+        };
+
+        let this = self.clone();
+        let name = formula.name.clone();
+        // Use effective_version() which includes rebuild suffix if applicable
+        let version = formula.effective_version();
+        self.store
+            .spawn_blocking(move || {
+                #[cfg(test)]
+                if let Some(hook) = &this.hook {
+                    hook();
+                }
+                let materialize_started = std::time::Instant::now();
+                let (keg_path, relocated) = if replace {
+                    let (_, relocated) = this.cellar.stage_keg(&name, &version, &store_entry)?;
+                    (this.cellar.keg_path(&name, &version), relocated)
+                } else {
+                    this.cellar
+                        .materialize_reporting(&name, &version, &store_entry)?
+                };
+                timings.materialize = materialize_started.elapsed();
+                Ok(Unpacked {
+                    keg_path,
+                    replaces: replace,
+                    relocated,
+                    timings,
+                })
+            })
+            .await
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("materialize task failed: {e}"),
+            })?
     }
 
     /// Try to extract a download, with automatic retry on corruption
@@ -103,7 +412,28 @@ impl Installer {
         let mut last_error = None;
 
         for attempt in 0..MAX_CORRUPTION_RETRIES {
-            match self.store.ensure_entry(&bottle.sha256, &blob_path) {
+            let store = self.store.clone();
+            let (key, path) = (bottle.sha256.clone(), blob_path.clone());
+            let (name, unpack_progress) = (formula.name.clone(), progress.clone());
+            let extracted = self
+                .store
+                .spawn_blocking(move || {
+                    let report = |extracted, total| {
+                        if let Some(cb) = &unpack_progress {
+                            cb(InstallProgress::UnpackProgress {
+                                name: name.clone(),
+                                extracted,
+                                total,
+                            });
+                        }
+                    };
+                    store.ensure_entry_with_progress(&key, &path, Some(&report))
+                })
+                .await
+                .map_err(|e| Error::StoreCorruption {
+                    message: format!("extraction task failed: {e}"),
+                })?;
+            match extracted {
                 Ok(entry) => return Ok(entry),
                 Err(Error::StoreCorruption { message }) => {
                     // Remove the corrupted blob
@@ -121,7 +451,8 @@ impl Installer {
                         // Re-download
                         let request = DownloadRequest {
                             url: bottle.url.clone(),
-                            sha256: bottle.sha256.clone(),
+                            fallback_urls: mirror_urls(&bottle.url, &self.fallback_mirrors),
+                            checksum: Checksum::Sha256(bottle.sha256.clone()),
                             name: formula.name.clone(),
                         };
 
@@ -158,710 +489,4902 @@ impl Installer {
             message: "extraction failed with unknown error".to_string(),
         }))
     }
+}
 
-    /// Recursively fetch a formula and all its dependencies in parallel batches
-    async fn fetch_all_formulas(
-        &self,
-        names: &[String],
-    ) -> Result<BTreeMap<String, Formula>, Error> {
-        use std::collections::HashSet;
-        use zb_core::select_bottle;
+impl Installer {
+    pub fn new(
+        api_client: ApiClient,
+        blob_cache: BlobCache,
+        store: Store,
+        cellar: Cellar,
+        linker: Linker,
+        db: Database,
+    ) -> Self {
+        Self {
+            api_client,
+            downloader: ParallelDownloader::new(blob_cache.clone()),
+            blob_cache,
+            store,
+            cellar,
+            linker,
+            db,
+            include_build: false,
+            ignore_dependencies: false,
+            accept_licenses: false,
+            keep_going: false,
+            trust_on_first_use: false,
+            fallback_mirrors: Vec::new(),
+            reinstall: HashSet::new(),
+            bottle_tag: None,
+            deadline: None,
+            unpack_concurrency: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            #[cfg(test)]
+            unpack_hook: None,
+        }
+    }
 
-        let mut formulas = BTreeMap::new();
-        let mut fetched: HashSet<String> = HashSet::new();
-        let mut to_fetch: Vec<String> = names.to_vec();
+    /// Choose how store entries are transferred into kegs
+    pub fn with_materialize_mode(mut self, mode: MaterializeMode) -> Self {
+        self.cellar = self.cellar.with_materialize_mode(mode);
+        self
+    }
 
-        while !to_fetch.is_empty() {
-            // Fetch current batch in parallel
-            let batch: Vec<String> = to_fetch
-                .drain(..)
-                .filter(|n| !fetched.contains(n))
-                .collect();
+    /// Rewrite library paths hardcoded to Homebrew's prefix to point into ours
+    pub fn with_relocation(mut self, relocate: bool) -> Self {
+        self.cellar = self.cellar.with_relocation(relocate);
+        self
+    }
 
-            if batch.is_empty() {
-                break;
-            }
+    /// Abandon planning and installing once `budget` has passed since this
+    /// call, cleaning up as a cancelled install does. Unlike
+    /// [`Self::with_timeout`] this bounds the whole operation, not a stall.
+    pub fn with_deadline(mut self, budget: Duration) -> Self {
+        self.deadline = Some(tokio::time::Instant::now() + budget);
+        self
+    }
 
-            // Mark as fetched before starting (to avoid re-queueing)
-            for n in &batch {
-                fetched.insert(n.clone());
-            }
+    /// Fail requests that can't connect, or stop receiving data, within `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.api_client = self.api_client.with_timeout(timeout);
+        self.downloader = self.downloader.with_timeout(timeout);
+        self
+    }
 
-            // Fetch all in parallel
-            let futures: Vec<_> = batch
-                .iter()
-                .map(|n| self.api_client.get_formula(n))
-                .collect();
+    /// Fetch formula metadata and bottles through `proxy` (see
+    /// [`crate::parse_proxy`]) rather than the environment's proxy settings
+    pub fn with_proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.api_client = self.api_client.with_proxy(proxy.clone());
+        self.downloader = self.downloader.with_proxy(proxy);
+        self
+    }
 
-            let results = futures::future::join_all(futures).await;
+    /// Apply `policy` to formula metadata requests and bottle downloads alike
+    pub fn with_redirect_policy(mut self, policy: crate::RedirectPolicy) -> Self {
+        self.api_client = self.api_client.with_redirect_policy(policy);
+        self.downloader = self.downloader.with_redirect_policy(policy);
+        self
+    }
 
-            // Process results and queue new dependencies
-            for (i, result) in results.into_iter().enumerate() {
-                let formula = match result {
-                    Ok(f) => f,
-                    Err(e) => return Err(e),
-                };
+    /// Cap combined bottle download bandwidth at `bytes_per_sec`
+    pub fn with_max_download_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.downloader = self.downloader.with_max_rate(bytes_per_sec);
+        self
+    }
 
-                // Check if this formula has a bottle for the current platform
-                // If not, skip it (it's likely a system-provided dependency on this platform)
-                if select_bottle(&formula).is_err() {
-                    eprintln!(
-                        "    Skipping {} (no bottle available for this platform)",
-                        formula.name
-                    );
-                    continue;
-                }
+    /// Keep the bottle cache under `max_bytes`, evicting least recently used bottles
+    pub fn with_cache_max_size(mut self, max_bytes: u64) -> Self {
+        self.blob_cache = self.blob_cache.with_max_size(max_bytes);
+        self
+    }
 
-                // Queue dependencies for next batch
-                for dep in &formula.dependencies {
-                    if !fetched.contains(dep) && !to_fetch.contains(dep) {
-                        to_fetch.push(dep.clone());
-                    }
-                }
+    /// Work only from cached formula JSON and bottles, never the network
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.api_client = self.api_client.with_offline(offline);
+        self.downloader = self.downloader.with_offline(offline);
+        self
+    }
 
-                formulas.insert(batch[i].clone(), formula);
-            }
-        }
+    /// Fetch formula metadata and bottles afresh instead of reading them from
+    /// the caches, refreshing the cached copies as it goes
+    pub fn with_no_cache(mut self, no_cache: bool) -> Self {
+        self.api_client = self.api_client.with_no_cache(no_cache);
+        self.downloader = self.downloader.with_no_cache(no_cache);
+        self
+    }
 
-        Ok(formulas)
+    /// Re-hash cached bottles before installing from them
+    pub fn with_verify_cached(mut self, verify: bool) -> Self {
+        self.downloader = self.downloader.with_verify_cached(verify);
+        self
     }
 
-    /// Execute the install plan
-    pub async fn execute(&mut self, plan: InstallPlan, link: bool) -> Result<ExecuteResult, Error> {
-        self.execute_with_progress(plan, link, None).await
+    /// Hardlink identical files across store entries. Set this before
+    /// [`Self::with_streaming_extraction`], which takes its own copy of the store.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.store = self.store.with_dedup(dedup);
+        self
     }
 
-    /// Execute the install plan with progress callback
-    /// Uses streaming extraction - starts extracting each package as soon as its download completes
-    pub async fn execute_with_progress(
-        &mut self,
-        plan: InstallPlan,
-        link: bool,
-        progress: Option<Arc<ProgressCallback>>,
-    ) -> Result<ExecuteResult, Error> {
-        let report = |event: InstallProgress| {
-            if let Some(ref cb) = progress {
-                cb(event);
-            }
-        };
+    /// Unpack bottles while they download rather than re-reading each blob
+    /// afterwards, saving a pass over the disk for large bottles
+    pub fn with_streaming_extraction(mut self, streaming: bool) -> Self {
+        if streaming {
+            self.downloader = self
+                .downloader
+                .with_streaming_extraction(self.store.clone());
+        }
+        self
+    }
 
-        // Pair formulas with bottles
-        let to_install: Vec<(Formula, SelectedBottle)> = plan
-            .formulas
-            .into_iter()
-            .zip(plan.bottles.into_iter())
-            .collect();
+    /// Check new bottle downloads against trusted minisign keys
+    pub fn with_signature_verifier(mut self, verifier: SignatureVerifier) -> Self {
+        self.downloader = self.downloader.with_signature_verifier(verifier);
+        self
+    }
 
-        if to_install.is_empty() {
-            return Ok(ExecuteResult { installed: 0 });
-        }
+    /// Resolve `formula.name` to `formula` rather than fetching it, e.g. one read
+    /// with [`ApiClient::formula_from_file`]
+    pub fn with_local_formula(mut self, formula: Formula) -> Self {
+        self.api_client = self.api_client.with_local_formula(formula);
+        self
+    }
 
-        // Download all bottles
-        let requests: Vec<DownloadRequest> = to_install
-            .iter()
-            .map(|(f, b)| DownloadRequest {
-                url: b.url.clone(),
-                sha256: b.sha256.clone(),
-                name: f.name.clone(),
-            })
-            .collect();
+    /// Fetch formula metadata from `url` instead of formulae.brew.sh
+    pub fn with_api_url(mut self, url: &str) -> Self {
+        self.api_client = self.api_client.with_api_url(url);
+        self
+    }
 
-        // Convert progress callback for download
-        let download_progress: Option<DownloadProgressCallback> = progress.clone().map(|cb| {
-            Arc::new(move |event: InstallProgress| {
-                cb(event);
-            }) as DownloadProgressCallback
-        });
+    /// Keep formula metadata in `cache` instead, e.g. the install database's
+    /// with [`ApiCache::in_database`]
+    pub fn with_api_cache(mut self, cache: ApiCache) -> Self {
+        self.api_client = self.api_client.with_cache(cache);
+        self
+    }
 
-        // Use streaming downloads - process each as it completes
-        let mut rx = self
+    /// Resolve `tap/formula` names from `tap` against `base_url`
+    pub fn with_tap(mut self, tap: &str, base_url: &str) -> Self {
+        self.api_client = self.api_client.with_tap(tap, base_url);
+        self
+    }
+
+    /// Whether formula names from `tap` can be installed
+    pub fn has_tap(&self, tap: &str) -> bool {
+        self.api_client.has_tap(tap)
+    }
+
+    /// Download ghcr.io bottles from `mirror` instead, keeping the rest of the path
+    pub fn with_bottle_mirror(mut self, mirror: &str) -> Self {
+        let mirror = format!("{}/", mirror.trim_end_matches('/'));
+        self.downloader = self
             .downloader
-            .download_streaming(requests, download_progress.clone());
+            .with_url_rewrite("https://ghcr.io/", &mirror);
+        self
+    }
 
-        // Track results by index to maintain install order for database records
-        let total = to_install.len();
-        let mut completed: Vec<Option<ProcessedPackage>> = vec![None; total];
-        let mut error: Option<Error> = None;
+    /// When a ghcr.io bottle fails to download, try it from each of `mirrors`
+    /// in turn, with the rest of the path kept as for [`Self::with_bottle_mirror`]
+    pub fn with_fallback_mirrors(mut self, mirrors: Vec<String>) -> Self {
+        self.fallback_mirrors = mirrors;
+        self
+    }
 
-        // Process downloads as they complete
-        while let Some(result) = rx.recv().await {
-            match result {
-                Ok(download) => {
-                    let idx = download.index;
-                    let (formula, bottle) = &to_install[idx];
+    /// Install bottles built for `tag` (e.g. `x86_64_linux`) instead of the
+    /// ones detected for this platform, as when running under emulation.
+    /// Formulas without a bottle for exactly that tag can't be installed.
+    pub fn with_bottle_tag(mut self, tag: &str) -> Self {
+        self.bottle_tag = Some(tag.to_string());
+        self
+    }
 
-                    report(InstallProgress::UnpackStarted {
-                        name: formula.name.clone(),
-                    });
+    /// Also install build-only dependencies, as a source build would need
+    pub fn with_build_dependencies(mut self, include_build: bool) -> Self {
+        self.include_build = include_build;
+        self
+    }
 
-                    // Try extraction with retry logic for corrupted downloads
-                    let store_entry = match self
-                        .extract_with_retry(&download, formula, bottle, download_progress.clone())
-                        .await
-                    {
-                        Ok(entry) => entry,
-                        Err(e) => {
-                            error = Some(e);
-                            continue;
-                        }
-                    };
+    /// Install only the requested formulas, without resolving or installing
+    /// any of their dependencies. For debugging a bottle in isolation; what
+    /// it installs may not run.
+    pub fn with_ignore_dependencies(mut self, ignore: bool) -> Self {
+        self.ignore_dependencies = ignore;
+        self
+    }
 
-                    // Materialize to cellar
-                    // Use effective_version() which includes rebuild suffix if applicable
-                    let keg_path = match self.cellar.materialize(
-                        &formula.name,
-                        &formula.effective_version(),
-                        &store_entry,
-                    ) {
-                        Ok(path) => path,
-                        Err(e) => {
-                            error = Some(e);
-                            continue;
-                        }
-                    };
+    /// Accept the licenses of formulas that require it. Without this,
+    /// executing a plan that would install one fails with
+    /// [`Error::LicenseNotAccepted`] before anything is downloaded.
+    pub fn with_accepted_licenses(mut self, accept: bool) -> Self {
+        self.accept_licenses = accept;
+        self
+    }
 
-                    report(InstallProgress::UnpackCompleted {
-                        name: formula.name.clone(),
-                    });
+    /// Extract and materialize up to `concurrency` bottles at once (defaults
+    /// to the number of available cores)
+    pub fn with_unpack_concurrency(mut self, concurrency: usize) -> Self {
+        self.unpack_concurrency = concurrency;
+        self
+    }
 
-                    // Link executables if requested
-                    let linked_files = if link {
-                        report(InstallProgress::LinkStarted {
-                            name: formula.name.clone(),
-                        });
-                        match self.linker.link_keg(&keg_path) {
-                            Ok(files) => {
-                                report(InstallProgress::LinkCompleted {
-                                    name: formula.name.clone(),
-                                });
-                                files
-                            }
-                            Err(e) => {
-                                error = Some(e);
-                                continue;
-                            }
-                        }
-                    } else {
-                        Vec::new()
-                    };
+    /// Keep installing the rest of a plan when a package fails, skipping only
+    /// what depends on it. Failures are returned in [`ExecuteResult::failed`].
+    pub fn with_keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
 
-                    // Report installation completed for this package
-                    report(InstallProgress::InstallCompleted {
-                        name: formula.name.clone(),
-                    });
+    /// Install bottles whose formula has no sha256: each is downloaded first
+    /// and recorded under the digest computed, which is printed so it can be
+    /// added to the formula. Off by default, refusing such bottles.
+    pub fn with_trust_on_first_use(mut self, trust: bool) -> Self {
+        self.trust_on_first_use = trust;
+        self.downloader = self.downloader.with_trust_on_first_use(trust);
+        self
+    }
 
-                    completed[idx] = Some(ProcessedPackage {
-                        name: formula.name.clone(),
-                        version: formula.effective_version(),
-                        store_key: bottle.sha256.clone(),
-                        linked_files,
-                    });
-                }
-                Err(e) => {
-                    error = Some(e);
-                }
-            }
-        }
+    /// Replace conflicting files and links in the prefix when linking
+    pub fn with_link_overwrite(mut self, overwrite: bool) -> Self {
+        self.linker = self.linker.with_overwrite(overwrite);
+        self
+    }
 
-        // Return error if any download failed
-        if let Some(e) = error {
-            return Err(e);
-        }
+    /// Resolve dependencies and plan the install
+    pub async fn plan(&self, names: &[String]) -> Result<InstallPlan, Error> {
+        self.plan_with_progress(names, None).await
+    }
 
-        // Record all successful installs in database (in order)
-        for processed in completed.into_iter().flatten() {
-            let tx = self.db.transaction()?;
-            tx.record_install(&processed.name, &processed.version, &processed.store_key)?;
+    /// Like [`Self::plan`], reporting [`InstallProgress::ResolveProgress`] as
+    /// each formula's metadata arrives
+    #[tracing::instrument(name = "resolve", skip(self, progress))]
+    pub async fn plan_with_progress(
+        &self,
+        names: &[String],
+        progress: Option<Arc<ProgressCallback>>,
+    ) -> Result<InstallPlan, Error> {
+        let names = &self.resolve_versions(names).await?;
 
-            for linked in &processed.linked_files {
-                tx.record_linked_file(
-                    &processed.name,
-                    &processed.version,
-                    &linked.link_path.to_string_lossy(),
-                    &linked.target_path.to_string_lossy(),
-                )?;
+        // Recursively fetch all formulas we need
+        let report = |event: InstallProgress| {
+            if let Some(ref cb) = progress {
+                cb(event);
             }
+        };
+        let formulas = within_deadline(
+            self.deadline,
+            self.fetch_all_formulas_reporting(names, &report),
+        )
+        .await
+        .ok_or(Error::Timeout)??;
 
-            tx.commit()?;
+        // Resolve in topological order
+        let ordered = if self.ignore_dependencies {
+            let mut seen = HashSet::new();
+            names
+                .iter()
+                .filter(|name| seen.insert(name.as_str()))
+                .cloned()
+                .collect()
+        } else {
+            resolve_closure_with(names, &formulas, self.include_build)?
+        };
+        tracing::debug!(packages = ?ordered, "resolved install order");
+
+        // Build list of formulas in order
+        let all_formulas: Vec<Formula> = ordered
+            .iter()
+            .map(|n| formulas.get(n).cloned().unwrap())
+            .collect();
+
+        // Select bottles for each formula
+        let mut bottles = Vec::new();
+        for formula in &all_formulas {
+            let bottle = self.select_bottle(formula)?;
+            bottles.push(bottle);
         }
 
-        Ok(ExecuteResult {
-            installed: to_install.len(),
+        Ok(InstallPlan {
+            formulas: all_formulas,
+            bottles,
         })
     }
 
-    /// Convenience method to plan and execute in one call
-    pub async fn install(&mut self, names: &[String], link: bool) -> Result<ExecuteResult, Error> {
-        let plan = self.plan(names).await?;
-        self.execute(plan, link).await
+    /// The bottle to install for `formula`: the one for the forced tag (or
+    /// the platform-independent `all` bottle) if there is one, otherwise the
+    /// best match for this platform
+    fn select_bottle(&self, formula: &Formula) -> Result<SelectedBottle, Error> {
+        match &self.bottle_tag {
+            Some(tag) => select_bottle_for(formula, &[tag.clone(), "all".to_string()]),
+            None => select_bottle(formula),
+        }
     }
 
-    /// Uninstall a formula
-    pub fn uninstall(&mut self, name: &str) -> Result<(), Error> {
-        // Check if installed
-        let installed = self.db.get_installed(name).ok_or(Error::NotInstalled {
-            name: name.to_string(),
-        })?;
-
-        // Unlink executables
-        let keg_path = self.cellar.keg_path(name, &installed.version);
-        self.linker.unlink_keg(&keg_path)?;
+    /// Turn each `name@version` into the formula that provides it: Homebrew's
+    /// versioned formula of that name if there is one, otherwise `name` itself
+    /// when its current version is `version` (or starts with `version.`)
+    async fn resolve_versions(&self, names: &[String]) -> Result<Vec<String>, Error> {
+        let mut resolved = Vec::with_capacity(names.len());
+        for requested in names {
+            let Some((name, version)) = requested.split_once('@') else {
+                resolved.push(requested.clone());
+                continue;
+            };
+            if version.eq_ignore_ascii_case("head") {
+                return Err(Error::HeadUnsupported {
+                    name: name.to_string(),
+                });
+            }
 
-        // Remove from database (decrements store ref)
-        {
-            let tx = self.db.transaction()?;
-            tx.record_uninstall(name)?;
-            tx.commit()?;
+            match self.api_client.get_formula(requested).await {
+                Ok(_) => resolved.push(requested.clone()),
+                Err(Error::MissingFormula { .. }) => {
+                    let formula = self.api_client.get_formula(name).await?;
+                    let stable = &formula.versions.stable;
+                    if stable == version
+                        || formula.effective_version() == version
+                        || stable.starts_with(&format!("{version}."))
+                    {
+                        resolved.push(name.to_string());
+                    } else {
+                        return Err(Error::VersionUnavailable {
+                            name: name.to_string(),
+                            version: version.to_string(),
+                            available: formula.effective_version(),
+                        });
+                    }
+                }
+                Err(e) => return Err(e),
+            }
         }
+        Ok(resolved)
+    }
 
-        // Remove cellar entry
-        self.cellar.remove_keg(name, &installed.version)?;
-
-        Ok(())
+    /// Plan `names` together, leaving out any of them that can't be resolved.
+    /// Returns the plan for the rest along with each unresolvable name and why.
+    pub async fn plan_partial(
+        &self,
+        names: &[String],
+    ) -> Result<(InstallPlan, Vec<(String, Error)>), Error> {
+        self.plan_partial_with_progress(names, None).await
     }
 
-    /// Garbage collect unreferenced store entries
-    pub fn gc(&mut self) -> Result<Vec<String>, Error> {
-        let unreferenced = self.db.get_unreferenced_store_keys()?;
-        let mut removed = Vec::new();
+    /// Like [`Self::plan_partial`], reporting resolve progress for the
+    /// first attempt at planning everything together
+    pub async fn plan_partial_with_progress(
+        &self,
+        names: &[String],
+        progress: Option<Arc<ProgressCallback>>,
+    ) -> Result<(InstallPlan, Vec<(String, Error)>), Error> {
+        match self.plan_with_progress(names, progress).await {
+            Ok(plan) => return Ok((plan, Vec::new())),
+            Err(e) if names.len() < 2 => return Err(e),
+            Err(_) => {}
+        }
 
-        for store_key in unreferenced {
-            self.store.remove_entry(&store_key)?;
-            removed.push(store_key);
+        let mut resolvable = Vec::new();
+        let mut failed = Vec::new();
+        for name in names {
+            match self.plan(std::slice::from_ref(name)).await {
+                Ok(_) => resolvable.push(name.clone()),
+                Err(e) => failed.push((name.clone(), e)),
+            }
         }
 
-        Ok(removed)
+        if resolvable.is_empty() {
+            return Err(failed.remove(0).1);
+        }
+        Ok((self.plan(&resolvable).await?, failed))
     }
 
-    /// Check if a formula is installed
-    pub fn is_installed(&self, name: &str) -> bool {
-        self.db.get_installed(name).is_some()
-    }
+    /// Plan installing what `names` depend on, leaving out `names` themselves
+    pub async fn plan_dependencies(&self, names: &[String]) -> Result<InstallPlan, Error> {
+        let names = &self.resolve_versions(names).await?;
+        let plan = self.plan(names).await?;
+        let (formulas, bottles) = plan
+            .formulas
+            .into_iter()
+            .zip(plan.bottles)
+            .filter(|(formula, _)| !names.contains(&formula.name))
+            .unzip();
 
-    /// Get info about an installed formula
-    pub fn get_installed(&self, name: &str) -> Option<crate::db::InstalledKeg> {
-        self.db.get_installed(name)
+        Ok(InstallPlan { formulas, bottles })
     }
 
-    /// List all installed formulas
-    pub fn list_installed(&self) -> Result<Vec<crate::db::InstalledKeg>, Error> {
-        self.db.list_installed()
-    }
+    /// Plan a single-formula install from an explicit bottle URL and checksum.
+    /// The formula API is not consulted and dependencies are not resolved.
+    pub fn plan_bottle(
+        &self,
+        name: &str,
+        version: &str,
+        url: &str,
+        sha256: &str,
+    ) -> Result<InstallPlan, Error> {
+        if name.is_empty() || name.contains('/') {
+            return Err(Error::InvalidArgument {
+                message: format!("invalid formula name '{name}'"),
+            });
+        }
+        if version.is_empty() || version.contains('/') {
+            return Err(Error::InvalidArgument {
+                message: format!("invalid version '{version}'"),
+            });
+        }
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::InvalidArgument {
+                message: format!("'{sha256}' is not a valid sha256 checksum"),
+            });
+        }
 
-    /// Get the path to a keg in the cellar
-    pub fn keg_path(&self, name: &str, version: &str) -> std::path::PathBuf {
-        self.cellar.keg_path(name, version)
-    }
-}
+        let sha256 = sha256.to_ascii_lowercase();
+        let mut files = BTreeMap::new();
+        files.insert(
+            "all".to_string(),
+            BottleFile {
+                url: url.to_string(),
+                sha256: sha256.clone(),
+                size: None,
+            },
+        );
 
-/// Create an Installer with standard paths
-pub fn create_installer(
-    root: &Path,
-    prefix: &Path,
-    concurrency: usize,
-) -> Result<Installer, Error> {
-    use std::fs;
+        let formula = Formula {
+            name: name.to_string(),
+            versions: Versions {
+                stable: version.to_string(),
+            },
+            dependencies: Vec::new(),
+            build_dependencies: Vec::new(),
+            bottle: Bottle {
+                stable: BottleStable { files, rebuild: 0 },
+            },
+            revision: 0,
+            keg_only: false,
+            keg_only_reason: None,
+            caveats: None,
+            desc: None,
+            homepage: None,
+            license: None,
+            requires_license_acceptance: false,
+            tap: None,
+        };
+        let bottle = SelectedBottle {
+            tag: "all".to_string(),
+            url: url.to_string(),
+            sha256,
+            size: None,
+        };
 
-    // First ensure the root directory exists
-    if !root.exists() {
-        fs::create_dir_all(root).map_err(|e| {
-            if e.kind() == std::io::ErrorKind::PermissionDenied {
-                Error::StoreCorruption {
-                    message: format!(
-                        "cannot create root directory '{}': permission denied.\n\n\
-                        Create it with:\n  sudo mkdir -p {} && sudo chown $USER {}",
-                        root.display(),
-                        root.display(),
-                        root.display()
-                    ),
-                }
-            } else {
-                Error::StoreCorruption {
-                    message: format!("failed to create root directory '{}': {e}", root.display()),
-                }
-            }
-        })?;
+        Ok(InstallPlan {
+            formulas: vec![formula],
+            bottles: vec![bottle],
+        })
     }
 
-    // Ensure all subdirectories exist
-    fs::create_dir_all(root.join("db")).map_err(|e| Error::StoreCorruption {
-        message: format!("failed to create db directory: {e}"),
-    })?;
+    /// Recursively fetch a formula and all its dependencies in parallel batches.
+    /// Only formula metadata is fetched; nothing is downloaded or installed.
+    /// Formulas with no bottle for this platform are left out.
+    pub async fn fetch_all_formulas(
+        &self,
+        names: &[String],
+    ) -> Result<BTreeMap<String, Formula>, Error> {
+        self.fetch_all_formulas_reporting(names, &|_| {}).await
+    }
 
-    let api_client = ApiClient::new();
-    let blob_cache = BlobCache::new(&root.join("cache")).map_err(|e| Error::StoreCorruption {
-        message: format!("failed to create blob cache: {e}"),
-    })?;
-    let store = Store::new(root).map_err(|e| Error::StoreCorruption {
-        message: format!("failed to create store: {e}"),
-    })?;
-    // Use prefix/Cellar so bottles' hardcoded rpaths work
-    let cellar = Cellar::new_at(prefix.join("Cellar")).map_err(|e| Error::StoreCorruption {
-        message: format!("failed to create cellar: {e}"),
-    })?;
-    let linker = Linker::new(prefix).map_err(|e| Error::StoreCorruption {
-        message: format!("failed to create linker: {e}"),
-    })?;
-    let db = Database::open(&root.join("db/zb.sqlite3"))?;
+    async fn fetch_all_formulas_reporting(
+        &self,
+        names: &[String],
+        report: &dyn Fn(InstallProgress),
+    ) -> Result<BTreeMap<String, Formula>, Error> {
+        use futures::StreamExt;
+        use std::collections::HashSet;
 
-    use crate::download::ParallelDownloader;
-    let parallel_downloader = ParallelDownloader::with_concurrency(blob_cache, concurrency);
-
-    Ok(Installer {
-        api_client,
-        downloader: parallel_downloader,
-        store,
-        cellar,
-        linker,
-        db,
-    })
-}
+        let mut formulas = BTreeMap::new();
+        let mut fetched: HashSet<String> = HashSet::new();
+        let mut to_fetch: Vec<String> = names.to_vec();
+        let mut resolved = 0;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::TempDir;
-    use wiremock::matchers::{method, path};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
+        while !to_fetch.is_empty() {
+            // Fetch current batch in parallel
+            let batch: Vec<String> = to_fetch
+                .drain(..)
+                .filter(|n| !fetched.contains(n))
+                .collect();
 
-    fn create_bottle_tarball(formula_name: &str) -> Vec<u8> {
-        use flate2::Compression;
-        use flate2::write::GzEncoder;
-        use std::io::Write;
-        use tar::Builder;
+            if batch.is_empty() {
+                break;
+            }
 
-        let mut builder = Builder::new(Vec::new());
+            // Mark as fetched before starting (to avoid re-queueing)
+            for n in &batch {
+                fetched.insert(n.clone());
+            }
 
-        // Create bin directory with executable
-        let mut header = tar::Header::new_gnu();
-        header
-            .set_path(format!("{}/1.0.0/bin/{}", formula_name, formula_name))
-            .unwrap();
-        header.set_size(20);
-        header.set_mode(0o755);
-        header.set_cksum();
+            // Fetch all in parallel, handling each as it arrives
+            let mut results: futures::stream::FuturesUnordered<_> = batch
+                .iter()
+                .enumerate()
+                .map(|(i, n)| async move { (i, self.api_client.get_formula(n).await) })
+                .collect();
 
-        let content = format!("#!/bin/sh\necho {}", formula_name);
-        builder.append(&header, content.as_bytes()).unwrap();
+            // Process results and queue new dependencies
+            while let Some((i, result)) = results.next().await {
+                let formula = match result {
+                    Ok(f) => f,
+                    Err(Error::MissingFormula { name, .. }) if names.contains(&batch[i]) => {
+                        let suggestions = self.similar_formula_names(&name).await;
+                        return Err(Error::MissingFormula { name, suggestions });
+                    }
+                    Err(e) => return Err(e),
+                };
+                resolved += 1;
+
+                // Check if this formula has a bottle for the current platform.
+                // A requested formula without one can't be installed at all,
+                // since zerobrew doesn't build from source; a dependency without
+                // one is likely provided by the system on this platform.
+                if let Err(e) = self.select_bottle(&formula) {
+                    if names.contains(&batch[i]) {
+                        return Err(e);
+                    }
+                    eprintln!(
+                        "    Skipping {} (no bottle available for this platform)",
+                        formula.name
+                    );
+                    continue;
+                }
 
-        let tar_data = builder.into_inner().unwrap();
+                tracing::debug!(
+                    name = %formula.name,
+                    version = %formula.effective_version(),
+                    "fetched formula"
+                );
 
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&tar_data).unwrap();
-        encoder.finish().unwrap()
-    }
+                // Queue dependencies for next batch
+                let dependencies = if self.ignore_dependencies {
+                    Vec::new()
+                } else {
+                    formula.install_dependencies(self.include_build)
+                };
+                for dep in dependencies {
+                    if !fetched.contains(&dep.name) && !to_fetch.contains(&dep.name) {
+                        to_fetch.push(dep.name);
+                    }
+                }
 
-    fn sha256_hex(data: &[u8]) -> String {
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        format!("{:x}", hasher.finalize())
+                formulas.insert(batch[i].clone(), formula);
+                report(InstallProgress::ResolveProgress {
+                    resolved,
+                    total_known: fetched.len() + to_fetch.len(),
+                });
+            }
+        }
+
+        Ok(formulas)
     }
 
-    fn get_test_bottle_tag() -> &'static str {
-        if cfg!(target_os = "linux") {
-            "x86_64_linux"
-        } else {
-            "arm64_sonoma"
+    /// Fail on the first formula `plan` would newly install whose license
+    /// must be accepted first
+    fn check_licenses(&self, plan: &InstallPlan) -> Result<(), Error> {
+        let gated = plan.formulas.iter().find(|formula| {
+            formula.requires_license_acceptance
+                && self
+                    .db
+                    .get_installed(&formula.name)
+                    .is_none_or(|keg| keg.version != formula.effective_version())
+        });
+        match gated {
+            Some(formula) => Err(Error::LicenseNotAccepted {
+                name: formula.name.clone(),
+                license: formula.license.clone(),
+            }),
+            None => Ok(()),
         }
     }
 
-    #[tokio::test]
-    async fn install_completes_successfully() {
-        let mock_server = MockServer::start().await;
-        let tmp = TempDir::new().unwrap();
-
-        // Create bottle
-        let bottle = create_bottle_tarball("testpkg");
+    /// Execute the install plan
+    pub async fn execute(&mut self, plan: InstallPlan, link: bool) -> Result<ExecuteResult, Error> {
+        self.execute_with_progress(plan, link, None).await
+    }
+
+    /// Like [`Self::execute_with_progress`], but stops as soon as `cancel`
+    /// completes: downloads in flight are abandoned and their partial files,
+    /// along with any half-unpacked store entry or keg, are removed. What had
+    /// already been completed is kept. Fails with [`Error::Cancelled`], or
+    /// [`Error::Timeout`] if the deadline passes first.
+    pub async fn execute_until(
+        &mut self,
+        plan: InstallPlan,
+        link: bool,
+        progress: Option<Arc<ProgressCallback>>,
+        cancel: impl std::future::Future<Output = ()>,
+    ) -> Result<ExecuteResult, Error> {
+        if !self.accept_licenses {
+            self.check_licenses(&plan)?;
+        }
+
+        let planned: Vec<(String, String, String)> = plan
+            .formulas
+            .iter()
+            .zip(&plan.bottles)
+            .map(|(f, b)| (f.name.clone(), f.effective_version(), b.sha256.clone()))
+            .collect();
+
+        let deadline = self.deadline;
+        let stopped = tokio::select! {
+            result = within_deadline(deadline, self.install_plan(plan, link, progress)) => {
+                match result {
+                    Some(result) => return result,
+                    None => Error::Timeout,
+                }
+            }
+            () = cancel => Error::Cancelled,
+        };
+
+        tracing::debug!(reason = %stopped, "install stopped, removing partial state");
+        // Extraction and materializing carry on in the background until done
+        self.store.wait_for_blocking().await;
+        for (name, version, sha256) in &planned {
+            self.remove_partials(sha256).await;
+            self.store.discard_staging(sha256);
+            self.cellar.discard_staging(name, version);
+        }
+        Err(stopped)
+    }
+
+    /// Remove the partial downloads of a stopped install. Abandoned downloads
+    /// let go of a resumable partial as their tasks wind down; one still
+    /// locked after that is another process's, and is left alone.
+    async fn remove_partials(&self, sha256: &str) {
+        for _ in 0..PARTIAL_RELEASE_CHECKS {
+            let _ = self.blob_cache.remove_partials(sha256);
+            if !self.blob_cache.partial_path(sha256).exists() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    /// Execute the install plan with progress callback
+    /// Uses streaming extraction - starts extracting each package as soon as its download completes
+    pub async fn execute_with_progress(
+        &mut self,
+        plan: InstallPlan,
+        link: bool,
+        progress: Option<Arc<ProgressCallback>>,
+    ) -> Result<ExecuteResult, Error> {
+        self.execute_until(plan, link, progress, std::future::pending())
+            .await
+    }
+
+    /// Download every bottle that has no checksum and give it the digest
+    /// computed, so its blob, store entry and install record are keyed by
+    /// the real hash rather than a placeholder
+    async fn hash_unknown_bottles(&self, bottles: &mut [SelectedBottle]) -> Result<(), Error> {
+        for bottle in bottles {
+            let checksum = Checksum::Sha256(bottle.sha256.clone());
+            if !checksum.is_unknown() {
+                continue;
+            }
+            let (_, computed) = self
+                .downloader
+                .download_trusting_first_use(&bottle.url, &checksum)
+                .await?;
+            bottle.sha256 = computed.hex().to_string();
+        }
+        Ok(())
+    }
+
+    /// Take the `gc` lock shared, keeping `zb gc` out until the install is
+    /// recorded, then the install lock of every bottle in `to_install`.
+    /// Those are taken up front and in sorted order, so two processes
+    /// installing overlapping sets can't each hold a lock the other is
+    /// waiting on.
+    async fn lock_installs(
+        &self,
+        to_install: &[(Formula, SelectedBottle)],
+    ) -> Result<Vec<FileLock>, Error> {
+        let mut keys: Vec<String> = to_install
+            .iter()
+            .map(|(_, bottle)| format!("install-{}", bottle.sha256))
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut locks = vec![store.lock_shared("gc")?];
+            for key in &keys {
+                locks.push(store.lock(key)?);
+            }
+            Ok(locks)
+        })
+        .await
+        .map_err(|e| Error::StoreCorruption {
+            message: format!("lock task failed: {e}"),
+        })?
+    }
+
+    async fn install_plan(
+        &mut self,
+        plan: InstallPlan,
+        link: bool,
+        progress: Option<Arc<ProgressCallback>>,
+    ) -> Result<ExecuteResult, Error> {
+        let report = |event: InstallProgress| {
+            if let Some(ref cb) = progress {
+                cb(event);
+            }
+        };
+
+        let mut bottles = plan.bottles;
+        if self.trust_on_first_use {
+            self.hash_unknown_bottles(&mut bottles).await?;
+        }
+
+        // Pair formulas with bottles, setting aside what's already installed
+        // from the same bottle unless it's to be reinstalled
+        let reinstall = std::mem::take(&mut self.reinstall);
+        let (present, to_install): (Vec<_>, Vec<_>) = plan
+            .formulas
+            .into_iter()
+            .zip(bottles)
+            .partition(|(f, b)| !reinstall.contains(&f.name) && self.is_installed_from(f, b));
+        let already_installed: Vec<InstalledPackage> = present
+            .into_iter()
+            .map(|(f, b)| InstalledPackage {
+                version: f.effective_version(),
+                name: f.name,
+                store_key: b.sha256,
+            })
+            .collect();
+
+        if to_install.is_empty() {
+            return Ok(ExecuteResult {
+                installed: 0,
+                packages: Vec::new(),
+                already_installed,
+                failed: Vec::new(),
+                timings: BTreeMap::new(),
+            });
+        }
+
+        report(InstallProgress::PlanSized {
+            total_bytes: to_install.iter().map(|(_, b)| b.size).sum(),
+            packages: to_install.len(),
+        });
+        let planned_sizes: HashMap<String, u64> = to_install
+            .iter()
+            .filter_map(|(f, b)| Some((f.name.clone(), b.size?)))
+            .collect();
+
+        // Nothing this install downloads may be evicted before it's unpacked
+        let _pins: Vec<_> = to_install
+            .iter()
+            .map(|(_, b)| self.blob_cache.pin(&b.sha256))
+            .collect();
+
+        // Download all bottles
+        let requests: Vec<DownloadRequest> = to_install
+            .iter()
+            .map(|(f, b)| DownloadRequest {
+                url: b.url.clone(),
+                fallback_urls: mirror_urls(&b.url, &self.fallback_mirrors),
+                checksum: Checksum::Sha256(b.sha256.clone()),
+                name: f.name.clone(),
+            })
+            .collect();
+
+        // Convert progress callback for download, falling back to the planned
+        // size when the server doesn't send a content length, and sum the
+        // in-flight downloads into an overall progress event
+        let planned_total: Option<u64> = to_install.iter().map(|(_, b)| b.size).sum();
+        let package_count = to_install.len();
+        let tally = Arc::new(Mutex::new(DownloadTally::default()));
+        let download_progress: Option<DownloadProgressCallback> = progress.clone().map(|cb| {
+            Arc::new(move |event: InstallProgress| {
+                let event = match event {
+                    InstallProgress::DownloadStarted {
+                        name,
+                        total_bytes: None,
+                    } => InstallProgress::DownloadStarted {
+                        total_bytes: planned_sizes.get(&name).copied(),
+                        name,
+                    },
+                    InstallProgress::DownloadProgress {
+                        name,
+                        downloaded,
+                        total_bytes: None,
+                    } => InstallProgress::DownloadProgress {
+                        total_bytes: planned_sizes.get(&name).copied(),
+                        name,
+                        downloaded,
+                    },
+                    event => event,
+                };
+
+                let overall = {
+                    let mut tally = tally.lock().unwrap();
+                    match &event {
+                        InstallProgress::DownloadProgress {
+                            name, downloaded, ..
+                        } => tally.progress(name, *downloaded),
+                        InstallProgress::DownloadCompleted { name, total_bytes } => {
+                            // Cache hits report zero bytes; count them at their planned size
+                            let bytes = match total_bytes {
+                                0 => planned_sizes.get(name).copied().unwrap_or(0),
+                                n => *n,
+                            };
+                            tally.complete(name, bytes, package_count)
+                        }
+                        _ => None,
+                    }
+                    .map(|(downloaded, finished)| {
+                        InstallProgress::OverallProgress {
+                            downloaded,
+                            total: if finished {
+                                Some(downloaded)
+                            } else {
+                                planned_total
+                            },
+                        }
+                    })
+                };
+
+                cb(event);
+                if let Some(overall) = overall {
+                    cb(overall);
+                }
+            }) as DownloadProgressCallback
+        });
+
+        // Held until linking is done: another zb process installing the same
+        // bottle waits on it
+        let _install_locks = self.lock_installs(&to_install).await?;
+
+        // Use streaming downloads - process each as it completes
+        let mut rx = self
+            .downloader
+            .download_streaming(requests, download_progress.clone());
+
+        // Unpack each bottle as soon as it arrives, several at a time, while
+        // the rest are still downloading
+        let unpacker = Unpacker {
+            store: self.store.clone(),
+            cellar: self.cellar.clone(),
+            downloader: self.downloader.clone(),
+            fallback_mirrors: self.fallback_mirrors.clone(),
+            #[cfg(test)]
+            hook: self.unpack_hook.clone(),
+        };
+        let unpack_slots = Arc::new(Semaphore::new(self.unpack_concurrency.max(1)));
+        let mut unpacking = tokio::task::JoinSet::new();
+        let total = to_install.len();
+        let mut unpacked: Vec<Option<Unpacked>> = (0..total).map(|_| None).collect();
+        let mut failures: Vec<(usize, Error)> = Vec::new();
+
+        while let Some(result) = rx.recv().await {
+            let download = match result {
+                Ok(download) => download,
+                Err(failure) => {
+                    failures.push((failure.index, failure.error));
+                    continue;
+                }
+            };
+            let idx = download.index;
+            let (formula, bottle) = to_install[idx].clone();
+            let replace =
+                reinstall.contains(&formula.name) && self.is_installed_from(&formula, &bottle);
+            let unpacker = unpacker.clone();
+            let slots = unpack_slots.clone();
+            let progress = progress.clone();
+            let download_progress = download_progress.clone();
+            unpacking.spawn(async move {
+                let _slot = slots.acquire_owned().await;
+                let report = |event: InstallProgress| {
+                    if let Some(ref cb) = progress {
+                        cb(event);
+                    }
+                };
+
+                report(InstallProgress::UnpackStarted {
+                    name: formula.name.clone(),
+                });
+                let result = unpacker
+                    .unpack(&download, &formula, &bottle, download_progress, replace)
+                    .await;
+                if let Ok(done) = &result {
+                    if !done.relocated.is_empty() {
+                        report(InstallProgress::Relocated {
+                            name: formula.name.clone(),
+                            files: done.relocated.clone(),
+                        });
+                    }
+                    report(InstallProgress::UnpackCompleted {
+                        name: formula.name.clone(),
+                    });
+                }
+                (idx, result)
+            });
+        }
+
+        while let Some(joined) = unpacking.join_next().await {
+            match joined {
+                Ok((idx, Ok(done))) => unpacked[idx] = Some(done),
+                Ok((idx, Err(e))) => failures.push((idx, e)),
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+
+        if !self.keep_going && !failures.is_empty() {
+            for (idx, done) in unpacked.iter().enumerate() {
+                if done.as_ref().is_some_and(|done| done.replaces) {
+                    let formula = &to_install[idx].0;
+                    self.cellar
+                        .discard_staging(&formula.name, &formula.effective_version());
+                }
+            }
+            return Err(failures.swap_remove(0).1);
+        }
+
+        // Skip whatever depends on a package that failed to download or
+        // unpack, since it can't work either
+        let mut broken: HashSet<&str> = failures
+            .iter()
+            .map(|(i, _)| to_install[*i].0.name.as_str())
+            .collect();
+        let mut ready: Vec<(usize, Unpacked)> = Vec::new();
+        for (idx, (formula, _)) in to_install.iter().enumerate() {
+            let Some(done) = unpacked[idx].take() else {
+                continue;
+            };
+            if let Some(dependency) = broken_dependency(formula, &broken, self.include_build) {
+                if done.replaces {
+                    self.cellar
+                        .discard_staging(&formula.name, &formula.effective_version());
+                }
+                broken.insert(&formula.name);
+                failures.push((
+                    idx,
+                    Error::DependencyFailed {
+                        name: formula.name.clone(),
+                        dependency,
+                    },
+                ));
+                continue;
+            }
+            // Only now that everything it needs has arrived is a reinstalled
+            // keg swapped in for the old one
+            if done.replaces
+                && let Err(e) = self.replace_keg(formula, &done.keg_path)
+            {
+                broken.insert(&formula.name);
+                failures.push((idx, e));
+                continue;
+            }
+            ready.push((idx, done));
+        }
+
+        // Link in waves: each wave is every remaining keg whose dependencies
+        // in this plan are already linked, and its kegs are linked at once.
+        // Whatever depends on a keg that failed to link is left unlinked.
+        let mut completed: Vec<Option<ProcessedPackage>> = vec![None; total];
+        let mut pending = ready;
+        while !pending.is_empty() {
+            let waiting: HashSet<&str> = pending
+                .iter()
+                .map(|(idx, _)| to_install[*idx].0.name.as_str())
+                .collect();
+            let (mut wave, rest): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(idx, _)| {
+                broken_dependency(&to_install[*idx].0, &waiting, self.include_build).is_none()
+            });
+            // Only a dependency cycle leaves nothing ready; link it all then
+            let rest = if wave.is_empty() {
+                wave = rest;
+                Vec::new()
+            } else {
+                rest
+            };
+
+            let linking: Vec<(&Formula, &Path)> = wave
+                .iter()
+                .map(|(idx, done)| (&to_install[*idx].0, done.keg_path.as_path()))
+                .collect();
+            let outcomes = self.link_unpacked(&linking, link, &report);
+            let mut linked: Vec<(usize, ProcessedPackage)> = Vec::new();
+            let mut link_failures: Vec<(usize, Error)> = Vec::new();
+            for ((idx, done), outcome) in wave.iter().zip(outcomes) {
+                let (formula, bottle) = &to_install[*idx];
+                match outcome.linked {
+                    Ok(linked_files) => {
+                        let timings = PhaseTimings {
+                            link: outcome.elapsed,
+                            ..done.timings
+                        };
+                        linked.push((
+                            *idx,
+                            ProcessedPackage {
+                                name: formula.name.clone(),
+                                version: formula.effective_version(),
+                                store_key: bottle.sha256.clone(),
+                                description: formula.desc.clone(),
+                                tap: formula.tap.clone(),
+                                linked_files,
+                                timings,
+                            },
+                        ));
+                    }
+                    Err(e) => link_failures.push((*idx, e)),
+                }
+            }
+
+            if !self.keep_going && !link_failures.is_empty() {
+                for processed in completed
+                    .iter()
+                    .flatten()
+                    .chain(linked.iter().map(|(_, processed)| processed))
+                {
+                    self.unlink_abandoned(processed);
+                }
+                return Err(link_failures.swap_remove(0).1);
+            }
+
+            for (idx, e) in link_failures {
+                broken.insert(&to_install[idx].0.name);
+                failures.push((idx, e));
+            }
+            for (idx, processed) in linked {
+                let timings = processed.timings;
+                tracing::debug!(
+                    name = %processed.name,
+                    download = ?timings.download,
+                    extract = ?timings.extract,
+                    materialize = ?timings.materialize,
+                    link = ?timings.link,
+                    "installed"
+                );
+                report(InstallProgress::InstallCompleted {
+                    name: processed.name.clone(),
+                });
+                completed[idx] = Some(processed);
+            }
+
+            pending = Vec::new();
+            for (idx, done) in rest {
+                let formula = &to_install[idx].0;
+                if let Some(dependency) = broken_dependency(formula, &broken, self.include_build) {
+                    broken.insert(&formula.name);
+                    failures.push((
+                        idx,
+                        Error::DependencyFailed {
+                            name: formula.name.clone(),
+                            dependency,
+                        },
+                    ));
+                    continue;
+                }
+                pending.push((idx, done));
+            }
+        }
+        failures.sort_by_key(|(idx, _)| *idx);
+        let failed: Vec<(String, Error)> = failures
+            .into_iter()
+            .map(|(idx, e)| (to_install[idx].0.name.clone(), e))
+            .collect();
+
+        let packages: Vec<InstalledPackage> = completed
+            .iter()
+            .flatten()
+            .map(|processed| InstalledPackage {
+                name: processed.name.clone(),
+                version: processed.version.clone(),
+                store_key: processed.store_key.clone(),
+            })
+            .collect();
+        let timings: BTreeMap<String, PhaseTimings> = completed
+            .iter()
+            .flatten()
+            .map(|processed| (processed.name.clone(), processed.timings))
+            .collect();
+
+        // Record all successful installs in database (in order)
+        let _db_lock = self.store.lock_async("db").await?;
+        for processed in completed.into_iter().flatten() {
+            // Re-read under the lock: another process may have recorded this
+            // formula since we looked
+            let current = self.db.get_installed(&processed.name);
+            let tx = self.db.transaction()?;
+            if let Some(old) = &current {
+                tx.record_replaced(old)?;
+            }
+            tx.record_install(&processed.name, &processed.version, &processed.store_key)?;
+            tx.record_description(&processed.name, processed.description.as_deref())?;
+            tx.record_tap(&processed.name, processed.tap.as_deref())?;
+            tx.record_history(
+                &processed.name,
+                current
+                    .as_ref()
+                    .map(|old| (old.version.as_str(), old.store_key.as_str())),
+                Some((&processed.version, &processed.store_key)),
+            )?;
+
+            for linked in &processed.linked_files {
+                tx.record_linked_file(
+                    &processed.name,
+                    &processed.version,
+                    &linked.link_path.to_string_lossy(),
+                    &linked.target_path.to_string_lossy(),
+                )?;
+            }
+
+            tx.commit()?;
+        }
+
+        Ok(ExecuteResult {
+            installed: packages.len(),
+            packages,
+            already_installed,
+            failed,
+            timings,
+        })
+    }
+
+    /// Unlink the installed keg at `keg_path` and swap the one staged for
+    /// `formula` in for it, relinking the old keg if that fails
+    fn replace_keg(&self, formula: &Formula, keg_path: &Path) -> Result<(), Error> {
+        let unlinked = self.linker.unlink_keg(keg_path)?;
+        if let Err(e) = self
+            .cellar
+            .replace_keg(&formula.name, &formula.effective_version())
+        {
+            let _ = self.linker.link_opt(keg_path);
+            if !unlinked.is_empty() {
+                let _ = self.linker.link_keg(keg_path);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Whether `formula` is installed from `bottle` with its keg still there,
+    /// so installing it again would change nothing
+    fn is_installed_from(&self, formula: &Formula, bottle: &SelectedBottle) -> bool {
+        let version = formula.effective_version();
+        self.db
+            .get_installed(&formula.name)
+            .is_some_and(|keg| keg.version == version && keg.store_key == bottle.sha256)
+            && self.cellar.has_keg(&formula.name, &version)
+    }
+
+    /// Link freshly materialized kegs, each replacing the links of any older
+    /// version, returning how each went in order. Independent kegs are
+    /// linked concurrently. Keg-only formulas only get their opt/ link.
+    fn link_unpacked(
+        &self,
+        kegs: &[(&Formula, &Path)],
+        link: bool,
+        report: &dyn Fn(InstallProgress),
+    ) -> Vec<LinkOutcome> {
+        let unlinked = || LinkOutcome {
+            linked: Ok(Vec::new()),
+            elapsed: Duration::ZERO,
+        };
+        if !link {
+            return kegs.iter().map(|_| unlinked()).collect();
+        }
+
+        // An older version's links would conflict with the new keg's
+        let old_kegs: Vec<Option<PathBuf>> = kegs
+            .iter()
+            .map(|(formula, _)| {
+                self.db
+                    .get_installed(&formula.name)
+                    .filter(|old| old.version != formula.effective_version())
+                    .map(|old| self.cellar.keg_path(&old.name, &old.version))
+            })
+            .collect();
+
+        let mut outcomes: Vec<Option<LinkOutcome>> = kegs.iter().map(|_| None).collect();
+        let mut requests = Vec::new();
+        let mut requested = Vec::new();
+        for (i, ((formula, keg_path), old_keg)) in kegs.iter().zip(&old_kegs).enumerate() {
+            if formula.keg_only {
+                let started = std::time::Instant::now();
+                let linked = self.linker.link_opt(keg_path).map(|()| Vec::new());
+                outcomes[i] = Some(LinkOutcome {
+                    linked,
+                    elapsed: started.elapsed(),
+                });
+                continue;
+            }
+            report(InstallProgress::LinkStarted {
+                name: formula.name.clone(),
+            });
+            requests.push(LinkRequest {
+                keg: keg_path,
+                replacing: old_keg.as_deref(),
+            });
+            requested.push(i);
+        }
+
+        for (i, outcome) in requested.into_iter().zip(self.linker.link_kegs(&requests)) {
+            let (formula, keg_path) = kegs[i];
+            match &outcome.linked {
+                Ok(_) => report(InstallProgress::LinkCompleted {
+                    name: formula.name.clone(),
+                }),
+                Err(_) => {
+                    // Drop any links made before the failure and put the
+                    // previous version back so nothing is left unlinked
+                    let _ = self.linker.unlink_keg(keg_path);
+                    if let Some(old_keg) = &old_kegs[i] {
+                        let _ = self.linker.link_keg(old_keg);
+                    }
+                }
+            }
+            outcomes[i] = Some(outcome);
+        }
+
+        outcomes.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Undo the links of a package that was unpacked but won't be recorded,
+    /// putting back the previously installed version's links if there was one
+    fn unlink_abandoned(&self, processed: &ProcessedPackage) {
+        if processed.linked_files.is_empty() {
+            return;
+        }
+        let keg = self.cellar.keg_path(&processed.name, &processed.version);
+        let _ = self.linker.unlink_keg(&keg);
+        if let Some(old) = self
+            .db
+            .get_installed(&processed.name)
+            .filter(|old| old.version != processed.version)
+        {
+            let _ = self
+                .linker
+                .link_keg(&self.cellar.keg_path(&old.name, &old.version));
+        }
+    }
+
+    /// Convenience method to plan and execute in one call
+    pub async fn install(&mut self, names: &[String], link: bool) -> Result<ExecuteResult, Error> {
+        let plan = self.plan(names).await?;
+        self.execute(plan, link).await
+    }
+
+    /// Installed formulas that list `name` as a direct dependency, according to
+    /// their cached formula JSON. An installed formula that was never cached
+    /// can't be ruled out, so that is an error; call
+    /// [`Installer::cache_installed_formulas`] first.
+    pub fn installed_dependents(&self, name: &str) -> Result<Vec<String>, Error> {
+        let mut dependents = Vec::new();
+        for keg in self.db.list_installed()? {
+            if keg.name == name {
+                continue;
+            }
+            let formula = self
+                .api_client
+                .cached_formula(&keg.qualified_name())
+                .ok_or_else(|| Error::ExecutionError {
+                    message: format!(
+                        "can't tell whether {} depends on {name}: its formula isn't cached",
+                        keg.name
+                    ),
+                })?;
+            if formula
+                .install_dependencies(false)
+                .iter()
+                .any(|d| d.name == name)
+            {
+                dependents.push(keg.name);
+            }
+        }
+        dependents.sort();
+        Ok(dependents)
+    }
+
+    /// Fetch the formula JSON of every installed formula missing from the
+    /// cache, so [`Installer::installed_dependents`] can check them all.
+    /// Formulas that can't be fetched stay uncached.
+    pub async fn cache_installed_formulas(&self) -> Result<(), Error> {
+        let uncached: Vec<String> = self
+            .db
+            .list_installed()?
+            .into_iter()
+            .map(|keg| keg.qualified_name())
+            .filter(|keg| self.api_client.cached_formula(keg).is_none())
+            .collect();
+        futures::future::join_all(uncached.iter().map(|n| self.api_client.get_formula(n))).await;
+        Ok(())
+    }
+
+    /// Formulas that list `name` as a direct dependency. With `all`, the whole
+    /// formula index is searched; otherwise only installed formulas, fetching
+    /// any formula JSON missing from the cache first.
+    pub async fn uses(&self, name: &str, all: bool) -> Result<Vec<String>, Error> {
+        if all {
+            let mut dependents: Vec<String> = self
+                .api_client
+                .get_formula_index()
+                .await?
+                .into_iter()
+                .filter(|f| {
+                    f.dependencies
+                        .iter()
+                        .map(|dep| DependencySpec::parse(dep))
+                        .any(|dep| dep.is_runtime() && dep.name == name)
+                })
+                .map(|f| f.name)
+                .collect();
+            dependents.sort();
+            return Ok(dependents);
+        }
+
+        self.cache_installed_formulas().await?;
+        self.installed_dependents(name)
+    }
+
+    /// Installed formulas whose current stable version is newer than the installed keg
+    pub async fn outdated(&self) -> Result<Vec<OutdatedKeg>, Error> {
+        let installed = self.db.list_installed()?;
+
+        let results =
+            futures::future::join_all(installed.iter().map(|keg| async move {
+                self.api_client.get_formula(&keg.qualified_name()).await
+            }))
+            .await;
+
+        let mut outdated = Vec::new();
+        for (keg, result) in installed.into_iter().zip(results) {
+            let formula = match result {
+                Ok(formula) => formula,
+                // Formulas removed upstream can't be upgraded
+                Err(Error::MissingFormula { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+
+            let available = formula.effective_version();
+            if Version::parse(&available) > Version::parse(&keg.version) {
+                outdated.push(OutdatedKeg {
+                    name: keg.name,
+                    installed: keg.version,
+                    available,
+                    pinned: keg.pinned,
+                });
+            }
+        }
+
+        Ok(outdated)
+    }
+
+    /// Plan an upgrade of `names`, including any dependencies they newly require.
+    /// Formulas already installed at their current version, and pinned ones,
+    /// are left out.
+    pub async fn plan_upgrade(&self, names: &[String]) -> Result<InstallPlan, Error> {
+        // Installed formulas upgrade from the tap they came from
+        let names: Vec<String> = names
+            .iter()
+            .map(|name| match self.db.get_installed(name) {
+                Some(keg) => keg.qualified_name(),
+                None => name.clone(),
+            })
+            .collect();
+        let plan = self.plan(&names).await?;
+
+        let (formulas, bottles) = plan
+            .formulas
+            .into_iter()
+            .zip(plan.bottles)
+            .filter(|(formula, _)| {
+                self.db
+                    .get_installed(&formula.name)
+                    .is_none_or(|keg| !keg.pinned && keg.version != formula.effective_version())
+            })
+            .unzip();
+
+        Ok(InstallPlan { formulas, bottles })
+    }
+
+    /// Hold an installed formula at its current version. Returns false if it
+    /// was already pinned.
+    pub fn pin(&self, name: &str) -> Result<bool, Error> {
+        if !self.is_installed(name) {
+            return Err(Error::NotInstalled {
+                name: name.to_string(),
+            });
+        }
+        self.db.pin(name)
+    }
+
+    /// Allow a pinned formula to be upgraded again. Returns false if it
+    /// wasn't pinned.
+    pub fn unpin(&self, name: &str) -> Result<bool, Error> {
+        if !self.is_installed(name) {
+            return Err(Error::NotInstalled {
+                name: name.to_string(),
+            });
+        }
+        self.db.unpin(name)
+    }
+
+    /// Every installed formula with its version and pin, sorted by name
+    pub fn export(&self) -> Result<ExportManifest, Error> {
+        let mut formulas: Vec<ExportedFormula> = self
+            .db
+            .list_installed()?
+            .into_iter()
+            .map(|keg| ExportedFormula {
+                name: keg.name,
+                version: Some(keg.version),
+                pinned: keg.pinned,
+            })
+            .collect();
+        formulas.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ExportManifest { formulas })
+    }
+
+    /// Plan installing what `manifest` lists, at the recorded version where
+    /// that can still be found and the current one otherwise, which the plan
+    /// lists as unavailable. Formulas already installed at the version
+    /// planned are left out.
+    pub async fn plan_import(&self, manifest: &ExportManifest) -> Result<ImportPlan, Error> {
+        let mut names = Vec::with_capacity(manifest.formulas.len());
+        let mut unavailable = Vec::new();
+        for entry in &manifest.formulas {
+            let installed = self.db.get_installed(&entry.name);
+            match &entry.version {
+                _ if entry.name.contains('@') => names.push(entry.name.clone()),
+                Some(version)
+                    if installed
+                        .as_ref()
+                        .is_some_and(|keg| &keg.version == version) => {}
+                Some(version) => {
+                    let requested = format!("{}@{version}", entry.name);
+                    match self
+                        .resolve_versions(std::slice::from_ref(&requested))
+                        .await
+                    {
+                        Ok(resolved) => names.extend(resolved),
+                        Err(Error::VersionUnavailable { available, .. }) => {
+                            unavailable.push(UnavailableVersion {
+                                name: entry.name.clone(),
+                                recorded: version.clone(),
+                                current: available,
+                            });
+                            names.push(entry.name.clone());
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                None if installed.is_some() => {}
+                None => names.push(entry.name.clone()),
+            }
+        }
+
+        if names.is_empty() {
+            return Ok(ImportPlan {
+                plan: InstallPlan {
+                    formulas: Vec::new(),
+                    bottles: Vec::new(),
+                },
+                unavailable,
+            });
+        }
+
+        let plan = self.plan(&names).await?;
+        let (formulas, bottles) = plan
+            .formulas
+            .into_iter()
+            .zip(plan.bottles)
+            .filter(|(formula, _)| {
+                self.db
+                    .get_installed(&formula.name)
+                    .is_none_or(|keg| keg.version != formula.effective_version())
+            })
+            .unzip();
+
+        Ok(ImportPlan {
+            plan: InstallPlan { formulas, bottles },
+            unavailable,
+        })
+    }
+
+    /// Resolve `names` and record every formula in the plan, dependencies
+    /// included, with the version and bottle it resolved to
+    pub async fn freeze(&self, names: &[String]) -> Result<Lockfile, Error> {
+        let plan = self.plan(names).await?;
+        let formulas = plan
+            .formulas
+            .iter()
+            .zip(&plan.bottles)
+            .map(|(formula, bottle)| LockedFormula {
+                name: formula.name.clone(),
+                tap: formula.tap.clone(),
+                version: formula.effective_version(),
+                tag: bottle.tag.clone(),
+                sha256: bottle.sha256.clone(),
+            })
+            .collect();
+        Ok(Lockfile { formulas })
+    }
+
+    /// Plan installing exactly what `lockfile` records. Fails with
+    /// [`Error::LockMismatch`] if the API now resolves any formula to another
+    /// version or bottle, or pulls in one the lockfile doesn't list.
+    pub async fn plan_locked(&self, lockfile: &Lockfile) -> Result<InstallPlan, Error> {
+        let names: Vec<String> = lockfile
+            .formulas
+            .iter()
+            .map(LockedFormula::qualified_name)
+            .collect();
+        let plan = self.plan(&names).await?;
+
+        for (formula, bottle) in plan.formulas.iter().zip(&plan.bottles) {
+            let served = format!(
+                "{} ({} bottle {})",
+                formula.effective_version(),
+                bottle.tag,
+                bottle.sha256
+            );
+            let locked = lockfile
+                .formulas
+                .iter()
+                .find(|f| f.name == formula.name && f.tap == formula.tap);
+            match locked {
+                Some(locked)
+                    if locked.version == formula.effective_version()
+                        && locked.tag == bottle.tag
+                        && locked.sha256 == bottle.sha256 => {}
+                Some(locked) => {
+                    return Err(Error::LockMismatch {
+                        name: formula.name.clone(),
+                        locked: locked.describe(),
+                        served,
+                    });
+                }
+                None => {
+                    return Err(Error::LockMismatch {
+                        name: formula.name.clone(),
+                        locked: "nothing".to_string(),
+                        served,
+                    });
+                }
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Pin the installed formulas `manifest` marks as pinned, returning those
+    /// newly pinned
+    pub fn pin_imported(&self, manifest: &ExportManifest) -> Result<Vec<String>, Error> {
+        let mut pinned = Vec::new();
+        for entry in manifest.formulas.iter().filter(|entry| entry.pinned) {
+            if self.is_installed(&entry.name) && self.db.pin(&entry.name)? {
+                pinned.push(entry.name.clone());
+            }
+        }
+        Ok(pinned)
+    }
+
+    /// Convenience method to install everything `manifest` lists and restore
+    /// its pins
+    pub async fn import(
+        &mut self,
+        manifest: &ExportManifest,
+        link: bool,
+    ) -> Result<ExecuteResult, Error> {
+        let import = self.plan_import(manifest).await?;
+        let result = self.execute(import.plan, link).await?;
+        self.pin_imported(manifest)?;
+        Ok(result)
+    }
+
+    /// Uninstall a formula. Unless `force` is set, this refuses to remove a
+    /// formula that other installed formulas depend on.
+    pub fn uninstall(&mut self, name: &str, force: bool) -> Result<(), Error> {
+        // Check if installed
+        let installed = self.db.get_installed(name).ok_or(Error::NotInstalled {
+            name: name.to_string(),
+        })?;
+
+        if !force {
+            let dependents = self.installed_dependents(name)?;
+            if !dependents.is_empty() {
+                return Err(Error::DependencyConflict {
+                    name: name.to_string(),
+                    dependents,
+                });
+            }
+        }
+
+        // Unlink executables
+        let keg_path = self.cellar.keg_path(name, &installed.version);
+        self.linker.unlink_keg(&keg_path)?;
+
+        // Remove from database (decrements store ref)
+        {
+            let _db_lock = self.store.lock("db")?;
+            let tx = self.db.transaction()?;
+            tx.record_uninstall(name)?;
+            tx.record_history(name, Some((&installed.version, &installed.store_key)), None)?;
+            tx.commit()?;
+        }
+
+        // Remove cellar entry
+        self.cellar.remove_keg(name, &installed.version)?;
+
+        Ok(())
+    }
+
+    /// Have the next `execute` install the formulas among `names` again even
+    /// if they're already installed from the same bottle. Each is downloaded
+    /// and unpacked into a fresh store entry and keg before the installed
+    /// keg is swapped out and relinked, so a failed reinstall leaves the
+    /// installed one working. Cached bottles are reused unless `--no-cache`
+    /// is set.
+    pub fn prepare_reinstall(&mut self, names: &[String]) {
+        self.reinstall.extend(names.iter().cloned());
+    }
+
+    /// Switch `name` back to the version it had before its last upgrade (or
+    /// rollback), relinking that keg if the current one is linked. Returns the
+    /// `(from, to)` versions.
+    pub fn rollback(&mut self, name: &str) -> Result<(String, String), Error> {
+        let current = self.db.get_installed(name).ok_or(Error::NotInstalled {
+            name: name.to_string(),
+        })?;
+
+        let previous = self
+            .db
+            .history(name)?
+            .into_iter()
+            .find(|entry| entry.new_version.as_deref() == Some(current.version.as_str()))
+            .and_then(|entry| entry.old_version.zip(entry.old_store_key))
+            .filter(|(version, _)| *version != current.version);
+        let Some((version, store_key)) = previous else {
+            return Err(Error::InvalidArgument {
+                message: format!("'{name}' has no previous version to roll back to"),
+            });
+        };
+
+        if !self.cellar.has_keg(name, &version) {
+            return Err(Error::FileError {
+                message: format!(
+                    "{name} {version} is no longer in the cellar (removed by cleanup); \
+                     reinstall it instead"
+                ),
+            });
+        }
+
+        let current_keg = self.cellar.keg_path(name, &current.version);
+        let previous_keg = self.cellar.keg_path(name, &version);
+        let linked_files = if self.linker.is_linked(&current_keg) {
+            match self.linker.relink_atomic(&current_keg, &previous_keg) {
+                Ok(files) => files,
+                Err(e) => {
+                    let _ = self.linker.unlink_keg(&previous_keg);
+                    let _ = self.linker.link_keg(&current_keg);
+                    return Err(e);
+                }
+            }
+        } else {
+            Vec::new()
+        };
+
+        let _db_lock = self.store.lock("db")?;
+        let tx = self.db.transaction()?;
+        tx.record_replaced(&current)?;
+        tx.record_install(name, &version, &store_key)?;
+        for linked in &linked_files {
+            tx.record_linked_file(
+                name,
+                &version,
+                &linked.link_path.to_string_lossy(),
+                &linked.target_path.to_string_lossy(),
+            )?;
+        }
+        tx.record_history(
+            name,
+            Some((&current.version, &current.store_key)),
+            Some((&version, &store_key)),
+        )?;
+        tx.commit()?;
+
+        Ok((current.version, version))
+    }
+
+    /// Garbage collect store entries, cellar kegs and cached bottles no
+    /// installed formula uses. Old versions of installed formulas are left for
+    /// rollback; `cleanup` reclaims those, and cached bottles used within the
+    /// last day are kept. With `store_only`, only store entries whose
+    /// refcount has dropped to zero are removed.
+    pub fn gc(&mut self, store_only: bool) -> Result<GcReport, Error> {
+        // Installs hold this shared until they're recorded, so nothing they
+        // are still materializing looks unreferenced
+        let _gc_lock = self.store.lock("gc")?;
+        let mut report = GcReport::default();
+        let mut doomed = self.db.get_unreferenced_store_keys()?;
+
+        let installed = self.db.list_installed()?;
+        let live: HashSet<&str> = installed.iter().map(|k| k.store_key.as_str()).collect();
+        if !store_only {
+            let on_disk = self
+                .store
+                .list_entries()
+                .map_err(|e| Error::StoreCorruption {
+                    message: format!("failed to list store entries: {e}"),
+                })?;
+            doomed.extend(
+                on_disk
+                    .into_iter()
+                    .filter(|key| !live.contains(key.as_str())),
+            );
+            doomed.sort();
+            doomed.dedup();
+        }
+
+        for store_key in doomed {
+            let entry = self.store.entry_path(&store_key);
+            if entry.exists() {
+                report.bytes_reclaimed += dir_size(&entry);
+            }
+            self.store.remove_entry(&store_key)?;
+            report.store_entries.push(store_key);
+        }
+        self.store.prune_objects()?;
+
+        if store_only {
+            return Ok(report);
+        }
+
+        let installed_names: HashSet<&str> = installed.iter().map(|k| k.name.as_str()).collect();
+        for name in self.cellar.list_formulas() {
+            if installed_names.contains(name.as_str()) {
+                continue;
+            }
+            for version in self.cellar.list_versions(&name) {
+                let keg_path = self.cellar.keg_path(&name, &version);
+                report.bytes_reclaimed += dir_size(&keg_path);
+                self.linker.unlink_keg(&keg_path)?;
+                self.cellar.remove_keg(&name, &version)?;
+                report.kegs.push(keg_path);
+            }
+        }
+
+        let blobs = self.blob_cache.list_blobs().map_err(|e| Error::FileError {
+            message: format!("failed to list cached blobs: {e}"),
+        })?;
+        let now = std::time::SystemTime::now();
+        for blob in blobs {
+            // `zb fetch` prefetches and bottles a running install holds are
+            // kept even though nothing references them yet
+            let recent = now
+                .duration_since(blob.modified)
+                .is_ok_and(|age| age < GC_BLOB_GRACE);
+            if live.contains(blob.sha256.as_str())
+                || recent
+                || self.blob_cache.is_pinned(&blob.sha256)
+            {
+                continue;
+            }
+            self.blob_cache
+                .remove_blob(&blob.sha256)
+                .map_err(|e| Error::FileError {
+                    message: format!("failed to remove {}: {e}", blob.path.display()),
+                })?;
+            report.bytes_reclaimed += blob.size;
+            report.blobs.push(blob.path);
+        }
+
+        Ok(report)
+    }
+
+    /// Formula JSON from the local API cache, without touching the network
+    pub fn cached_formula(&self, name: &str) -> Option<Formula> {
+        self.api_client.cached_formula(name)
+    }
+
+    /// Formula JSON from the API, or the cache while it's fresh
+    pub async fn get_formula(&self, name: &str) -> Result<Formula, Error> {
+        self.api_client.get_formula(name).await
+    }
+
+    /// Remove cached bottles no installed keg was unpacked from (or, with
+    /// `prune_days`, any older than that) and cellar versions older than the
+    /// installed one. Newer versions, such as one rolled back from, and
+    /// whatever is linked are kept. With `dry_run` nothing is deleted.
+    pub fn cleanup(
+        &mut self,
+        dry_run: bool,
+        prune_days: Option<u64>,
+    ) -> Result<CleanupReport, Error> {
+        let installed = self.db.list_installed()?;
+        let referenced: HashSet<&str> = installed.iter().map(|k| k.store_key.as_str()).collect();
+        // A cutoff before the epoch means nothing is old enough to prune
+        let cutoff = prune_days.and_then(|days| {
+            SystemTime::now().checked_sub(Duration::from_secs(days.saturating_mul(24 * 60 * 60)))
+        });
+        let mut report = CleanupReport::default();
+
+        let blobs = self.blob_cache.list_blobs().map_err(|e| Error::FileError {
+            message: format!("failed to list cached blobs: {e}"),
+        })?;
+        for blob in blobs {
+            let expired = cutoff.is_some_and(|cutoff| blob.modified < cutoff);
+            if referenced.contains(blob.sha256.as_str()) && !expired {
+                continue;
+            }
+            if !dry_run {
+                self.blob_cache
+                    .remove_blob(&blob.sha256)
+                    .map_err(|e| Error::FileError {
+                        message: format!("failed to remove {}: {e}", blob.path.display()),
+                    })?;
+            }
+            report.bytes_reclaimed += blob.size;
+            report.blobs.push(blob.path);
+        }
+
+        for keg in &installed {
+            let recorded = Version::parse(&keg.version);
+            for version in self.cellar.list_versions(&keg.name) {
+                if Version::parse(&version) >= recorded {
+                    continue;
+                }
+                let keg_path = self.cellar.keg_path(&keg.name, &version);
+                if self.linker.is_linked(&keg_path) {
+                    continue;
+                }
+                let size = dir_size(&keg_path);
+                if !dry_run {
+                    self.linker.unlink_keg(&keg_path)?;
+                    self.cellar.remove_keg(&keg.name, &version)?;
+                }
+                report.bytes_reclaimed += size;
+                report.kegs.push(keg_path);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Installed formulas whose keg is missing from the cellar
+    pub fn missing_kegs(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .db
+            .list_installed()?
+            .into_iter()
+            .filter(|keg| !self.cellar.has_keg(&keg.name, &keg.version))
+            .map(|keg| keg.name)
+            .collect())
+    }
+
+    /// Installed formulas whose store entry is missing
+    pub fn missing_store_entries(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .db
+            .list_installed()?
+            .into_iter()
+            .filter(|keg| !self.store.has_entry(&keg.store_key))
+            .map(|keg| keg.name)
+            .collect())
+    }
+
+    /// Rehash the store entry of every installed formula (or just `name`) and
+    /// check its keg still has everything the bottle unpacked
+    pub fn verify(&self, name: Option<&str>) -> Result<Vec<VerifyReport>, Error> {
+        let kegs = match name {
+            Some(name) => vec![
+                self.db
+                    .get_installed(name)
+                    .ok_or_else(|| Error::NotInstalled {
+                        name: name.to_string(),
+                    })?,
+            ],
+            None => self.db.list_installed()?,
+        };
+
+        let mut reports = Vec::new();
+        for keg in kegs {
+            let mut report = VerifyReport {
+                name: keg.name.clone(),
+                problems: Vec::new(),
+                store_checked: false,
+            };
+
+            if !self.store.has_entry(&keg.store_key) {
+                report.problems.push("store entry is missing".to_string());
+                reports.push(report);
+                continue;
+            }
+            if let Some(mismatches) = self.store.verify(&keg.store_key)? {
+                report.store_checked = true;
+                report.problems.extend(
+                    mismatches
+                        .iter()
+                        .map(|mismatch| format!("store entry {mismatch}")),
+                );
+            }
+
+            if !self.cellar.has_keg(&keg.name, &keg.version) {
+                report.problems.push("keg is missing".to_string());
+            } else {
+                let entry = self.store.entry_path(&keg.store_key);
+                report.problems.extend(
+                    self.cellar
+                        .missing_from_keg(&keg.name, &keg.version, &entry)?
+                        .into_iter()
+                        .map(|path| format!("keg missing: {path}")),
+                );
+            }
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+
+    /// Symlinks in the prefix that point at nothing
+    pub fn dangling_links(&self) -> Vec<PathBuf> {
+        self.linker.dangling_links()
+    }
+
+    /// Check if a formula is installed
+    pub fn is_installed(&self, name: &str) -> bool {
+        self.db.get_installed(name).is_some()
+    }
+
+    /// Get info about an installed formula, by its plain name or qualified
+    /// with the tap it was installed from
+    pub fn get_installed(&self, name: &str) -> Option<crate::db::InstalledKeg> {
+        match name.rsplit_once('/') {
+            Some((tap, formula)) => self
+                .db
+                .get_installed(formula)
+                .filter(|keg| keg.tap.as_deref().unwrap_or("homebrew/core") == tap),
+            None => self.db.get_installed(name),
+        }
+    }
+
+    /// List all installed formulas
+    pub fn list_installed(&self) -> Result<Vec<crate::db::InstalledKeg>, Error> {
+        self.db.list_installed()
+    }
+
+    /// Get the path to a keg in the cellar
+    pub fn keg_path(&self, name: &str, version: &str) -> std::path::PathBuf {
+        self.cellar.keg_path(name, version)
+    }
+
+    /// Search the formula index by case-insensitive substring of name or description.
+    /// Name matches sort ahead of description-only matches, exact names first.
+    pub async fn search(&self, query: &str) -> Result<Vec<FormulaSummary>, Error> {
+        let query = query.to_lowercase();
+        let index = self.api_client.get_formula_index().await?;
+
+        let mut matches: Vec<(u8, FormulaSummary)> = index
+            .into_iter()
+            .filter_map(|formula| {
+                let name = formula.name.to_lowercase();
+                let rank = if name == query {
+                    0
+                } else if name.contains(&query) {
+                    1
+                } else if formula
+                    .desc
+                    .as_deref()
+                    .is_some_and(|desc| desc.to_lowercase().contains(&query))
+                {
+                    2
+                } else {
+                    return None;
+                };
+                Some((rank, formula))
+            })
+            .collect();
+
+        matches.sort_by(|(a_rank, a), (b_rank, b)| a_rank.cmp(b_rank).then(a.name.cmp(&b.name)));
+        Ok(matches.into_iter().map(|(_, formula)| formula).collect())
+    }
+
+    /// Download `url` into the bottle cache without a formula or an expected
+    /// checksum, returning the cached file and its sha256 hex digest
+    pub async fn fetch_url(&self, url: &str) -> Result<(PathBuf, String), Error> {
+        let (path, checksum) = self.downloader.download_unverified(url).await?;
+        Ok((path, checksum.hex().to_string()))
+    }
+
+    /// Formula names a few edits away from `name`, closest first, for
+    /// suggesting what a typo meant. Empty if the index can't be fetched.
+    async fn similar_formula_names(&self, name: &str) -> Vec<String> {
+        // Tap formulas aren't in the core index
+        if name.contains('/') {
+            return Vec::new();
+        }
+        let Ok(index) = self.api_client.get_formula_index().await else {
+            return Vec::new();
+        };
+
+        let name = name.to_lowercase();
+        let max_distance = (name.chars().count() / 3).clamp(1, 3);
+        let mut close: Vec<(usize, String)> = index
+            .into_iter()
+            .filter_map(|formula| {
+                let distance = edit_distance(&name, &formula.name.to_lowercase());
+                (distance <= max_distance).then_some((distance, formula.name))
+            })
+            .collect();
+        close.sort();
+        close
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, name)| name)
+            .collect()
+    }
+}
+
+/// Run `fut` to completion, or until `deadline` if there is one; `None`
+/// means the deadline passed first
+async fn within_deadline<T>(
+    deadline: Option<tokio::time::Instant>,
+    fut: impl std::future::Future<Output = T>,
+) -> Option<T> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// `url` on each of `mirrors`, for a bottle hosted on ghcr.io; bottles from
+/// anywhere else have no mirrors to fall back to
+fn mirror_urls(url: &str, mirrors: &[String]) -> Vec<String> {
+    let Some(path) = url.strip_prefix("https://ghcr.io/") else {
+        return Vec::new();
+    };
+    mirrors
+        .iter()
+        .map(|mirror| format!("{}/{path}", mirror.trim_end_matches('/')))
+        .collect()
+}
+
+/// Levenshtein distance: the fewest single-character insertions, deletions
+/// and substitutions turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Create an Installer with standard paths
+pub fn create_installer(
+    root: &Path,
+    prefix: &Path,
+    api_concurrency: usize,
+    download_concurrency: usize,
+) -> Result<Installer, Error> {
+    use std::fs;
+
+    // First ensure the root directory exists
+    if !root.exists() {
+        fs::create_dir_all(root).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                Error::StoreCorruption {
+                    message: format!(
+                        "cannot create root directory '{}': permission denied.\n\n\
+                        Create it with:\n  sudo mkdir -p {} && sudo chown $USER {}",
+                        root.display(),
+                        root.display(),
+                        root.display()
+                    ),
+                }
+            } else {
+                Error::StoreCorruption {
+                    message: format!("failed to create root directory '{}': {e}", root.display()),
+                }
+            }
+        })?;
+    }
+
+    // Ensure all subdirectories exist
+    fs::create_dir_all(root.join("db")).map_err(|e| Error::StoreCorruption {
+        message: format!("failed to create db directory: {e}"),
+    })?;
+
+    let api_cache =
+        ApiCache::open(&root.join("db/api_cache.sqlite3")).map_err(|e| Error::StoreCorruption {
+            message: format!("failed to open API cache: {e}"),
+        })?;
+    let api_client = ApiClient::new()
+        .with_cache(api_cache)
+        .with_concurrency(api_concurrency);
+    let blob_cache = BlobCache::new(&root.join("cache"))
+        .map_err(|e| Error::StoreCorruption {
+            message: format!("failed to create blob cache: {e}"),
+        })?
+        .with_shared_pins(&root.join("db/zb.sqlite3"))?;
+    let store = Store::new(root).map_err(|e| Error::StoreCorruption {
+        message: format!("failed to create store: {e}"),
+    })?;
+    // Use prefix/Cellar so bottles' hardcoded rpaths work
+    let cellar = Cellar::new_at(prefix.join("Cellar")).map_err(|e| Error::StoreCorruption {
+        message: format!("failed to create cellar: {e}"),
+    })?;
+    let linker = Linker::new(prefix).map_err(|e| Error::StoreCorruption {
+        message: format!("failed to create linker: {e}"),
+    })?;
+    let db = Database::open(&root.join("db/zb.sqlite3"))?;
+
+    use crate::download::ParallelDownloader;
+    let parallel_downloader =
+        ParallelDownloader::with_concurrency(blob_cache.clone(), download_concurrency);
+
+    let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+    installer.downloader = parallel_downloader;
+    Ok(installer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn create_bottle_tarball(formula_name: &str) -> Vec<u8> {
+        create_versioned_bottle_tarball(formula_name, "1.0.0")
+    }
+
+    fn create_versioned_bottle_tarball(formula_name: &str, version: &str) -> Vec<u8> {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+        use tar::Builder;
+
+        let mut builder = Builder::new(Vec::new());
+
+        // Create bin directory with executable
+        let content = format!("#!/bin/sh\necho {} {}", formula_name, version);
+        let mut header = tar::Header::new_gnu();
+        header
+            .set_path(format!("{}/{}/bin/{}", formula_name, version, formula_name))
+            .unwrap();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+
+        builder.append(&header, content.as_bytes()).unwrap();
+
+        let tar_data = builder.into_inner().unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar_data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn get_test_bottle_tag() -> String {
+        zb_core::Platform::current().bottle_tags().remove(0)
+    }
+
+    #[tokio::test]
+    async fn install_completes_successfully() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        // Create bottle
+        let bottle = create_bottle_tarball("testpkg");
+        let bottle_sha = sha256_hex(&bottle);
+
+        // Create formula JSON
+        let tag = get_test_bottle_tag();
+        let formula_json = format!(
+            r#"{{
+                "name": "testpkg",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [],
+                "bottle": {{
+                    "stable": {{
+                        "files": {{
+                            "{}": {{
+                                "url": "{}/bottles/testpkg-1.0.0.{}.bottle.tar.gz",
+                                "sha256": "{}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            tag,
+            mock_server.uri(),
+            tag,
+            bottle_sha
+        );
+
+        // Mount formula API mock
+        Mock::given(method("GET"))
+            .and(path("/testpkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .mount(&mock_server)
+            .await;
+
+        // Mount bottle download mock
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/bottles/testpkg-1.0.0.{}.bottle.tar.gz",
+                tag
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .mount(&mock_server)
+            .await;
+
+        // Create installer with mocked API
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Install
+        installer
+            .install(&["testpkg".to_string()], true)
+            .await
+            .unwrap();
+
+        // Verify keg exists
+        assert!(root.join("cellar/testpkg/1.0.0").exists());
+
+        // Verify link exists
+        assert!(prefix.join("bin/testpkg").exists());
+
+        // Verify database records
+        let installed = installer.db.get_installed("testpkg");
+        assert!(installed.is_some());
+        assert_eq!(installed.unwrap().version, "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn uninstall_cleans_everything() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        // Create bottle
+        let bottle = create_bottle_tarball("uninstallme");
+        let bottle_sha = sha256_hex(&bottle);
+
+        // Create formula JSON
+        let tag = get_test_bottle_tag();
+        let formula_json = format!(
+            r#"{{
+                "name": "uninstallme",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [],
+                "bottle": {{
+                    "stable": {{
+                        "files": {{
+                            "{}": {{
+                                "url": "{}/bottles/uninstallme-1.0.0.{}.bottle.tar.gz",
+                                "sha256": "{}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            tag,
+            mock_server.uri(),
+            tag,
+            bottle_sha
+        );
+
+        // Mount mocks
+        Mock::given(method("GET"))
+            .and(path("/uninstallme.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/bottles/uninstallme-1.0.0.{}.bottle.tar.gz",
+                tag
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .mount(&mock_server)
+            .await;
+
+        // Create installer
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Install
+        installer
+            .install(&["uninstallme".to_string()], true)
+            .await
+            .unwrap();
+
+        // Verify installed
+        assert!(installer.is_installed("uninstallme"));
+        assert!(root.join("cellar/uninstallme/1.0.0").exists());
+        assert!(prefix.join("bin/uninstallme").exists());
+
+        // Uninstall
+        installer.uninstall("uninstallme", false).unwrap();
+
+        // Verify everything cleaned up
+        assert!(!installer.is_installed("uninstallme"));
+        assert!(!root.join("cellar/uninstallme/1.0.0").exists());
+        assert!(!prefix.join("bin/uninstallme").exists());
+    }
+
+    #[tokio::test]
+    async fn reinstall_replaces_an_existing_keg_with_a_fresh_one() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let bottle = create_bottle_tarball("redo");
+        let bottle_sha = sha256_hex(&bottle);
+        let tag = get_test_bottle_tag();
+        let formula_json = format!(
+            r#"{{
+                "name": "redo",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [],
+                "bottle": {{
+                    "stable": {{
+                        "files": {{
+                            "{tag}": {{
+                                "url": "{}/bottles/redo-1.0.0.{tag}.bottle.tar.gz",
+                                "sha256": "{bottle_sha}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            mock_server.uri()
+        );
+
+        Mock::given(method("GET"))
+            .and(path("/redo.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .mount(&mock_server)
+            .await;
+        let bottle_mock = || {
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/redo-1.0.0.{tag}.bottle.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+        };
+        let serving = bottle_mock().mount_as_scoped(&mock_server).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer =
+            Installer::new(api_client, blob_cache.clone(), store, cellar, linker, db);
+        let names = vec!["redo".to_string()];
+        installer.install(&names, true).await.unwrap();
+
+        // Damage the keg: clobber its executable and leave a stray file
+        let keg = root.join("cellar/redo/1.0.0");
+        fs::write(keg.join("bin/redo"), "broken").unwrap();
+        fs::write(keg.join("stray"), "left over").unwrap();
+
+        // A reinstall whose bottle can't be fetched leaves the keg alone
+        blob_cache.remove_blob(&bottle_sha).unwrap();
+        drop(serving);
+        installer.prepare_reinstall(&names);
+        assert!(installer.install(&names, true).await.is_err());
+        assert_eq!(fs::read_to_string(keg.join("bin/redo")).unwrap(), "broken");
+        assert!(prefix.join("bin/redo").exists());
+        bottle_mock().mount(&mock_server).await;
+
+        installer.prepare_reinstall(&names);
+        installer.install(&names, true).await.unwrap();
+
+        assert_eq!(
+            fs::read_to_string(keg.join("bin/redo")).unwrap(),
+            "#!/bin/sh\necho redo 1.0.0"
+        );
+        assert!(!keg.join("stray").exists());
+        assert!(prefix.join("bin/redo").exists());
+        let installed = installer.db.get_installed("redo").unwrap();
+        assert_eq!(installed.version, "1.0.0");
+        assert_eq!(installer.db.get_store_refcount(&bottle_sha), 1);
+    }
+
+    #[tokio::test]
+    async fn gc_removes_unreferenced_store_entries() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        // Create bottle
+        let bottle = create_bottle_tarball("gctest");
+        let bottle_sha = sha256_hex(&bottle);
+
+        // Create formula JSON
+        let tag = get_test_bottle_tag();
+        let formula_json = format!(
+            r#"{{
+                "name": "gctest",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [],
+                "bottle": {{
+                    "stable": {{
+                        "files": {{
+                            "{}": {{
+                                "url": "{}/bottles/gctest-1.0.0.{}.bottle.tar.gz",
+                                "sha256": "{}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            tag,
+            mock_server.uri(),
+            tag,
+            bottle_sha
+        );
+
+        // Mount mocks
+        Mock::given(method("GET"))
+            .and(path("/gctest.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path(format!("/bottles/gctest-1.0.0.{}.bottle.tar.gz", tag)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .mount(&mock_server)
+            .await;
+
+        // Create installer
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Install and uninstall
+        installer
+            .install(&["gctest".to_string()], true)
+            .await
+            .unwrap();
+
+        // Store entry should exist before GC
+        assert!(root.join("store").join(&bottle_sha).exists());
+
+        installer.uninstall("gctest", false).unwrap();
+
+        // Store entry should still exist (refcount decremented but not GC'd)
+        assert!(root.join("store").join(&bottle_sha).exists());
+
+        // Run GC
+        let removed = installer.gc(true).unwrap().store_entries;
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0], bottle_sha);
+
+        // Store entry should now be gone
+        assert!(!root.join("store").join(&bottle_sha).exists());
+    }
+
+    #[tokio::test]
+    async fn gc_does_not_remove_referenced_store_entries() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        // Create bottle
+        let bottle = create_bottle_tarball("keepme");
+        let bottle_sha = sha256_hex(&bottle);
+
+        // Create formula JSON
+        let tag = get_test_bottle_tag();
+        let formula_json = format!(
+            r#"{{
+                "name": "keepme",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [],
+                "bottle": {{
+                    "stable": {{
+                        "files": {{
+                            "{}": {{
+                                "url": "{}/bottles/keepme-1.0.0.{}.bottle.tar.gz",
+                                "sha256": "{}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            tag,
+            mock_server.uri(),
+            tag,
+            bottle_sha
+        );
+
+        // Mount mocks
+        Mock::given(method("GET"))
+            .and(path("/keepme.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path(format!("/bottles/keepme-1.0.0.{}.bottle.tar.gz", tag)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .mount(&mock_server)
+            .await;
+
+        // Create installer
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Install but don't uninstall
+        installer
+            .install(&["keepme".to_string()], true)
+            .await
+            .unwrap();
+
+        // Store entry should exist
+        assert!(root.join("store").join(&bottle_sha).exists());
+
+        // Run GC - should not remove anything
+        let report = installer.gc(false).unwrap();
+        assert!(report.store_entries.is_empty());
+        assert!(report.kegs.is_empty());
+        assert!(report.blobs.is_empty());
+
+        // Store entry should still exist
+        assert!(root.join("store").join(&bottle_sha).exists());
+    }
+
+    #[tokio::test]
+    async fn gc_removes_store_entry_keg_and_blob_of_a_forgotten_formula() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_formula(&mock_server, "forgotten", &[]).await;
+        mount_formula(&mock_server, "kept", &[]).await;
+        let forgotten_sha = sha256_hex(&create_bottle_tarball("forgotten"));
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        installer
+            .install(&["forgotten".to_string(), "kept".to_string()], true)
+            .await
+            .unwrap();
+
+        rusqlite::Connection::open(root.join("db/zb.sqlite3"))
+            .unwrap()
+            .execute("DELETE FROM installed_kegs WHERE name = 'forgotten'", [])
+            .unwrap();
+
+        // A blob has to sit unused for a while before it's collected; a
+        // fresh one may be a `zb fetch` prefetch
+        let blob = root.join(format!("cache/blobs/{forgotten_sha}.tar.gz"));
+        let prefetched = root.join(format!("cache/blobs/{}.tar.gz", "0".repeat(64)));
+        fs::write(&prefetched, b"prefetched").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&blob)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - 2 * GC_BLOB_GRACE)
+            .unwrap();
+
+        let report = installer.gc(false).unwrap();
+        assert_eq!(report.store_entries, vec![forgotten_sha.clone()]);
+        assert_eq!(report.kegs, vec![root.join("cellar/forgotten/1.0.0")]);
+        assert_eq!(report.blobs.len(), 1);
+        assert!(report.bytes_reclaimed > 0);
+
+        assert!(!root.join("store").join(&forgotten_sha).exists());
+        assert!(!root.join("cellar/forgotten").exists());
+        assert!(!blob.exists());
+        assert!(prefetched.exists());
+        assert!(!prefix.join("bin/forgotten").exists());
+        assert!(root.join("cellar/kept/1.0.0").exists());
+        assert_eq!(installer.list_installed().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn install_with_dependencies() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        // Create bottles
+        let dep_bottle = create_bottle_tarball("deplib");
+        let dep_sha = sha256_hex(&dep_bottle);
+
+        let main_bottle = create_bottle_tarball("mainpkg");
+        let main_sha = sha256_hex(&main_bottle);
+
+        // Create formula JSONs
+        let tag = get_test_bottle_tag();
+        let dep_json = format!(
+            r#"{{
+                "name": "deplib",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [],
+                "bottle": {{
+                    "stable": {{
+                        "files": {{
+                            "{}": {{
+                                "url": "{}/bottles/deplib-1.0.0.{}.bottle.tar.gz",
+                                "sha256": "{}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            tag,
+            mock_server.uri(),
+            tag,
+            dep_sha
+        );
+
+        let main_json = format!(
+            r#"{{
+                "name": "mainpkg",
+                "versions": {{ "stable": "2.0.0" }},
+                "dependencies": ["deplib"],
+                "bottle": {{
+                    "stable": {{
+                        "files": {{
+                            "{}": {{
+                                "url": "{}/bottles/mainpkg-2.0.0.{}.bottle.tar.gz",
+                                "sha256": "{}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            tag,
+            mock_server.uri(),
+            tag,
+            main_sha
+        );
+
+        // Mount mocks
+        Mock::given(method("GET"))
+            .and(path("/deplib.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&dep_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/mainpkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&main_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path(format!("/bottles/deplib-1.0.0.{}.bottle.tar.gz", tag)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(dep_bottle))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/bottles/mainpkg-2.0.0.{}.bottle.tar.gz",
+                tag
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(main_bottle))
+            .mount(&mock_server)
+            .await;
+
+        // Create installer
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Install main package (should also install dependency)
+        installer
+            .install(&["mainpkg".to_string()], true)
+            .await
+            .unwrap();
+
+        // Both packages should be installed
+        assert!(installer.db.get_installed("mainpkg").is_some());
+        assert!(installer.db.get_installed("deplib").is_some());
+    }
+
+    #[tokio::test]
+    async fn requested_formula_without_a_bottle_for_this_platform_fails_at_plan_time() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let formula_json = serde_json::json!({
+            "name": "sourceonly",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                "some_other_platform": {
+                    "url": format!("{}/bottles/sourceonly.tar.gz", mock_server.uri()),
+                    "sha256": "a".repeat(64),
+                }
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/sourceonly.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/sourceonly.tar.gz"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        let mut installer = Installer::new(
+            ApiClient::with_base_url(mock_server.uri()),
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(&root).unwrap(),
+            Cellar::new(&root).unwrap(),
+            Linker::new(&tmp.path().join("homebrew")).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+        );
+
+        let Err(Error::NoBottleForPlatform { name, .. }) =
+            installer.install(&["sourceonly".to_string()], true).await
+        else {
+            panic!("planning should fail for want of a bottle");
+        };
+        assert_eq!(name, "sourceonly");
+    }
+
+    #[tokio::test]
+    async fn forced_bottle_tag_overrides_platform_detection() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let files: serde_json::Map<String, serde_json::Value> = ["x86_64_linux", "arm64_sequoia"]
+            .into_iter()
+            .map(|tag| {
+                let file = serde_json::json!({
+                    "url": format!("{}/bottles/tagged.{tag}.tar.gz", mock_server.uri()),
+                    "sha256": "a".repeat(64),
+                });
+                (tag.to_string(), file)
+            })
+            .collect();
+        let formula_json = serde_json::json!({
+            "name": "tagged",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": files } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/tagged.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+        let anywhere_json = serde_json::json!({
+            "name": "anywhere",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": { "all": {
+                "url": format!("{}/bottles/anywhere.all.tar.gz", mock_server.uri()),
+                "sha256": "b".repeat(64),
+            } } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/anywhere.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(anywhere_json))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        let installer_for = |tag: &str| {
+            Installer::new(
+                ApiClient::with_base_url(mock_server.uri()),
+                BlobCache::new(&root.join("cache")).unwrap(),
+                Store::new(&root).unwrap(),
+                Cellar::new(&root).unwrap(),
+                Linker::new(&tmp.path().join("homebrew")).unwrap(),
+                Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+            )
+            .with_bottle_tag(tag)
+        };
+        let names = ["tagged".to_string()];
+
+        for tag in ["x86_64_linux", "arm64_sequoia"] {
+            let plan = installer_for(tag).plan(&names).await.unwrap();
+            assert_eq!(plan.bottles[0].tag, tag);
+            assert!(
+                plan.bottles[0]
+                    .url
+                    .ends_with(&format!("tagged.{tag}.tar.gz"))
+            );
+        }
+
+        let Err(Error::NoBottleForPlatform { tags_tried, .. }) =
+            installer_for("arm64_linux").plan(&names).await
+        else {
+            panic!("a tag the formula has no bottle for should be refused");
+        };
+        assert_eq!(tags_tried, ["arm64_linux", "all"]);
+
+        // Platform-independent bottles suit any forced tag
+        let plan = installer_for("arm64_linux")
+            .plan(&["anywhere".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(plan.bottles[0].tag, "all");
+    }
+
+    #[tokio::test]
+    async fn planning_reports_resolve_progress_per_formula() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        for (name, deps) in [
+            ("top", vec!["left", "right"]),
+            ("left", vec!["base"]),
+            ("right", vec!["base"]),
+            ("base", vec![]),
+        ] {
+            let formula_json = serde_json::json!({
+                "name": name,
+                "versions": { "stable": "1.0.0" },
+                "dependencies": deps,
+                "bottle": { "stable": { "files": {
+                    tag.as_str(): {
+                        "url": format!("{}/bottles/{name}.tar.gz", mock_server.uri()),
+                        "sha256": "a".repeat(64),
+                    }
+                } } }
+            });
+            Mock::given(method("GET"))
+                .and(path(format!("/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+                .mount(&mock_server)
+                .await;
+        }
+
+        let root = tmp.path().join("zerobrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        let installer = Installer::new(
+            ApiClient::with_base_url(mock_server.uri()),
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(&root).unwrap(),
+            Cellar::new(&root).unwrap(),
+            Linker::new(&tmp.path().join("homebrew")).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        let progress: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
+            if let InstallProgress::ResolveProgress {
+                resolved,
+                total_known,
+            } = event
+            {
+                events_clone.lock().unwrap().push((resolved, total_known));
+            }
+        }));
+
+        let plan = installer
+            .plan_with_progress(&["top".to_string()], Some(progress))
+            .await
+            .unwrap();
+        assert_eq!(plan.formulas.len(), 4);
+
+        let events = events.lock().unwrap();
+        let resolved: Vec<usize> = events.iter().map(|(r, _)| *r).collect();
+        assert_eq!(resolved, vec![1, 2, 3, 4]);
+        assert!(events.iter().all(|(r, total)| r <= total));
+        assert_eq!(events.last(), Some(&(4, 4)));
+    }
+
+    #[tokio::test]
+    async fn execute_result_lists_installed_and_already_installed_packages() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        let mut shas = HashMap::new();
+        for (name, version, deps) in [
+            ("reslib", "1.0.0", vec![]),
+            ("resapp", "2.0.0", vec!["reslib"]),
+        ] {
+            let bottle = create_versioned_bottle_tarball(name, version);
+            let sha = sha256_hex(&bottle);
+            let formula_json = serde_json::json!({
+                "name": name,
+                "versions": { "stable": version },
+                "dependencies": deps,
+                "bottle": { "stable": { "files": {
+                    tag.as_str(): {
+                        "url": format!("{}/bottles/{name}.tar.gz", mock_server.uri()),
+                        "sha256": sha,
+                    }
+                } } }
+            });
+            Mock::given(method("GET"))
+                .and(path(format!("/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+                .mount(&mock_server)
+                .await;
+            shas.insert(name, sha);
+        }
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        let mut installer = Installer::new(
+            ApiClient::with_base_url(mock_server.uri()),
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(&root).unwrap(),
+            Cellar::new(&root).unwrap(),
+            Linker::new(&prefix).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+        );
+
+        let expected = vec![
+            InstalledPackage {
+                name: "reslib".to_string(),
+                version: "1.0.0".to_string(),
+                store_key: shas["reslib"].clone(),
+            },
+            InstalledPackage {
+                name: "resapp".to_string(),
+                version: "2.0.0".to_string(),
+                store_key: shas["resapp"].clone(),
+            },
+        ];
+
+        let result = installer
+            .install(&["resapp".to_string()], true)
+            .await
+            .unwrap();
+        assert_eq!(result.installed, 2);
+        assert_eq!(result.packages, expected);
+        assert!(result.already_installed.is_empty());
+
+        // Installing again changes nothing and says so
+        let again = installer
+            .install(&["resapp".to_string()], true)
+            .await
+            .unwrap();
+        assert_eq!(again.installed, 0);
+        assert!(again.packages.is_empty());
+        assert_eq!(again.already_installed, expected);
+    }
+
+    #[tokio::test]
+    async fn parallel_api_fetching_with_deep_deps() {
+        // Tests that parallel API fetching works with a deeper dependency tree:
+        // root -> mid1 -> leaf1
+        //      -> mid2 -> leaf2
+        //              -> leaf1 (shared)
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        // Create bottles
+        let leaf1_bottle = create_bottle_tarball("leaf1");
+        let leaf1_sha = sha256_hex(&leaf1_bottle);
+        let leaf2_bottle = create_bottle_tarball("leaf2");
+        let leaf2_sha = sha256_hex(&leaf2_bottle);
+        let mid1_bottle = create_bottle_tarball("mid1");
+        let mid1_sha = sha256_hex(&mid1_bottle);
+        let mid2_bottle = create_bottle_tarball("mid2");
+        let mid2_sha = sha256_hex(&mid2_bottle);
+        let root_bottle = create_bottle_tarball("root");
+        let root_sha = sha256_hex(&root_bottle);
+
+        // Formula JSONs
+        let tag = get_test_bottle_tag();
+        let leaf1_json = format!(
+            r#"{{"name":"leaf1","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/leaf1.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+            tag,
+            mock_server.uri(),
+            leaf1_sha
+        );
+        let leaf2_json = format!(
+            r#"{{"name":"leaf2","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/leaf2.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+            tag,
+            mock_server.uri(),
+            leaf2_sha
+        );
+        let mid1_json = format!(
+            r#"{{"name":"mid1","versions":{{"stable":"1.0.0"}},"dependencies":["leaf1"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/mid1.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+            tag,
+            mock_server.uri(),
+            mid1_sha
+        );
+        let mid2_json = format!(
+            r#"{{"name":"mid2","versions":{{"stable":"1.0.0"}},"dependencies":["leaf1","leaf2"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/mid2.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+            tag,
+            mock_server.uri(),
+            mid2_sha
+        );
+        let root_json = format!(
+            r#"{{"name":"root","versions":{{"stable":"1.0.0"}},"dependencies":["mid1","mid2"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/root.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+            tag,
+            mock_server.uri(),
+            root_sha
+        );
+
+        // Mount all mocks
+        for (name, json) in [
+            ("leaf1", &leaf1_json),
+            ("leaf2", &leaf2_json),
+            ("mid1", &mid1_json),
+            ("mid2", &mid2_json),
+            ("root", &root_json),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}.json", name)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(json))
+                .mount(&mock_server)
+                .await;
+        }
+        for (name, bottle) in [
+            ("leaf1", &leaf1_bottle),
+            ("leaf2", &leaf2_bottle),
+            ("mid1", &mid1_bottle),
+            ("mid2", &mid2_bottle),
+            ("root", &root_bottle),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{}.tar.gz", name)))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+                .mount(&mock_server)
+                .await;
+        }
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Install root (should install all 5 packages)
+        installer
+            .install(&["root".to_string()], true)
+            .await
+            .unwrap();
+
+        // All packages should be installed
+        assert!(installer.db.get_installed("root").is_some());
+        assert!(installer.db.get_installed("mid1").is_some());
+        assert!(installer.db.get_installed("mid2").is_some());
+        assert!(installer.db.get_installed("leaf1").is_some());
+        assert!(installer.db.get_installed("leaf2").is_some());
+    }
+
+    #[tokio::test]
+    async fn streaming_extraction_processes_as_downloads_complete() {
+        // Tests that streaming extraction works correctly by verifying
+        // packages with delayed downloads still get installed properly
+        use std::time::Duration;
+
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        // Create bottles
+        let fast_bottle = create_bottle_tarball("fastpkg");
+        let fast_sha = sha256_hex(&fast_bottle);
+        let slow_bottle = create_bottle_tarball("slowpkg");
+        let slow_sha = sha256_hex(&slow_bottle);
+
+        // Fast package formula
+        let tag = get_test_bottle_tag();
+        let fast_json = format!(
+            r#"{{"name":"fastpkg","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/fast.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+            tag,
+            mock_server.uri(),
+            fast_sha
+        );
+
+        // Slow package formula (depends on fast)
+        let slow_json = format!(
+            r#"{{"name":"slowpkg","versions":{{"stable":"1.0.0"}},"dependencies":["fastpkg"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/slow.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+            tag,
+            mock_server.uri(),
+            slow_sha
+        );
+
+        // Mount API mocks
+        Mock::given(method("GET"))
+            .and(path("/fastpkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&fast_json))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/slowpkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&slow_json))
+            .mount(&mock_server)
+            .await;
+
+        // Fast bottle responds immediately
+        Mock::given(method("GET"))
+            .and(path("/bottles/fast.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(fast_bottle.clone()))
+            .mount(&mock_server)
+            .await;
+
+        // Slow bottle has a delay (simulates slow network)
+        Mock::given(method("GET"))
+            .and(path("/bottles/slow.tar.gz"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_bytes(slow_bottle.clone())
+                    .set_delay(Duration::from_millis(100)),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Install slow package (which depends on fast)
+        // With streaming, fast should be extracted while slow is still downloading
+        installer
+            .install(&["slowpkg".to_string()], true)
+            .await
+            .unwrap();
+
+        // Both packages should be installed
+        assert!(installer.db.get_installed("fastpkg").is_some());
+        assert!(installer.db.get_installed("slowpkg").is_some());
+
+        // Verify kegs exist
+        assert!(root.join("cellar/fastpkg/1.0.0").exists());
+        assert!(root.join("cellar/slowpkg/1.0.0").exists());
+
+        // Verify links exist
+        assert!(prefix.join("bin/fastpkg").exists());
+        assert!(prefix.join("bin/slowpkg").exists());
+    }
+
+    #[tokio::test]
+    async fn retries_on_corrupted_download() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        // Create valid bottle
+        let bottle = create_bottle_tarball("retrypkg");
+        let bottle_sha = sha256_hex(&bottle);
+
+        // Create formula JSON
+        let tag = get_test_bottle_tag();
+        let formula_json = format!(
+            r#"{{
+                "name": "retrypkg",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [],
+                "bottle": {{
+                    "stable": {{
+                        "files": {{
+                            "{}": {{
+                                "url": "{}/bottles/retrypkg-1.0.0.{}.bottle.tar.gz",
+                                "sha256": "{}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            tag,
+            mock_server.uri(),
+            tag,
+            bottle_sha
+        );
+
+        // Mount formula API mock
+        Mock::given(method("GET"))
+            .and(path("/retrypkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .mount(&mock_server)
+            .await;
+
+        // Track download attempts
+        let attempt_count = Arc::new(AtomicUsize::new(0));
+        let attempt_clone = attempt_count.clone();
+        let valid_bottle = bottle.clone();
+
+        // First request returns corrupted data (wrong content but matches sha for download)
+        // This simulates CDN corruption where sha passes but tar is invalid
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/bottles/retrypkg-1.0.0.{}.bottle.tar.gz",
+                tag
+            )))
+            .respond_with(move |_: &wiremock::Request| {
+                let attempt = attempt_clone.fetch_add(1, Ordering::SeqCst);
+                if attempt == 0 {
+                    // First attempt: return corrupted data
+                    // We need to return data that has the right sha256 but is corrupt
+                    // Since we can't fake sha256, we'll return invalid tar that will fail extraction
+                    // But actually the sha256 check happens during download...
+                    // So we need to return the valid bottle (sha passes) but corrupt the blob after
+                    // This is tricky to test since corruption happens at tar level
+                    // For now, just return valid data - the retry mechanism will work in real scenarios
+                    ResponseTemplate::new(200).set_body_bytes(valid_bottle.clone())
+                } else {
+                    // Subsequent attempts: return valid bottle
+                    ResponseTemplate::new(200).set_body_bytes(valid_bottle.clone())
+                }
+            })
+            .mount(&mock_server)
+            .await;
+
+        // Create installer
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Install - should succeed (first download is valid in this test)
+        installer
+            .install(&["retrypkg".to_string()], true)
+            .await
+            .unwrap();
+
+        // Verify installation succeeded
+        assert!(installer.is_installed("retrypkg"));
+        assert!(root.join("cellar/retrypkg/1.0.0").exists());
+        assert!(prefix.join("bin/retrypkg").exists());
+    }
+
+    #[tokio::test]
+    async fn fails_after_max_retries() {
+        // This test verifies that after MAX_CORRUPTION_RETRIES failed attempts,
+        // the installer gives up with an appropriate error message.
+        // Note: This is hard to test without mocking the store layer since
+        // corruption is detected during tar extraction, not during download.
+        // The retry mechanism is validated by the code structure.
+
+        // For a proper integration test, we would need to inject corruption
+        // into the blob cache after download but before extraction.
+        // This is left as a documentation of the expected behavior:
+        // - First attempt: download succeeds, extraction fails (corruption)
+        // - Second attempt: re-download, extraction fails (corruption)
+        // - Third attempt: re-download, extraction fails (corruption)
+        // - Returns error: "Failed after 3 attempts..."
+    }
+
+    #[tokio::test]
+    async fn api_and_download_concurrency_are_limited_independently() {
+        use std::time::{Duration, Instant};
+
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+        let delay = Duration::from_millis(100);
+        let names = ["limita", "limitb", "limitc"];
+
+        for name in names {
+            let bottle = create_bottle_tarball(name);
+            let formula_json = format!(
+                r#"{{"name":"{name}","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{tag}":{{"url":"{}/bottles/{name}.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+                mock_server.uri(),
+                sha256_hex(&bottle)
+            );
+
+            Mock::given(method("GET"))
+                .and(path(format!("/{name}.json")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(formula_json)
+                        .set_delay(delay),
+                )
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_bytes(bottle)
+                        .set_delay(delay),
+                )
+                .mount(&mock_server)
+                .await;
+        }
+
+        let make_installer = |dir: &str, api_concurrency: usize, download_concurrency: usize| {
+            let root = tmp.path().join(dir).join("zerobrew");
+            let prefix = tmp.path().join(dir).join("homebrew");
+            fs::create_dir_all(root.join("db")).unwrap();
+
+            let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+            let mut installer = Installer::new(
+                ApiClient::with_base_url(mock_server.uri()).with_concurrency(api_concurrency),
+                blob_cache.clone(),
+                Store::new(&root).unwrap(),
+                Cellar::new(&root).unwrap(),
+                Linker::new(&prefix).unwrap(),
+                Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+            );
+            installer.downloader =
+                ParallelDownloader::with_concurrency(blob_cache, download_concurrency);
+            installer
+        };
+        let roots: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+
+        // A limit of 1 on API fetches serializes planning
+        let mut api_limited = make_installer("api", 1, 16);
+        let start = Instant::now();
+        let plan = api_limited.plan(&roots).await.unwrap();
+        assert!(start.elapsed() >= delay * 3, "API fetches overlapped");
+        api_limited.execute(plan, false).await.unwrap();
+
+        // A limit of 1 on downloads serializes execution
+        let mut download_limited = make_installer("download", 16, 1);
+        let plan = download_limited.plan(&roots).await.unwrap();
+        let start = Instant::now();
+        download_limited.execute(plan, false).await.unwrap();
+        assert!(start.elapsed() >= delay * 3, "bottle downloads overlapped");
+    }
+
+    #[tokio::test]
+    async fn install_from_pinned_bottle_url() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let bottle = create_bottle_tarball("pinned");
         let bottle_sha = sha256_hex(&bottle);
 
-        // Create formula JSON
+        // The formula API must never be consulted
+        Mock::given(method("GET"))
+            .and(path("/pinned.json"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/bottles/pinned.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let url = format!("{}/bottles/pinned.tar.gz", mock_server.uri());
+        let plan = installer
+            .plan_bottle("pinned", "1.0.0", &url, &bottle_sha)
+            .unwrap();
+        assert_eq!(plan.formulas.len(), 1);
+        assert_eq!(plan.bottles[0].sha256, bottle_sha);
+
+        installer.execute(plan, true).await.unwrap();
+
+        assert!(root.join("cellar/pinned/1.0.0/bin/pinned").exists());
+        assert!(prefix.join("bin/pinned").exists());
+        let installed = installer.db.get_installed("pinned").unwrap();
+        assert_eq!(installed.version, "1.0.0");
+        assert_eq!(installed.store_key, bottle_sha);
+    }
+
+    #[test]
+    fn plan_bottle_rejects_invalid_checksum() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let installer = Installer::new(
+            ApiClient::new(),
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(&root).unwrap(),
+            Cellar::new(&root).unwrap(),
+            Linker::new(&tmp.path().join("homebrew")).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+        );
+
+        let err = installer
+            .plan_bottle("foo", "1.0.0", "https://example.com/foo.tar.gz", "nothex")
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidArgument { .. }));
+    }
+
+    #[tokio::test]
+    async fn search_ranks_name_matches_before_descriptions() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let index = r#"[
+            {"name": "gojq", "desc": "Pure Go implementation of jq", "versions": {"stable": "0.12.16"}},
+            {"name": "jid", "desc": "Drill down JSON with jq filters", "versions": {"stable": "0.7.6"}},
+            {"name": "jq", "desc": "Lightweight and flexible command-line JSON processor", "versions": {"stable": "1.7.1"}},
+            {"name": "wget", "desc": "Internet file retriever", "versions": {"stable": "1.24.5"}}
+        ]"#;
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(index))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let names: Vec<String> = installer
+            .search("JQ")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|f| f.name)
+            .collect();
+        assert_eq!(names, ["jq", "gojq", "jid"]);
+
+        assert!(
+            installer
+                .search("nothing-like-this")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_formula_suggests_close_names_from_the_index() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let index = r#"[
+            {"name": "rg", "versions": {"stable": "1.0"}},
+            {"name": "ripgrep", "versions": {"stable": "14.1.0"}},
+            {"name": "ripgrep-all", "versions": {"stable": "0.10.6"}},
+            {"name": "wget", "versions": {"stable": "1.24.5"}}
+        ]"#;
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(index))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/formula/ripgrp.json"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let Err(Error::MissingFormula { name, suggestions }) =
+            installer.plan(&["ripgrp".to_string()]).await
+        else {
+            panic!("expected a missing formula error");
+        };
+        assert_eq!(name, "ripgrp");
+        assert_eq!(suggestions, ["ripgrep"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_url_caches_the_download_and_reports_its_sha256() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let bottle = create_bottle_tarball("fetched");
+
+        Mock::given(method("GET"))
+            .and(path("/bottles/fetched.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let (path, sha256) = installer
+            .fetch_url(&format!("{}/bottles/fetched.tar.gz", mock_server.uri()))
+            .await
+            .unwrap();
+
+        assert_eq!(sha256, sha256_hex(&bottle));
+        assert_eq!(fs::read(&path).unwrap(), bottle);
+        assert!(path.ends_with(format!("{sha256}.tar.gz")));
+    }
+
+    #[tokio::test]
+    async fn head_requests_are_rejected_before_fetching_anything() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        for requested in ["jq@HEAD", "jq@head"] {
+            let Err(Error::HeadUnsupported { name }) =
+                installer.plan(&[requested.to_string()]).await
+            else {
+                panic!("expected {requested} to be rejected");
+            };
+            assert_eq!(name, "jq");
+        }
+        assert!(mock_server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("ripgrp", "ripgrep"), 1);
+        assert_eq!(edit_distance("wegt", "wget"), 2);
+        assert_eq!(edit_distance("", "jq"), 2);
+        assert_eq!(edit_distance("jq", "jq"), 0);
+    }
+
+    #[tokio::test]
+    async fn upgrade_replaces_outdated_keg_and_keeps_old_one_for_cleanup() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        let formula_json = |version: &str, sha: &str| {
+            format!(
+                r#"{{
+                    "name": "upgpkg",
+                    "versions": {{ "stable": "{version}" }},
+                    "dependencies": [],
+                    "bottle": {{ "stable": {{ "files": {{
+                        "{tag}": {{ "url": "{}/bottles/upgpkg-{version}.tar.gz", "sha256": "{sha}" }}
+                    }} }} }}
+                }}"#,
+                mock_server.uri()
+            )
+        };
+
+        let old_bottle = create_versioned_bottle_tarball("upgpkg", "1.0.0");
+        let new_bottle = create_versioned_bottle_tarball("upgpkg", "1.10.0");
+        let old_sha = sha256_hex(&old_bottle);
+        let new_sha = sha256_hex(&new_bottle);
+
+        for (version, bottle) in [("1.0.0", &old_bottle), ("1.10.0", &new_bottle)] {
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/upgpkg-{version}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+                .mount(&mock_server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/upgpkg.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(formula_json("1.0.0", &old_sha)),
+            )
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new_at(prefix.join("Cellar")).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        installer
+            .install(&["upgpkg".to_string()], true)
+            .await
+            .unwrap();
+
+        // A newer version is published
+        Mock::given(method("GET"))
+            .and(path("/upgpkg.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(formula_json("1.10.0", &new_sha)),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let outdated = installer.outdated().await.unwrap();
+        assert_eq!(
+            outdated,
+            [OutdatedKeg {
+                name: "upgpkg".to_string(),
+                installed: "1.0.0".to_string(),
+                available: "1.10.0".to_string(),
+                pinned: false,
+            }]
+        );
+
+        let plan = installer
+            .plan_upgrade(&["upgpkg".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(plan.formulas.len(), 1);
+        installer.execute(plan, true).await.unwrap();
+
+        assert_eq!(installer.get_installed("upgpkg").unwrap().version, "1.10.0");
+        assert!(installer.outdated().await.unwrap().is_empty());
+
+        let link = prefix.join("bin/upgpkg");
+        assert!(fs::read_to_string(&link).unwrap().contains("1.10.0"));
+
+        // The old keg stays on disk, but its store entry is no longer referenced
+        assert!(installer.keg_path("upgpkg", "1.0.0").exists());
+        assert_eq!(installer.db.get_store_refcount(&old_sha), 0);
+        assert_eq!(installer.db.get_store_refcount(&new_sha), 1);
+
+        // Nothing left to upgrade
+        let plan = installer
+            .plan_upgrade(&["upgpkg".to_string()])
+            .await
+            .unwrap();
+        assert!(plan.formulas.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rollback_relinks_previous_keg_while_it_is_in_the_cellar() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        let formula_json = |version: &str, sha: &str| {
+            format!(
+                r#"{{
+                    "name": "rbpkg",
+                    "versions": {{ "stable": "{version}" }},
+                    "dependencies": [],
+                    "bottle": {{ "stable": {{ "files": {{
+                        "{tag}": {{ "url": "{}/bottles/rbpkg-{version}.tar.gz", "sha256": "{sha}" }}
+                    }} }} }}
+                }}"#,
+                mock_server.uri()
+            )
+        };
+
+        let old_bottle = create_versioned_bottle_tarball("rbpkg", "1.0.0");
+        let new_bottle = create_versioned_bottle_tarball("rbpkg", "2.0.0");
+        let old_sha = sha256_hex(&old_bottle);
+        let new_sha = sha256_hex(&new_bottle);
+
+        for (version, bottle) in [("1.0.0", &old_bottle), ("2.0.0", &new_bottle)] {
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/rbpkg-{version}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+                .mount(&mock_server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/rbpkg.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(formula_json("1.0.0", &old_sha)),
+            )
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new_at(prefix.join("Cellar")).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        installer
+            .install(&["rbpkg".to_string()], true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            installer.rollback("rbpkg"),
+            Err(Error::InvalidArgument { .. })
+        ));
+
+        Mock::given(method("GET"))
+            .and(path("/rbpkg.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(formula_json("2.0.0", &new_sha)),
+            )
+            .mount(&mock_server)
+            .await;
+        let plan = installer
+            .plan_upgrade(&["rbpkg".to_string()])
+            .await
+            .unwrap();
+        installer.execute(plan, true).await.unwrap();
+
+        let link = prefix.join("bin/rbpkg");
+        assert!(fs::read_to_string(&link).unwrap().contains("2.0.0"));
+
+        let (from, to) = installer.rollback("rbpkg").unwrap();
+        assert_eq!((from.as_str(), to.as_str()), ("2.0.0", "1.0.0"));
+        assert_eq!(installer.get_installed("rbpkg").unwrap().version, "1.0.0");
+        assert!(fs::read_to_string(&link).unwrap().contains("1.0.0"));
+        assert_eq!(installer.db.get_store_refcount(&old_sha), 1);
+        assert_eq!(installer.db.get_store_refcount(&new_sha), 0);
+
+        // Cleanup only takes versions older than the installed one, so
+        // rolling back again still returns to 2.0.0
+        installer.cleanup(false, None).unwrap();
+        assert!(installer.keg_path("rbpkg", "2.0.0").exists());
+
+        // Unless that keg is gone
+        fs::remove_dir_all(installer.keg_path("rbpkg", "2.0.0")).unwrap();
+        assert!(matches!(
+            installer.rollback("rbpkg"),
+            Err(Error::FileError { .. })
+        ));
+        assert_eq!(installer.get_installed("rbpkg").unwrap().version, "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn only_dependencies_skips_the_requested_formula() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_formula(&mock_server, "depsonly", &["depsdep"]).await;
+        mount_formula(&mock_server, "depsdep", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new_at(prefix.join("Cellar")).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let plan = installer
+            .plan_dependencies(&["depsonly".to_string()])
+            .await
+            .unwrap();
+        installer.execute(plan, true).await.unwrap();
+
+        assert!(installer.is_installed("depsdep"));
+        assert!(installer.keg_path("depsdep", "1.0.0").exists());
+        assert!(!installer.is_installed("depsonly"));
+        assert!(!installer.keg_path("depsonly", "1.0.0").exists());
+    }
+
+    #[tokio::test]
+    async fn ignore_dependencies_installs_only_the_root() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        // The dependency isn't mounted: fetching it at all would fail the plan
+        mount_formula(&mock_server, "loner", &["loneronly"]).await;
+        mount_formula(&mock_server, "buddy", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new_at(prefix.join("Cellar")).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db)
+            .with_ignore_dependencies(true);
+
+        // A name given twice is planned once
+        let names = ["loner", "buddy", "loner"].map(String::from);
+        let plan = installer.plan(&names).await.unwrap();
+        assert_eq!(plan.formulas.len(), 2);
+        installer.execute(plan, true).await.unwrap();
+
+        assert_eq!(installer.get_installed("loner").unwrap().version, "1.0.0");
+        assert!(installer.keg_path("loner", "1.0.0").exists());
+        assert!(!installer.is_installed("loneronly"));
+    }
+
+    #[tokio::test]
+    async fn description_is_recorded_at_install_and_listed() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+        let bottle = create_bottle_tarball("described");
+        let formula_json = serde_json::json!({
+            "name": "described",
+            "desc": "Formula with a description",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/described.tar.gz", mock_server.uri()),
+                    "sha256": sha256_hex(&bottle),
+                }
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/described.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/described.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+        mount_formula(&mock_server, "undescribed", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new_at(prefix.join("Cellar")).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        installer
+            .install(&["described".to_string(), "undescribed".to_string()], true)
+            .await
+            .unwrap();
+
+        let installed = installer.list_installed().unwrap();
+        let descriptions: Vec<_> = installed
+            .iter()
+            .map(|keg| (keg.name.as_str(), keg.description.as_deref()))
+            .collect();
+        assert_eq!(
+            descriptions,
+            [
+                ("described", Some("Formula with a description")),
+                ("undescribed", None)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn license_gated_formula_installs_only_once_accepted() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
         let tag = get_test_bottle_tag();
-        let formula_json = format!(
-            r#"{{
-                "name": "testpkg",
-                "versions": {{ "stable": "1.0.0" }},
-                "dependencies": [],
-                "bottle": {{
-                    "stable": {{
-                        "files": {{
-                            "{}": {{
-                                "url": "{}/bottles/testpkg-1.0.0.{}.bottle.tar.gz",
-                                "sha256": "{}"
-                            }}
-                        }}
-                    }}
-                }}
-            }}"#,
-            tag,
-            mock_server.uri(),
-            tag,
-            bottle_sha
+        let bottle = create_bottle_tarball("licensed");
+        let formula_json = serde_json::json!({
+            "name": "licensed",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "license": "LicenseRef-EULA",
+            "requires_license_acceptance": true,
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/licensed.tar.gz", mock_server.uri()),
+                    "sha256": sha256_hex(&bottle),
+                }
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/licensed.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/licensed.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        let api_url = format!("{}/formula", mock_server.uri());
+        let open = |accept: bool| {
+            Installer::new(
+                ApiClient::with_base_url(api_url.clone()),
+                BlobCache::new(&root.join("cache")).unwrap(),
+                Store::new(&root).unwrap(),
+                Cellar::new_at(prefix.join("Cellar")).unwrap(),
+                Linker::new(&prefix).unwrap(),
+                Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+            )
+            .with_accepted_licenses(accept)
+        };
+
+        let mut installer = open(false);
+        let Err(Error::LicenseNotAccepted { name, license }) =
+            installer.install(&["licensed".to_string()], true).await
+        else {
+            panic!("expected the unaccepted license to stop the install");
+        };
+        assert_eq!(name, "licensed");
+        assert_eq!(license.as_deref(), Some("LicenseRef-EULA"));
+        assert!(!installer.is_installed("licensed"));
+        let bottle_fetches = mock_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.url.path().starts_with("/bottles/"))
+            .count();
+        assert_eq!(bottle_fetches, 0);
+        drop(installer);
+
+        let mut installer = open(true);
+        installer
+            .install(&["licensed".to_string()], true)
+            .await
+            .unwrap();
+        assert!(installer.is_installed("licensed"));
+    }
+
+    #[tokio::test]
+    async fn keep_going_installs_what_it_can_and_skips_dependents_of_failures() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        mount_formula(&mock_server, "goodone", &[]).await;
+        mount_formula(&mock_server, "goodtwo", &[]).await;
+        mount_formula(&mock_server, "needsbad", &["badbottle"]).await;
+        let formula_json = serde_json::json!({
+            "name": "badbottle",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/badbottle.tar.gz", mock_server.uri()),
+                    "sha256": "b".repeat(64),
+                }
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/badbottle.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/badbottle.tar.gz"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let names = ["goodone", "badbottle", "goodtwo"].map(String::from);
+        let plan = installer.plan(&names).await.unwrap();
+        assert!(installer.execute(plan, true).await.is_err());
+        assert!(!installer.is_installed("goodone"));
+
+        let mut installer = installer.with_keep_going(true);
+        let names = ["goodone", "needsbad", "goodtwo"].map(String::from);
+        let plan = installer.plan(&names).await.unwrap();
+        let result = installer.execute(plan, true).await.unwrap();
+
+        assert_eq!(result.installed, 2);
+        let failed: Vec<&str> = result.failed.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(failed, ["badbottle", "needsbad"]);
+        assert_eq!(
+            result.failed[1].1,
+            Error::DependencyFailed {
+                name: "needsbad".to_string(),
+                dependency: "badbottle".to_string(),
+            }
         );
+        assert!(installer.is_installed("goodone"));
+        assert!(installer.is_installed("goodtwo"));
+        assert!(!installer.is_installed("needsbad"));
+        assert!(!prefix.join("bin/needsbad").exists());
+        assert!(prefix.join("bin/goodone").exists());
+    }
 
-        // Mount formula API mock
+    #[tokio::test]
+    async fn keep_going_never_links_dependents_of_a_keg_that_failed_to_link() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_formula(&mock_server, "clashes", &[]).await;
+        mount_formula(&mock_server, "needsclash", &["clashes"]).await;
+        mount_formula(&mock_server, "bystander", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        fs::create_dir_all(prefix.join("bin")).unwrap();
+        fs::write(prefix.join("bin/clashes"), "not ours").unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer =
+            Installer::new(api_client, blob_cache, store, cellar, linker, db).with_keep_going(true);
+
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let recorded = started.clone();
+        let callback: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
+            if let InstallProgress::LinkStarted { name } = event {
+                recorded.lock().unwrap().push(name);
+            }
+        }));
+        let names = ["needsclash", "bystander"].map(String::from);
+        let plan = installer.plan(&names).await.unwrap();
+        let result = installer
+            .execute_with_progress(plan, true, Some(callback))
+            .await
+            .unwrap();
+
+        let failed: Vec<&str> = result.failed.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(failed, ["clashes", "needsclash"]);
+        assert!(matches!(result.failed[1].1, Error::DependencyFailed { .. }));
+        let started = started.lock().unwrap();
+        assert!(!started.iter().any(|name| name == "needsclash"));
+        assert!(installer.is_installed("bystander"));
+    }
+
+    #[tokio::test]
+    async fn plan_lists_a_diamond_dependency_once_before_its_dependents() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_formula(&mock_server, "diamond-a", &["diamond-b", "diamond-c"]).await;
+        mount_formula(&mock_server, "diamond-b", &["diamond-d"]).await;
+        mount_formula(&mock_server, "diamond-c", &["diamond-d"]).await;
+        mount_formula(&mock_server, "diamond-d", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let plan = installer.plan(&["diamond-a".to_string()]).await.unwrap();
+        let order: Vec<&str> = plan.formulas.iter().map(|f| f.name.as_str()).collect();
+        let position = |name: &str| order.iter().position(|&n| n == name).unwrap();
+
+        assert_eq!(order.len(), 4);
+        assert_eq!(order.iter().filter(|&&n| n == "diamond-d").count(), 1);
+        assert!(position("diamond-d") < position("diamond-b"));
+        assert!(position("diamond-d") < position("diamond-c"));
+        assert_eq!(position("diamond-a"), 3);
+        assert_eq!(plan.bottles.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn multi_root_install_fetches_shared_dependency_once() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        mount_formula(&mock_server, "rootone", &["shareddep"]).await;
+        mount_formula(&mock_server, "roottwo", &["shareddep"]).await;
+
+        let bottle = create_bottle_tarball("shareddep");
+        let formula_json = serde_json::json!({
+            "name": "shareddep",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/shareddep.tar.gz", mock_server.uri()),
+                    "sha256": sha256_hex(&bottle),
+                }
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/shareddep.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/shareddep.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/formula/nosuchroot.json"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
+            .with_cache(ApiCache::in_memory().unwrap());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let names = ["rootone", "nosuchroot", "roottwo"].map(String::from);
+        let (plan, failed) = installer.plan_partial(&names).await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "nosuchroot");
+
+        let planned: Vec<&str> = plan.formulas.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(planned.iter().filter(|&&n| n == "shareddep").count(), 1);
+        assert_eq!(planned[0], "shareddep");
+
+        // Racing connections may each request the bottle, but it must only be
+        // downloaded (and installed) as one package
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let recorded = completed.clone();
+        let callback: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
+            if let InstallProgress::DownloadCompleted { name, .. } = event {
+                recorded.lock().unwrap().push(name);
+            }
+        }));
+        installer
+            .execute_with_progress(plan, true, Some(callback))
+            .await
+            .unwrap();
+
+        let completed = completed.lock().unwrap();
+        assert_eq!(completed.iter().filter(|n| *n == "shareddep").count(), 1);
+        for name in ["rootone", "roottwo", "shareddep"] {
+            assert!(installer.is_installed(name));
+        }
+    }
+
+    #[tokio::test]
+    async fn execute_reports_planned_and_overall_download_size() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_formula(&mock_server, "sizedapp", &["sizeddep"]).await;
+        mount_formula(&mock_server, "sizeddep", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let plan = installer.plan(&["sizedapp".to_string()]).await.unwrap();
+        let expected = plan.download_size().unwrap();
+        assert!(expected > 0);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let overall = Arc::new(Mutex::new(Vec::new()));
+        let (recorded, recorded_overall) = (events.clone(), overall.clone());
+        let callback: Arc<ProgressCallback> = Arc::new(Box::new(move |event| match event {
+            InstallProgress::PlanSized {
+                total_bytes,
+                packages,
+            } => recorded.lock().unwrap().push((total_bytes, packages)),
+            InstallProgress::OverallProgress { downloaded, total } => {
+                recorded_overall.lock().unwrap().push((downloaded, total))
+            }
+            _ => {}
+        }));
+        installer
+            .execute_with_progress(plan, true, Some(callback))
+            .await
+            .unwrap();
+
+        assert_eq!(*events.lock().unwrap(), [(Some(expected), 2)]);
+        let overall = overall.lock().unwrap();
+        assert!(
+            overall
+                .iter()
+                .all(|&(downloaded, _)| downloaded <= expected)
+        );
+        assert_eq!(overall.last(), Some(&(expected, Some(expected))));
+    }
+
+    #[tokio::test]
+    async fn outdated_detects_revision_bumps() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        let bottle = create_bottle_tarball("revpkg");
+        let bottle_sha = sha256_hex(&bottle);
+        let formula_json = |revision: u32| {
+            format!(
+                r#"{{
+                    "name": "revpkg",
+                    "versions": {{ "stable": "1.0.0" }},
+                    "revision": {revision},
+                    "dependencies": [],
+                    "bottle": {{ "stable": {{ "files": {{
+                        "{tag}": {{ "url": "{}/bottles/revpkg.tar.gz", "sha256": "{bottle_sha}" }}
+                    }} }} }}
+                }}"#,
+                mock_server.uri()
+            )
+        };
+
+        Mock::given(method("GET"))
+            .and(path("/bottles/revpkg.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/revpkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(formula_json(0)))
+            .up_to_n_times(2)
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        installer
+            .install(&["revpkg".to_string()], false)
+            .await
+            .unwrap();
+        assert!(installer.outdated().await.unwrap().is_empty());
+
+        Mock::given(method("GET"))
+            .and(path("/revpkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(formula_json(1)))
+            .mount(&mock_server)
+            .await;
+
+        let outdated = installer.outdated().await.unwrap();
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated[0].installed, "1.0.0");
+        assert_eq!(outdated[0].available, "1.0.0_1");
+        assert!(!outdated[0].pinned);
+
+        // A pinned formula is still reported, but upgrades leave it alone
+        assert!(installer.pin("revpkg").unwrap());
+        assert!(!installer.pin("revpkg").unwrap());
+        assert!(installer.outdated().await.unwrap()[0].pinned);
+        let names = ["revpkg".to_string()];
+        assert!(
+            installer
+                .plan_upgrade(&names)
+                .await
+                .unwrap()
+                .formulas
+                .is_empty()
+        );
+
+        assert!(installer.unpin("revpkg").unwrap());
+        let plan = installer.plan_upgrade(&names).await.unwrap();
+        assert_eq!(plan.formulas.len(), 1);
+        assert_eq!(plan.formulas[0].effective_version(), "1.0.0_1");
+
+        assert!(matches!(
+            installer.pin("missing"),
+            Err(Error::NotInstalled { .. })
+        ));
+    }
+
+    /// Serve a formula under `/formula/{name}.json` along with its bottle
+    async fn mount_formula(mock_server: &MockServer, name: &str, deps: &[&str]) {
+        let tag = get_test_bottle_tag();
+        let bottle = create_bottle_tarball(name);
+        let bottle_sha = sha256_hex(&bottle);
+        let formula_json = serde_json::json!({
+            "name": name,
+            "versions": { "stable": "1.0.0" },
+            "dependencies": deps,
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/{name}.tar.gz", mock_server.uri()),
+                    "sha256": bottle_sha,
+                    "size": bottle.len(),
+                }
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path(format!("/formula/{name}.json")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/bottles/{name}.tar.gz")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(mock_server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn failed_bottle_downloads_fall_back_to_the_configured_mirrors() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let tag = get_test_bottle_tag();
+        let bottle = create_bottle_tarball("mirrored");
+        let blob = "v2/homebrew/core/mirrored/blobs/sha256:abc";
+        let formula_json = serde_json::json!({
+            "name": "mirrored",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("https://ghcr.io/{blob}"),
+                    "sha256": sha256_hex(&bottle),
+                }
+            } } }
+        });
         Mock::given(method("GET"))
-            .and(path("/testpkg.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .and(path("/formula/mirrored.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
             .mount(&mock_server)
             .await;
-
-        // Mount bottle download mock
         Mock::given(method("GET"))
-            .and(path(format!(
-                "/bottles/testpkg-1.0.0.{}.bottle.tar.gz",
-                tag
-            )))
-            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .and(path(format!("/primary/{blob}")))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/fallback/{blob}")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .expect(1)
             .mount(&mock_server)
             .await;
 
-        // Create installer with mocked API
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
         fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
         let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
         let store = Store::new(&root).unwrap();
         let cellar = Cellar::new(&root).unwrap();
         let linker = Linker::new(&prefix).unwrap();
         let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db)
+            .with_bottle_mirror(&format!("{}/primary", mock_server.uri()))
+            .with_fallback_mirrors(vec![format!("{}/fallback/", mock_server.uri())]);
 
-        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
-
-        // Install
         installer
-            .install(&["testpkg".to_string()], true)
+            .install(&["mirrored".to_string()], true)
             .await
             .unwrap();
+        assert!(installer.is_installed("mirrored"));
+    }
 
-        // Verify keg exists
-        assert!(root.join("cellar/testpkg/1.0.0").exists());
-
-        // Verify link exists
-        assert!(prefix.join("bin/testpkg").exists());
-
-        // Verify database records
-        let installed = installer.db.get_installed("testpkg");
-        assert!(installed.is_some());
-        assert_eq!(installed.unwrap().version, "1.0.0");
+    #[test]
+    fn only_ghcr_bottles_have_mirror_urls() {
+        let mirrors = vec!["https://a.example.com".to_string()];
+        assert_eq!(
+            mirror_urls("https://ghcr.io/v2/x/blobs/sha256:1", &mirrors),
+            vec!["https://a.example.com/v2/x/blobs/sha256:1".to_string()]
+        );
+        assert!(mirror_urls("https://example.com/x.tar.gz", &mirrors).is_empty());
     }
 
     #[tokio::test]
-    async fn uninstall_cleans_everything() {
+    async fn trust_on_first_use_installs_under_the_computed_digest() {
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
 
-        // Create bottle
-        let bottle = create_bottle_tarball("uninstallme");
-        let bottle_sha = sha256_hex(&bottle);
-
-        // Create formula JSON
         let tag = get_test_bottle_tag();
-        let formula_json = format!(
-            r#"{{
-                "name": "uninstallme",
-                "versions": {{ "stable": "1.0.0" }},
-                "dependencies": [],
-                "bottle": {{
-                    "stable": {{
-                        "files": {{
-                            "{}": {{
-                                "url": "{}/bottles/uninstallme-1.0.0.{}.bottle.tar.gz",
-                                "sha256": "{}"
-                            }}
-                        }}
-                    }}
-                }}
-            }}"#,
-            tag,
-            mock_server.uri(),
-            tag,
-            bottle_sha
-        );
-
-        // Mount mocks
+        let bottle = create_bottle_tarball("unsigned");
+        let formula_json = serde_json::json!({
+            "name": "unsigned",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/unsigned.tar.gz", mock_server.uri()),
+                    "sha256": "",
+                }
+            } } }
+        });
         Mock::given(method("GET"))
-            .and(path("/uninstallme.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .and(path("/formula/unsigned.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
             .mount(&mock_server)
             .await;
-
         Mock::given(method("GET"))
-            .and(path(format!(
-                "/bottles/uninstallme-1.0.0.{}.bottle.tar.gz",
-                tag
-            )))
+            .and(path("/bottles/unsigned.tar.gz"))
             .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
             .mount(&mock_server)
             .await;
 
-        // Create installer
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
         fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let make_installer = || {
+            let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+            let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+            let store = Store::new(&root).unwrap();
+            let cellar = Cellar::new(&root).unwrap();
+            let linker = Linker::new(&prefix).unwrap();
+            let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+            Installer::new(api_client, blob_cache, store, cellar, linker, db)
+        };
+
+        // Never the default
+        let Err(Error::InvalidArgument { .. }) = make_installer()
+            .install(&["unsigned".to_string()], true)
+            .await
+        else {
+            panic!("installed a bottle with no checksum");
+        };
+
+        let mut installer = make_installer().with_trust_on_first_use(true);
+        installer
+            .install(&["unsigned".to_string()], true)
+            .await
+            .unwrap();
+        let sha = sha256_hex(&bottle);
+        let keg = installer.get_installed("unsigned").unwrap();
+        assert_eq!(keg.store_key, sha);
+        assert!(installer.store.has_entry(&sha));
+
+        // Installing again finds it installed from that same bottle
+        let result = installer
+            .install(&["unsigned".to_string()], true)
+            .await
+            .unwrap();
+        assert_eq!(result.installed, 0);
+    }
+
+    #[tokio::test]
+    async fn formulas_from_a_tap_remember_it_once_installed() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_formula(&mock_server, "tool", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        // homebrew/core knows nothing of `tool`; only the tap serves it
+        let api_client = ApiClient::with_base_url(format!("{}/core", mock_server.uri()));
         let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
         let store = Store::new(&root).unwrap();
         let cellar = Cellar::new(&root).unwrap();
         let linker = Linker::new(&prefix).unwrap();
         let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db)
+            .with_tap("mycompany/tools", &format!("{}/formula", mock_server.uri()));
 
-        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
-
-        // Install
         installer
-            .install(&["uninstallme".to_string()], true)
+            .install(&["mycompany/tools/tool".to_string()], true)
             .await
             .unwrap();
 
-        // Verify installed
-        assert!(installer.is_installed("uninstallme"));
-        assert!(root.join("cellar/uninstallme/1.0.0").exists());
-        assert!(prefix.join("bin/uninstallme").exists());
-
-        // Uninstall
-        installer.uninstall("uninstallme").unwrap();
+        let keg = installer.get_installed("tool").unwrap();
+        assert_eq!(keg.tap.as_deref(), Some("mycompany/tools"));
+        assert!(installer.get_installed("mycompany/tools/tool").is_some());
+        assert!(installer.get_installed("other/tap/tool").is_none());
 
-        // Verify everything cleaned up
-        assert!(!installer.is_installed("uninstallme"));
-        assert!(!root.join("cellar/uninstallme/1.0.0").exists());
-        assert!(!prefix.join("bin/uninstallme").exists());
+        // A newer release in the tap shows up as an upgrade
+        Mock::given(method("GET"))
+            .and(path("/formula/tool.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "name": "tool",
+                "versions": { "stable": "2.0.0" },
+                "dependencies": [],
+                "bottle": { "stable": { "files": {} } }
+            })))
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        let outdated = installer.outdated().await.unwrap();
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated[0].available, "2.0.0");
     }
 
     #[tokio::test]
-    async fn gc_removes_unreferenced_store_entries() {
+    async fn execute_result_times_every_phase_of_each_package() {
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
+        mount_formula(&mock_server, "timed", &[]).await;
 
-        // Create bottle
-        let bottle = create_bottle_tarball("gctest");
-        let bottle_sha = sha256_hex(&bottle);
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
 
-        // Create formula JSON
-        let tag = get_test_bottle_tag();
-        let formula_json = format!(
-            r#"{{
-                "name": "gctest",
-                "versions": {{ "stable": "1.0.0" }},
-                "dependencies": [],
-                "bottle": {{
-                    "stable": {{
-                        "files": {{
-                            "{}": {{
-                                "url": "{}/bottles/gctest-1.0.0.{}.bottle.tar.gz",
-                                "sha256": "{}"
-                            }}
-                        }}
-                    }}
-                }}
-            }}"#,
-            tag,
-            mock_server.uri(),
-            tag,
-            bottle_sha
-        );
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
 
-        // Mount mocks
-        Mock::given(method("GET"))
-            .and(path("/gctest.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
-            .mount(&mock_server)
-            .await;
+        let result = installer
+            .install(&["timed".to_string()], true)
+            .await
+            .unwrap();
 
+        let timings = result.timings["timed"];
+        assert!(timings.download > Duration::ZERO);
+        assert!(timings.extract > Duration::ZERO);
+        assert!(timings.materialize > Duration::ZERO);
+        assert!(timings.link > Duration::ZERO);
+
+        let json = serde_json::to_value(timings).unwrap();
+        assert!(json["download"].as_f64().unwrap() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn uses_finds_installed_and_indexed_dependents() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "app", &["deplib"]).await;
+        mount_formula(&mock_server, "deplib", &[]).await;
         Mock::given(method("GET"))
-            .and(path(format!("/bottles/gctest-1.0.0.{}.bottle.tar.gz", tag)))
-            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"[
+                    { "name": "app", "versions": { "stable": "1.0.0" }, "dependencies": ["deplib"] },
+                    { "name": "deplib", "versions": { "stable": "1.0.0" }, "dependencies": [] },
+                    { "name": "other", "versions": { "stable": "2.0" }, "dependencies": ["deplib"] }
+                ]"#,
+            ))
             .mount(&mock_server)
             .await;
 
-        // Create installer
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
         fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
+            .with_cache(ApiCache::in_memory().unwrap());
         let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
         let store = Store::new(&root).unwrap();
         let cellar = Cellar::new(&root).unwrap();
         let linker = Linker::new(&prefix).unwrap();
         let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        installer.install(&["app".to_string()], true).await.unwrap();
+
+        assert_eq!(installer.installed_dependents("deplib").unwrap(), ["app"]);
+        assert!(installer.installed_dependents("app").unwrap().is_empty());
+        assert_eq!(installer.uses("deplib", false).await.unwrap(), ["app"]);
+        assert_eq!(
+            installer.uses("deplib", true).await.unwrap(),
+            ["app", "other"]
+        );
+    }
+
+    #[tokio::test]
+    async fn uninstall_refuses_depended_upon_formula_unless_forced() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "app", &["deplib"]).await;
+        mount_formula(&mock_server, "deplib", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
 
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
+            .with_cache(ApiCache::in_memory().unwrap());
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
         let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
 
-        // Install and uninstall
-        installer
-            .install(&["gctest".to_string()], true)
+        installer.install(&["app".to_string()], true).await.unwrap();
+
+        let err = installer.uninstall("deplib", false).unwrap_err();
+        assert_eq!(
+            err,
+            Error::DependencyConflict {
+                name: "deplib".to_string(),
+                dependents: vec!["app".to_string()],
+            }
+        );
+        assert!(installer.is_installed("deplib"));
+
+        installer.uninstall("deplib", true).unwrap();
+        assert!(!installer.is_installed("deplib"));
+        assert!(installer.is_installed("app"));
+    }
+
+    #[tokio::test]
+    async fn uninstall_refuses_when_a_dependent_formula_isnt_cached() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "app", &["deplib"]).await;
+        mount_formula(&mock_server, "deplib", &[]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let make_installer = || {
+            let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
+                .with_cache(ApiCache::in_memory().unwrap());
+            let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+            let store = Store::new(&root).unwrap();
+            let cellar = Cellar::new(&root).unwrap();
+            let linker = Linker::new(&prefix).unwrap();
+            let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+            Installer::new(api_client, blob_cache, store, cellar, linker, db)
+        };
+        make_installer()
+            .install(&["app".to_string()], true)
             .await
             .unwrap();
 
-        // Store entry should exist before GC
-        assert!(root.join("store").join(&bottle_sha).exists());
+        // A fresh cache knows nothing about `app`, so it can't be ruled out
+        let mut installer = make_installer();
+        let Err(Error::ExecutionError { .. }) = installer.uninstall("deplib", false) else {
+            panic!("uninstall went ahead without checking app");
+        };
+        assert!(installer.is_installed("deplib"));
 
-        installer.uninstall("gctest").unwrap();
+        installer.cache_installed_formulas().await.unwrap();
+        let Err(Error::DependencyConflict { dependents, .. }) =
+            installer.uninstall("deplib", false)
+        else {
+            panic!("app should block the uninstall");
+        };
+        assert_eq!(dependents, ["app"]);
+    }
 
-        // Store entry should still exist (refcount decremented but not GC'd)
-        assert!(root.join("store").join(&bottle_sha).exists());
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn concurrent_installs_of_same_formula_keep_store_consistent() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
 
-        // Run GC
-        let removed = installer.gc().unwrap();
-        assert_eq!(removed.len(), 1);
-        assert_eq!(removed[0], bottle_sha);
+        mount_formula(&mock_server, "racer", &[]).await;
 
-        // Store entry should now be gone
-        assert!(!root.join("store").join(&bottle_sha).exists());
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        // Two installers sharing one root stand in for two zb processes
+        let make_installer = || {
+            let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+            let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+            let store = Store::new(&root).unwrap();
+            let cellar = Cellar::new(&root).unwrap();
+            let linker = Linker::new(&prefix).unwrap();
+            let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+            Installer::new(api_client, blob_cache, store, cellar, linker, db)
+        };
+        // Each on its own thread and runtime, so one blocking on a lock
+        // can't stall the other
+        let install = |mut installer: Installer| {
+            tokio::task::spawn_blocking(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                runtime
+                    .block_on(installer.install(&["racer".to_string()], true))
+                    .map(|_| ())
+            })
+        };
+        let (a, b) = tokio::join!(install(make_installer()), install(make_installer()));
+        a.unwrap().unwrap();
+        b.unwrap().unwrap();
+
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let keg = db.get_installed("racer").unwrap();
+        assert_eq!(db.get_store_refcount(&keg.store_key), 1);
+        assert_eq!(db.list_installed().unwrap().len(), 1);
+
+        let store = Store::new(&root).unwrap();
+        assert!(store.has_entry(&keg.store_key));
+        assert!(root.join("cellar/racer/1.0.0/bin/racer").exists());
+        assert!(prefix.join("bin/racer").exists());
+
+        // No temp directories left behind by either extraction
+        let leftovers: Vec<_> = fs::read_dir(root.join("store"))
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_name().to_string_lossy().starts_with('.'))
+            .collect();
+        assert!(leftovers.is_empty());
     }
 
     #[tokio::test]
-    async fn gc_does_not_remove_referenced_store_entries() {
+    async fn keg_only_formula_is_materialized_but_not_linked() {
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
 
-        // Create bottle
-        let bottle = create_bottle_tarball("keepme");
-        let bottle_sha = sha256_hex(&bottle);
-
-        // Create formula JSON
-        let tag = get_test_bottle_tag();
-        let formula_json = format!(
-            r#"{{
-                "name": "keepme",
-                "versions": {{ "stable": "1.0.0" }},
-                "dependencies": [],
-                "bottle": {{
-                    "stable": {{
-                        "files": {{
-                            "{}": {{
-                                "url": "{}/bottles/keepme-1.0.0.{}.bottle.tar.gz",
-                                "sha256": "{}"
-                            }}
-                        }}
-                    }}
-                }}
-            }}"#,
-            tag,
-            mock_server.uri(),
-            tag,
-            bottle_sha
-        );
-
-        // Mount mocks
+        let bottle = create_bottle_tarball("kegonly");
+        let bottle_sha = sha256_hex(&bottle);
+        let formula_json = serde_json::json!({
+            "name": "kegonly",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "keg_only": true,
+            "keg_only_reason": { "reason": ":provided_by_macos", "explanation": "" },
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/kegonly.tar.gz", mock_server.uri()),
+                    "sha256": bottle_sha,
+                }
+            } } }
+        });
         Mock::given(method("GET"))
-            .and(path("/keepme.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .and(path("/kegonly.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
             .mount(&mock_server)
             .await;
-
         Mock::given(method("GET"))
-            .and(path(format!("/bottles/keepme-1.0.0.{}.bottle.tar.gz", tag)))
-            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+            .and(path("/bottles/kegonly.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
             .mount(&mock_server)
             .await;
 
-        // Create installer
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
         fs::create_dir_all(root.join("db")).unwrap();
@@ -872,215 +5395,246 @@ mod tests {
         let cellar = Cellar::new(&root).unwrap();
         let linker = Linker::new(&prefix).unwrap();
         let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
         let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
 
-        // Install but don't uninstall
         installer
-            .install(&["keepme".to_string()], true)
+            .install(&["kegonly".to_string()], true)
             .await
             .unwrap();
 
-        // Store entry should exist
-        assert!(root.join("store").join(&bottle_sha).exists());
-
-        // Run GC - should not remove anything
-        let removed = installer.gc().unwrap();
-        assert!(removed.is_empty());
-
-        // Store entry should still exist
-        assert!(root.join("store").join(&bottle_sha).exists());
+        assert!(installer.is_installed("kegonly"));
+        assert!(root.join("cellar/kegonly/1.0.0/bin/kegonly").exists());
+        assert!(fs::read_dir(prefix.join("bin")).unwrap().next().is_none());
+        assert_eq!(
+            fs::read_link(prefix.join("opt/kegonly")).unwrap(),
+            root.join("cellar/kegonly/1.0.0")
+        );
     }
 
     #[tokio::test]
-    async fn install_with_dependencies() {
+    async fn offline_install_uses_only_cached_formula_and_bottle() {
+        use crate::cache::CacheEntry;
+        use std::io::Write;
+
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
-
-        // Create bottles
-        let dep_bottle = create_bottle_tarball("deplib");
-        let dep_sha = sha256_hex(&dep_bottle);
-
-        let main_bottle = create_bottle_tarball("mainpkg");
-        let main_sha = sha256_hex(&main_bottle);
-
-        // Create formula JSONs
         let tag = get_test_bottle_tag();
-        let dep_json = format!(
-            r#"{{
-                "name": "deplib",
-                "versions": {{ "stable": "1.0.0" }},
-                "dependencies": [],
-                "bottle": {{
-                    "stable": {{
-                        "files": {{
-                            "{}": {{
-                                "url": "{}/bottles/deplib-1.0.0.{}.bottle.tar.gz",
-                                "sha256": "{}"
-                            }}
-                        }}
-                    }}
-                }}
-            }}"#,
-            tag,
-            mock_server.uri(),
-            tag,
-            dep_sha
-        );
 
-        let main_json = format!(
-            r#"{{
-                "name": "mainpkg",
-                "versions": {{ "stable": "2.0.0" }},
-                "dependencies": ["deplib"],
-                "bottle": {{
-                    "stable": {{
-                        "files": {{
-                            "{}": {{
-                                "url": "{}/bottles/mainpkg-2.0.0.{}.bottle.tar.gz",
-                                "sha256": "{}"
-                            }}
-                        }}
-                    }}
-                }}
-            }}"#,
-            tag,
-            mock_server.uri(),
-            tag,
-            main_sha
-        );
+        let bottle = create_bottle_tarball("cachedpkg");
+        let bottle_sha = sha256_hex(&bottle);
+        let bottle_url = format!("{}/bottles/cachedpkg.tar.gz", mock_server.uri());
+        let formula_json = serde_json::json!({
+            "name": "cachedpkg",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: { "url": bottle_url, "sha256": bottle_sha }
+            } } }
+        });
 
-        // Mount mocks
         Mock::given(method("GET"))
-            .and(path("/deplib.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&dep_json))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
             .mount(&mock_server)
             .await;
 
-        Mock::given(method("GET"))
-            .and(path("/mainpkg.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&main_json))
-            .mount(&mock_server)
-            .await;
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
 
-        Mock::given(method("GET"))
-            .and(path(format!("/bottles/deplib-1.0.0.{}.bottle.tar.gz", tag)))
-            .respond_with(ResponseTemplate::new(200).set_body_bytes(dep_bottle))
-            .mount(&mock_server)
-            .await;
+        // Seed both caches as an earlier online run would have
+        let api_cache = ApiCache::in_memory().unwrap();
+        api_cache
+            .put(
+                &format!("{}/cachedpkg.json", mock_server.uri()),
+                &CacheEntry {
+                    etag: None,
+                    last_modified: None,
+                    body: formula_json.to_string(),
+                },
+            )
+            .unwrap();
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let mut writer = blob_cache.start_write(&bottle_sha).unwrap();
+        writer.write_all(&bottle).unwrap();
+        writer.commit().unwrap();
 
-        Mock::given(method("GET"))
-            .and(path(format!(
-                "/bottles/mainpkg-2.0.0.{}.bottle.tar.gz",
-                tag
-            )))
-            .respond_with(ResponseTemplate::new(200).set_body_bytes(main_bottle))
-            .mount(&mock_server)
-            .await;
+        let api_client = ApiClient::with_base_url(mock_server.uri()).with_cache(api_cache);
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer =
+            Installer::new(api_client, blob_cache, store, cellar, linker, db).with_offline(true);
+
+        installer
+            .install(&["cachedpkg".to_string()], true)
+            .await
+            .unwrap();
+        assert!(installer.is_installed("cachedpkg"));
+
+        let result = installer.install(&["uncached".to_string()], true).await;
+        assert!(matches!(result, Err(Error::OfflineMiss { .. })));
+    }
+
+    #[tokio::test]
+    async fn cleanup_removes_superseded_kegs_and_unreferenced_blobs() {
+        use std::io::Write;
+
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "tidy", &[]).await;
 
-        // Create installer
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
         fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
         let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
         let store = Store::new(&root).unwrap();
         let cellar = Cellar::new(&root).unwrap();
         let linker = Linker::new(&prefix).unwrap();
         let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer =
+            Installer::new(api_client, blob_cache.clone(), store, cellar, linker, db);
 
-        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
-
-        // Install main package (should also install dependency)
         installer
-            .install(&["mainpkg".to_string()], true)
+            .install(&["tidy".to_string()], true)
             .await
             .unwrap();
-
-        // Both packages should be installed
-        assert!(installer.db.get_installed("mainpkg").is_some());
-        assert!(installer.db.get_installed("deplib").is_some());
+        let store_key = installer.get_installed("tidy").unwrap().store_key;
+
+        // Leftovers from an earlier version: its keg and its downloaded bottle
+        let old_keg = root.join("cellar/tidy/0.9.0");
+        fs::create_dir_all(old_keg.join("bin")).unwrap();
+        fs::write(old_keg.join("bin/tidy"), b"old!").unwrap();
+        let stale_sha = "f".repeat(64);
+        let mut writer = blob_cache.start_write(&stale_sha).unwrap();
+        writer.write_all(b"stale bottle").unwrap();
+        writer.commit().unwrap();
+        // A newer version, e.g. one rolled back from, isn't superseded
+        let newer_keg = root.join("cellar/tidy/1.1.0");
+        fs::create_dir_all(newer_keg.join("bin")).unwrap();
+
+        let report = installer.cleanup(true, None).unwrap();
+        assert_eq!(report.blobs, [blob_cache.blob_path(&stale_sha)]);
+        assert_eq!(report.kegs, std::slice::from_ref(&old_keg));
+        assert_eq!(report.bytes_reclaimed, 12 + 4);
+        assert!(old_keg.exists() && blob_cache.has_blob(&stale_sha));
+
+        installer.cleanup(false, None).unwrap();
+        assert!(!old_keg.exists());
+        assert!(!blob_cache.has_blob(&stale_sha));
+        assert!(blob_cache.has_blob(&store_key));
+        assert!(root.join("cellar/tidy/1.0.0").exists());
+        assert!(newer_keg.exists());
+
+        // Nothing is that old, and the age doesn't overflow
+        let report = installer.cleanup(true, Some(u64::MAX)).unwrap();
+        assert!(report.blobs.is_empty());
+
+        // Pruning by age drops even bottles that are still in use
+        let report = installer.cleanup(false, Some(0)).unwrap();
+        assert_eq!(report.blobs, [blob_cache.blob_path(&store_key)]);
+        assert!(installer.is_installed("tidy"));
     }
 
     #[tokio::test]
-    async fn parallel_api_fetching_with_deep_deps() {
-        // Tests that parallel API fetching works with a deeper dependency tree:
-        // root -> mid1 -> leaf1
-        //      -> mid2 -> leaf2
-        //              -> leaf1 (shared)
+    async fn installs_from_local_formula_and_bottle_files() {
         let mock_server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
         let tmp = TempDir::new().unwrap();
+        let local = tmp.path().join("local");
+        fs::create_dir_all(&local).unwrap();
+        let tag = get_test_bottle_tag();
+        let mut formulas = Vec::new();
+        for (name, deps) in [("app", vec!["deplib"]), ("deplib", vec![])] {
+            let bottle = create_bottle_tarball(name);
+            let bottle_path = local.join(format!("{name}.tar.gz"));
+            fs::write(&bottle_path, &bottle).unwrap();
+            let formula_json = serde_json::json!({
+                "name": name,
+                "versions": { "stable": "1.0.0" },
+                "dependencies": deps,
+                "bottle": { "stable": { "files": {
+                    tag.clone(): {
+                        "url": reqwest::Url::from_file_path(&bottle_path).unwrap().to_string(),
+                        "sha256": sha256_hex(&bottle),
+                    }
+                } } }
+            });
+            let json_path = local.join(format!("{name}.json"));
+            fs::write(&json_path, formula_json.to_string()).unwrap();
+            formulas.push(ApiClient::formula_from_file(&json_path).unwrap());
+        }
 
-        // Create bottles
-        let leaf1_bottle = create_bottle_tarball("leaf1");
-        let leaf1_sha = sha256_hex(&leaf1_bottle);
-        let leaf2_bottle = create_bottle_tarball("leaf2");
-        let leaf2_sha = sha256_hex(&leaf2_bottle);
-        let mid1_bottle = create_bottle_tarball("mid1");
-        let mid1_sha = sha256_hex(&mid1_bottle);
-        let mid2_bottle = create_bottle_tarball("mid2");
-        let mid2_sha = sha256_hex(&mid2_bottle);
-        let root_bottle = create_bottle_tarball("root");
-        let root_sha = sha256_hex(&root_bottle);
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
 
-        // Formula JSONs
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+        for formula in formulas {
+            installer = installer.with_local_formula(formula);
+        }
+
+        installer.install(&["app".to_string()], true).await.unwrap();
+
+        assert!(installer.is_installed("app"));
+        assert!(installer.is_installed("deplib"));
+        assert!(root.join("cellar/app/1.0.0/bin/app").exists());
+
+        // A bottle that doesn't match its checksum is still refused
+        fs::write(local.join("deplib.tar.gz"), b"tampered").unwrap();
+        let tampered = ApiClient::formula_from_file(&local.join("deplib.json")).unwrap();
+        let bottle = select_bottle(&tampered).unwrap();
+        let downloader =
+            crate::download::Downloader::new(BlobCache::new(&tmp.path().join("other")).unwrap());
+        let err = downloader
+            .download(&bottle.url, &bottle.sha256)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch { .. }), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn installs_versioned_formulae_by_name_at_version() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
         let tag = get_test_bottle_tag();
-        let leaf1_json = format!(
-            r#"{{"name":"leaf1","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/leaf1.tar.gz","sha256":"{}"}}}}}}}}}}"#,
-            tag,
-            mock_server.uri(),
-            leaf1_sha
-        );
-        let leaf2_json = format!(
-            r#"{{"name":"leaf2","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/leaf2.tar.gz","sha256":"{}"}}}}}}}}}}"#,
-            tag,
-            mock_server.uri(),
-            leaf2_sha
-        );
-        let mid1_json = format!(
-            r#"{{"name":"mid1","versions":{{"stable":"1.0.0"}},"dependencies":["leaf1"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/mid1.tar.gz","sha256":"{}"}}}}}}}}}}"#,
-            tag,
-            mock_server.uri(),
-            mid1_sha
-        );
-        let mid2_json = format!(
-            r#"{{"name":"mid2","versions":{{"stable":"1.0.0"}},"dependencies":["leaf1","leaf2"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/mid2.tar.gz","sha256":"{}"}}}}}}}}}}"#,
-            tag,
-            mock_server.uri(),
-            mid2_sha
-        );
-        let root_json = format!(
-            r#"{{"name":"root","versions":{{"stable":"1.0.0"}},"dependencies":["mid1","mid2"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/root.tar.gz","sha256":"{}"}}}}}}}}}}"#,
-            tag,
-            mock_server.uri(),
-            root_sha
-        );
 
-        // Mount all mocks
-        for (name, json) in [
-            ("leaf1", &leaf1_json),
-            ("leaf2", &leaf2_json),
-            ("mid1", &mid1_json),
-            ("mid2", &mid2_json),
-            ("root", &root_json),
-        ] {
+        for (name, version) in [("node@18", "18.20.4"), ("node", "22.3.0")] {
+            let bottle = create_bottle_tarball(name);
+            let formula_json = serde_json::json!({
+                "name": name,
+                "versions": { "stable": version },
+                "dependencies": [],
+                "bottle": { "stable": { "files": {
+                    tag.clone(): {
+                        "url": format!("{}/bottles/{name}.tar.gz", mock_server.uri()),
+                        "sha256": sha256_hex(&bottle),
+                    }
+                } } }
+            });
             Mock::given(method("GET"))
-                .and(path(format!("/{}.json", name)))
-                .respond_with(ResponseTemplate::new(200).set_body_string(json))
+                .and(path(format!("/formula/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
                 .mount(&mock_server)
                 .await;
-        }
-        for (name, bottle) in [
-            ("leaf1", &leaf1_bottle),
-            ("leaf2", &leaf2_bottle),
-            ("mid1", &mid1_bottle),
-            ("mid2", &mid2_bottle),
-            ("root", &root_bottle),
-        ] {
             Mock::given(method("GET"))
-                .and(path(format!("/bottles/{}.tar.gz", name)))
-                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle.clone()))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
                 .mount(&mock_server)
                 .await;
         }
@@ -1089,238 +5643,434 @@ mod tests {
         let prefix = tmp.path().join("homebrew");
         fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        // Homebrew's versioned formula
+        installer
+            .install(&["node@18".to_string()], false)
+            .await
+            .unwrap();
+        assert!(root.join("cellar/node@18/18.20.4").exists());
+        assert_eq!(
+            installer.get_installed("node@18").unwrap().version,
+            "18.20.4"
+        );
+
+        // No node@22 formula, but it's the current major of node itself
+        installer
+            .install(&["node@22".to_string()], false)
+            .await
+            .unwrap();
+        assert!(root.join("cellar/node/22.3.0").exists());
+
+        let Err(err) = installer.plan(&["node@20".to_string()]).await else {
+            panic!("node@20 should not resolve");
+        };
+        assert_eq!(
+            err,
+            Error::VersionUnavailable {
+                name: "node".to_string(),
+                version: "20".to_string(),
+                available: "22.3.0".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn plan_surfaces_caveats_from_formula_json() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        mount_formula(&mock_server, "plainlib", &[]).await;
+        let formula_json = serde_json::json!({
+            "name": "noisyapp",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": ["plainlib"],
+            "caveats": "To start noisyapp now:\n  noisyapp serve\n",
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{}/bottles/noisyapp.tar.gz", mock_server.uri()),
+                    "sha256": "a".repeat(64),
+                }
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/noisyapp.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
         let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
         let store = Store::new(&root).unwrap();
         let cellar = Cellar::new(&root).unwrap();
         let linker = Linker::new(&prefix).unwrap();
         let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let plan = installer.plan(&["noisyapp".to_string()]).await.unwrap();
+        assert_eq!(
+            plan.caveats(),
+            vec![("noisyapp", "To start noisyapp now:\n  noisyapp serve")]
+        );
+    }
+
+    #[tokio::test]
+    async fn export_lists_installed_formulas_from_the_db() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "zlib", &[]).await;
+        mount_formula(&mock_server, "curl", &["zlib"]).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
 
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
         let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
 
-        // Install root (should install all 5 packages)
         installer
-            .install(&["root".to_string()], true)
+            .install(&["curl".to_string()], true)
             .await
             .unwrap();
+        installer.pin("zlib").unwrap();
 
-        // All packages should be installed
-        assert!(installer.db.get_installed("root").is_some());
-        assert!(installer.db.get_installed("mid1").is_some());
-        assert!(installer.db.get_installed("mid2").is_some());
-        assert!(installer.db.get_installed("leaf1").is_some());
-        assert!(installer.db.get_installed("leaf2").is_some());
+        let manifest = installer.export().unwrap();
+        let mut expected: Vec<ExportedFormula> = installer
+            .list_installed()
+            .unwrap()
+            .into_iter()
+            .map(|keg| ExportedFormula {
+                name: keg.name,
+                version: Some(keg.version),
+                pinned: keg.pinned,
+            })
+            .collect();
+        expected.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(manifest.formulas, expected);
+        assert_eq!(manifest.formulas[0].name, "curl");
+        assert!(manifest.formulas[1].pinned);
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ExportManifest>(&json).unwrap(),
+            manifest
+        );
     }
 
     #[tokio::test]
-    async fn streaming_extraction_processes_as_downloads_complete() {
-        // Tests that streaming extraction works correctly by verifying
-        // packages with delayed downloads still get installed properly
-        use std::time::Duration;
-
+    async fn import_installs_listed_formulas_and_restores_pins() {
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
 
-        // Create bottles
-        let fast_bottle = create_bottle_tarball("fastpkg");
-        let fast_sha = sha256_hex(&fast_bottle);
-        let slow_bottle = create_bottle_tarball("slowpkg");
-        let slow_sha = sha256_hex(&slow_bottle);
+        mount_formula(&mock_server, "zlib", &[]).await;
+        mount_formula(&mock_server, "curl", &["zlib"]).await;
+        mount_formula(&mock_server, "jq", &[]).await;
 
-        // Fast package formula
-        let tag = get_test_bottle_tag();
-        let fast_json = format!(
-            r#"{{"name":"fastpkg","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/fast.tar.gz","sha256":"{}"}}}}}}}}}}"#,
-            tag,
-            mock_server.uri(),
-            fast_sha
-        );
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
 
-        // Slow package formula (depends on fast)
-        let slow_json = format!(
-            r#"{{"name":"slowpkg","versions":{{"stable":"1.0.0"}},"dependencies":["fastpkg"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/slow.tar.gz","sha256":"{}"}}}}}}}}}}"#,
-            tag,
-            mock_server.uri(),
-            slow_sha
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let manifest: ExportManifest = serde_json::from_value(serde_json::json!({
+            "formulas": [
+                { "name": "curl", "version": "1.0.0", "pinned": true },
+                // No longer available at this version, so the current one is used
+                { "name": "jq", "version": "0.9" },
+            ]
+        }))
+        .unwrap();
+
+        let import = installer.plan_import(&manifest).await.unwrap();
+        assert_eq!(
+            import.unavailable,
+            vec![UnavailableVersion {
+                name: "jq".to_string(),
+                recorded: "0.9".to_string(),
+                current: "1.0.0".to_string(),
+            }]
         );
 
-        // Mount API mocks
-        Mock::given(method("GET"))
-            .and(path("/fastpkg.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&fast_json))
-            .mount(&mock_server)
-            .await;
+        let result = installer.import(&manifest, true).await.unwrap();
+        assert_eq!(result.installed, 3);
+        assert!(result.failed.is_empty());
 
-        Mock::given(method("GET"))
-            .and(path("/slowpkg.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&slow_json))
-            .mount(&mock_server)
-            .await;
+        let curl = installer.get_installed("curl").unwrap();
+        assert_eq!(curl.version, "1.0.0");
+        assert!(curl.pinned);
+        assert!(installer.is_installed("zlib"));
+        assert!(!installer.get_installed("jq").unwrap().pinned);
 
-        // Fast bottle responds immediately
-        Mock::given(method("GET"))
-            .and(path("/bottles/fast.tar.gz"))
-            .respond_with(ResponseTemplate::new(200).set_body_bytes(fast_bottle.clone()))
-            .mount(&mock_server)
-            .await;
+        // Importing again has nothing left to do
+        let import = installer.plan_import(&manifest).await.unwrap();
+        assert!(import.plan.formulas.is_empty());
+    }
 
-        // Slow bottle has a delay (simulates slow network)
-        Mock::given(method("GET"))
-            .and(path("/bottles/slow.tar.gz"))
-            .respond_with(
-                ResponseTemplate::new(200)
-                    .set_body_bytes(slow_bottle.clone())
-                    .set_delay(Duration::from_millis(100)),
-            )
-            .mount(&mock_server)
-            .await;
+    #[tokio::test]
+    async fn locked_install_refuses_a_lockfile_whose_sha_no_longer_matches() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "zlib", &[]).await;
+        mount_formula(&mock_server, "curl", &["zlib"]).await;
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
         fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
         let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
         let store = Store::new(&root).unwrap();
         let cellar = Cellar::new(&root).unwrap();
         let linker = Linker::new(&prefix).unwrap();
         let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
         let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
 
-        // Install slow package (which depends on fast)
-        // With streaming, fast should be extracted while slow is still downloading
-        installer
-            .install(&["slowpkg".to_string()], true)
-            .await
-            .unwrap();
-
-        // Both packages should be installed
-        assert!(installer.db.get_installed("fastpkg").is_some());
-        assert!(installer.db.get_installed("slowpkg").is_some());
+        let lockfile = installer.freeze(&["curl".to_string()]).await.unwrap();
+        let locked: Vec<_> = lockfile.formulas.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(locked, ["zlib", "curl"]);
+        let json = serde_json::to_string(&lockfile).unwrap();
+        assert_eq!(serde_json::from_str::<Lockfile>(&json).unwrap(), lockfile);
+
+        let mut stale = lockfile.clone();
+        stale.formulas[0].sha256 = "0".repeat(64);
+        let Err(Error::LockMismatch { name, locked, .. }) = installer.plan_locked(&stale).await
+        else {
+            panic!("expected the changed sha256 to be refused");
+        };
+        assert_eq!(name, "zlib");
+        assert!(locked.contains(&"0".repeat(64)));
+
+        // A lockfile frozen on another platform recorded other bottles
+        let mut elsewhere = lockfile.clone();
+        elsewhere.formulas[1].tag = "other_platform".to_string();
+        let Err(Error::LockMismatch { name, .. }) = installer.plan_locked(&elsewhere).await else {
+            panic!("expected a bottle for another platform to be refused");
+        };
+        assert_eq!(name, "curl");
 
-        // Verify kegs exist
-        assert!(root.join("cellar/fastpkg/1.0.0").exists());
-        assert!(root.join("cellar/slowpkg/1.0.0").exists());
+        let plan = installer.plan_locked(&lockfile).await.unwrap();
+        installer.execute(plan, true).await.unwrap();
+        assert!(installer.is_installed("curl"));
+        assert!(installer.is_installed("zlib"));
+    }
 
-        // Verify links exist
-        assert!(prefix.join("bin/fastpkg").exists());
-        assert!(prefix.join("bin/slowpkg").exists());
+    /// Serves the headers and the first part of a large body on every
+    /// connection, then stalls
+    fn spawn_stalling_server() -> String {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                std::thread::spawn(move || {
+                    let mut request = [0u8; 4096];
+                    let n = stream.read(&mut request).unwrap_or(0);
+                    let _ =
+                        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10000000\r\n\r\n");
+                    if !request[..n].starts_with(b"HEAD") {
+                        let _ = stream.write_all(&[0u8; 64 * 1024]);
+                    }
+                    let _ = stream.flush();
+                    std::thread::sleep(Duration::from_secs(30));
+                });
+            }
+        });
+        format!("http://{addr}")
     }
 
     #[tokio::test]
-    async fn retries_on_corrupted_download() {
-        use std::sync::atomic::{AtomicUsize, Ordering};
-
+    async fn cancelled_install_leaves_no_partial_downloads() {
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
-
-        // Create valid bottle
-        let bottle = create_bottle_tarball("retrypkg");
-        let bottle_sha = sha256_hex(&bottle);
-
-        // Create formula JSON
         let tag = get_test_bottle_tag();
-        let formula_json = format!(
-            r#"{{
-                "name": "retrypkg",
-                "versions": {{ "stable": "1.0.0" }},
-                "dependencies": [],
-                "bottle": {{
-                    "stable": {{
-                        "files": {{
-                            "{}": {{
-                                "url": "{}/bottles/retrypkg-1.0.0.{}.bottle.tar.gz",
-                                "sha256": "{}"
-                            }}
-                        }}
-                    }}
-                }}
-            }}"#,
-            tag,
-            mock_server.uri(),
-            tag,
-            bottle_sha
-        );
-
-        // Mount formula API mock
+        let stalling = spawn_stalling_server();
+
+        let formula_json = serde_json::json!({
+            "name": "slow",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{stalling}/bottles/slow.tar.gz"),
+                    "sha256": "a".repeat(64),
+                }
+            } } }
+        });
         Mock::given(method("GET"))
-            .and(path("/retrypkg.json"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .and(path("/formula/slow.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
             .mount(&mock_server)
             .await;
 
-        // Track download attempts
-        let attempt_count = Arc::new(AtomicUsize::new(0));
-        let attempt_clone = attempt_count.clone();
-        let valid_bottle = bottle.clone();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
 
-        // First request returns corrupted data (wrong content but matches sha for download)
-        // This simulates CDN corruption where sha passes but tar is invalid
-        Mock::given(method("GET"))
-            .and(path(format!(
-                "/bottles/retrypkg-1.0.0.{}.bottle.tar.gz",
-                tag
-            )))
-            .respond_with(move |_: &wiremock::Request| {
-                let attempt = attempt_clone.fetch_add(1, Ordering::SeqCst);
-                if attempt == 0 {
-                    // First attempt: return corrupted data
-                    // We need to return data that has the right sha256 but is corrupt
-                    // Since we can't fake sha256, we'll return invalid tar that will fail extraction
-                    // But actually the sha256 check happens during download...
-                    // So we need to return the valid bottle (sha passes) but corrupt the blob after
-                    // This is tricky to test since corruption happens at tar level
-                    // For now, just return valid data - the retry mechanism will work in real scenarios
-                    ResponseTemplate::new(200).set_body_bytes(valid_bottle.clone())
-                } else {
-                    // Subsequent attempts: return valid bottle
-                    ResponseTemplate::new(200).set_body_bytes(valid_bottle.clone())
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+
+        let partials = || -> Vec<PathBuf> {
+            fs::read_dir(root.join("cache/tmp"))
+                .unwrap()
+                .filter_map(Result::ok)
+                .map(|e| e.path())
+                .filter(|p| p.to_string_lossy().ends_with(".part"))
+                .collect()
+        };
+        // Interrupt once the download is under way and writing to disk
+        let cancel = async {
+            while partials().is_empty() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+
+        let plan = installer.plan(&["slow".to_string()]).await.unwrap();
+        let result = tokio::time::timeout(
+            Duration::from_secs(20),
+            installer.execute_until(plan, true, None, cancel),
+        )
+        .await
+        .expect("cancellation didn't stop the install");
+
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(partials(), Vec::<PathBuf>::new());
+        assert!(!installer.is_installed("slow"));
+        assert!(!root.join("cellar/slow").exists());
+    }
+
+    #[tokio::test]
+    async fn install_past_its_deadline_times_out_and_cleans_up() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+        let stalling = spawn_stalling_server();
+
+        let formula_json = serde_json::json!({
+            "name": "slow",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": {
+                tag: {
+                    "url": format!("{stalling}/bottles/slow.tar.gz"),
+                    "sha256": "a".repeat(64),
                 }
-            })
+            } } }
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/slow.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
             .mount(&mock_server)
             .await;
 
-        // Create installer
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
         fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client = ApiClient::with_base_url(mock_server.uri());
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
         let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
         let store = Store::new(&root).unwrap();
         let cellar = Cellar::new(&root).unwrap();
         let linker = Linker::new(&prefix).unwrap();
         let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db)
+            .with_deadline(Duration::from_secs(1));
+
+        let started = std::time::Instant::now();
+        let plan = installer.plan(&["slow".to_string()]).await.unwrap();
+        let result = installer.execute(plan, true).await;
+
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let partials: Vec<_> = fs::read_dir(root.join("cache/tmp"))
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".part"))
+            .collect();
+        assert!(partials.is_empty());
+        assert!(!installer.is_installed("slow"));
+        assert!(!root.join("cellar/slow").exists());
+    }
 
-        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db);
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn independent_packages_materialize_concurrently() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
 
-        // Install - should succeed (first download is valid in this test)
-        installer
-            .install(&["retrypkg".to_string()], true)
-            .await
-            .unwrap();
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let names = ["par1", "par2", "par3", "par4"];
+        for name in names {
+            mount_formula(&mock_server, name, &[]).await;
+        }
 
-        // Verify installation succeeded
-        assert!(installer.is_installed("retrypkg"));
-        assert!(root.join("cellar/retrypkg/1.0.0").exists());
-        assert!(prefix.join("bin/retrypkg").exists());
-    }
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
 
-    #[tokio::test]
-    async fn fails_after_max_retries() {
-        // This test verifies that after MAX_CORRUPTION_RETRIES failed attempts,
-        // the installer gives up with an appropriate error message.
-        // Note: This is hard to test without mocking the store layer since
-        // corruption is detected during tar extraction, not during download.
-        // The retry mechanism is validated by the code structure.
+        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()));
+        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
+        let store = Store::new(&root).unwrap();
+        let cellar = Cellar::new(&root).unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+        let mut installer = Installer::new(api_client, blob_cache, store, cellar, linker, db)
+            .with_unpack_concurrency(4);
+
+        // Count materializations in flight, holding each open long enough for
+        // the others to arrive if they run in parallel
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        installer.unpack_hook = Some(Arc::new({
+            let (active, peak) = (active.clone(), peak.clone());
+            move || {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(300));
+                active.fetch_sub(1, Ordering::SeqCst);
+            }
+        }));
 
-        // For a proper integration test, we would need to inject corruption
-        // into the blob cache after download but before extraction.
-        // This is left as a documentation of the expected behavior:
-        // - First attempt: download succeeds, extraction fails (corruption)
-        // - Second attempt: re-download, extraction fails (corruption)
-        // - Third attempt: re-download, extraction fails (corruption)
-        // - Returns error: "Failed after 3 attempts..."
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let result = installer.install(&names, true).await.unwrap();
+
+        assert_eq!(result.installed, 4);
+        assert!(
+            peak.load(Ordering::SeqCst) >= 2,
+            "materializations never overlapped"
+        );
+        for name in &names {
+            assert!(prefix.join("bin").join(name).exists());
+        }
     }
 }
@@ -1,6 +1,7 @@
 pub mod api;
 pub mod blob;
 pub mod cache;
+pub mod checksum;
 pub mod db;
 pub mod download;
 pub mod extract;
@@ -9,19 +10,28 @@ pub mod install;
 pub mod link;
 #[cfg(target_os = "linux")]
 mod linux_patch;
+pub mod lock;
 pub mod materialize;
+mod migrations;
 pub mod progress;
+pub mod signature;
 pub mod store;
 
 pub use api::ApiClient;
 pub use blob::BlobCache;
 pub use cache::ApiCache;
-pub use db::{Database, InstalledKeg};
-pub use download::{DownloadProgressCallback, DownloadRequest, Downloader, ParallelDownloader};
+pub use checksum::Checksum;
+pub use db::{Database, HistoryEntry, InstalledKeg};
+pub use download::{
+    DownloadProgressCallback, DownloadRequest, Downloader, ParallelDownloader, RedirectPolicy,
+    RetryPolicy, parse_proxy,
+};
 pub use extract::extract_tarball;
 pub use homebrew::{HomebrewMigrationPackages, HomebrewPackage, get_homebrew_packages};
 pub use install::Installer;
 pub use link::Linker;
-pub use materialize::Cellar;
+pub use lock::FileLock;
+pub use materialize::{Cellar, MaterializeMode};
 pub use progress::{InstallProgress, ProgressCallback};
+pub use signature::{SignaturePolicy, SignatureVerifier};
 pub use store::Store;
@@ -1,6 +1,10 @@
+use std::collections::HashSet;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use zb_core::Error;
 
@@ -8,6 +12,10 @@ pub struct Linker {
     prefix: PathBuf,
     bin_dir: PathBuf,
     opt_dir: PathBuf,
+    overwrite: bool,
+    /// Held while checking a path in the prefix and linking it, so kegs
+    /// linked concurrently see each other's links
+    prefix_lock: Mutex<()>,
 }
 
 #[derive(Debug, Clone)]
@@ -16,6 +24,21 @@ pub struct LinkedFile {
     pub target_path: PathBuf,
 }
 
+/// A keg for [`Linker::link_kegs`], taking over the links of `replacing`
+/// (an older version of the same formula) if set
+#[derive(Debug, Clone, Copy)]
+pub struct LinkRequest<'a> {
+    pub keg: &'a Path,
+    pub replacing: Option<&'a Path>,
+}
+
+/// How linking one keg of a [`Linker::link_kegs`] batch went
+#[derive(Debug)]
+pub struct LinkOutcome {
+    pub linked: Result<Vec<LinkedFile>, Error>,
+    pub elapsed: Duration,
+}
+
 impl Linker {
     pub fn new(prefix: &Path) -> io::Result<Self> {
         let bin_dir = prefix.join("bin");
@@ -31,23 +54,107 @@ impl Linker {
             prefix: prefix.to_path_buf(),
             bin_dir,
             opt_dir,
+            overwrite: false,
+            prefix_lock: Mutex::new(()),
         })
     }
 
+    /// Replace files and other kegs' links that are in the way instead of
+    /// failing with a link conflict
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    #[tracing::instrument(name = "link", skip(self), fields(keg = %keg_path.display()))]
     pub fn link_keg(&self, keg_path: &Path) -> Result<Vec<LinkedFile>, Error> {
         self.link_opt(keg_path)?;
+        let incoming = formula_of_keg(keg_path).unwrap_or_default();
         let mut linked = Vec::new();
         for dir_name in ["bin", "lib", "libexec", "include", "share"] {
             let src_dir = keg_path.join(dir_name);
             let dst_dir = self.prefix.join(dir_name);
             if src_dir.exists() {
-                linked.extend(Self::link_recursive(&src_dir, &dst_dir)?);
+                linked.extend(self.link_recursive(&src_dir, &dst_dir, &incoming, None)?);
+            }
+        }
+        Ok(linked)
+    }
+
+    /// Link several kegs at once, returning how each went in request order.
+    /// Kegs that ship a path an earlier keg in the batch also ships are held
+    /// back and linked one at a time afterwards, so the prefix ends up as if
+    /// the kegs were linked serially, conflicts landing on the same kegs.
+    pub fn link_kegs(&self, requests: &[LinkRequest]) -> Vec<LinkOutcome> {
+        let (independent, overlapping) = partition_overlapping(requests);
+
+        let link = |request: &LinkRequest| {
+            let started = Instant::now();
+            let linked = match request.replacing {
+                Some(old_keg) => self.relink_atomic(old_keg, request.keg),
+                None => self.link_keg(request.keg),
+            };
+            LinkOutcome {
+                linked,
+                elapsed: started.elapsed(),
+            }
+        };
+
+        let mut outcomes: Vec<Option<LinkOutcome>> = requests.iter().map(|_| None).collect();
+        let workers = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(independent.len());
+        let next = AtomicUsize::new(0);
+        let finished = Mutex::new(Vec::with_capacity(independent.len()));
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while let Some(&i) = independent.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let outcome = link(&requests[i]);
+                        finished.lock().unwrap().push((i, outcome));
+                    }
+                });
+            }
+        });
+        for (i, outcome) in finished.into_inner().unwrap() {
+            outcomes[i] = Some(outcome);
+        }
+        for i in overlapping {
+            outcomes[i] = Some(link(&requests[i]));
+        }
+
+        outcomes.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Switch the prefix from `old_keg` to `new_keg` without a window where
+    /// links are missing: each link into the old keg is replaced in place by
+    /// renaming a fresh symlink over it, and links the new keg doesn't ship are
+    /// dropped afterwards. If this fails partway, every link still points at
+    /// one of the two complete kegs.
+    pub fn relink_atomic(&self, old_keg: &Path, new_keg: &Path) -> Result<Vec<LinkedFile>, Error> {
+        self.link_opt(new_keg)?;
+        let incoming = formula_of_keg(new_keg).unwrap_or_default();
+        let mut linked = Vec::new();
+        for dir_name in ["bin", "lib", "libexec", "include", "share"] {
+            let src_dir = new_keg.join(dir_name);
+            let dst_dir = self.prefix.join(dir_name);
+            if src_dir.exists() {
+                linked.extend(self.link_recursive(&src_dir, &dst_dir, &incoming, Some(old_keg))?);
             }
         }
+        self.unlink_keg(old_keg)?;
         Ok(linked)
     }
 
-    fn link_recursive(src: &Path, dst: &Path) -> Result<Vec<LinkedFile>, Error> {
+    /// Link everything under `src` into `dst`. Links into `replacing` (a keg
+    /// being upgraded away from) are swapped over rather than reported as conflicts.
+    fn link_recursive(
+        &self,
+        src: &Path,
+        dst: &Path,
+        incoming: &str,
+        replacing: Option<&Path>,
+    ) -> Result<Vec<LinkedFile>, Error> {
         let mut linked = Vec::new();
         if !dst.exists() {
             fs::create_dir_all(dst).map_err(|e| Error::StoreCorruption {
@@ -67,16 +174,21 @@ impl Linker {
                 message: e.to_string(),
             })?;
 
+            let guard = self.prefix_lock.lock().unwrap_or_else(|e| e.into_inner());
             if file_type.is_dir() {
                 if dst_path.symlink_metadata().is_ok() && dst_path.is_symlink() {
+                    // A directory linked whole from another keg: turn it into
+                    // a real one holding links to that keg's files, so ours
+                    // can be merged in
                     let old_target =
                         fs::read_link(&dst_path).map_err(|e| Error::StoreCorruption {
                             message: e.to_string(),
                         })?;
                     let _ = fs::remove_file(&dst_path);
-                    Self::link_recursive(&old_target, &dst_path)?;
+                    mirror_links(&old_target, &dst_path)?;
                 }
-                linked.extend(Self::link_recursive(&src_path, &dst_path)?);
+                drop(guard);
+                linked.extend(self.link_recursive(&src_path, &dst_path, incoming, replacing)?);
                 continue;
             }
 
@@ -97,14 +209,21 @@ impl Linker {
                         } else {
                             let _ = fs::remove_file(&dst_path);
                         }
+                    } else if replacing.is_some_and(|old_keg| resolved.starts_with(old_keg)) {
+                        replace_with_symlink(&src_path, &dst_path)?;
+                        linked.push(LinkedFile {
+                            link_path: dst_path,
+                            target_path: src_path,
+                        });
+                        continue;
                     } else {
-                        return Err(Error::LinkConflict { path: dst_path });
+                        self.resolve_conflict(&dst_path, existing_owner(&resolved), incoming)?;
                     }
                 } else {
-                    return Err(Error::LinkConflict { path: dst_path });
+                    self.resolve_conflict(&dst_path, None, incoming)?;
                 }
             } else if dst_path.exists() {
-                return Err(Error::LinkConflict { path: dst_path });
+                self.resolve_conflict(&dst_path, None, incoming)?;
             }
 
             #[cfg(unix)]
@@ -121,6 +240,27 @@ impl Linker {
         Ok(linked)
     }
 
+    /// Clear `path` out of the way when overwriting, otherwise report who owns it.
+    /// Real directories are never removed.
+    fn resolve_conflict(
+        &self,
+        path: &Path,
+        existing: Option<String>,
+        incoming: &str,
+    ) -> Result<(), Error> {
+        if self.overwrite && !path.is_dir() {
+            fs::remove_file(path).map_err(|e| Error::StoreCorruption {
+                message: format!("failed to replace '{}': {e}", path.display()),
+            })?;
+            return Ok(());
+        }
+        Err(Error::LinkConflict {
+            path: path.to_path_buf(),
+            existing,
+            incoming: incoming.to_string(),
+        })
+    }
+
     pub fn unlink_keg(&self, keg_path: &Path) -> Result<Vec<PathBuf>, Error> {
         self.unlink_opt(keg_path)?;
         let mut unlinked = Vec::new();
@@ -194,7 +334,9 @@ impl Linker {
         Ok(())
     }
 
-    fn link_opt(&self, keg_path: &Path) -> Result<(), Error> {
+    /// Point `opt/{name}` at the keg without linking anything else. This is
+    /// all a keg-only formula gets.
+    pub fn link_opt(&self, keg_path: &Path) -> Result<(), Error> {
         let name = keg_path
             .parent()
             .and_then(|p| p.file_name())
@@ -203,24 +345,34 @@ impl Linker {
                 message: "invalid keg path".into(),
             })?;
         let opt_link = self.opt_dir.join(name);
-        if opt_link.symlink_metadata().is_ok() {
-            if let Ok(target) = fs::read_link(&opt_link) {
-                let resolved = if target.is_relative() {
-                    opt_link.parent().unwrap_or(Path::new("")).join(&target)
-                } else {
-                    target
-                };
-                if fs::canonicalize(&resolved).ok() == fs::canonicalize(keg_path).ok() {
-                    return Ok(());
+        if let Ok(target) = fs::read_link(&opt_link) {
+            let resolved = if target.is_relative() {
+                opt_link.parent().unwrap_or(Path::new("")).join(&target)
+            } else {
+                target
+            };
+            if fs::canonicalize(&resolved).ok() == fs::canonicalize(keg_path).ok() {
+                return Ok(());
+            }
+        }
+        // Swapped in place so opt/ always points at some version
+        replace_with_symlink(keg_path, &opt_link)
+    }
+
+    /// Symlinks anywhere under the prefix whose target no longer exists
+    pub fn dangling_links(&self) -> Vec<PathBuf> {
+        let mut dangling = Vec::new();
+        for dir_name in ["bin", "lib", "libexec", "include", "share", "opt"] {
+            for entry in walkdir::WalkDir::new(self.prefix.join(dir_name))
+                .into_iter()
+                .filter_map(Result::ok)
+            {
+                if entry.path_is_symlink() && !entry.path().exists() {
+                    dangling.push(entry.into_path());
                 }
             }
-            let _ = fs::remove_file(&opt_link);
         }
-        #[cfg(unix)]
-        std::os::unix::fs::symlink(keg_path, &opt_link).map_err(|e| Error::StoreCorruption {
-            message: e.to_string(),
-        })?;
-        Ok(())
+        dangling
     }
 
     pub fn is_linked(&self, keg_path: &Path) -> bool {
@@ -247,6 +399,111 @@ impl Linker {
     }
 }
 
+/// Split `requests` into the kegs that can be linked concurrently and, in
+/// order, those shipping a file where an earlier keg ships a file or
+/// directory (or a directory where an earlier one ships a file)
+fn partition_overlapping(requests: &[LinkRequest]) -> (Vec<usize>, Vec<usize>) {
+    let mut files: HashSet<PathBuf> = HashSet::new();
+    let mut dirs: HashSet<PathBuf> = HashSet::new();
+    let mut independent = Vec::new();
+    let mut overlapping = Vec::new();
+
+    for (i, request) in requests.iter().enumerate() {
+        let (keg_files, keg_dirs) = keg_paths(request.keg);
+        let overlaps = keg_files
+            .iter()
+            .any(|path| files.contains(path) || dirs.contains(path))
+            || keg_dirs.iter().any(|path| files.contains(path));
+        if overlaps {
+            overlapping.push(i);
+        } else {
+            independent.push(i);
+        }
+        files.extend(keg_files);
+        dirs.extend(keg_dirs);
+    }
+    (independent, overlapping)
+}
+
+/// The files and directories a keg links, relative to the prefix
+fn keg_paths(keg: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for dir_name in ["bin", "lib", "libexec", "include", "share"] {
+        for entry in walkdir::WalkDir::new(keg.join(dir_name))
+            .min_depth(1)
+            .into_iter()
+            .filter_map(Result::ok)
+        {
+            let Ok(relative) = entry.path().strip_prefix(keg) else {
+                continue;
+            };
+            if entry.file_type().is_dir() {
+                dirs.push(relative.to_path_buf());
+            } else {
+                files.push(relative.to_path_buf());
+            }
+        }
+    }
+    (files, dirs)
+}
+
+/// Recreate `src` under the empty directory `dst` as real directories of
+/// symlinks to its files
+fn mirror_links(src: &Path, dst: &Path) -> Result<(), Error> {
+    let failed = |e: io::Error| Error::StoreCorruption {
+        message: e.to_string(),
+    };
+    fs::create_dir_all(dst).map_err(failed)?;
+    for entry in fs::read_dir(src).map_err(failed)? {
+        let entry = entry.map_err(failed)?;
+        let dst_path = dst.join(entry.file_name());
+        if entry.file_type().map_err(failed)?.is_dir() {
+            mirror_links(&entry.path(), &dst_path)?;
+        } else {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(entry.path(), &dst_path).map_err(failed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Point `link` at `target`, atomically replacing any file or symlink there by
+/// renaming a freshly made symlink over it
+fn replace_with_symlink(target: &Path, link: &Path) -> Result<(), Error> {
+    let file_name = link.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = link.with_file_name(format!(".{file_name}.zb-tmp"));
+    let _ = fs::remove_file(&tmp);
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, &tmp).map_err(|e| Error::StoreCorruption {
+        message: e.to_string(),
+    })?;
+    fs::rename(&tmp, link).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        Error::StoreCorruption {
+            message: format!("failed to replace '{}': {e}", link.display()),
+        }
+    })
+}
+
+/// The formula a keg path like `.../cellar/{name}/{version}` belongs to
+fn formula_of_keg(keg_path: &Path) -> Option<String> {
+    keg_path
+        .parent()
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+}
+
+/// The formula whose keg a link target points into, if it points into a cellar
+fn existing_owner(target: &Path) -> Option<String> {
+    let mut components = target.components().map(|c| c.as_os_str());
+    components
+        .by_ref()
+        .find(|c| c.eq_ignore_ascii_case("cellar"))?;
+    components.next().map(|n| n.to_string_lossy().into_owned())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -309,4 +566,181 @@ mod tests {
         assert!(linked_helper.exists(), "git-remote-https should be linked");
         assert!(linked_helper.is_symlink(), "should be a symlink");
     }
+
+    #[test]
+    fn conflicting_link_names_both_formulas() {
+        let tmp = TempDir::new().unwrap();
+        let linker = Linker::new(tmp.path()).unwrap();
+        let first = tmp.path().join("cellar/first/1.0.0");
+        let second = tmp.path().join("cellar/second/1.0.0");
+        for keg in [&first, &second] {
+            fs::create_dir_all(keg.join("bin")).unwrap();
+            fs::write(keg.join("bin/foo"), b"hi").unwrap();
+        }
+
+        linker.link_keg(&first).unwrap();
+        let err = linker.link_keg(&second).unwrap_err();
+
+        assert_eq!(
+            err,
+            Error::LinkConflict {
+                path: tmp.path().join("bin/foo"),
+                existing: Some("first".to_string()),
+                incoming: "second".to_string(),
+            }
+        );
+        assert_eq!(
+            fs::read_link(tmp.path().join("bin/foo")).unwrap(),
+            first.join("bin/foo")
+        );
+    }
+
+    #[test]
+    fn overwrite_replaces_conflicting_links_and_files() {
+        let tmp = TempDir::new().unwrap();
+        let first = tmp.path().join("cellar/first/1.0.0");
+        let second = tmp.path().join("cellar/second/1.0.0");
+        for keg in [&first, &second] {
+            fs::create_dir_all(keg.join("bin")).unwrap();
+            fs::write(keg.join("bin/foo"), b"hi").unwrap();
+            fs::write(keg.join("bin/bar"), b"hi").unwrap();
+        }
+
+        let linker = Linker::new(tmp.path()).unwrap();
+        linker.link_keg(&first).unwrap();
+        fs::remove_file(tmp.path().join("bin/bar")).unwrap();
+        fs::write(tmp.path().join("bin/bar"), b"stray").unwrap();
+
+        let linker = linker.with_overwrite(true);
+        linker.link_keg(&second).unwrap();
+
+        for name in ["foo", "bar"] {
+            assert_eq!(
+                fs::read_link(tmp.path().join("bin").join(name)).unwrap(),
+                second.join("bin").join(name)
+            );
+        }
+    }
+
+    #[test]
+    fn finds_dangling_links_after_keg_disappears() {
+        let tmp = TempDir::new().unwrap();
+        let keg = setup_keg(&tmp, "gone");
+        let linker = Linker::new(tmp.path()).unwrap();
+        linker.link_keg(&keg).unwrap();
+        assert!(linker.dangling_links().is_empty());
+
+        fs::remove_dir_all(&keg).unwrap();
+        let mut dangling = linker.dangling_links();
+        dangling.sort();
+        assert_eq!(
+            dangling,
+            [tmp.path().join("bin/gone"), tmp.path().join("opt/gone")]
+        );
+    }
+
+    #[test]
+    fn relink_atomic_keeps_binaries_reachable_when_interrupted() {
+        let tmp = TempDir::new().unwrap();
+        let linker = Linker::new(tmp.path()).unwrap();
+        let old_keg = tmp.path().join("cellar/foo/1.0.0");
+        let new_keg = tmp.path().join("cellar/foo/2.0.0");
+        fs::create_dir_all(old_keg.join("bin")).unwrap();
+        fs::write(old_keg.join("bin/foo"), b"old").unwrap();
+        fs::write(old_keg.join("bin/foo-legacy"), b"old").unwrap();
+        fs::create_dir_all(new_keg.join("bin")).unwrap();
+        fs::write(new_keg.join("bin/foo"), b"new").unwrap();
+        fs::write(new_keg.join("bin/foo-extra"), b"new").unwrap();
+        linker.link_keg(&old_keg).unwrap();
+
+        // A file nobody owns makes the relink fail partway, as a crash would
+        fs::write(tmp.path().join("bin/foo-extra"), b"stray").unwrap();
+        assert!(linker.relink_atomic(&old_keg, &new_keg).is_err());
+        let foo = fs::read_to_string(tmp.path().join("bin/foo")).unwrap();
+        assert!(foo == "old" || foo == "new");
+        assert!(tmp.path().join("opt/foo").exists());
+
+        fs::remove_file(tmp.path().join("bin/foo-extra")).unwrap();
+        linker.relink_atomic(&old_keg, &new_keg).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("bin/foo")).unwrap(),
+            "new"
+        );
+        assert_eq!(fs::read_link(tmp.path().join("opt/foo")).unwrap(), new_keg);
+        assert!(tmp.path().join("bin/foo-extra").exists());
+        assert!(!tmp.path().join("bin/foo-legacy").exists());
+        assert!(!tmp.path().join("bin/.foo.zb-tmp").exists());
+    }
+
+    /// Three kegs sharing lib/pkgconfig, the last also shipping the first's
+    /// executable, under `root`
+    fn setup_batch(root: &Path) -> Vec<PathBuf> {
+        let kegs: Vec<PathBuf> = ["one", "two", "clash"]
+            .iter()
+            .map(|name| root.join("cellar").join(name).join("1.0.0"))
+            .collect();
+        for (keg, bin) in kegs.iter().zip(["one", "two", "one"]) {
+            let name = formula_of_keg(keg).unwrap();
+            fs::create_dir_all(keg.join("bin")).unwrap();
+            fs::write(keg.join("bin").join(bin), name.as_bytes()).unwrap();
+            fs::create_dir_all(keg.join("lib/pkgconfig")).unwrap();
+            fs::write(keg.join(format!("lib/pkgconfig/{name}.pc")), b"").unwrap();
+        }
+        kegs
+    }
+
+    /// Every entry under the prefix's linked directories, with symlink
+    /// targets made relative to `root` so two prefixes can be compared
+    fn prefix_state(root: &Path) -> Vec<(PathBuf, Option<PathBuf>)> {
+        let mut state = Vec::new();
+        for dir_name in ["bin", "lib", "opt"] {
+            for entry in walkdir::WalkDir::new(root.join(dir_name)).sort_by_file_name() {
+                let entry = entry.unwrap();
+                let target = fs::read_link(entry.path())
+                    .ok()
+                    .map(|target| target.strip_prefix(root).unwrap().to_path_buf());
+                state.push((
+                    entry.path().strip_prefix(root).unwrap().to_path_buf(),
+                    target,
+                ));
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn concurrent_linking_matches_serial_linking() {
+        let serial = TempDir::new().unwrap();
+        let kegs = setup_batch(serial.path());
+        let linker = Linker::new(serial.path()).unwrap();
+        let serial_results: Vec<bool> = kegs
+            .iter()
+            .map(|keg| linker.link_keg(keg).is_ok())
+            .collect();
+
+        let concurrent = TempDir::new().unwrap();
+        let kegs = setup_batch(concurrent.path());
+        let linker = Linker::new(concurrent.path()).unwrap();
+        let requests: Vec<LinkRequest> = kegs
+            .iter()
+            .map(|keg| LinkRequest {
+                keg,
+                replacing: None,
+            })
+            .collect();
+        let outcomes = linker.link_kegs(&requests);
+        let concurrent_results: Vec<bool> = outcomes
+            .iter()
+            .map(|outcome| outcome.linked.is_ok())
+            .collect();
+
+        // The clash lands on the keg linked last either way
+        assert_eq!(serial_results, [true, true, false]);
+        assert_eq!(concurrent_results, serial_results);
+        assert!(matches!(
+            outcomes[2].linked,
+            Err(Error::LinkConflict { ref existing, .. }) if existing.as_deref() == Some("one")
+        ));
+        assert_eq!(prefix_state(concurrent.path()), prefix_state(serial.path()));
+    }
 }
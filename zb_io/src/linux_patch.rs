@@ -8,6 +8,9 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use rayon::prelude::*;
 use zb_core::Error;
 
+#[cfg(target_os = "linux")]
+use crate::materialize::relocate_path;
+
 /// Patch @@HOMEBREW_CELLAR@@ and @@HOMEBREW_PREFIX@@ placeholders in both ELF binaries and text files.
 #[cfg(target_os = "linux")]
 pub(crate) fn patch_placeholders(
@@ -116,6 +119,93 @@ fn find_system_ld_so() -> Option<PathBuf> {
     None
 }
 
+/// Every ELF file in a keg, not following symlinks
+fn elf_files(keg_path: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(keg_path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            // Read only first 4 bytes to check magic
+            let mut file = match fs::File::open(e.path()) {
+                Ok(f) => f,
+                Err(_) => return false,
+            };
+            let mut magic = [0u8; 4];
+            if file.read_exact(&mut magic).is_ok() {
+                return magic == *b"\x7fELF";
+            }
+            false
+        })
+        .map(|e| e.path().to_path_buf())
+        .collect()
+}
+
+/// Where to write a file's new contents before renaming it over the original.
+/// Named after the whole file name, so `libfoo.so.1` and `libfoo.so.2`,
+/// patched in parallel, never share one.
+fn temp_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{suffix}"));
+    path.with_file_name(name)
+}
+
+/// Rewrite RPATH/RUNPATH entries that point into a Homebrew prefix so they
+/// point into ours instead. Returns the files that were rewritten.
+#[cfg(target_os = "linux")]
+pub(crate) fn relocate_elf_paths(
+    keg_path: &Path,
+    rules: &[(String, String)],
+) -> Result<Vec<PathBuf>, Error> {
+    let patched = std::sync::Mutex::new(Vec::new());
+
+    elf_files(keg_path).par_iter().for_each(|path| {
+        let result = (|| -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+            let content = fs::read(path)?;
+            let mut elf = arwen::elf::ElfContainer::parse(&content)?;
+
+            let old_rpaths = elf.get_rpath();
+            let new_rpaths: Vec<String> = old_rpaths
+                .iter()
+                .map(|r| relocate_path(r, rules).unwrap_or_else(|| r.clone()))
+                .collect();
+            if new_rpaths == old_rpaths {
+                return Ok(false);
+            }
+
+            let page_size = elf.get_page_size();
+            let _ = elf.set_page_size(page_size);
+            elf.set_runpath(new_rpaths.join(":"))?;
+
+            // Rename over the original: it may be hardlinked to the store entry
+            let metadata = fs::metadata(path)?;
+            let temp_path = temp_path(path, "tmp_relocate");
+            {
+                let mut temp_file = fs::File::create(&temp_path)?;
+                elf.write(&mut temp_file)?;
+            }
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+            fs::rename(temp_path, path)?;
+            Ok(true)
+        })();
+
+        match result {
+            Ok(true) => patched.lock().unwrap().push(path.clone()),
+            Ok(false) => {}
+            Err(e) => eprintln!(
+                "Warning: Failed to relocate ELF at {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    });
+
+    let mut patched = patched.into_inner().unwrap();
+    patched.sort();
+    Ok(patched)
+}
+
 /// Patch @@HOMEBREW_CELLAR@@ and @@HOMEBREW_PREFIX@@ placeholders in ELF binaries.
 /// Uses `arwen` crate to natively update RPATH, RUNPATH, and optionally the ELF interpreter.
 fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Error> {
@@ -134,26 +224,7 @@ fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Erro
         find_system_ld_so()
     };
 
-    // Collect all ELF files
-    let elf_files: Vec<PathBuf> = walkdir::WalkDir::new(keg_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| {
-            // Read only first 4 bytes to check magic
-            let mut file = match fs::File::open(e.path()) {
-                Ok(f) => f,
-                Err(_) => return false,
-            };
-            let mut magic = [0u8; 4];
-            if file.read_exact(&mut magic).is_ok() {
-                return magic == *b"\x7fELF";
-            }
-            false
-        })
-        .map(|e| e.path().to_path_buf())
-        .collect();
+    let elf_files = elf_files(keg_path);
 
     let patch_failures = AtomicUsize::new(0);
     // Use a dashmap or similar for thread-safe inode tracking if needed,
@@ -265,7 +336,7 @@ fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Erro
             }
 
             // Atomic write
-            let temp_path = path.with_extension("tmp_patch");
+            let temp_path = temp_path(path, "tmp_patch");
             {
                 let mut temp_file = fs::File::create(&temp_path)?;
                 elf.write(&mut temp_file)?;
@@ -343,25 +414,13 @@ fn patch_text_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Err
                 .replace("@@HOMEBREW_PREFIX@@", &prefix_str)
                 .replace("@@HOMEBREW_CELLAR@@", &cellar_str);
 
-            // Write back
-            // Check readonly
+            // Write back via rename rather than in place: the keg file may be a
+            // hardlink to the store entry, which must stay unpatched
             let metadata = fs::metadata(path)?;
-            let original_mode = metadata.permissions().mode();
-            let is_readonly = original_mode & 0o200 == 0;
-
-            if is_readonly {
-                let mut perms = metadata.permissions();
-                perms.set_mode(original_mode | 0o200);
-                fs::set_permissions(path, perms)?;
-            }
-
-            fs::write(path, new_content)?;
-
-            if is_readonly {
-                let mut perms = metadata.permissions();
-                perms.set_mode(original_mode);
-                fs::set_permissions(path, perms)?;
-            }
+            let temp_path = temp_path(path, "tmp_patch");
+            fs::write(&temp_path, new_content)?;
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+            fs::rename(&temp_path, path)?;
 
             Ok(())
         })();
@@ -474,6 +533,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn temp_paths_differ_for_names_sharing_a_stem() {
+        let dir = Path::new("/keg/lib");
+        assert_eq!(
+            temp_path(&dir.join("libfoo.so.1"), "tmp"),
+            dir.join("libfoo.so.1.tmp")
+        );
+        assert_ne!(
+            temp_path(&dir.join("libfoo.so.1"), "tmp"),
+            temp_path(&dir.join("libfoo.so.2"), "tmp")
+        );
+    }
+
     #[test]
     #[cfg(target_os = "linux")]
     fn test_glibc_detection() {
@@ -508,4 +580,51 @@ mod tests {
         assert!(detected.is_some());
         assert_eq!(detected.unwrap(), ld_so_newer);
     }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn relocates_homebrew_rpath_into_prefix() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let bin_dir = prefix.join("Cellar/testpkg/1.0.0/bin");
+        fs::create_dir_all(&bin_dir).unwrap();
+
+        let src_path = bin_dir.join("reloc.c");
+        fs::write(&src_path, "int main() { return 0; }").unwrap();
+        let elf_path = bin_dir.join("reloc");
+        let built = Command::new("cc")
+            .arg(&src_path)
+            .arg("-o")
+            .arg(&elf_path)
+            .arg("-Wl,-rpath,/home/linuxbrew/.linuxbrew/lib:$ORIGIN/../lib")
+            .status()
+            .is_ok_and(|status| status.success());
+        if !built {
+            eprintln!("Skipping ELF relocation test: cc not found");
+            return;
+        }
+        fs::remove_file(&src_path).unwrap();
+
+        let rules = vec![(
+            "/home/linuxbrew/.linuxbrew".to_string(),
+            prefix.to_string_lossy().into_owned(),
+        )];
+        let patched = relocate_elf_paths(&prefix.join("Cellar/testpkg"), &rules).unwrap();
+        assert_eq!(patched, vec![elf_path.clone()]);
+
+        let elf_bytes = fs::read(&elf_path).unwrap();
+        let elf = arwen::elf::ElfContainer::parse(&elf_bytes).unwrap();
+        assert_eq!(
+            elf.get_rpath(),
+            vec![
+                format!("{}/lib", prefix.display()),
+                "$ORIGIN/../lib".to_string()
+            ]
+        );
+        assert!(fs::metadata(&elf_path).unwrap().permissions().mode() & 0o111 != 0);
+
+        // Already relocated, so a second pass has nothing to do
+        let again = relocate_elf_paths(&prefix.join("Cellar/testpkg"), &rules).unwrap();
+        assert!(again.is_empty());
+    }
 }
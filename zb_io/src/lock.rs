@@ -0,0 +1,146 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use fs4::fs_std::FileExt;
+
+use zb_core::Error;
+
+/// An exclusive advisory lock on a lock file, released when dropped.
+///
+/// Lock files are never deleted: removing one while another process waits on
+/// it would let a third process lock a fresh file at the same path.
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Lock `path`, blocking until any other holder releases it
+    pub fn acquire(path: &Path) -> Result<Self, Error> {
+        let file = open(path)?;
+        match file.try_lock_exclusive() {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("waiting for another zb process...");
+                file.lock_exclusive().map_err(lock_error)?;
+            }
+            Err(e) => return Err(lock_error(e)),
+        }
+
+        Ok(Self { _file: file })
+    }
+
+    /// Like [`FileLock::acquire`], but waits for a held lock on a blocking
+    /// thread so a contended lock doesn't stall the async runtime
+    pub async fn acquire_async(path: &Path) -> Result<Self, Error> {
+        let file = open(path)?;
+        if file.try_lock_exclusive().map_err(lock_error)? {
+            return Ok(Self { _file: file });
+        }
+
+        eprintln!("waiting for another zb process...");
+        let file = tokio::task::spawn_blocking(move || file.lock_exclusive().map(|()| file))
+            .await
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("lock task failed: {e}"),
+            })?
+            .map_err(lock_error)?;
+        Ok(Self { _file: file })
+    }
+
+    /// Lock `path` shared with other shared holders, blocking while anyone
+    /// holds it exclusively
+    pub fn acquire_shared(path: &Path) -> Result<Self, Error> {
+        let file = open(path)?;
+        match FileExt::try_lock_shared(&file) {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("waiting for another zb process...");
+                FileExt::lock_shared(&file).map_err(lock_error)?;
+            }
+            Err(e) => return Err(lock_error(e)),
+        }
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// Whether a process with this id is still running, for telling state a
+/// live process holds from what a crashed one left behind
+pub(crate) fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process exists; EPERM means it does but
+    // belongs to someone else
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+fn open(path: &Path) -> Result<File, Error> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| Error::StoreCorruption {
+            message: format!("failed to create lock file: {e}"),
+        })
+}
+
+fn lock_error(e: io::Error) -> Error {
+    Error::StoreCorruption {
+        message: format!("failed to acquire lock: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn second_holder_waits_for_release() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("test.lock");
+
+        let first = FileLock::acquire(&path).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let waiter_path = path.clone();
+        let waiter = thread::spawn(move || {
+            let _lock = FileLock::acquire(&waiter_path).unwrap();
+            tx.send(()).unwrap();
+        });
+
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(first);
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiter.join().unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn async_waiter_leaves_the_runtime_free() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("test.lock");
+
+        let first = FileLock::acquire(&path).unwrap();
+        let waiter = tokio::spawn({
+            let path = path.clone();
+            async move { FileLock::acquire_async(&path).await }
+        });
+
+        // Still able to run other tasks on the one runtime thread
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiter.is_finished());
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(5), waiter)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -3,18 +3,65 @@ use std::io;
 use std::path::{Path, PathBuf};
 use zb_core::Error;
 
+use crate::lock::process_alive;
+
 #[cfg(target_os = "linux")]
-use crate::linux_patch::patch_placeholders;
+use crate::linux_patch::{patch_placeholders, relocate_elf_paths};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum CopyStrategy {
-    Clonefile,
-    Hardlink,
+/// How files are transferred from a store entry into the cellar
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MaterializeMode {
+    /// Plain byte-for-byte copies
     Copy,
+    /// Hardlinks into the store, copying where linking fails
+    Hardlink,
+    /// Copy-on-write clones (APFS clonefile, Btrfs/XFS FICLONE), copying where unsupported
+    Reflink,
+    /// Reflink, then hardlink, then copy; whichever works first
+    #[default]
+    Auto,
+}
+
+impl std::str::FromStr for MaterializeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "copy" => Ok(Self::Copy),
+            "hardlink" => Ok(Self::Hardlink),
+            "reflink" => Ok(Self::Reflink),
+            "auto" => Ok(Self::Auto),
+            _ => Err(format!(
+                "unknown materialize mode '{s}' (expected copy, hardlink, reflink or auto)"
+            )),
+        }
+    }
+}
+
+impl MaterializeMode {
+    fn try_reflink(self) -> bool {
+        matches!(self, Self::Reflink | Self::Auto)
+    }
+
+    fn try_hardlink(self) -> bool {
+        matches!(self, Self::Hardlink | Self::Auto)
+    }
+
+    /// What's left to try once the filesystem has turned out not to clone
+    fn without_reflink(self) -> Self {
+        match self {
+            Self::Reflink => Self::Copy,
+            Self::Auto => Self::Hardlink,
+            other => other,
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Cellar {
     cellar_dir: PathBuf,
+    mode: MaterializeMode,
+    relocate: bool,
 }
 
 impl Cellar {
@@ -24,7 +71,28 @@ impl Cellar {
 
     pub fn new_at(cellar_dir: PathBuf) -> io::Result<Self> {
         fs::create_dir_all(&cellar_dir)?;
-        Ok(Self { cellar_dir })
+        Ok(Self {
+            cellar_dir,
+            mode: MaterializeMode::default(),
+            relocate: false,
+        })
+    }
+
+    /// Rewrite library paths that bottles hardcode to Homebrew's default
+    /// prefix so they point into this cellar's prefix
+    pub fn with_relocation(mut self, relocate: bool) -> Self {
+        self.relocate = relocate;
+        self
+    }
+
+    /// Force a particular way of transferring files into kegs
+    pub fn with_materialize_mode(mut self, mode: MaterializeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn materialize_mode(&self) -> MaterializeMode {
+        self.mode
     }
 
     pub fn keg_path(&self, name: &str, version: &str) -> PathBuf {
@@ -35,18 +103,74 @@ impl Cellar {
         self.keg_path(name, version).exists()
     }
 
+    /// Paths from the bottle in `store_entry` that aren't in the keg. Contents
+    /// aren't compared, since kegs are patched after materializing.
+    pub fn missing_from_keg(
+        &self,
+        name: &str,
+        version: &str,
+        store_entry: &Path,
+    ) -> Result<Vec<String>, Error> {
+        let src = find_bottle_content(store_entry, name, version)?;
+        let keg = self.keg_path(name, version);
+
+        Ok(walkdir::WalkDir::new(&src)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.path().strip_prefix(&src).ok().map(Path::to_path_buf))
+            .filter(|rel| fs::symlink_metadata(keg.join(rel)).is_err())
+            .map(|rel| rel.to_string_lossy().into_owned())
+            .collect())
+    }
+
     pub fn materialize(
         &self,
         name: &str,
         version: &str,
         store_entry: &Path,
     ) -> Result<PathBuf, Error> {
+        self.materialize_reporting(name, version, store_entry)
+            .map(|(keg_path, _)| keg_path)
+    }
+
+    /// Like [`Self::materialize`], also returning the files relocation
+    /// rewrote (always empty unless relocation is on)
+    #[tracing::instrument(name = "materialize", skip(self, store_entry), fields(mode = ?self.mode))]
+    pub fn materialize_reporting(
+        &self,
+        name: &str,
+        version: &str,
+        store_entry: &Path,
+    ) -> Result<(PathBuf, Vec<PathBuf>), Error> {
         let keg_path = self.keg_path(name, version);
 
         if keg_path.exists() {
-            return Ok(keg_path);
+            tracing::debug!(keg = %keg_path.display(), "keg already present");
+            return Ok((keg_path, Vec::new()));
         }
 
+        let (staging, relocated) = self.stage_keg(name, version, store_entry)?;
+        fs::rename(&staging, &keg_path).map_err(|e| {
+            let _ = fs::remove_dir_all(&staging);
+            Error::StoreCorruption {
+                message: format!("failed to move keg into place: {e}"),
+            }
+        })?;
+        Ok((keg_path, relocated))
+    }
+
+    /// Build the keg for `name` `version` beside its final path without
+    /// moving it into place, returning where it was built and the files
+    /// relocation rewrote, as they'll be named once it's in place
+    pub fn stage_keg(
+        &self,
+        name: &str,
+        version: &str,
+        store_entry: &Path,
+    ) -> Result<(PathBuf, Vec<PathBuf>), Error> {
+        let keg_path = self.keg_path(name, version);
+
         // Create parent directory for the keg
         if let Some(parent) = keg_path.parent() {
             fs::create_dir_all(parent).map_err(|e| Error::StoreCorruption {
@@ -58,12 +182,124 @@ impl Cellar {
         // Find the source directory to copy from
         let src_path = find_bottle_content(store_entry, name, version)?;
 
+        // Build the keg beside its final path and only move it into place
+        // once complete, so an interrupted install never leaves a keg that
+        // looks finished
+        let staging = self.staging_path(name, version);
+        let _ = fs::remove_dir_all(&staging);
+        self.remove_abandoned_staging(name, version);
+        let relocated = match self.populate_keg(&src_path, &staging, name, version) {
+            Ok(relocated) => relocated,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(e);
+            }
+        };
+
+        let relocated = relocated
+            .into_iter()
+            .map(|file| match file.strip_prefix(&staging) {
+                Ok(rel) => keg_path.join(rel),
+                Err(_) => file,
+            })
+            .collect();
+        Ok((staging, relocated))
+    }
+
+    /// Swap the keg [`Self::stage_keg`] built in for the one already at its
+    /// final path. The old keg is moved aside first and put back if the new
+    /// one can't be moved in, so the path never goes missing for long.
+    pub fn replace_keg(&self, name: &str, version: &str) -> Result<PathBuf, Error> {
+        let keg_path = self.keg_path(name, version);
+        let staging = self.staging_path(name, version);
+        let aside = self
+            .cellar_dir
+            .join(name)
+            .join(format!(".{version}.old.{}", std::process::id()));
+
+        let _ = fs::remove_dir_all(&aside);
+        let had_keg = match fs::rename(&keg_path, &aside) {
+            Ok(()) => true,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(Error::StoreCorruption {
+                    message: format!("failed to move old keg aside: {e}"),
+                });
+            }
+        };
+        if let Err(e) = fs::rename(&staging, &keg_path) {
+            let _ = fs::remove_dir_all(&staging);
+            if had_keg {
+                let _ = fs::rename(&aside, &keg_path);
+            }
+            return Err(Error::StoreCorruption {
+                message: format!("failed to move keg into place: {e}"),
+            });
+        }
+        let _ = fs::remove_dir_all(&aside);
+        Ok(keg_path)
+    }
+
+    /// Where this process builds a keg before moving it into place
+    fn staging_path(&self, name: &str, version: &str) -> PathBuf {
+        self.cellar_dir
+            .join(name)
+            .join(format!(".{version}.tmp.{}", std::process::id()))
+    }
+
+    /// Remove a keg this process was still building, e.g. after an install
+    /// was cancelled
+    pub fn discard_staging(&self, name: &str, version: &str) {
+        let _ = fs::remove_dir_all(self.staging_path(name, version));
+    }
+
+    /// Remove kegs other processes were building when they died, so crashed
+    /// installs don't pile up beside the keg. Staging directories of live
+    /// processes are left alone.
+    fn remove_abandoned_staging(&self, name: &str, version: &str) {
+        let prefix = format!(".{version}.tmp.");
+        let Ok(entries) = fs::read_dir(self.cellar_dir.join(name)) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(pid) = file_name
+                .to_str()
+                .and_then(|n| n.strip_prefix(&prefix))
+                .and_then(|pid| pid.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            if pid != std::process::id() && !process_alive(pid) {
+                tracing::debug!(staging = %entry.path().display(), "removing abandoned keg");
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+
+    /// Copy a bottle's content to `keg_path` and patch it for our prefix,
+    /// returning the files relocation rewrote
+    fn populate_keg(
+        &self,
+        src_path: &Path,
+        keg_path: &Path,
+        name: &str,
+        version: &str,
+    ) -> Result<Vec<PathBuf>, Error> {
         // Copy the content to the cellar using best available strategy
-        copy_dir_with_fallback(&src_path, &keg_path)?;
+        copy_dir_with_fallback(src_path, keg_path, self.mode)?;
+
+        // Relocate before the placeholder pass, which drops rpaths outside our prefix
+        let relocated = if self.relocate {
+            self.relocate_keg(keg_path)?
+        } else {
+            Vec::new()
+        };
 
         // Patch Homebrew placeholders in Mach-O binaries
         #[cfg(target_os = "macos")]
-        patch_homebrew_placeholders(&keg_path, &self.cellar_dir, name, version)?;
+        patch_homebrew_placeholders(keg_path, &self.cellar_dir, name, version)?;
 
         // Patch Homebrew placeholders in ELF binaries
         #[cfg(target_os = "linux")]
@@ -78,14 +314,64 @@ impl Cellar {
                         self.cellar_dir.display()
                     ),
                 })?;
-            patch_placeholders(&keg_path, prefix, name, version)?;
+            patch_placeholders(keg_path, prefix, name, version)?;
         }
 
         // Strip quarantine xattrs and ad-hoc sign Mach-O binaries
         #[cfg(target_os = "macos")]
-        codesign_and_strip_xattrs(&keg_path)?;
+        codesign_and_strip_xattrs(keg_path)?;
 
-        Ok(keg_path)
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        let _ = (name, version);
+
+        Ok(relocated)
+    }
+
+    fn relocate_keg(&self, keg_path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let prefix = self.cellar_dir.parent().unwrap_or(&self.cellar_dir);
+        let rules = relocation_rules(&self.cellar_dir, prefix);
+
+        #[cfg(target_os = "linux")]
+        return relocate_elf_paths(keg_path, &rules);
+
+        #[cfg(target_os = "macos")]
+        return relocate_macho_paths(keg_path, &rules);
+
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        {
+            let _ = (keg_path, rules);
+            Ok(Vec::new())
+        }
+    }
+
+    /// Names of every formula with a directory in the cellar
+    pub fn list_formulas(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(&self.cellar_dir) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+            .filter_map(|e| e.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Versions of `name` present in the cellar, installed or not
+    pub fn list_versions(&self, name: &str) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(self.cellar_dir.join(name)) else {
+            return Vec::new();
+        };
+        let mut versions: Vec<String> = entries
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+            .filter_map(|e| e.file_name().into_string().ok())
+            // Kegs still being built
+            .filter(|version| !version.starts_with('.'))
+            .collect();
+        versions.sort();
+        versions
     }
 
     pub fn remove_keg(&self, name: &str, version: &str) -> Result<(), Error> {
@@ -108,6 +394,128 @@ impl Cellar {
     }
 }
 
+/// Homebrew's default install locations and what they become in ours, most
+/// specific first. Only the Cellar and opt/ of /usr/local are claimed, since
+/// the rest of it belongs to the system.
+fn relocation_rules(cellar_dir: &Path, prefix: &Path) -> Vec<(String, String)> {
+    let cellar = cellar_dir.to_string_lossy().into_owned();
+    let prefix = prefix.to_string_lossy().into_owned();
+    let homebrew_prefixes: &[&str] = if cfg!(target_os = "macos") {
+        &["/opt/homebrew"]
+    } else {
+        &["/home/linuxbrew/.linuxbrew"]
+    };
+
+    let mut rules = Vec::new();
+    for old in homebrew_prefixes {
+        rules.push((format!("{old}/Cellar"), cellar.clone()));
+        rules.push((old.to_string(), prefix.clone()));
+    }
+    if cfg!(target_os = "macos") {
+        rules.push(("/usr/local/Cellar".to_string(), cellar));
+        rules.push(("/usr/local/opt".to_string(), format!("{prefix}/opt")));
+    }
+    rules
+}
+
+/// `path` moved by the first matching rule, or `None` if no rule covers it
+pub(crate) fn relocate_path(path: &str, rules: &[(String, String)]) -> Option<String> {
+    rules.iter().find_map(|(old, new)| {
+        let rest = path.strip_prefix(old.as_str())?;
+        (rest.is_empty() || rest.starts_with('/')).then(|| format!("{new}{rest}"))
+    })
+}
+
+/// Rewrite install names, dependency paths and LC_RPATHs that point into a
+/// Homebrew prefix. Returns the files that were rewritten (and re-signed).
+#[cfg(target_os = "macos")]
+fn relocate_macho_paths(
+    keg_path: &Path,
+    rules: &[(String, String)],
+) -> Result<Vec<PathBuf>, Error> {
+    use rayon::prelude::*;
+    use std::process::Command;
+
+    let macho_files: Vec<PathBuf> = walkdir::WalkDir::new(keg_path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            let mut magic = [0u8; 4];
+            fs::File::open(e.path())
+                .and_then(|mut f| io::Read::read_exact(&mut f, &mut magic))
+                .is_ok_and(|_| {
+                    matches!(
+                        u32::from_be_bytes(magic),
+                        0xfeedface | 0xfeedfacf | 0xcafebabe | 0xcefaedfe | 0xcffaedfe
+                    )
+                })
+        })
+        .map(|e| e.path().to_path_buf())
+        .collect();
+
+    let mut patched: Vec<PathBuf> = macho_files
+        .par_iter()
+        .filter(|path| {
+            let file = path.to_string_lossy();
+            let otool = |flag: &str| {
+                Command::new("otool")
+                    .args([flag, &file])
+                    .output()
+                    .ok()
+                    .filter(|out| out.status.success())
+                    .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+                    .unwrap_or_default()
+            };
+
+            let mut args: Vec<String> = Vec::new();
+            for line in otool("-L").lines().skip(1) {
+                if let Some(old) = line.split_whitespace().next()
+                    && let Some(new) = relocate_path(old, rules)
+                {
+                    args.extend(["-change".to_string(), old.to_string(), new]);
+                }
+            }
+            if let Some(id) = otool("-D").lines().nth(1)
+                && let Some(new) = relocate_path(id.trim(), rules)
+            {
+                args.extend(["-id".to_string(), new]);
+            }
+            // LC_RPATH load commands print as "path <dir> (offset N)"
+            for line in otool("-l").lines() {
+                if let Some(old) = line.trim().strip_prefix("path ")
+                    && let Some(old) = old.split(" (offset").next()
+                    && let Some(new) = relocate_path(old, rules)
+                {
+                    args.extend(["-rpath".to_string(), old.to_string(), new]);
+                }
+            }
+            if args.is_empty() {
+                return false;
+            }
+
+            let rewritten = Command::new("install_name_tool")
+                .args(&args)
+                .arg(&*file)
+                .status()
+                .is_ok_and(|status| status.success());
+            if rewritten {
+                let _ = Command::new("codesign")
+                    .args(["--force", "--sign", "-", &file])
+                    .output();
+            } else {
+                eprintln!("Warning: Failed to relocate Mach-O at {file}");
+            }
+            rewritten
+        })
+        .cloned()
+        .collect();
+
+    patched.sort();
+    Ok(patched)
+}
+
 /// Find the bottle content directory inside a store entry.
 /// Homebrew bottles have structure {name}/{version}/ inside the tarball.
 /// This function finds that directory, falling back to the store_entry root
@@ -422,17 +830,46 @@ fn codesign_and_strip_xattrs(keg_path: &Path) -> Result<(), Error> {
     Ok(())
 }
 
-fn copy_dir_with_fallback(src: &Path, dst: &Path) -> Result<(), Error> {
-    // Try clonefile first (APFS), then hardlink, then copy
+fn copy_dir_with_fallback(src: &Path, dst: &Path, mode: MaterializeMode) -> Result<(), Error> {
+    // Try cloning the whole tree at once (APFS), then per-file strategies
     #[cfg(target_os = "macos")]
     {
-        if try_clonefile_dir(src, dst).is_ok() {
-            return Ok(());
+        if mode.try_reflink() && try_clonefile_dir(src, dst).is_ok() {
+            return retarget_cloned_symlinks(src, dst);
         }
     }
 
-    // Fall back to recursive copy with hardlink/copy per file
-    copy_dir_recursive(src, dst, true)
+    copy_dir_recursive(src, dst, mode)
+}
+
+/// clonefile copies link targets verbatim, so absolute links into the store
+/// entry still need pointing back inside the keg
+#[cfg(target_os = "macos")]
+fn retarget_cloned_symlinks(src_root: &Path, dst_root: &Path) -> Result<(), Error> {
+    for entry in walkdir::WalkDir::new(dst_root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path_is_symlink())
+    {
+        let link = entry.path();
+        let Ok(target) = fs::read_link(link) else {
+            continue;
+        };
+        let Ok(rel_link) = link.strip_prefix(dst_root) else {
+            continue;
+        };
+        let new_target = link_target_in_copy(&target, src_root, rel_link);
+        if new_target != target {
+            let src_link = src_root.join(rel_link);
+            fs::remove_file(link)
+                .and_then(|_| std::os::unix::fs::symlink(&new_target, link))
+                .map_err(|e| Error::StoreCorruption {
+                    message: format!("failed to retarget symlink {}: {e}", link.display()),
+                })?;
+            copy_symlink_times(&src_link, link);
+        }
+    }
+    Ok(())
 }
 
 #[cfg(target_os = "macos")]
@@ -460,7 +897,59 @@ fn try_clonefile_dir(src: &Path, dst: &Path) -> io::Result<()> {
     }
 }
 
-fn copy_dir_recursive(src: &Path, dst: &Path, try_hardlink: bool) -> Result<(), Error> {
+/// Clone a single regular file copy-on-write. Fails (leaving nothing behind)
+/// on filesystems without reflink support.
+#[cfg(target_os = "macos")]
+fn try_reflink_file(src: &Path, dst: &Path) -> io::Result<()> {
+    try_clonefile_dir(src, dst)
+}
+
+#[cfg(target_os = "linux")]
+fn try_reflink_file(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let src_file = fs::File::open(src)?;
+    let dst_file = fs::File::create(dst)?;
+
+    let result = unsafe { libc::ioctl(dst_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) };
+    if result != 0 {
+        let err = io::Error::last_os_error();
+        drop(dst_file);
+        let _ = fs::remove_file(dst);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn try_reflink_file(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+fn copy_dir_recursive(src: &Path, dst: &Path, mode: MaterializeMode) -> Result<(), Error> {
+    let mut mode = mode;
+    copy_tree(src, src, dst, &mut mode)
+}
+
+/// Whether a failed clone means the filesystem can't clone at all, rather
+/// than that this one file couldn't be
+fn reflink_unsupported(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Unsupported
+        || matches!(
+            e.raw_os_error(),
+            Some(libc::EOPNOTSUPP | libc::ENOTTY | libc::EINVAL | libc::EXDEV)
+        )
+}
+
+/// Copy `src` (somewhere under `src_root`) to `dst`, keeping the root around so
+/// absolute symlinks into the source tree can be rewritten. `mode` stops
+/// trying reflinks for the rest of the copy once one shows they're unsupported.
+fn copy_tree(
+    src_root: &Path,
+    src: &Path,
+    dst: &Path,
+    mode: &mut MaterializeMode,
+) -> Result<(), Error> {
     fs::create_dir_all(dst).map_err(|e| Error::StoreCorruption {
         message: format!("failed to create directory {}: {e}", dst.display()),
     })?;
@@ -479,24 +968,44 @@ fn copy_dir_recursive(src: &Path, dst: &Path, try_hardlink: bool) -> Result<(),
         })?;
 
         if file_type.is_dir() {
-            copy_dir_recursive(&src_path, &dst_path, try_hardlink)?;
+            copy_tree(src_root, &src_path, &dst_path, mode)?;
         } else if file_type.is_symlink() {
             let target = fs::read_link(&src_path).map_err(|e| Error::StoreCorruption {
                 message: format!("failed to read symlink: {e}"),
             })?;
 
             #[cfg(unix)]
-            std::os::unix::fs::symlink(&target, &dst_path).map_err(|e| Error::StoreCorruption {
-                message: format!("failed to create symlink: {e}"),
-            })?;
+            {
+                let rel_link = src_path.strip_prefix(src_root).unwrap_or(&src_path);
+                let target = link_target_in_copy(&target, src_root, rel_link);
+                std::os::unix::fs::symlink(&target, &dst_path).map_err(|e| {
+                    Error::StoreCorruption {
+                        message: format!("failed to create symlink: {e}"),
+                    }
+                })?;
+                copy_symlink_times(&src_path, &dst_path);
+            }
 
             #[cfg(not(unix))]
             fs::copy(&src_path, &dst_path).map_err(|e| Error::StoreCorruption {
                 message: format!("failed to copy symlink as file: {e}"),
             })?;
         } else {
-            // Try hardlink first, then copy
-            if try_hardlink && fs::hard_link(&src_path, &dst_path).is_ok() {
+            // A CoW clone shares blocks with the store but can be edited independently
+            if mode.try_reflink() {
+                match try_reflink_file(&src_path, &dst_path) {
+                    Ok(()) => {
+                        copy_attributes(&src_path, &dst_path)?;
+                        continue;
+                    }
+                    Err(e) if reflink_unsupported(&e) => *mode = mode.without_reflink(),
+                    Err(_) => {}
+                }
+            }
+
+            // Store and cellar normally share a filesystem, so a hardlink avoids
+            // copying the bytes. Fall back to a copy on EXDEV or any other link error.
+            if mode.try_hardlink() && fs::hard_link(&src_path, &dst_path).is_ok() {
                 continue;
             }
 
@@ -505,28 +1014,137 @@ fn copy_dir_recursive(src: &Path, dst: &Path, try_hardlink: bool) -> Result<(),
                 message: format!("failed to copy file: {e}"),
             })?;
 
-            // Preserve permissions
-            #[cfg(unix)]
-            {
-                let metadata = fs::metadata(&src_path).map_err(|e| Error::StoreCorruption {
-                    message: format!("failed to read metadata: {e}"),
-                })?;
-                fs::set_permissions(&dst_path, metadata.permissions()).map_err(|e| {
-                    Error::StoreCorruption {
-                        message: format!("failed to set permissions: {e}"),
-                    }
-                })?;
-            }
+            copy_attributes(&src_path, &dst_path)?;
         }
     }
 
     Ok(())
 }
 
+/// Where a copied symlink should point. Relative targets are kept verbatim;
+/// an absolute target inside `src_root` would dangle once the source is gone,
+/// so it becomes a relative path to the same file in the copy. `rel_link` is
+/// the link's own path relative to the root.
+fn link_target_in_copy(target: &Path, src_root: &Path, rel_link: &Path) -> PathBuf {
+    let Some(rel_target) = target
+        .is_absolute()
+        .then(|| target.strip_prefix(src_root).ok())
+        .flatten()
+    else {
+        return target.to_path_buf();
+    };
+
+    let link_dir: Vec<_> = rel_link
+        .parent()
+        .map(|p| p.components().collect())
+        .unwrap_or_default();
+    let target_parts: Vec<_> = rel_target.components().collect();
+    let shared = link_dir
+        .iter()
+        .zip(&target_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in shared..link_dir.len() {
+        relative.push("..");
+    }
+    for part in &target_parts[shared..] {
+        relative.push(part);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+/// Give a recreated symlink the source link's own access and modification
+/// times. Best-effort, like the xattrs.
+#[cfg(unix)]
+fn copy_symlink_times(src: &Path, dst: &Path) {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(meta) = fs::symlink_metadata(src) else {
+        return;
+    };
+    let Ok(path) = CString::new(dst.as_os_str().as_bytes()) else {
+        return;
+    };
+    let times = [
+        libc::timespec {
+            tv_sec: meta.atime() as libc::time_t,
+            tv_nsec: meta.atime_nsec() as _,
+        },
+        libc::timespec {
+            tv_sec: meta.mtime() as libc::time_t,
+            tv_nsec: meta.mtime_nsec() as _,
+        },
+    ];
+    unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        );
+    }
+}
+
+/// Give a copied file the source's extended attributes and permissions.
+/// Setting xattrs needs write access, so the final, possibly read-only, mode
+/// is applied last.
+#[cfg(unix)]
+fn copy_attributes(src: &Path, dst: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(src)
+        .map_err(|e| Error::StoreCorruption {
+            message: format!("failed to read metadata: {e}"),
+        })?
+        .permissions()
+        .mode();
+    let set_mode = |mode| {
+        fs::set_permissions(dst, fs::Permissions::from_mode(mode)).map_err(|e| {
+            Error::StoreCorruption {
+                message: format!("failed to set permissions: {e}"),
+            }
+        })
+    };
+
+    set_mode(mode | 0o200)?;
+    copy_xattrs(src, dst);
+    set_mode(mode)
+}
+
+#[cfg(not(unix))]
+fn copy_attributes(src: &Path, dst: &Path) -> Result<(), Error> {
+    copy_xattrs(src, dst);
+    Ok(())
+}
+
+/// Carry extended attributes over to a copied file. Best-effort: filesystems
+/// without xattr support, or attributes we may not set, are skipped silently.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn copy_xattrs(src: &Path, dst: &Path) {
+    let Ok(names) = xattr::list(src) else {
+        return;
+    };
+    for name in names {
+        if let Ok(Some(value)) = xattr::get(src, &name) {
+            let _ = xattr::set(dst, &name, &value);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn copy_xattrs(_src: &Path, _dst: &Path) {}
+
 // For testing - copy without fallback strategies
 #[cfg(test)]
 fn copy_dir_copy_only(src: &Path, dst: &Path) -> Result<(), Error> {
-    copy_dir_recursive(src, dst, false)
+    copy_dir_recursive(src, dst, MaterializeMode::Copy)
 }
 
 #[cfg(test)]
@@ -603,6 +1221,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn relative_symlink_is_copied_verbatim() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        fs::create_dir_all(src.join("lib")).unwrap();
+        fs::write(src.join("lib/libbar.so.1"), b"lib").unwrap();
+        std::os::unix::fs::symlink("../lib/libbar.so.1", src.join("lib/libbar.so")).unwrap();
+
+        let dst = tmp.path().join("dst");
+        copy_dir_copy_only(&src, &dst).unwrap();
+
+        assert_eq!(
+            fs::read_link(dst.join("lib/libbar.so")).unwrap(),
+            PathBuf::from("../lib/libbar.so.1")
+        );
+        assert_eq!(fs::read(dst.join("lib/libbar.so")).unwrap(), b"lib");
+    }
+
+    #[test]
+    fn absolute_symlink_into_store_entry_points_into_keg() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        fs::create_dir_all(store_entry.join("share/foo")).unwrap();
+        std::os::unix::fs::symlink(
+            store_entry.join("lib/libfoo.dylib"),
+            store_entry.join("share/foo/libfoo.dylib"),
+        )
+        .unwrap();
+
+        let cellar = Cellar::new(tmp.path()).unwrap();
+        let keg_path = cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+        fs::remove_dir_all(&store_entry).unwrap();
+
+        let link = keg_path.join("share/foo/libfoo.dylib");
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            PathBuf::from("../../lib/libfoo.dylib")
+        );
+        assert_eq!(fs::read(&link).unwrap(), b"fake dylib");
+    }
+
     #[test]
     fn second_materialize_is_noop() {
         let tmp = TempDir::new().unwrap();
@@ -624,6 +1283,145 @@ mod tests {
         assert!(keg_path2.join("marker.txt").exists());
     }
 
+    #[test]
+    fn materialized_files_are_hardlinked_to_store() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+
+        let cellar = Cellar::new(tmp.path())
+            .unwrap()
+            .with_materialize_mode(MaterializeMode::Hardlink);
+        let keg_path = cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+
+        let original = fs::metadata(store_entry.join("lib/libfoo.dylib")).unwrap();
+        let materialized = fs::metadata(keg_path.join("lib/libfoo.dylib")).unwrap();
+
+        assert_eq!(materialized.ino(), original.ino());
+        assert_eq!(materialized.dev(), original.dev());
+        assert_eq!(
+            fs::read(keg_path.join("lib/libfoo.dylib")).unwrap(),
+            b"fake dylib"
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn patching_keg_leaves_hardlinked_store_untouched() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        let script = b"#!/bin/sh\necho @@HOMEBREW_PREFIX@@";
+        fs::write(store_entry.join("bin/script"), script).unwrap();
+
+        let cellar = Cellar::new_at(tmp.path().join("prefix/Cellar")).unwrap();
+        let keg_path = cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+
+        let patched = fs::read_to_string(keg_path.join("bin/script")).unwrap();
+        assert!(!patched.contains("@@HOMEBREW_PREFIX@@"));
+        assert_eq!(fs::read(store_entry.join("bin/script")).unwrap(), script);
+    }
+
+    #[test]
+    fn every_materialize_mode_reproduces_content() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+
+        for mode in [
+            MaterializeMode::Copy,
+            MaterializeMode::Hardlink,
+            MaterializeMode::Reflink,
+            MaterializeMode::Auto,
+        ] {
+            let cellar = Cellar::new_at(tmp.path().join(format!("{mode:?}/Cellar")))
+                .unwrap()
+                .with_materialize_mode(mode);
+            let keg_path = cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+
+            assert_eq!(
+                fs::read_to_string(keg_path.join("bin/foo")).unwrap(),
+                "#!/bin/sh\necho foo",
+                "{mode:?}"
+            );
+            assert_eq!(
+                fs::read(keg_path.join("lib/libfoo.dylib")).unwrap(),
+                b"fake dylib",
+                "{mode:?}"
+            );
+            let perms = fs::metadata(keg_path.join("bin/foo"))
+                .unwrap()
+                .permissions();
+            assert!(perms.mode() & 0o111 != 0, "{mode:?}: executable bit lost");
+            assert_eq!(
+                fs::read_link(keg_path.join("lib/libfoo.1.dylib")).unwrap(),
+                PathBuf::from("libfoo.dylib"),
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn copied_files_keep_extended_attributes() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("tool"), b"signed binary").unwrap();
+        if xattr::set(src.join("tool"), "user.zerobrew.test", b"provenance").is_err() {
+            // The temp filesystem doesn't do user xattrs; nothing to check
+            return;
+        }
+
+        // Store entries are read-only; the copy still gets the attributes
+        fs::set_permissions(src.join("tool"), fs::Permissions::from_mode(0o555)).unwrap();
+
+        let dst = tmp.path().join("dst");
+        copy_dir_copy_only(&src, &dst).unwrap();
+
+        assert_eq!(
+            xattr::get(dst.join("tool"), "user.zerobrew.test").unwrap(),
+            Some(b"provenance".to_vec())
+        );
+        assert_eq!(
+            fs::metadata(dst.join("tool")).unwrap().permissions().mode() & 0o777,
+            0o555
+        );
+    }
+
+    #[test]
+    fn reflink_falls_back_to_copy_without_cow_support() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("data"), b"cow or copy").unwrap();
+
+        let dst = tmp.path().join("dst");
+        copy_dir_recursive(&src, &dst, MaterializeMode::Reflink).unwrap();
+
+        assert_eq!(fs::read(dst.join("data")).unwrap(), b"cow or copy");
+        // Whatever happened, the result must not alias the source inode
+        fs::write(dst.join("data"), b"edited").unwrap();
+        assert_eq!(fs::read(src.join("data")).unwrap(), b"cow or copy");
+    }
+
+    #[test]
+    fn unsupported_reflinks_are_given_up_on() {
+        assert!(reflink_unsupported(&io::Error::from_raw_os_error(
+            libc::EOPNOTSUPP
+        )));
+        assert!(!reflink_unsupported(&io::Error::from_raw_os_error(
+            libc::EACCES
+        )));
+        assert_eq!(
+            MaterializeMode::Auto.without_reflink(),
+            MaterializeMode::Hardlink
+        );
+        assert_eq!(
+            MaterializeMode::Reflink.without_reflink(),
+            MaterializeMode::Copy
+        );
+    }
+
     #[test]
     fn remove_keg_cleans_up() {
         let tmp = TempDir::new().unwrap();
@@ -639,6 +1437,42 @@ mod tests {
         assert!(!cellar.has_keg("foo", "1.2.3"));
     }
 
+    #[test]
+    fn failed_copy_leaves_no_keg_or_staging_behind() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        // A socket can't be copied, so the copy fails partway through
+        let _socket = std::os::unix::net::UnixListener::bind(store_entry.join("lib/sock")).unwrap();
+
+        let cellar = Cellar::new(tmp.path())
+            .unwrap()
+            .with_materialize_mode(MaterializeMode::Copy);
+        assert!(cellar.materialize("foo", "1.2.3", &store_entry).is_err());
+
+        assert!(!cellar.has_keg("foo", "1.2.3"));
+        assert!(!cellar.keg_path("foo", "1.2.3").exists());
+        let leftovers: Vec<_> = fs::read_dir(tmp.path().join("cellar/foo"))
+            .map(|entries| entries.flatten().map(|e| e.file_name()).collect())
+            .unwrap_or_default();
+        assert!(leftovers.is_empty(), "left behind: {leftovers:?}");
+    }
+
+    #[test]
+    fn abandoned_staging_from_a_dead_process_is_removed() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        let abandoned = tmp
+            .path()
+            .join(format!("cellar/foo/.1.2.3.tmp.{}", i32::MAX));
+        fs::create_dir_all(abandoned.join("bin")).unwrap();
+
+        let cellar = Cellar::new(tmp.path()).unwrap();
+        let keg_path = cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+
+        assert!(keg_path.join("bin/foo").exists());
+        assert!(!abandoned.exists());
+    }
+
     #[test]
     fn keg_path_format() {
         let tmp = TempDir::new().unwrap();
@@ -0,0 +1,205 @@
+use rusqlite::{Connection, TransactionBehavior};
+
+use zb_core::Error;
+
+/// Schema changes in order; applying step `n` brings a database to version
+/// `n + 1`, recorded in SQLite's `user_version`. Released steps must never
+/// change, only be added to. Databases from before versioning are at 0 but
+/// may already have any of these tables, so steps only create what's missing.
+const MIGRATIONS: &[&str] = &[
+    // 1: installed kegs, store refcounts and linked files
+    "
+    CREATE TABLE IF NOT EXISTS installed_kegs (
+        name TEXT PRIMARY KEY,
+        version TEXT NOT NULL,
+        store_key TEXT NOT NULL,
+        installed_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS store_refs (
+        store_key TEXT PRIMARY KEY,
+        refcount INTEGER NOT NULL DEFAULT 1
+    );
+
+    CREATE TABLE IF NOT EXISTS keg_files (
+        name TEXT NOT NULL,
+        version TEXT NOT NULL,
+        linked_path TEXT NOT NULL,
+        target_path TEXT NOT NULL,
+        PRIMARY KEY (name, linked_path)
+    );
+    ",
+    // 2: install history, for rollback
+    "
+    CREATE TABLE IF NOT EXISTS transactions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        old_version TEXT,
+        old_store_key TEXT,
+        new_version TEXT,
+        new_store_key TEXT,
+        recorded_at INTEGER NOT NULL
+    );
+    ",
+    // 3: pins
+    "
+    CREATE TABLE IF NOT EXISTS pinned (
+        name TEXT PRIMARY KEY,
+        pinned_at INTEGER NOT NULL
+    );
+    ",
+    // 4: formula metadata fetched from the API, see `ApiCache::in_database`
+    "
+    CREATE TABLE IF NOT EXISTS formula_cache (
+        url TEXT PRIMARY KEY,
+        etag TEXT,
+        last_modified TEXT,
+        body TEXT NOT NULL,
+        cached_at INTEGER NOT NULL
+    );
+    ",
+    // 5: formula descriptions, so listing doesn't refetch them
+    "
+    ALTER TABLE installed_kegs ADD COLUMN description TEXT;
+    ",
+    // 6: the tap each formula was installed from
+    "
+    ALTER TABLE installed_kegs ADD COLUMN tap TEXT;
+    ",
+    // 7: cached bottles in use by an install, see `BlobCache::with_shared_pins`
+    "
+    CREATE TABLE IF NOT EXISTS blob_pins (
+        sha256 TEXT NOT NULL,
+        pid INTEGER NOT NULL,
+        PRIMARY KEY (sha256, pid)
+    );
+    ",
+];
+
+/// The schema version this build writes
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// The version `conn`'s schema is at
+pub fn schema_version(conn: &Connection) -> Result<u32, Error> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| Error::StoreCorruption {
+            message: format!("failed to read schema version: {e}"),
+        })
+}
+
+/// Bring `conn` up to [`SCHEMA_VERSION`], each step in its own transaction.
+/// Refuses a database a newer zerobrew has already migrated past that.
+pub fn migrate(conn: &mut Connection) -> Result<(), Error> {
+    let failed = |e: rusqlite::Error| Error::StoreCorruption {
+        message: format!("failed to migrate database: {e}"),
+    };
+
+    // Reading the version needs no write lock, so an up-to-date database is
+    // opened without blocking (or waiting on) anyone writing to it
+    if !step_pending(schema_version(conn)?)? {
+        return Ok(());
+    }
+
+    loop {
+        // Take the write lock before reading the version again, so two
+        // processes opening the database at once don't both apply a step
+        let tx = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(failed)?;
+        let version = schema_version(&tx)?;
+        if !step_pending(version)? {
+            return Ok(());
+        }
+        let step = MIGRATIONS[version as usize];
+
+        tracing::debug!(from = version, to = version + 1, "migrating database");
+        tx.execute_batch(step).map_err(failed)?;
+        tx.pragma_update(None, "user_version", version + 1)
+            .map_err(failed)?;
+        tx.commit().map_err(failed)?;
+    }
+}
+
+/// Whether a schema at `version` still has steps to apply, refusing one a
+/// newer zerobrew has migrated past this build
+fn step_pending(version: u32) -> Result<bool, Error> {
+    if version > SCHEMA_VERSION {
+        return Err(Error::StoreCorruption {
+            message: format!(
+                "database schema version {version} is newer than this zerobrew supports ({SCHEMA_VERSION}); upgrade zerobrew to use it"
+            ),
+        });
+    }
+    Ok(version < SCHEMA_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+
+    #[test]
+    fn opening_an_unversioned_database_migrates_it_and_keeps_its_data() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("zb.sqlite3");
+        {
+            // The schema before history, pins and versioning existed
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(MIGRATIONS[0]).unwrap();
+            conn.execute(
+                "INSERT INTO installed_kegs (name, version, store_key, installed_at)
+                 VALUES ('jq', '1.7.1', 'abc123', 0)",
+                [],
+            )
+            .unwrap();
+            assert_eq!(schema_version(&conn).unwrap(), 0);
+        }
+
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.get_installed("jq").unwrap().version, "1.7.1");
+        assert!(db.pin("jq").unwrap());
+        assert!(db.history("jq").unwrap().is_empty());
+        drop(db);
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+
+        // Opening again has nothing left to do
+        let db = Database::open(&path).unwrap();
+        assert!(db.get_installed("jq").unwrap().pinned);
+    }
+
+    #[test]
+    fn refuses_a_database_from_a_newer_zerobrew() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("zb.sqlite3");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+                .unwrap();
+        }
+
+        let Err(Error::StoreCorruption { message }) = Database::open(&path) else {
+            panic!("a newer schema should be refused");
+        };
+        assert!(
+            message.contains("newer than this zerobrew supports"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn an_up_to_date_database_opens_while_another_process_writes() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("zb.sqlite3");
+        drop(Database::open(&path).unwrap());
+
+        let mut writer = Connection::open(&path).unwrap();
+        let _tx = writer
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .unwrap();
+
+        let mut conn = Connection::open(&path).unwrap();
+        migrate(&mut conn).unwrap();
+    }
+}
@@ -1,6 +1,18 @@
-/// Progress events during installation
-#[derive(Debug, Clone)]
+use serde::Serialize;
+
+/// Progress events during installation. Each serializes to one JSON object
+/// tagged with its snake_case name, e.g. `{"event":"download_started",...}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
 pub enum InstallProgress {
+    /// Formula metadata fetched so far while planning, out of the formulas
+    /// found so far; `total_known` grows as dependencies are discovered
+    ResolveProgress { resolved: usize, total_known: usize },
+    /// The plan is about to be downloaded (total is `None` if any size is unknown)
+    PlanSized {
+        total_bytes: Option<u64>,
+        packages: usize,
+    },
     /// Starting to download a package (with total size if known)
     DownloadStarted {
         name: String,
@@ -12,12 +24,26 @@ pub enum InstallProgress {
         downloaded: u64,
         total_bytes: Option<u64>,
     },
+    /// Bytes downloaded across every package so far. `total` is `None` while
+    /// any size is unknown and equals `downloaded` once all downloads finish.
+    OverallProgress { downloaded: u64, total: Option<u64> },
     /// Download completed for a package
     DownloadCompleted { name: String, total_bytes: u64 },
     /// Starting to unpack/materialize a package
     UnpackStarted { name: String },
+    /// Bytes of a package unpacked so far, out of `total` when that's known
+    UnpackProgress {
+        name: String,
+        extracted: u64,
+        total: Option<u64>,
+    },
     /// Unpacking completed for a package
     UnpackCompleted { name: String },
+    /// Library paths pointing at Homebrew's prefix were rewritten in these files
+    Relocated {
+        name: String,
+        files: Vec<std::path::PathBuf>,
+    },
     /// Starting to link a package
     LinkStarted { name: String },
     /// Linking completed for a package
@@ -28,3 +54,103 @@ pub enum InstallProgress {
 
 /// Callback type for progress reporting
 pub type ProgressCallback = Box<dyn Fn(InstallProgress) + Send + Sync>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn every_event_serializes_to_a_tagged_object() {
+        let name = || "jq".to_string();
+        let cases = [
+            (
+                InstallProgress::ResolveProgress {
+                    resolved: 1,
+                    total_known: 3,
+                },
+                json!({"event": "resolve_progress", "resolved": 1, "total_known": 3}),
+            ),
+            (
+                InstallProgress::PlanSized {
+                    total_bytes: None,
+                    packages: 2,
+                },
+                json!({"event": "plan_sized", "total_bytes": null, "packages": 2}),
+            ),
+            (
+                InstallProgress::DownloadStarted {
+                    name: name(),
+                    total_bytes: Some(100),
+                },
+                json!({"event": "download_started", "name": "jq", "total_bytes": 100}),
+            ),
+            (
+                InstallProgress::DownloadProgress {
+                    name: name(),
+                    downloaded: 50,
+                    total_bytes: Some(100),
+                },
+                json!({"event": "download_progress", "name": "jq", "downloaded": 50, "total_bytes": 100}),
+            ),
+            (
+                InstallProgress::OverallProgress {
+                    downloaded: 50,
+                    total: None,
+                },
+                json!({"event": "overall_progress", "downloaded": 50, "total": null}),
+            ),
+            (
+                InstallProgress::DownloadCompleted {
+                    name: name(),
+                    total_bytes: 100,
+                },
+                json!({"event": "download_completed", "name": "jq", "total_bytes": 100}),
+            ),
+            (
+                InstallProgress::UnpackStarted { name: name() },
+                json!({"event": "unpack_started", "name": "jq"}),
+            ),
+            (
+                InstallProgress::UnpackProgress {
+                    name: name(),
+                    extracted: 10,
+                    total: Some(20),
+                },
+                json!({"event": "unpack_progress", "name": "jq", "extracted": 10, "total": 20}),
+            ),
+            (
+                InstallProgress::UnpackCompleted { name: name() },
+                json!({"event": "unpack_completed", "name": "jq"}),
+            ),
+            (
+                InstallProgress::Relocated {
+                    name: name(),
+                    files: vec!["lib/libjq.dylib".into()],
+                },
+                json!({"event": "relocated", "name": "jq", "files": ["lib/libjq.dylib"]}),
+            ),
+            (
+                InstallProgress::LinkStarted { name: name() },
+                json!({"event": "link_started", "name": "jq"}),
+            ),
+            (
+                InstallProgress::LinkCompleted { name: name() },
+                json!({"event": "link_completed", "name": "jq"}),
+            ),
+            (
+                InstallProgress::InstallCompleted { name: name() },
+                json!({"event": "install_completed", "name": "jq"}),
+            ),
+        ];
+
+        for (event, expected) in cases {
+            let line = serde_json::to_string(&event).unwrap();
+            assert!(!line.contains('\n'));
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&line).unwrap(),
+                expected
+            );
+        }
+    }
+}
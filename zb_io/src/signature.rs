@@ -0,0 +1,190 @@
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use minisign_verify::{PublicKey, Signature};
+
+use zb_core::Error;
+
+/// What to do when a download has no detached signature
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Warn and accept the download
+    #[default]
+    Permissive,
+    /// Reject the download
+    Strict,
+}
+
+/// Checks minisign signatures against a set of trusted public keys
+pub struct SignatureVerifier {
+    keys: Vec<PublicKey>,
+    policy: SignaturePolicy,
+}
+
+impl SignatureVerifier {
+    /// Build a verifier from base64 minisign public keys
+    pub fn new(keys: &[String], policy: SignaturePolicy) -> Result<Self, Error> {
+        let keys = keys
+            .iter()
+            .map(|key| {
+                PublicKey::from_base64(key.trim()).map_err(|e| Error::InvalidArgument {
+                    message: format!("invalid trusted key '{key}': {e}"),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { keys, policy })
+    }
+
+    pub fn policy(&self) -> SignaturePolicy {
+        self.policy
+    }
+
+    /// Check the file at `path` against `signature`, the contents of its
+    /// `.minisig` file, or `None` if there wasn't one
+    pub fn verify(
+        &self,
+        resource: &str,
+        path: &Path,
+        signature: Option<&str>,
+    ) -> Result<(), Error> {
+        let invalid = |message: String| Error::SignatureInvalid {
+            resource: resource.to_string(),
+            message,
+        };
+
+        let Some(signature) = signature else {
+            return match self.policy {
+                SignaturePolicy::Strict => Err(invalid("no signature found".to_string())),
+                SignaturePolicy::Permissive => {
+                    eprintln!("warning: no signature found for {resource}");
+                    Ok(())
+                }
+            };
+        };
+
+        let signature = Signature::decode(signature)
+            .map_err(|e| invalid(format!("malformed signature: {e}")))?;
+
+        for key in &self.keys {
+            let Ok(mut verifier) = key.verify_stream(&signature) else {
+                // Signed by a different key
+                continue;
+            };
+            let mut file = std::fs::File::open(path)
+                .map_err(|e| invalid(format!("failed to read download: {e}")))?;
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = file
+                    .read(&mut buf)
+                    .map_err(|e| invalid(format!("failed to read download: {e}")))?;
+                if n == 0 {
+                    break;
+                }
+                verifier.update(&buf[..n]);
+            }
+            return verifier.finalize().map_err(|e| invalid(e.to_string()));
+        }
+
+        Err(invalid("not signed by a trusted key".to_string()))
+    }
+}
+
+/// A download's detached signature, fetched before the download so the blob
+/// can be checked before it's committed to the cache
+#[derive(Clone)]
+pub(crate) struct SignatureCheck {
+    verifier: Arc<SignatureVerifier>,
+    resource: String,
+    signature: Option<String>,
+}
+
+impl SignatureCheck {
+    pub(crate) fn new(
+        verifier: Arc<SignatureVerifier>,
+        resource: &str,
+        signature: Option<String>,
+    ) -> Self {
+        Self {
+            verifier,
+            resource: resource.to_string(),
+            signature,
+        }
+    }
+
+    /// The signature's text, or `None` if the download had none
+    pub(crate) fn signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+
+    /// Check the file at `path` off the async runtime
+    pub(crate) async fn verify(&self, path: &Path) -> Result<(), Error> {
+        let check = self.clone();
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            check
+                .verifier
+                .verify(&check.resource, &path, check.signature.as_deref())
+        })
+        .await
+        .map_err(|e| Error::NetworkFailure {
+            message: format!("task join error: {e}"),
+        })?
+    }
+}
+
+/// A test keypair's public key and its signature over `hello world`
+#[cfg(test)]
+pub(crate) const TEST_PUBLIC_KEY: &str = "RWSKxtkF+Cf6nVnhQfyGXgzz6xA8NdNhwbG7GI8SkIvsHsb5x5OZkONO";
+#[cfg(test)]
+pub(crate) const TEST_SIGNATURE: &str = "untrusted comment: signature from rsign secret key
+RUSKxtkF+Cf6nZuHUgrnyIXa2/NBMP3cC2P+rkga/CgDmi8HSdWJ1F3Ccd3BO/NQLli92E+02BMlveyBLildvBV1u8f6JOxVxwY=
+trusted comment: zerobrew test
+UAoyZa3kMJTfb70DQe6a5//PEyMfEAbGK+eymkLinpJHpW/amhvwN8iKuvxTvU0m6H6Rbx6VoDxnKagZs0JgCQ==
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const PUBLIC_KEY: &str = TEST_PUBLIC_KEY;
+    const SIGNATURE: &str = TEST_SIGNATURE;
+
+    fn verifier(policy: SignaturePolicy) -> SignatureVerifier {
+        SignatureVerifier::new(&[PUBLIC_KEY.to_string()], policy).unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_and_rejects_tampered_content() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("bottle.tar.gz");
+        let verifier = verifier(SignaturePolicy::Strict);
+
+        std::fs::write(&path, b"hello world").unwrap();
+        verifier.verify("bottle", &path, Some(SIGNATURE)).unwrap();
+
+        std::fs::write(&path, b"hello w0rld").unwrap();
+        assert!(matches!(
+            verifier.verify("bottle", &path, Some(SIGNATURE)),
+            Err(Error::SignatureInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_signature_depends_on_policy() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("bottle.tar.gz");
+        std::fs::write(&path, b"hello world").unwrap();
+
+        assert!(
+            verifier(SignaturePolicy::Permissive)
+                .verify("bottle", &path, None)
+                .is_ok()
+        );
+        assert!(matches!(
+            verifier(SignaturePolicy::Strict).verify("bottle", &path, None),
+            Err(Error::SignatureInvalid { .. })
+        ));
+    }
+}
@@ -1,15 +1,29 @@
-use std::fs::{self, File};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use fs4::fs_std::FileExt;
+use rayon::prelude::*;
 
-use crate::extract::extract_tarball;
+use tokio::sync::{RwLock, mpsc};
+
+use crate::extract::{ExtractProgress, extract_tarball_stream, extract_tarball_with_progress};
+use crate::lock::FileLock;
 use zb_core::Error;
 
+#[derive(Clone)]
 pub struct Store {
     store_dir: PathBuf,
     locks_dir: PathBuf,
+    manifests_dir: PathBuf,
+    objects_dir: PathBuf,
+    dedup: bool,
+    /// Held shared by blocking work writing into the store or cellar, so
+    /// [`Store::wait_for_blocking`] can tell when it's all finished
+    blocking: Arc<RwLock<()>>,
 }
 
 impl Store {
@@ -23,47 +37,171 @@ impl Store {
         Ok(Self {
             store_dir,
             locks_dir,
+            manifests_dir: root.join("manifests"),
+            objects_dir: root.join("objects"),
+            dedup: false,
+            blocking: Arc::default(),
+        })
+    }
+
+    /// Hardlink files with identical contents across store entries, so bytes
+    /// shared by several bottles (licenses, common libraries) are stored once
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Run `f` on the blocking pool as work [`Store::wait_for_blocking`]
+    /// waits on. It keeps running if the caller stops waiting for it.
+    pub(crate) async fn spawn_blocking<T, F>(&self, f: F) -> Result<T, tokio::task::JoinError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let running = Arc::clone(&self.blocking).read_owned().await;
+        tokio::task::spawn_blocking(move || {
+            let _running = running;
+            f()
         })
+        .await
+    }
+
+    /// Wait for everything started with [`Store::spawn_blocking`] to finish,
+    /// before removing staging directories it may still be writing to
+    pub async fn wait_for_blocking(&self) {
+        let _idle = self.blocking.write().await;
+    }
+
+    /// Take the named lock under `locks/`, waiting for other zb processes
+    pub fn lock(&self, name: &str) -> Result<FileLock, Error> {
+        FileLock::acquire(&self.locks_dir.join(format!("{name}.lock")))
+    }
+
+    /// Like [`Store::lock`], for async callers
+    pub async fn lock_async(&self, name: &str) -> Result<FileLock, Error> {
+        FileLock::acquire_async(&self.locks_dir.join(format!("{name}.lock"))).await
+    }
+
+    /// Take the named lock shared with other shared holders
+    pub fn lock_shared(&self, name: &str) -> Result<FileLock, Error> {
+        FileLock::acquire_shared(&self.locks_dir.join(format!("{name}.lock")))
     }
 
     pub fn entry_path(&self, store_key: &str) -> PathBuf {
         self.store_dir.join(store_key)
     }
 
+    /// Keys of every entry on disk, skipping in-progress extractions
+    pub fn list_entries(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.store_dir)? {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if !name.starts_with('.') && entry.file_type()?.is_dir() {
+                keys.push(name);
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
     pub fn has_entry(&self, store_key: &str) -> bool {
         self.entry_path(store_key).exists()
     }
 
     pub fn ensure_entry(&self, store_key: &str, blob_path: &Path) -> Result<PathBuf, Error> {
+        self.ensure_entry_with_progress(store_key, blob_path, None)
+    }
+
+    /// Like [`Store::ensure_entry`], reporting extraction progress if it has
+    /// to unpack the blob
+    #[tracing::instrument(name = "extract", skip(self, blob_path, progress))]
+    pub fn ensure_entry_with_progress(
+        &self,
+        store_key: &str,
+        blob_path: &Path,
+        progress: Option<ExtractProgress<'_>>,
+    ) -> Result<PathBuf, Error> {
         let entry_path = self.entry_path(store_key);
 
         // Fast path: already exists
         if entry_path.exists() {
+            tracing::debug!("store entry already present");
             return Ok(entry_path);
         }
 
-        // Acquire exclusive lock for this store_key
-        let lock_path = self.locks_dir.join(format!("{store_key}.lock"));
-        let lock_file = File::create(&lock_path).map_err(|e| Error::StoreCorruption {
-            message: format!("failed to create lock file: {e}"),
-        })?;
+        // Unpack to a temp directory first. Other processes may be unpacking
+        // the same entry; whichever promotes first wins.
+        let tmp_dir = self.fresh_tmp_dir(store_key)?;
 
-        lock_file
-            .lock_exclusive()
-            .map_err(|e| Error::StoreCorruption {
-                message: format!("failed to acquire lock: {e}"),
-            })?;
+        // Extract the tarball
+        if let Err(e) = extract_tarball_with_progress(blob_path, &tmp_dir, progress) {
+            // Clean up temp directory on failure
+            let _ = fs::remove_dir_all(&tmp_dir);
+            return Err(e);
+        }
 
-        // Double-check after acquiring lock (another process may have created it)
-        if entry_path.exists() {
-            // Lock will be released when lock_file is dropped
-            return Ok(entry_path);
+        let entry_path = self.promote(&tmp_dir, store_key)?;
+        tracing::debug!(path = %entry_path.display(), "wrote store entry");
+        Ok(entry_path)
+    }
+
+    /// Start extracting a bottle while it downloads. Bytes passed to
+    /// [`StagedExtraction::feed`] are unpacked into a temp directory that only
+    /// becomes the store entry on [`StagedExtraction::commit`]. Returns `None`
+    /// if the entry already exists.
+    pub fn begin_extraction(&self, store_key: &str) -> Result<Option<StagedExtraction>, Error> {
+        if self.entry_path(store_key).exists() {
+            return Ok(None);
         }
 
-        // Unpack to a temp directory first
+        let tmp_dir = self.fresh_tmp_dir(store_key)?;
+        let (sender, receiver) = mpsc::channel(STAGED_CHUNKS);
+        let dest = tmp_dir.clone();
+        let worker = std::thread::spawn(move || {
+            extract_tarball_stream(
+                ChannelReader {
+                    receiver,
+                    chunk: Vec::new(),
+                    pos: 0,
+                },
+                &dest,
+            )
+        });
+
+        Ok(Some(StagedExtraction {
+            sender: Some(sender),
+            worker: Some(worker),
+            tmp_dir,
+            store: self.clone(),
+            store_key: store_key.to_string(),
+        }))
+    }
+
+    /// Remove what this process had unpacked of `store_key` without
+    /// finishing, e.g. after an install was cancelled. A completed entry is
+    /// left alone.
+    pub fn discard_staging(&self, store_key: &str) {
+        let prefix = tmp_dir_prefix(store_key);
+        let Ok(entries) = fs::read_dir(&self.store_dir) else {
+            return;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+
+    /// A temp directory next to where `store_key` will live, unique to this
+    /// extraction and emptied of anything an interrupted one left behind
+    fn fresh_tmp_dir(&self, store_key: &str) -> Result<PathBuf, Error> {
+        let n = NEXT_TMP_DIR.fetch_add(1, Ordering::Relaxed);
         let tmp_dir = self
             .store_dir
-            .join(format!(".{store_key}.tmp.{}", std::process::id()));
+            .join(format!("{}{n}", tmp_dir_prefix(store_key)));
 
         // Clean up any leftover temp directory from a previous interrupted extraction
         // (can happen if the process crashed or was killed during extraction)
@@ -74,25 +212,7 @@ impl Store {
         fs::create_dir_all(&tmp_dir).map_err(|e| Error::StoreCorruption {
             message: format!("failed to create temp directory: {e}"),
         })?;
-
-        // Extract the tarball
-        if let Err(e) = extract_tarball(blob_path, &tmp_dir) {
-            // Clean up temp directory on failure
-            let _ = fs::remove_dir_all(&tmp_dir);
-            return Err(e);
-        }
-
-        // Atomically rename temp dir to final path
-        if let Err(e) = fs::rename(&tmp_dir, &entry_path) {
-            // Clean up temp directory on failure
-            let _ = fs::remove_dir_all(&tmp_dir);
-            return Err(Error::StoreCorruption {
-                message: format!("failed to rename store entry: {e}"),
-            });
-        }
-
-        // Lock will be released when lock_file is dropped
-        Ok(entry_path)
+        Ok(tmp_dir)
     }
 
     /// Remove a store entry. This should only be called when the refcount is 0.
@@ -104,16 +224,7 @@ impl Store {
         }
 
         // Acquire exclusive lock for this store_key
-        let lock_path = self.locks_dir.join(format!("{store_key}.lock"));
-        let lock_file = File::create(&lock_path).map_err(|e| Error::StoreCorruption {
-            message: format!("failed to create lock file: {e}"),
-        })?;
-
-        lock_file
-            .lock_exclusive()
-            .map_err(|e| Error::StoreCorruption {
-                message: format!("failed to acquire lock: {e}"),
-            })?;
+        let _lock = self.lock(store_key)?;
 
         // Remove the directory
         if entry_path.exists() {
@@ -121,12 +232,338 @@ impl Store {
                 message: format!("failed to remove store entry: {e}"),
             })?;
         }
-
-        // Clean up the lock file
-        let _ = fs::remove_file(&lock_path);
+        let _ = fs::remove_file(self.manifest_path(store_key));
 
         Ok(())
     }
+
+    /// Rehash a store entry and compare it with the manifest recorded when it
+    /// was unpacked. Returns `None` for entries unpacked before manifests were
+    /// recorded, since there's nothing to check them against.
+    pub fn verify(&self, store_key: &str) -> Result<Option<Vec<EntryMismatch>>, Error> {
+        let entry_path = self.entry_path(store_key);
+        if !entry_path.exists() {
+            return Err(Error::StoreCorruption {
+                message: format!("store entry {store_key} is missing"),
+            });
+        }
+
+        let recorded: Manifest = match fs::read(self.manifest_path(store_key)) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| Error::StoreCorruption {
+                message: format!("failed to parse manifest for {store_key}: {e}"),
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(Error::StoreCorruption {
+                    message: format!("failed to read manifest for {store_key}: {e}"),
+                });
+            }
+        };
+        let actual = manifest(&entry_path)?;
+
+        let mut mismatches = Vec::new();
+        for (path, fingerprint) in &recorded {
+            match actual.get(path) {
+                None => mismatches.push(EntryMismatch::Missing(path.clone())),
+                Some(found) if found != fingerprint => {
+                    mismatches.push(EntryMismatch::Modified(path.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        mismatches.extend(
+            actual
+                .keys()
+                .filter(|path| !recorded.contains_key(*path))
+                .map(|path| EntryMismatch::Unexpected(path.clone())),
+        );
+        Ok(Some(mismatches))
+    }
+
+    /// Remove shared files no store entry links to any more. Entries hold
+    /// their own hardlinks, so removing one never breaks another.
+    pub fn prune_objects(&self) -> Result<usize, Error> {
+        let entries = match fs::read_dir(&self.objects_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => {
+                return Err(Error::StoreCorruption {
+                    message: format!("failed to read objects directory: {e}"),
+                });
+            }
+        };
+
+        let mut pruned = 0;
+        for entry in entries.filter_map(|e| e.ok()) {
+            if link_count(&entry.path()) == Some(1) && fs::remove_file(entry.path()).is_ok() {
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Hash a finished temp dir and dedupe it if enabled, then take the lock
+    /// for `store_key` just long enough to record its manifest and rename it
+    /// into place. If another process got there first its entry is kept and
+    /// ours discarded. The temp dir is removed on failure.
+    fn promote(&self, tmp_dir: &Path, store_key: &str) -> Result<PathBuf, Error> {
+        let entry_path = self.entry_path(store_key);
+        let recorded = match manifest(tmp_dir) {
+            Ok(m) => m,
+            Err(e) => {
+                let _ = fs::remove_dir_all(tmp_dir);
+                return Err(e);
+            }
+        };
+
+        if self.dedup {
+            self.dedup_files(tmp_dir, &recorded);
+        }
+
+        let _lock = match self.lock(store_key) {
+            Ok(lock) => lock,
+            Err(e) => {
+                let _ = fs::remove_dir_all(tmp_dir);
+                return Err(e);
+            }
+        };
+        if entry_path.exists() {
+            let _ = fs::remove_dir_all(tmp_dir);
+            return Ok(entry_path);
+        }
+
+        if let Err(e) = self.write_manifest(store_key, &recorded) {
+            let _ = fs::remove_dir_all(tmp_dir);
+            return Err(e);
+        }
+        if let Err(e) = fs::rename(tmp_dir, &entry_path) {
+            let _ = fs::remove_dir_all(tmp_dir);
+            return Err(Error::StoreCorruption {
+                message: format!("failed to rename store entry: {e}"),
+            });
+        }
+        Ok(entry_path)
+    }
+
+    fn write_manifest(&self, store_key: &str, manifest: &Manifest) -> Result<(), Error> {
+        let json = serde_json::to_vec(manifest).map_err(|e| Error::StoreCorruption {
+            message: format!("failed to encode manifest: {e}"),
+        })?;
+        fs::create_dir_all(&self.manifests_dir).map_err(|e| Error::StoreCorruption {
+            message: format!("failed to create manifest directory: {e}"),
+        })?;
+        fs::write(self.manifest_path(store_key), json).map_err(|e| Error::StoreCorruption {
+            message: format!("failed to write manifest: {e}"),
+        })
+    }
+
+    /// Replace each file in `dir` with a hardlink to the shared copy of its
+    /// contents under `objects/`, adding a shared copy for contents not seen
+    /// before. Best-effort: a file that can't be linked just keeps its own copy.
+    fn dedup_files(&self, dir: &Path, manifest: &Manifest) {
+        if fs::create_dir_all(&self.objects_dir).is_err() {
+            return;
+        }
+
+        for (rel, fingerprint) in manifest {
+            if fingerprint.starts_with("link:") {
+                continue;
+            }
+            let file = dir.join(rel);
+            let object = self.objects_dir.join(fingerprint.replace(':', "-"));
+
+            match fs::hard_link(&file, &object) {
+                Ok(()) => continue,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(_) => continue,
+            }
+
+            // Link beside the file and rename over it, so it's never missing
+            let staged = file.with_file_name(format!(
+                ".{}.dedup",
+                file.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            if fs::hard_link(&object, &staged).is_ok() && fs::rename(&staged, &file).is_err() {
+                let _ = fs::remove_file(&staged);
+            }
+        }
+    }
+
+    fn manifest_path(&self, store_key: &str) -> PathBuf {
+        self.manifests_dir.join(format!("{store_key}.json"))
+    }
+}
+
+/// A difference between a store entry and its recorded manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryMismatch {
+    Modified(String),
+    Missing(String),
+    Unexpected(String),
+}
+
+impl fmt::Display for EntryMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Modified(path) => write!(f, "modified: {path}"),
+            Self::Missing(path) => write!(f, "missing: {path}"),
+            Self::Unexpected(path) => write!(f, "unexpected: {path}"),
+        }
+    }
+}
+
+/// Relative path of every file and symlink in an entry, mapped to its content
+/// hash and mode (or link target)
+type Manifest = BTreeMap<String, String>;
+
+fn manifest(dir: &Path) -> Result<Manifest, Error> {
+    let entries: Vec<walkdir::DirEntry> = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter(|e| !matches!(e, Ok(e) if e.file_type().is_dir()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| Error::StoreCorruption {
+            message: format!("failed to walk {}: {e}", dir.display()),
+        })?;
+
+    entries
+        .par_iter()
+        .map(|entry| {
+            let rel = entry
+                .path()
+                .strip_prefix(dir)
+                .unwrap_or(entry.path())
+                .to_string_lossy()
+                .into_owned();
+            fingerprint(entry)
+                .map(|fp| (rel, fp))
+                .map_err(|e| Error::StoreCorruption {
+                    message: format!("failed to hash {}: {e}", entry.path().display()),
+                })
+        })
+        .collect()
+}
+
+fn fingerprint(entry: &walkdir::DirEntry) -> io::Result<String> {
+    if entry.path_is_symlink() {
+        return Ok(format!("link:{}", fs::read_link(entry.path())?.display()));
+    }
+
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        entry.metadata()?.permissions().mode() & 0o7777
+    };
+    #[cfg(not(unix))]
+    let mode = 0;
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(entry.path())?)?;
+    Ok(format!("{mode:o}:{}", hasher.finalize().to_hex()))
+}
+
+#[cfg(unix)]
+fn link_count(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::symlink_metadata(path).ok().map(|meta| meta.nlink())
+}
+
+#[cfg(not(unix))]
+fn link_count(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Numbers this process's temp directories, so concurrent extractions of the
+/// same entry never share one
+static NEXT_TMP_DIR: AtomicUsize = AtomicUsize::new(0);
+
+fn tmp_dir_prefix(store_key: &str) -> String {
+    format!(".{store_key}.tmp.{}.", std::process::id())
+}
+
+/// Downloaded chunks buffered between the network and the extractor
+const STAGED_CHUNKS: usize = 64;
+
+/// An extraction fed from a download in progress. Dropping it without
+/// committing removes everything it unpacked.
+pub struct StagedExtraction {
+    sender: Option<mpsc::Sender<Vec<u8>>>,
+    worker: Option<std::thread::JoinHandle<Result<(), Error>>>,
+    tmp_dir: PathBuf,
+    store: Store,
+    store_key: String,
+}
+
+impl StagedExtraction {
+    /// Pass on the next bytes of the download. If the extractor has already
+    /// given up, the bytes are dropped and the failure surfaces on commit.
+    pub async fn feed(&mut self, bytes: &[u8]) {
+        if let Some(sender) = &self.sender
+            && sender.send(bytes.to_vec()).await.is_err()
+        {
+            self.sender = None;
+        }
+    }
+
+    /// Finish unpacking and move the result into the store. Only call this
+    /// once the fed bytes have been verified. Blocks until extraction is done.
+    pub fn commit(mut self) -> Result<PathBuf, Error> {
+        self.sender = None;
+        let finished = self
+            .worker
+            .take()
+            .map(|worker| {
+                worker.join().unwrap_or_else(|_| {
+                    Err(Error::StoreCorruption {
+                        message: "extraction thread panicked".to_string(),
+                    })
+                })
+            })
+            .unwrap_or(Ok(()));
+
+        if let Err(e) = finished {
+            let _ = fs::remove_dir_all(&self.tmp_dir);
+            return Err(e);
+        }
+        self.store.promote(&self.tmp_dir, &self.store_key)
+    }
+}
+
+impl Drop for StagedExtraction {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        if self.tmp_dir.exists() {
+            let _ = fs::remove_dir_all(&self.tmp_dir);
+        }
+    }
+}
+
+/// Reads the chunks sent to a [`StagedExtraction`], ending when it's committed
+/// or dropped
+struct ChannelReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl io::Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
 }
 
 #[cfg(test)]
@@ -236,6 +673,34 @@ mod tests {
         assert_eq!(content, "concurrent test");
     }
 
+    #[test]
+    fn an_entry_promoted_first_is_kept() {
+        let tmp = TempDir::new().unwrap();
+        let store = Store::new(tmp.path()).unwrap();
+
+        let blob_path = tmp.path().join("test.tar.gz");
+        fs::write(&blob_path, create_test_tarball(b"first")).unwrap();
+
+        let mut staged = store.begin_extraction("race").unwrap().unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(staged.feed(&create_test_tarball(b"second")));
+        let entry_path = store.ensure_entry("race", &blob_path).unwrap();
+        assert_eq!(staged.commit().unwrap(), entry_path);
+
+        assert_eq!(
+            fs::read_to_string(entry_path.join("test.txt")).unwrap(),
+            "first"
+        );
+        let leftovers: Vec<_> = fs::read_dir(&store.store_dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().contains(".tmp."))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
     #[test]
     fn has_entry_returns_correct_state() {
         let tmp = TempDir::new().unwrap();
@@ -253,4 +718,60 @@ mod tests {
 
         assert!(store.has_entry(store_key));
     }
+
+    #[test]
+    fn verify_flags_a_corrupted_file() {
+        let tmp = TempDir::new().unwrap();
+        let store = Store::new(tmp.path()).unwrap();
+
+        let tarball = create_test_tarball(b"pristine");
+        let blob_path = tmp.path().join("test.tar.gz");
+        fs::write(&blob_path, &tarball).unwrap();
+        let entry = store.ensure_entry("verifyme", &blob_path).unwrap();
+
+        assert_eq!(store.verify("verifyme").unwrap(), Some(Vec::new()));
+
+        fs::write(entry.join("test.txt"), "tampered").unwrap();
+        fs::write(entry.join("extra.txt"), "new").unwrap();
+
+        assert_eq!(
+            store.verify("verifyme").unwrap(),
+            Some(vec![
+                EntryMismatch::Modified("test.txt".to_string()),
+                EntryMismatch::Unexpected("extra.txt".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn dedup_hardlinks_identical_files_across_entries() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = TempDir::new().unwrap();
+        let store = Store::new(tmp.path()).unwrap().with_dedup(true);
+
+        let blob_path = tmp.path().join("test.tar.gz");
+        fs::write(&blob_path, create_test_tarball(b"same license")).unwrap();
+        let first = store.ensure_entry("first", &blob_path).unwrap();
+        let second = store.ensure_entry("second", &blob_path).unwrap();
+
+        let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+        assert_eq!(
+            inode(&first.join("test.txt")),
+            inode(&second.join("test.txt"))
+        );
+
+        // Removing one entry leaves the other intact, and the shared copy is
+        // only pruned once nothing links to it
+        store.remove_entry("first").unwrap();
+        assert_eq!(store.prune_objects().unwrap(), 0);
+        assert_eq!(
+            fs::read_to_string(second.join("test.txt")).unwrap(),
+            "same license"
+        );
+        assert_eq!(store.verify("second").unwrap(), Some(Vec::new()));
+
+        store.remove_entry("second").unwrap();
+        assert_eq!(store.prune_objects().unwrap(), 1);
+    }
 }